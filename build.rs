@@ -103,6 +103,89 @@ fn write_binary(instr: &str, ty: &str, op: &str, source: &mut String) {
     ));
 }
 
+/// Division and remainder panic on a zero divisor or overflow, regardless of overflow checks.
+fn write_checked_div(instr: &str, ty: &str, op: &str, source: &mut String) {
+    let (zero_msg, overflow_msg) = match op {
+        "div" => (
+            "attempt to divide by zero",
+            "attempt to divide with overflow",
+        ),
+        "rem" => (
+            "attempt to calculate the remainder with a divisor of zero",
+            "attempt to calculate the remainder with overflow",
+        ),
+        _ => panic!("bad op {}", op),
+    };
+    source.push_str(&format!(
+        "
+    Instr::{instr}(out, lhs, rhs) => {{
+        let a: {ty} = read_stack(stack, *lhs);
+        let b: {ty} = read_stack(stack, *rhs);
+        let res = match a.checked_{op}(b) {{
+            Some(res) => res,
+            None if b == 0 => self.panic_with_message(func, stack, pc, \"{zero_msg}\"),
+            None => self.panic_with_message(func, stack, pc, \"{overflow_msg}\"),
+        }};
+        write_stack(stack, *out, res);
+    }}"
+    ));
+}
+
+/// Arithmetic with overflow checks enabled, panics instead of wrapping.
+fn write_checked_arith(instr: &str, ty: &str, op: &str, source: &mut String) {
+    let msg = match op {
+        "add" => "attempt to add with overflow",
+        "sub" => "attempt to subtract with overflow",
+        "mul" => "attempt to multiply with overflow",
+        _ => panic!("bad op {}", op),
+    };
+    source.push_str(&format!(
+        "
+    Instr::{instr}(out, lhs, rhs) => {{
+        let a: {ty} = read_stack(stack, *lhs);
+        let b: {ty} = read_stack(stack, *rhs);
+        let Some(res) = a.checked_{op}(b) else {{
+            self.panic_with_message(func, stack, pc, \"{msg}\")
+        }};
+        write_stack(stack, *out, res);
+    }}"
+    ));
+}
+
+fn write_checked_neg(instr: &str, ty: &str, source: &mut String) {
+    source.push_str(&format!(
+        "
+    Instr::{instr}(out, src) => {{
+        let x: {ty} = read_stack(stack, *src);
+        let Some(res) = x.checked_neg() else {{
+            self.panic_with_message(func, stack, pc, \"attempt to negate with overflow\")
+        }};
+        write_stack(stack, *out, res);
+    }}"
+    ));
+}
+
+/// Shifts with overflow checks enabled. The shift amount is widened to u128 by the compiler.
+fn write_checked_shift(instr: &str, ty: &str, op: &str, source: &mut String) {
+    let msg = match op {
+        "<<" => "attempt to shift left with overflow",
+        ">>" => "attempt to shift right with overflow",
+        _ => panic!("bad op {}", op),
+    };
+    source.push_str(&format!(
+        "
+    Instr::{instr}(out, lhs, rhs) => {{
+        let a: {ty} = read_stack(stack, *lhs);
+        let b: u128 = read_stack(stack, *rhs);
+        if b >= {ty}::BITS as u128 {{
+            self.panic_with_message(func, stack, pc, \"{msg}\");
+        }}
+        let res = a {op} b;
+        write_stack(stack, *out, res);
+    }}"
+    ));
+}
+
 fn write_shift(instr: &str, ty: &str, op: &str, source: &mut String) {
     source.push_str(&format!(
         "
@@ -152,12 +235,7 @@ fn write_cast(name: &str, dst_ty: &str, src_ty: &str, source: &mut String) {
 fn write_int_ops(signed: &str, unsigned: &str, source: &mut String) {
     let big = signed.to_uppercase();
 
-    if signed == "i128" {
-        // I128 constants are boxed
-        write_immediate(&format!("{}_Const", big), signed, "**x", source);
-    } else {
-        write_immediate(&format!("{}_Const", big), signed, "*x", source);
-    }
+    write_immediate(&format!("{}_Const", big), signed, "*x", source);
     write_unary(&format!("{}_Neg", big), signed, "x.wrapping_neg()", source);
     write_unary(&format!("{}_Not", big), signed, "!x", source);
     write_binary(&format!("{}_Eq", big), signed, "a == b", source);
@@ -176,18 +254,8 @@ fn write_int_ops(signed: &str, unsigned: &str, source: &mut String) {
     );
     write_binary(&format!("{}_S_Lt", big), signed, "a < b", source);
     write_binary(&format!("{}_S_LtEq", big), signed, "a <= b", source);
-    write_binary(
-        &format!("{}_S_Div", big),
-        signed,
-        "a.wrapping_div(b)",
-        source,
-    );
-    write_binary(
-        &format!("{}_S_Rem", big),
-        signed,
-        "a.wrapping_rem(b)",
-        source,
-    );
+    write_checked_div(&format!("{}_S_Div", big), signed, "div", source);
+    write_checked_div(&format!("{}_S_Rem", big), signed, "rem", source);
     write_shift(
         &format!("{}_S_ShiftR", big),
         signed,
@@ -196,28 +264,30 @@ fn write_int_ops(signed: &str, unsigned: &str, source: &mut String) {
     );
     write_binary(&format!("{}_U_Lt", big), unsigned, "a < b", source);
     write_binary(&format!("{}_U_LtEq", big), unsigned, "a <= b", source);
-    write_binary(
-        &format!("{}_U_Div", big),
+    write_checked_div(&format!("{}_U_Div", big), unsigned, "div", source);
+    write_checked_div(&format!("{}_U_Rem", big), unsigned, "rem", source);
+    write_shift(
+        &format!("{}_U_ShiftR", big),
         unsigned,
-        "a.wrapping_div(b)",
+        "a.wrapping_shr(b as _)",
         source,
     );
-    write_binary(
-        &format!("{}_U_Rem", big),
+    write_unary(
+        &format!("{}_PopCount", big),
         unsigned,
-        "a.wrapping_rem(b)",
+        &format!("x.count_ones() as {}", unsigned),
         source,
     );
-    write_shift(
-        &format!("{}_U_ShiftR", big),
+    write_unary(
+        &format!("{}_LeadingZeros", big),
         unsigned,
-        "a.wrapping_shr(b as _)",
+        &format!("x.leading_zeros() as {}", unsigned),
         source,
     );
     write_unary(
-        &format!("{}_PopCount", big),
+        &format!("{}_TrailingZeros", big),
         unsigned,
-        "x.count_ones()",
+        &format!("x.trailing_zeros() as {}", unsigned),
         source,
     );
     write_unary(
@@ -252,6 +322,18 @@ fn write_int_ops(signed: &str, unsigned: &str, source: &mut String) {
         "a.saturating_add(b)",
         source,
     );
+    write_binary(
+        &format!("{}_S_SatSub", big),
+        signed,
+        "a.saturating_sub(b)",
+        source,
+    );
+    write_binary(
+        &format!("{}_U_SatSub", big),
+        unsigned,
+        "a.saturating_sub(b)",
+        source,
+    );
 
     write_binary(
         &format!("{}_S_OverflowingAdd", big),
@@ -291,6 +373,84 @@ fn write_int_ops(signed: &str, unsigned: &str, source: &mut String) {
         "a.overflowing_mul(b)",
         source,
     );
+
+    write_checked_arith(&format!("{}_S_CheckedAdd", big), signed, "add", source);
+    write_checked_arith(&format!("{}_U_CheckedAdd", big), unsigned, "add", source);
+    write_checked_arith(&format!("{}_S_CheckedSub", big), signed, "sub", source);
+    write_checked_arith(&format!("{}_U_CheckedSub", big), unsigned, "sub", source);
+    write_checked_arith(&format!("{}_S_CheckedMul", big), signed, "mul", source);
+    write_checked_arith(&format!("{}_U_CheckedMul", big), unsigned, "mul", source);
+    write_checked_neg(&format!("{}_CheckedNeg", big), signed, source);
+    write_checked_shift(&format!("{}_CheckedShiftL", big), signed, "<<", source);
+    write_checked_shift(&format!("{}_S_CheckedShiftR", big), signed, ">>", source);
+    write_checked_shift(&format!("{}_U_CheckedShiftR", big), unsigned, ">>", source);
+}
+
+/// Superinstructions fused by the bytecode optimizer. Compare-and-branch instructions jump when
+/// the comparison is false, and come in three forms: two slots, a slot and an immediate, and a
+/// field read through a pointer and a slot.
+fn write_fused_ops(signed: &str, unsigned: &str, source: &mut String) {
+    let big = signed.to_uppercase();
+
+    // each comparison with the condition the instructions jump on, which is its negation
+    let compares = [
+        ("Eq", signed, "a != b"),
+        ("NotEq", signed, "a == b"),
+        ("S_Lt", signed, "a >= b"),
+        ("S_LtEq", signed, "a > b"),
+        ("S_Gt", signed, "a <= b"),
+        ("S_GtEq", signed, "a < b"),
+        ("U_Lt", unsigned, "a >= b"),
+        ("U_LtEq", unsigned, "a > b"),
+        ("U_Gt", unsigned, "a <= b"),
+        ("U_GtEq", unsigned, "a < b"),
+    ];
+
+    for (name, ty, jump_if) in compares {
+        // greater-than is only needed when one side is fixed, otherwise the slots are swapped
+        if !name.contains("Gt") {
+            source.push_str(&format!(
+                "
+    Instr::{big}_{name}JumpF(lhs, rhs, offset) => {{
+        let a: {ty} = read_stack(stack, *lhs);
+        let b: {ty} = read_stack(stack, *rhs);
+        if {jump_if} {{
+            pc = (pc as isize + *offset as isize) as usize;
+            continue;
+        }}
+    }}"
+            ));
+        }
+        source.push_str(&format!(
+            "
+    Instr::{big}_{name}ImmJumpF(lhs, imm, offset) => {{
+        let a: {ty} = read_stack(stack, *lhs);
+        let b = *imm as {ty};
+        if {jump_if} {{
+            pc = (pc as isize + *offset as isize) as usize;
+            continue;
+        }}
+    }}
+    Instr::{big}_{name}FieldJumpF(field, ptr, rhs, offset) => {{
+        let ptr: *mut u8 = read_stack(stack, *ptr);
+        let a = *(ptr.offset(*field as isize) as *mut {ty});
+        let b: {ty} = read_stack(stack, *rhs);
+        if {jump_if} {{
+            pc = (pc as isize + *offset as isize) as usize;
+            continue;
+        }}
+    }}"
+        ));
+    }
+
+    source.push_str(&format!(
+        "
+    Instr::{big}_AddImm(out, src, imm) => {{
+        let x: {signed} = read_stack(stack, *src);
+        let res = x.wrapping_add(*imm as {signed});
+        write_stack(stack, *out, res);
+    }}"
+    ));
 }
 
 fn write_float_ops(ty: &str, source: &mut String) {
@@ -314,6 +474,301 @@ fn write_float_ops(ty: &str, source: &mut String) {
     write_binary(&format!("{}_Max", big), ty, "a.max(b)", source);
 }
 
+/// Atomics are performed with the host's atomic types, with the orderings stored in the instruction.
+fn write_atomic_ops(signed: &str, unsigned: &str, source: &mut String) {
+    let big = signed.to_uppercase();
+    let atomic_s = format!("std::sync::atomic::Atomic{}", big);
+    let atomic_u = format!("std::sync::atomic::Atomic{}", unsigned.to_uppercase());
+
+    source.push_str(&format!(
+        "
+    Instr::{big}_AtomicLoad(ordering, out, ptr) => {{
+        let ptr: *mut {unsigned} = read_stack(stack, *ptr);
+        let res = {atomic_u}::from_ptr(ptr).load(*ordering);
+        write_stack(stack, *out, res);
+    }}
+    Instr::{big}_AtomicStore(ordering, ptr, val) => {{
+        let ptr: *mut {unsigned} = read_stack(stack, *ptr);
+        let val: {unsigned} = read_stack(stack, *val);
+        {atomic_u}::from_ptr(ptr).store(val, *ordering);
+    }}"
+    ));
+
+    let rmw_ops = [
+        ("AtomicXchg", &atomic_u, unsigned, "swap"),
+        ("AtomicAdd", &atomic_u, unsigned, "fetch_add"),
+        ("AtomicSub", &atomic_u, unsigned, "fetch_sub"),
+        ("AtomicAnd", &atomic_u, unsigned, "fetch_and"),
+        ("AtomicOr", &atomic_u, unsigned, "fetch_or"),
+        ("AtomicXor", &atomic_u, unsigned, "fetch_xor"),
+        ("AtomicNand", &atomic_u, unsigned, "fetch_nand"),
+        ("S_AtomicMax", &atomic_s, signed, "fetch_max"),
+        ("S_AtomicMin", &atomic_s, signed, "fetch_min"),
+        ("U_AtomicMax", &atomic_u, unsigned, "fetch_max"),
+        ("U_AtomicMin", &atomic_u, unsigned, "fetch_min"),
+    ];
+
+    for (name, atomic, ty, method) in rmw_ops {
+        source.push_str(&format!(
+            "
+    Instr::{big}_{name}(ordering, out, ptr, val) => {{
+        let ptr: *mut {ty} = read_stack(stack, *ptr);
+        let val: {ty} = read_stack(stack, *val);
+        let res = {atomic}::from_ptr(ptr).{method}(val, *ordering);
+        write_stack(stack, *out, res);
+    }}"
+        ));
+    }
+
+    for (name, method) in [
+        ("Cxchg", "compare_exchange"),
+        ("CxchgWeak", "compare_exchange_weak"),
+    ] {
+        source.push_str(&format!(
+            "
+    Instr::{big}_Atomic{name}(success, failure, out, ptr, val) => {{
+        let ptr: *mut {unsigned} = read_stack(stack, *ptr);
+        let expected: {unsigned} = read_stack(stack, *out);
+        let val: {unsigned} = read_stack(stack, *val);
+        let res = match {atomic_u}::from_ptr(ptr).{method}(expected, val, *success, *failure) {{
+            Ok(old) => (old, true),
+            Err(old) => (old, false),
+        }};
+        write_stack(stack, *out, res.0);
+        write_stack(stack, out.offset_by(std::mem::size_of::<{unsigned}>() as i32), res.1);
+    }}"
+        ));
+    }
+}
+
+/// Writes the interpreter's match over the compact form of bytecode. Each instruction is its
+/// opcode followed by its fields, and jumps are followed by their offset in bytes from the next
+/// instruction. Each arm reads its fields from `input`, which is left at the next instruction.
+/// The opcodes and the structs the fields are read into are derived from `Instr`.
+///
+/// The arms of the match are the same as in `exec_match.rs`, with their patterns matched against
+/// the fields read from the code instead. Instructions without an arm are written whole after
+/// `FALLBACK_OPCODE`, and share the catch-all arm. It is moved out of the loop into
+/// `exec_compact_fallback`, which reads them back as an `Instr`, since an `Instr` in the loop's
+/// frame makes every other arm slower. `has_compact_arm` tells the encoder which instructions have an arm.
+fn write_compact(exec_match: &str, out_dir: &str) {
+    let exec_match: syn::ExprMatch = syn::parse_str(exec_match).unwrap();
+
+    let mut decode = String::from("match opcode {");
+    let mut fallback = String::new();
+    let mut arm_patterns = Vec::new();
+    for arm in exec_match.arms {
+        let mut pattern = arm.pat;
+        let mut body = *arm.body;
+
+        let path = match &mut pattern {
+            syn::Pat::TupleStruct(pattern) => Some(&mut pattern.path),
+            syn::Pat::Struct(pattern) => Some(&mut pattern.path),
+            syn::Pat::Path(pattern) => Some(&mut pattern.path),
+            syn::Pat::Wild(_) => None,
+            _ => panic!(
+                "unexpected pattern in exec_match: {}",
+                quote::quote!(#pattern)
+            ),
+        };
+        let Some(path) = path else {
+            fallback = format!(
+                "impl<'vm> VMThread<'vm> {{
+    /// Runs an instruction written whole after `FALLBACK_OPCODE`, and returns `input` past it.
+    /// Unlike the loop's other arms, it can't jump or leave the loop.
+    #[cold]
+    #[inline(never)]
+    #[allow(unreachable_code, unused_mut, unused_variables)]
+    unsafe fn exec_compact_fallback<'a>(
+        &mut self,
+        func: &FunctionBytecode<'vm>,
+        stack: *mut u8,
+        drops_base: usize,
+        mut input: CompactReader<'a, 'vm>,
+        pc: usize,
+    ) -> CompactReader<'a, 'vm> {{
+        let instr = &Instr::read_compact(input.take_opcode(), &mut input);
+        {};
+        input
+    }}
+}}
+",
+                quote::quote!(#body)
+            );
+            decode.push_str(
+                "
+    FALLBACK_OPCODE => {
+        // passed by value, so that the cursor can stay in a register
+        input = self.exec_compact_fallback(func, stack, drops_base, input, pc);
+    }",
+            );
+            continue;
+        };
+        let name = path.segments.last().unwrap().ident.clone();
+        arm_patterns.push(format!("Instr::{name} {{ .. }}"));
+        *path = syn::parse_quote!(compact_fields::#name);
+
+        let take = if let syn::Pat::Path(_) = pattern {
+            String::new()
+        } else {
+            format!(
+                "
+        let {} = &compact_fields::{name}::take(&mut input);",
+                quote::quote!(#pattern)
+            )
+        };
+        let mut jumps = FindJumps(false);
+        syn::visit_mut::VisitMut::visit_expr_mut(&mut jumps, &mut body);
+        let jump = if jumps.0 {
+            "
+        let jump_bytes = input.take_jump();"
+        } else {
+            ""
+        };
+        decode.push_str(&format!(
+            "
+    opcode::{name} => {{{take}{jump}
+        {};
+    }}",
+            quote::quote!(#body)
+        ));
+    }
+    // the encoder only writes opcodes which have an arm. Checking for others in release builds
+    // keeps the match from compiling to a bare jump table, and doubles the time `dumb_bench` takes.
+    decode.push_str(
+        "
+    _ => {
+        debug_assert!(false, \"bad opcode {opcode} in compact code\");
+        std::hint::unreachable_unchecked()
+    }
+}",
+    );
+
+    let has_arm = format!(
+        "/// Whether the interpreter loop has an arm for an instruction.
+fn has_compact_arm(instr: &Instr) -> bool {{
+    matches!(instr, {})
+}}
+",
+        arm_patterns.join(" | ")
+    );
+    std::fs::write(format!("{out_dir}/compact_arms.rs"), has_arm).unwrap();
+    std::fs::write(format!("{out_dir}/exec_compact.rs"), decode).unwrap();
+    std::fs::write(format!("{out_dir}/exec_compact_fallback.rs"), fallback).unwrap();
+}
+
+/// Finds the jumps in an arm, which continue the loop at another instruction, and moves `input` by
+/// the offset read after the instruction before each one.
+struct FindJumps(bool);
+
+impl syn::visit_mut::VisitMut for FindJumps {
+    fn visit_expr_mut(&mut self, expr: &mut syn::Expr) {
+        if let syn::Expr::Continue(_) = expr {
+            self.0 = true;
+            *expr = syn::parse_quote!({
+                input.jump(jump_bytes);
+                continue;
+            });
+        } else {
+            syn::visit_mut::visit_expr_mut(self, expr);
+        }
+    }
+}
+
+/// Writes a handler method per arm of the interpreter's match, for the call-threaded loop, and
+/// `threaded_handler`, which resolves an instruction to its handler. Handlers match their arm's
+/// pattern against the instruction, and return the next pc instead of continuing the loop.
+/// `Return` returns `RETURN_PC` instead of breaking out of it.
+fn write_threaded(exec_match: &str, out_dir: &str) {
+    let exec_match: syn::ExprMatch = syn::parse_str(exec_match).unwrap();
+
+    let mut handlers = String::new();
+    let mut resolve = String::new();
+    for arm in exec_match.arms {
+        let pattern = arm.pat;
+        let mut body = *arm.body;
+        syn::visit_mut::VisitMut::visit_expr_mut(&mut LeaveLoop, &mut body);
+
+        let path = match &pattern {
+            syn::Pat::TupleStruct(pattern) => Some(&pattern.path),
+            syn::Pat::Struct(pattern) => Some(&pattern.path),
+            syn::Pat::Path(pattern) => Some(&pattern.path),
+            syn::Pat::Wild(_) => None,
+            _ => panic!(
+                "unexpected pattern in exec_match: {}",
+                quote::quote!(#pattern)
+            ),
+        };
+        let (name, bind) = match path {
+            Some(path) => {
+                let name = path.segments.last().unwrap().ident.to_string();
+                resolve.push_str(&format!(
+                    "
+            Instr::{name} {{ .. }} => Self::exec_{name},"
+                ));
+                let bind = format!(
+                    "
+        let {} = instr else {{
+            std::hint::unreachable_unchecked()
+        }};",
+                    quote::quote!(#pattern)
+                );
+                (name, bind)
+            }
+            // the catch-all arm
+            None => ("Other".to_owned(), String::new()),
+        };
+
+        handlers.push_str(&format!(
+            "
+    #[allow(non_snake_case, unreachable_code, unused_mut, unused_variables)]
+    unsafe fn exec_{name}(
+        &mut self,
+        func: &FunctionBytecode<'vm>,
+        stack: *mut u8,
+        drops_base: usize,
+        instr: &Instr<'vm>,
+        mut pc: usize,
+    ) -> usize {{{bind}
+        {};
+        pc + 1
+    }}
+",
+            quote::quote!(#body)
+        ));
+    }
+
+    let source = format!(
+        "impl<'vm> VMThread<'vm> {{{handlers}
+    pub(super) fn threaded_handler(instr: &Instr<'vm>) -> Handler<'vm> {{
+        match instr {{{resolve}
+            _ => Self::exec_Other,
+        }}
+    }}
+}}
+"
+    );
+    std::fs::write(format!("{out_dir}/exec_threaded.rs"), source).unwrap();
+}
+
+/// Turns an arm's jumps into returns of the next pc, and `break` into a return of `RETURN_PC`.
+/// Loops and closures inside the arm keep their own.
+struct LeaveLoop;
+
+impl syn::visit_mut::VisitMut for LeaveLoop {
+    fn visit_expr_mut(&mut self, expr: &mut syn::Expr) {
+        match expr {
+            syn::Expr::Continue(_) => *expr = syn::parse_quote!(return pc),
+            syn::Expr::Break(_) => *expr = syn::parse_quote!(return RETURN_PC),
+            syn::Expr::ForLoop(_)
+            | syn::Expr::While(_)
+            | syn::Expr::Loop(_)
+            | syn::Expr::Closure(_) => (),
+            _ => syn::visit_mut::visit_expr_mut(self, expr),
+        }
+    }
+}
+
 fn write_exec_match() {
     let mut source = String::new();
     source.push_str("match instr {");
@@ -324,6 +779,16 @@ fn write_exec_match() {
     write_int_ops("i64", "u64", &mut source);
     write_int_ops("i128", "u128", &mut source);
 
+    write_fused_ops("i8", "u8", &mut source);
+    write_fused_ops("i16", "u16", &mut source);
+    write_fused_ops("i32", "u32", &mut source);
+    write_fused_ops("i64", "u64", &mut source);
+
+    write_atomic_ops("i8", "u8", &mut source);
+    write_atomic_ops("i16", "u16", &mut source);
+    write_atomic_ops("i32", "u32", &mut source);
+    write_atomic_ops("i64", "u64", &mut source);
+
     write_float_ops("f32", &mut source);
     write_float_ops("f64", &mut source);
 
@@ -462,17 +927,49 @@ fn write_exec_match() {
         }
     }
     Instr::Call(frame,func) => {
+        self.call_stack().set_pc(pc);
         self.call(func,stack.add(frame.index()));
     }
     Instr::CallPtr{ frame, func_ptr } => {
         let func: &Function = read_stack(stack, *func_ptr);
+        self.call_stack().set_pc(pc);
         self.call(func,stack.add(frame.index()));
     }
+    Instr::ThreadLocalAddr(out,item) => {
+        let ptr = self.thread_local_addr(item);
+        write_stack(stack, *out, ptr);
+    }
+    Instr::CallerLocation(out) => {
+        self.call_stack().set_pc(pc);
+        let ptr = self.caller_location();
+        write_stack(stack, *out, ptr);
+    }
+    Instr::CatchUnwind{ out, frame, call_frame } => {
+        self.call_stack().set_pc(pc);
+        let res = self.call_try(stack.add(frame.index()), stack.add(call_frame.index()));
+        write_stack(stack, *out, res);
+    }
     Instr::VTableFunc(out,arg,index) => {
         let vtable: &VTable = read_stack(stack, *arg);
         let func = vtable.methods[*index as usize].expect("no method in vtable");
         write_stack(stack, *out, func);
     }
+    Instr::VTableSize(out,arg) => {
+        let vtable: &VTable = read_stack(stack, *arg);
+        write_stack(stack, *out, vtable.size as usize);
+    }
+    Instr::VTableAlign(out,arg) => {
+        let vtable: &VTable = read_stack(stack, *arg);
+        write_stack(stack, *out, vtable.align as usize);
+    }
+    Instr::VTableDrop{ frame, arg } => {
+        let (ptr, vtable): (usize, &VTable) = read_stack(stack, *arg);
+        if let Some(glue) = vtable.drop {
+            write_stack(stack, *frame, ptr);
+            self.call_stack().set_pc(pc);
+            self.call(glue, stack.add(frame.index()));
+        }
+    }
     Instr::SlotAddr(out,arg) => {
         let res = stack.add(arg.index()) as usize;
         write_stack(stack, *out, res);
@@ -505,7 +1002,7 @@ fn write_exec_match() {
     Instr::IndexCalc { arg_out, elem_size, elem_count } => {
         let index: usize = read_stack(stack, *arg_out);
         if index >= *elem_count as usize {
-            panic!("array index out of bounds");
+            self.panic_bounds_check(func, stack, pc, index, *elem_count as usize);
         }
         let offset = index * *elem_size as usize;
         write_stack(stack, *arg_out, offset);
@@ -514,8 +1011,7 @@ fn write_exec_match() {
         let index: usize = read_stack(stack, *arg_out);
         let elem_count: usize = read_stack(stack, *elem_count);
         if index >= elem_count {
-            println!("{} {}",index,elem_count);
-            panic!("array index out of bounds");
+            self.panic_bounds_check(func, stack, pc, index, elem_count);
         }
         let offset = index * *elem_size as usize;
         write_stack(stack, *arg_out, offset);
@@ -553,9 +1049,15 @@ fn write_exec_match() {
         let res = arg1 == arg2;
         write_stack(stack, *out, res);
     }
+    Instr::AtomicFence(ordering) => std::sync::atomic::fence(*ordering),
+    Instr::CompilerFence(ordering) => std::sync::atomic::compiler_fence(*ordering),
     Instr::Return => break,
     Instr::Skipped => panic!("encountered skipped instruction, this should never happen"),
-    Instr::Error(msg) => panic!("interpreter error: {}",msg),
+    Instr::Error(msg) => {
+        self.call_stack().set_pc(pc);
+        panic!("interpreter error: {}",msg)
+    }
+    Instr::Abort => std::process::abort(),
     Instr::Debug(msg) => println!("interpreter debug: {}",msg),
     Instr::Alloc{out,size,align} => {
         let res = self.vm.alloc_bytes(*size as usize,*align as usize);
@@ -572,13 +1074,15 @@ fn write_exec_match() {
         }
     }
     Instr::LocalDrop((start,end)) => {
+        self.call_stack().set_pc(pc);
         for i in (start.index()..=end.index()).rev() {
-            self.local_drop(drops_base, i, &func.drops, stack);
+            self.local_drop(drops_base, i, func, stack);
         }
     }
     Instr::LocalDropInit((start,end)) => {
+        self.call_stack().set_pc(pc);
         for i in (start.index()..=end.index()).rev() {
-            self.local_drop_init(drops_base, i, &func.drops, stack);
+            self.local_drop_init(drops_base, i, func, stack);
         }
     }
     _ => panic!("NYI {:?}",instr)
@@ -586,6 +1090,8 @@ fn write_exec_match() {
     );
 
     let out_dir = std::env::var("OUT_DIR").unwrap();
+    write_compact(&source, &out_dir);
+    write_threaded(&source, &out_dir);
     std::fs::write(format!("{out_dir}/exec_match.rs"), source).unwrap();
 
     let t = std::time::SystemTime::now()
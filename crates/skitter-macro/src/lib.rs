@@ -1,7 +1,7 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::{parse_macro_input, DeriveInput};
 
 #[proc_macro_derive(Persist)]
@@ -159,3 +159,167 @@ pub fn derive_answer_fn(input: TokenStream) -> TokenStream {
     }
     res
 }
+
+/// Derives the compact encoding of an instruction enum, see `vm::compact`. Generates:
+///
+/// - `write_compact`, which appends an instruction's opcode and then each of its fields, and
+///   `read_compact`, which reads the fields of an instruction back given its opcode.
+/// - A module `opcode`, with the opcode of each variant. Opcodes are the variants' indices, and
+///   `COUNT` is the number of variants. `NAMES` holds the variants' names, by opcode.
+/// - A module `compact_fields`, with a struct per variant which has fields, shaped like the
+///   variant. Its `take` reads the fields back, so the interpreter can match the same patterns
+///   against it as against the enum.
+#[proc_macro_derive(Compact)]
+pub fn derive_compact(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let enum_ident = input.ident;
+    let enum_generics = input.generics;
+
+    let syn::Data::Enum(data) = input.data else {
+        panic!("compact encoding of a non-enum?")
+    };
+
+    let mut write_cases = Vec::new();
+    let mut read_cases = Vec::new();
+    let mut opcodes = Vec::new();
+    let mut names = Vec::new();
+    let mut field_structs = Vec::new();
+
+    for (variant_n, variant) in data.variants.iter().enumerate() {
+        let variant_ident = &variant.ident;
+        let opcode = variant_n as u16;
+
+        opcodes.push(quote! {
+            pub const #variant_ident: u16 = #opcode;
+        });
+        names.push(variant_ident.to_string());
+
+        match variant.fields {
+            syn::Fields::Unit => {
+                write_cases.push(quote! {
+                    Self::#variant_ident => writer.put_opcode(#opcode),
+                });
+                read_cases.push(quote! {
+                    #opcode => Self::#variant_ident,
+                });
+            }
+            syn::Fields::Named(ref fields) => {
+                let field_names: Vec<_> = fields
+                    .named
+                    .iter()
+                    .map(|f| f.ident.as_ref().unwrap())
+                    .collect();
+                let field_tys: Vec<_> = fields.named.iter().map(|f| &f.ty).collect();
+
+                write_cases.push(quote! {
+                    Self::#variant_ident{#(#field_names),*} => {
+                        writer.put_opcode(#opcode);
+                        #(CompactField::put(#field_names, writer);)*
+                    },
+                });
+                read_cases.push(quote! {
+                    #opcode => Self::#variant_ident{
+                        #(#field_names: <#field_tys as CompactField<'vm>>::take_owned(input)),*
+                    },
+                });
+
+                field_structs.push(quote! {
+                    pub struct #variant_ident<'a, 'vm: 'a> {
+                        #(pub #field_names: <#field_tys as CompactField<'vm>>::Read<'a>),*
+                    }
+
+                    impl<'a, 'vm: 'a> #variant_ident<'a, 'vm> {
+                        #[cfg_attr(not(debug_assertions), inline(always))]
+                        pub unsafe fn take(input: &mut CompactReader<'a, 'vm>) -> Self {
+                            Self {
+                                #(#field_names: <#field_tys as CompactField<'vm>>::take(input)),*
+                            }
+                        }
+                    }
+                });
+            }
+            syn::Fields::Unnamed(ref fields) => {
+                let field_tmp_names: Vec<_> = (0..fields.unnamed.len())
+                    .map(|i| format_ident!("f{}", i))
+                    .collect();
+                let field_tys: Vec<_> = fields.unnamed.iter().map(|f| &f.ty).collect();
+
+                write_cases.push(quote! {
+                    Self::#variant_ident(#(#field_tmp_names),*) => {
+                        writer.put_opcode(#opcode);
+                        #(CompactField::put(#field_tmp_names, writer);)*
+                    },
+                });
+                read_cases.push(quote! {
+                    #opcode => Self::#variant_ident(
+                        #(<#field_tys as CompactField<'vm>>::take_owned(input)),*
+                    ),
+                });
+
+                field_structs.push(quote! {
+                    pub struct #variant_ident<'a, 'vm: 'a>(
+                        #(pub <#field_tys as CompactField<'vm>>::Read<'a>),*
+                    );
+
+                    impl<'a, 'vm: 'a> #variant_ident<'a, 'vm> {
+                        #[cfg_attr(not(debug_assertions), inline(always))]
+                        pub unsafe fn take(input: &mut CompactReader<'a, 'vm>) -> Self {
+                            Self(#(<#field_tys as CompactField<'vm>>::take(input)),*)
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    let opcode_count = data.variants.len() as u16;
+
+    let res = quote! {
+        impl<'vm> #enum_ident #enum_generics {
+            /// Appends this instruction to the compact form: its opcode, then its fields.
+            pub(crate) fn write_compact(&self, writer: &mut crate::vm::compact::CompactWriter<'vm>) {
+                use crate::vm::compact::CompactField;
+
+                match self {
+                    #(#write_cases)*
+                }
+            }
+
+            /// Reads the fields of the instruction with this opcode, which `input` is just past.
+            #[inline(never)]
+            pub(crate) unsafe fn read_compact(
+                opcode: u16,
+                input: &mut crate::vm::compact::CompactReader<'_, 'vm>,
+            ) -> Self {
+                use crate::vm::compact::CompactField;
+
+                match opcode {
+                    #(#read_cases)*
+                    _ => panic!("bad opcode {} in compact code", opcode),
+                }
+            }
+        }
+
+        /// The opcode of each instruction in the compact form.
+        #[allow(non_upper_case_globals)]
+        pub(crate) mod opcode {
+            #(#opcodes)*
+
+            pub const COUNT: u16 = #opcode_count;
+
+            pub const NAMES: [&str; COUNT as usize] = [#(#names),*];
+        }
+
+        /// The fields of each instruction, as they are read back from the compact form.
+        #[allow(non_camel_case_types)]
+        pub(crate) mod compact_fields {
+            use super::*;
+            use crate::vm::compact::{CompactField, CompactReader};
+
+            #(#field_structs)*
+        }
+    };
+
+    TokenStream::from(res)
+}
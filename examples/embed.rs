@@ -0,0 +1,74 @@
+//! Runs a small script through the embedding API.
+//!
+//! Run from the repository root, so the cached core, alloc and std are found.
+
+use skitter::{Engine, FunctionAbi, NativeArgs, Options, VMThread};
+
+const SCRIPT: &str = r#"
+pub fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+pub mod shapes {
+    pub fn area(width: f64, height: f64) -> f64 {
+        width * height
+    }
+}
+
+pub fn greet() {
+    println!("hello from the script");
+}
+
+extern "C" {
+    fn host_scale(x: i32) -> i32;
+    static HOST_OFFSET: i32;
+}
+
+pub fn scale_and_offset(x: i32) -> i32 {
+    unsafe { host_scale(x) + HOST_OFFSET }
+}
+
+pub fn fail() {
+    panic!("failed in the script");
+}
+"#;
+
+static HOST_OFFSET: i32 = 100;
+
+/// Called by the script's `host_scale`. The frame holds the return value, then the arguments.
+unsafe extern "C-unwind" fn host_scale(stack: *mut u8, _thread: &VMThread) {
+    let mut args = NativeArgs::new::<i32>(stack);
+    let x: i32 = args.next();
+    args.ret(x * 10);
+}
+
+fn main() {
+    let mut engine = Engine::new(Options::default());
+    // host functions and statics must be registered before the script first uses them
+    engine.register_extern(FunctionAbi::C, "host_scale", host_scale);
+    engine.register_extern_static(
+        FunctionAbi::C,
+        "HOST_OFFSET",
+        &HOST_OFFSET as *const i32 as *const u8,
+    );
+    engine.load_source(SCRIPT).unwrap();
+
+    let sum: i32 = engine.call("add", (2, 3)).unwrap();
+    println!("add(2, 3) = {}", sum);
+
+    let area: f64 = engine.call("shapes::area", (1.5, 4.0)).unwrap();
+    println!("area(1.5, 4.0) = {}", area);
+
+    engine.call::<_, ()>("greet", ()).unwrap();
+
+    let scaled: i32 = engine.call("scale_and_offset", (4,)).unwrap();
+    println!("scale_and_offset(4) = {}", scaled);
+
+    // argument types are checked against the signature
+    let err = engine.call::<_, i32>("add", (2u8, 3u8)).unwrap_err();
+    println!("{}", err);
+
+    // a panic in the script is returned as an error, after the usual message
+    let err = engine.call::<_, ()>("fail", ()).unwrap_err();
+    println!("{}", err);
+}
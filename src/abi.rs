@@ -20,8 +20,10 @@ impl PointerSize {
 
 pub const CALL_ALIGN: u32 = 16;
 
+// the interpreter runs the compact form of bytecode, `Instr`s are only indexed by tools and the
+// loops used with `--no-compact` or a debugger. `I128_Const` is the largest.
 const _: () = {
-    if std::mem::size_of::<crate::vm::instr::Instr>() != 16 {
+    if std::mem::size_of::<crate::vm::instr::Instr>() != 24 {
         panic!("bad Instr size");
     }
 };
@@ -1,5 +1,5 @@
 use paste::paste;
-use std::sync::Arc;
+use std::sync::{atomic::Ordering, Arc};
 
 use skitter_macro::Persist;
 
@@ -12,10 +12,7 @@ use crate::{
     ir::{glue_builder::glue_for_fn_trait, BinaryOp},
     items::{AssocValue, CrateId, IRFlag, Item, ItemPath},
     types::{IntSign, Sub, SubList, Type, TypeKind},
-    vm::{
-        instr::{Instr, Slot},
-        VM,
-    },
+    vm::{instr::Instr, VM},
 };
 
 #[derive(Copy, Clone, Debug, Persist)]
@@ -114,8 +111,9 @@ impl BuiltinTrait {
                         assert!(!is_dyn_star);
                         panic!("pointee dynamic");
                     }
+                    _ if ty.is_sized() => vm.common_types().void,
                     _ => {
-                        panic!("pointee none");
+                        panic!("pointee {}", ty);
                     }
                 };
 
@@ -210,7 +208,7 @@ impl BuiltinTrait {
                             _ => panic!(),
                         }
                     }
-                    TypeKind::FunctionPointer(sig) => {
+                    TypeKind::FunctionPointer(sig, _) => {
                         // todo how to handle generics?
                         // make sure the sig is concrete. will see if this poses issues down the line
                         {
@@ -305,7 +303,7 @@ impl BuiltinTrait {
 
                         match self {
                             BuiltinTrait::FnOnce => {
-                                let output = ir.sig.output;
+                                let output = ir.sig.output.sub(closure_subs);
 
                                 return Some(trait_impl(
                                     for_tys.clone(),
@@ -460,6 +458,16 @@ pub fn compile_rust_intrinsic<'vm>(
                 compiler.out_bc.push(copy);
             }
         }
+        "black_box" => {
+            assert!(subs.list.len() == 1);
+            assert!(args.len() == 1);
+
+            let arg_ty = subs.list[0].assert_ty();
+
+            if let Some(copy) = bytecode_select::copy(out.slot, args[0].slot, arg_ty) {
+                compiler.out_bc.push(copy);
+            }
+        }
         "bswap" => {
             assert!(subs.list.len() == 1);
             assert!(args.len() == 1);
@@ -476,20 +484,30 @@ pub fn compile_rust_intrinsic<'vm>(
                 ));
             }
         }
-        "offset" => {
-            assert!(subs.list.len() == 2);
+        "offset" | "arith_offset" => {
             assert!(args.len() == 2);
 
-            let ptr_ty = subs.list[0].assert_ty();
-            let offset_ty = subs.list[1].assert_ty();
+            let (elem_size, offset_ty) = if name == "offset" {
+                assert!(subs.list.len() == 2);
 
-            // todo wide pointers?
-            assert!(ptr_ty.layout().assert_size() == POINTER_SIZE.bytes());
+                let ptr_ty = subs.list[0].assert_ty();
+                let offset_ty = subs.list[1].assert_ty();
+
+                // todo wide pointers?
+                assert!(ptr_ty.layout().assert_size() == POINTER_SIZE.bytes());
 
-            let elem_size = if let TypeKind::Ptr(child, _) = ptr_ty.kind() {
-                child.layout().assert_size()
+                let elem_size = if let TypeKind::Ptr(child, _) = ptr_ty.kind() {
+                    child.layout().assert_size()
+                } else {
+                    panic!("offset intrinsic used on non-ptr");
+                };
+                (elem_size, offset_ty)
             } else {
-                panic!("offset intrinsic used on non-ptr");
+                // wrapping offset, which is the same for us
+                assert!(subs.list.len() == 1);
+
+                let elem_size = subs.list[0].assert_ty().layout().assert_size();
+                (elem_size, compiler.vm.common_types().isize)
             };
 
             if elem_size == 1 {
@@ -519,6 +537,19 @@ pub fn compile_rust_intrinsic<'vm>(
                     .push(offset_ctor(out.slot, out.slot, args[0].slot));
             }
         }
+        "ptr_guaranteed_cmp" => {
+            assert!(args.len() == 2);
+
+            // returns 1 if equal and 0 if not, at runtime the result is always known
+            let ptr_ty = args[0].ty();
+            assert!(ptr_ty.layout().assert_size() == POINTER_SIZE.bytes());
+
+            let usize_ty = compiler.vm.common_types().usize;
+            let (eq_ctor, _) = bytecode_select::binary(BinaryOp::Eq, usize_ty);
+            compiler
+                .out_bc
+                .push(eq_ctor(out.slot, args[0].slot, args[1].slot));
+        }
         "ptr_offset_from_unsigned" => {
             assert!(subs.list.len() == 1);
             assert!(args.len() == 2);
@@ -579,6 +610,30 @@ pub fn compile_rust_intrinsic<'vm>(
                 out.slot,
             ));
         }
+        "type_id" => {
+            assert!(subs.list.len() == 1);
+            assert!(args.is_empty());
+
+            let arg_ty = subs.list[0].assert_ty();
+
+            compiler.out_bc.push(bytecode_select::literal(
+                arg_ty.unique_id() as _,
+                16,
+                out.slot,
+            ));
+        }
+        "needs_drop" => {
+            assert!(subs.list.len() == 1);
+            assert!(args.is_empty());
+
+            let arg_ty = subs.list[0].assert_ty();
+
+            let res = arg_ty.drop_info().is_drop();
+
+            compiler
+                .out_bc
+                .push(bytecode_select::literal(res as _, 1, out.slot));
+        }
         "min_align_of_val" => {
             assert!(subs.list.len() == 1);
             assert!(args.len() == 1);
@@ -586,8 +641,10 @@ pub fn compile_rust_intrinsic<'vm>(
             let arg_ty = subs.list[0].assert_ty();
 
             if let TypeKind::Dynamic { .. } = arg_ty.kind() {
-                // it may be best to just force alignment to the max for these cases and use that compile-time value
-                panic!("todo, trait objects may have alignment only decidable at run-time");
+                let vtable_slot = args[0].slot.offset_by(POINTER_SIZE.bytes() as i32);
+                compiler
+                    .out_bc
+                    .push(Instr::VTableAlign(out.slot, vtable_slot));
             } else {
                 // everything else should be decidable at compile-time
                 let res = arg_ty.layout().align;
@@ -623,7 +680,17 @@ pub fn compile_rust_intrinsic<'vm>(
                         .unwrap()
                 };
 
-                match arg_ty.kind() {
+                // structs with an unsized tail: walk down to the tail, remembering its offset
+                let mut tail_ty = arg_ty;
+                let mut tail_offset = 0;
+                while let TypeKind::Adt(item) = tail_ty.kind() {
+                    let fields = item.item.adt_info().variant_fields.assert_single();
+                    let last = fields.len() - 1;
+                    tail_offset += tail_ty.layout().field_offsets.assert_single()[last];
+                    tail_ty = fields[last].sub(&item.subs);
+                }
+
+                match tail_ty.kind() {
                     TypeKind::Slice(child_ty) => {
                         let (mul_ctor, _) =
                             bytecode_select::binary(crate::ir::BinaryOp::Mul, ty_usize);
@@ -646,8 +713,38 @@ pub fn compile_rust_intrinsic<'vm>(
                         }
                     }
                     TypeKind::StringSlice => compiler.out_bc.push(byte_slice_size()),
+                    TypeKind::Dynamic { .. } => {
+                        // the tail's alignment is not known here, so it must be the whole value
+                        assert!(tail_offset == 0, "todo trait object in unsized tail");
+                        compiler.out_bc.push(Instr::VTableSize(
+                            out.slot,
+                            arg_slot.offset_by(ptr_size as i32),
+                        ));
+                    }
                     _ => panic!("todo unsized value size"),
                 }
+
+                if tail_offset != 0 {
+                    // size = (tail_offset + tail_size + align - 1) & !(align - 1)
+                    let align = arg_ty.layout().align;
+                    let (add_ctor, _) = bytecode_select::binary(crate::ir::BinaryOp::Add, ty_usize);
+                    let (and_ctor, _) =
+                        bytecode_select::binary(crate::ir::BinaryOp::BitAnd, ty_usize);
+
+                    let tmp_slot = compiler.stack.alloc_no_drop(ty_usize);
+                    compiler.out_bc.push(bytecode_select::literal(
+                        (tail_offset + align - 1) as _,
+                        ptr_size,
+                        tmp_slot,
+                    ));
+                    compiler.out_bc.push(add_ctor(out.slot, out.slot, tmp_slot));
+                    compiler.out_bc.push(bytecode_select::literal(
+                        !(align as i128 - 1),
+                        ptr_size,
+                        tmp_slot,
+                    ));
+                    compiler.out_bc.push(and_ctor(out.slot, out.slot, tmp_slot));
+                }
             };
         }
         "variant_count" => {
@@ -838,32 +935,9 @@ pub fn compile_rust_intrinsic<'vm>(
                 _ => panic!("can't ctpop {}", arg_ty),
             }
         }
-        "cttz_nonzero" => {
-            assert!(subs.list.len() == 1);
-            assert!(args.len() == 1);
-
-            let arg_ty = subs.list[0].assert_ty();
-            let arg_slot = args[0].slot;
-
-            match arg_ty.layout().assert_size() {
-                1 => compiler
-                    .out_bc
-                    .push(Instr::I8_TrailingZeros(out.slot, arg_slot)),
-                2 => compiler
-                    .out_bc
-                    .push(Instr::I16_TrailingZeros(out.slot, arg_slot)),
-                4 => compiler
-                    .out_bc
-                    .push(Instr::I32_TrailingZeros(out.slot, arg_slot)),
-                8 => compiler
-                    .out_bc
-                    .push(Instr::I64_TrailingZeros(out.slot, arg_slot)),
-                16 => compiler
-                    .out_bc
-                    .push(Instr::I128_TrailingZeros(out.slot, arg_slot)),
-                _ => panic!("can't cttz {}", arg_ty),
-            }
-        }
+        // zero inputs are handled by the host's leading_zeros / trailing_zeros
+        "ctlz" | "ctlz_nonzero" => select_unary_int!(LeadingZeros),
+        "cttz" | "cttz_nonzero" => select_unary_int!(TrailingZeros),
         "bitreverse" => select_unary_int!(ReverseBits),
 
         "rotate_left" => select_binary_int!(RotateLeft),
@@ -876,22 +950,38 @@ pub fn compile_rust_intrinsic<'vm>(
         "mul_with_overflow" => select_binary_signed!(OverflowingMul),
 
         "saturating_add" => select_binary_signed!(SatAdd),
+        "saturating_sub" => select_binary_signed!(SatSub),
 
         "abort" => {
-            compiler
-                .out_bc
-                .push(Instr::Error(Box::new("abort".to_owned())));
+            compiler.out_bc.push(Instr::Abort);
         }
         "unreachable" => {
             compiler
                 .out_bc
                 .push(Instr::Error(Box::new("unreachable".to_owned())));
-        } /*
+        }
         "caller_location" => {
-        out_bc.push(Instr::Error(Box::new(
-        "caller_location not implemented".to_owned(),
-        )));
-        }*/
+            assert!(args.is_empty());
+
+            let TypeKind::Ref(loc_ty, _) = out.ty().kind() else {
+                panic!("caller_location: bad return type");
+            };
+
+            compiler.vm.set_location_ty(*loc_ty);
+
+            if compiler.tracks_caller() {
+                // the location is the caller's, and only known at runtime
+                compiler.out_bc.push(Instr::CallerLocation(out.slot));
+            } else {
+                let (file, span) = compiler.current_location();
+                let loc_ptr = compiler.vm.alloc_location(file, span);
+                compiler.out_bc.push(bytecode_select::literal(
+                    loc_ptr as i128,
+                    POINTER_SIZE.bytes(),
+                    out.slot,
+                ));
+            }
+        }
         "assume" | "assert_zero_valid" | "assert_inhabited" => {
             // do nothing yeehaw
         }
@@ -1000,6 +1090,36 @@ pub fn compile_rust_intrinsic<'vm>(
                 .out_bc
                 .push(Instr::F64_Max(out.slot, args[0].slot, args[1].slot));
         }
+        "try" => {
+            assert!(args.len() == 3);
+
+            // copy try_fn, data, and catch_fn into a frame, the vm calls them from there
+            let ptr_ty = compiler.vm.common_types().usize;
+
+            compiler.stack.align_for_call();
+            let frame = compiler.stack.alloc_no_drop(ptr_ty);
+            for (i, arg) in args.iter().enumerate() {
+                let arg_slot = if i == 0 {
+                    frame
+                } else {
+                    compiler.stack.alloc_no_drop(ptr_ty)
+                };
+                compiler
+                    .out_bc
+                    .push(bytecode_select::copy(arg_slot, arg.slot, ptr_ty).unwrap());
+            }
+
+            // the functions are called after it, with the data pointer and panic payload
+            let call_frame = compiler.stack.align_for_call();
+            compiler.stack.alloc_no_drop(ptr_ty);
+            compiler.stack.alloc_no_drop(ptr_ty);
+
+            compiler.out_bc.push(Instr::CatchUnwind {
+                out: out.slot,
+                frame,
+                call_frame,
+            });
+        }
         // we redirect `std::ptr::drop_in_place` to this intrinsic
         "drop_in_place" => {
             assert!(subs.list.len() == 1);
@@ -1021,6 +1141,9 @@ pub fn compile_rust_intrinsic<'vm>(
                     .push(Instr::Call(call_slot, glue.function()));
             }
         }
+        _ if name.starts_with("atomic_") => {
+            compile_atomic_intrinsic(name, subs, args, out, compiler)
+        }
         // special skitter-specific intrinsics
         "skitter_box_new" => {
             assert!(subs.list.len() == 1);
@@ -1052,3 +1175,133 @@ pub fn compile_rust_intrinsic<'vm>(
         }
     }
 }
+
+fn atomic_ordering(name: &str) -> Ordering {
+    match name {
+        "seqcst" => Ordering::SeqCst,
+        "acquire" => Ordering::Acquire,
+        "release" => Ordering::Release,
+        "acqrel" => Ordering::AcqRel,
+        // unordered is weaker than relaxed, but nothing is gained by treating it differently
+        "relaxed" | "unordered" => Ordering::Relaxed,
+        _ => panic!("bad atomic ordering: {}", name),
+    }
+}
+
+/// Atomics are lowered to instructions which use the host's atomics, with the requested orderings.
+/// Pointers are operated on as integers of the same size.
+fn compile_atomic_intrinsic<'vm>(
+    name: &str,
+    subs: &SubList<'vm>,
+    args: Vec<Local<'vm>>,
+    out: Local<'vm>,
+    compiler: &mut BytecodeCompiler<'vm, '_>,
+) {
+    // atomic_{op}_{ordering}, or atomic_{op}_{success}_{failure} for compare-exchange
+    let mut parts = name.split('_');
+    parts.next();
+    let op = parts.next().expect("bad atomic intrinsic");
+    let ordering = atomic_ordering(parts.next().expect("bad atomic intrinsic"));
+
+    match op {
+        "fence" => {
+            compiler.out_bc.push(Instr::AtomicFence(ordering));
+            return;
+        }
+        "singlethreadfence" => {
+            compiler.out_bc.push(Instr::CompilerFence(ordering));
+            return;
+        }
+        _ => (),
+    }
+
+    assert!(subs.list.len() == 1);
+
+    let arg_ty = subs.list[0].assert_ty();
+    let size = arg_ty.layout().assert_size();
+    if !matches!(arg_ty.kind(), TypeKind::Int(..)) {
+        assert!(size == POINTER_SIZE.bytes());
+    }
+
+    let ptr_slot = args[0].slot;
+
+    macro_rules! select_atomic {
+        ($instr_name:ident, $($arg:expr),*) => {
+            paste! {
+                match size {
+                    1 => Instr:: [< I8_ $instr_name >] ($($arg),*),
+                    2 => Instr:: [< I16_ $instr_name >] ($($arg),*),
+                    4 => Instr:: [< I32_ $instr_name >] ($($arg),*),
+                    8 => Instr:: [< I64_ $instr_name >] ($($arg),*),
+                    _ => panic!("can't {} {}", name, arg_ty),
+                }
+            }
+        };
+    }
+
+    let instr = match op {
+        "load" => {
+            assert!(args.len() == 1);
+            select_atomic!(AtomicLoad, ordering, out.slot, ptr_slot)
+        }
+        "store" => {
+            assert!(args.len() == 2);
+            select_atomic!(AtomicStore, ordering, ptr_slot, args[1].slot)
+        }
+        "cxchg" | "cxchgweak" => {
+            assert!(args.len() == 3);
+            let failure = atomic_ordering(parts.next().expect("bad atomic intrinsic"));
+
+            // returns (old value, success), the instruction expects this exact layout
+            let offsets = out.ty().layout().field_offsets.assert_single();
+            assert!(offsets[0] == 0 && offsets[1] == size);
+
+            // the expected value is passed in the output slot
+            compiler
+                .out_bc
+                .push(bytecode_select::copy(out.slot, args[1].slot, arg_ty).unwrap());
+
+            if op == "cxchg" {
+                select_atomic!(
+                    AtomicCxchg,
+                    ordering,
+                    failure,
+                    out.slot,
+                    ptr_slot,
+                    args[2].slot
+                )
+            } else {
+                select_atomic!(
+                    AtomicCxchgWeak,
+                    ordering,
+                    failure,
+                    out.slot,
+                    ptr_slot,
+                    args[2].slot
+                )
+            }
+        }
+        _ => {
+            assert!(args.len() == 2);
+            let val_slot = args[1].slot;
+
+            match op {
+                "xchg" => select_atomic!(AtomicXchg, ordering, out.slot, ptr_slot, val_slot),
+                "xadd" => select_atomic!(AtomicAdd, ordering, out.slot, ptr_slot, val_slot),
+                "xsub" => select_atomic!(AtomicSub, ordering, out.slot, ptr_slot, val_slot),
+                "and" => select_atomic!(AtomicAnd, ordering, out.slot, ptr_slot, val_slot),
+                "or" => select_atomic!(AtomicOr, ordering, out.slot, ptr_slot, val_slot),
+                "xor" => select_atomic!(AtomicXor, ordering, out.slot, ptr_slot, val_slot),
+                "nand" => select_atomic!(AtomicNand, ordering, out.slot, ptr_slot, val_slot),
+                // the signedness of min / max is part of the name
+                "max" => select_atomic!(S_AtomicMax, ordering, out.slot, ptr_slot, val_slot),
+                "min" => select_atomic!(S_AtomicMin, ordering, out.slot, ptr_slot, val_slot),
+                "umax" => select_atomic!(U_AtomicMax, ordering, out.slot, ptr_slot, val_slot),
+                "umin" => select_atomic!(U_AtomicMin, ordering, out.slot, ptr_slot, val_slot),
+                _ => panic!("attempt compile intrinsic: {}{}", name, subs),
+            }
+        }
+    };
+
+    compiler.out_bc.push(instr);
+}
@@ -1,19 +1,23 @@
 use crate::abi::{CALL_ALIGN, POINTER_SIZE};
 use crate::builtins::compile_rust_intrinsic;
-use crate::bytecode_select;
+use crate::bytecode_optimizer;
+use crate::bytecode_select::{self, Compare, CompareArg};
 use crate::closure::FnTrait;
 use crate::ir::const_util::ConstStatus;
 use crate::ir::{
     BinaryOp, BindingMode, Block, ExprId, ExprKind, IRFunction, LogicOp, LoopId, MatchGuard,
-    Pattern, PatternId, PatternKind, PointerCast, Stmt,
+    Pattern, PatternId, PatternKind, PointerCast, Span, Stmt,
 };
-use crate::items::FunctionAbi;
+use crate::items::{FunctionAbi, FunctionSig};
 use crate::types::{
     DropBit, DropField, DropGlue, DropInfo, ItemWithSubs, Mutability, SubList, Type, TypeKind,
 };
 use crate::variants::VariantIndex;
 use crate::vm::instr::Instr;
-use crate::vm::{self, instr::Slot};
+use crate::vm::{self, instr::Slot, CompactCode, ThreadedCode};
+
+use std::cell::Cell;
+use std::panic::AssertUnwindSafe;
 
 pub struct BytecodeCompiler<'vm, 'f> {
     in_func_subs: &'f SubList<'vm>,
@@ -25,6 +29,11 @@ pub struct BytecodeCompiler<'vm, 'f> {
     loops: Vec<LoopInfo<'vm>>,
     loop_breaks: Vec<BreakInfo>,
     closure: Option<ClosureInfo<'vm>>,
+    /// Panic on arithmetic overflow, like rustc with `-C overflow-checks`.
+    overflow_checks: bool,
+    spans: SpanTable<'vm>,
+    current_span: Span,
+    debug_locals: Vec<DebugLocal<'vm>>,
 
     local_void: Local<'vm>,
     local_never: Local<'vm>,
@@ -42,14 +51,128 @@ struct LoopInfo<'vm> {
     start_index: usize,
 }
 
+/// The right hand side of a binary operation.
+#[derive(Clone, Copy)]
+enum Operand {
+    Slot(Slot),
+    Imm(i32),
+}
+
+/// The condition of a branch.
+#[derive(Clone, Copy)]
+enum Cond {
+    Slot(Slot),
+    Compare(u32, Compare, CompareArg, CompareArg),
+}
+
+impl Cond {
+    /// A jump by `offset` which is taken when the condition is false.
+    fn jump_f<'vm>(self, offset: i32) -> Instr<'vm> {
+        match self {
+            Cond::Slot(slot) => Instr::JumpF(offset, slot),
+            Cond::Compare(size, compare, lhs, rhs) => {
+                bytecode_select::compare_jump(size, compare, lhs, rhs, offset).unwrap()
+            }
+        }
+    }
+}
+
 struct BreakInfo {
     loop_id: LoopId,
     break_index: usize,
 }
 
 pub struct FunctionBytecode<'vm> {
-    pub code: Vec<Instr<'vm>>,
+    /// The code in the compact form run by the interpreter loop. `code.instrs()` decodes it for
+    /// tools like the debugger and disassembler.
+    pub code: CompactCode<'vm>,
     pub drops: Vec<(Slot, DropGlue<'vm>)>,
+    /// Bytes of stack used by the function, including the frames it writes arguments into.
+    pub frame_size: u32,
+    pub spans: SpanTable<'vm>,
+    /// Calls to `caller_location` see through this function to the location it was called from.
+    pub track_caller: bool,
+    /// Named variables, in the order they were bound.
+    pub locals: Vec<DebugLocal<'vm>>,
+    /// Handlers for the call-threaded loop, with the `threaded-dispatch` feature.
+    pub threaded: ThreadedCode<'vm>,
+}
+
+/// A variable in the source, for debuggers. It is only valid while the pc is within `start..end`.
+#[derive(Debug, Clone, Copy)]
+pub struct DebugLocal<'vm> {
+    pub name: &'vm str,
+    pub slot: Slot,
+    pub ty: Type<'vm>,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Maps instructions back to the source they were compiled from. Each entry covers the code from
+/// its pc up to the next entry, so runs of instructions from the same expression share one entry.
+#[derive(Default)]
+pub struct SpanTable<'vm> {
+    pub file: &'vm str,
+    entries: Vec<(u32, Span)>,
+}
+
+impl<'vm> SpanTable<'vm> {
+    pub fn new(file: &'vm str) -> Self {
+        Self {
+            file,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Sets the span of the code starting at `pc`.
+    pub fn set(&mut self, pc: usize, span: Span) {
+        let pc = pc as u32;
+        if let Some(last) = self.entries.last_mut() {
+            if last.0 == pc {
+                // nothing was emitted for the last span
+                last.1 = span;
+                let len = self.entries.len();
+                if len > 1 && self.entries[len - 2].1 == span {
+                    self.entries.pop();
+                }
+                return;
+            }
+            if last.1 == span {
+                return;
+            }
+        }
+        self.entries.push((pc, span));
+    }
+
+    /// Moves entries to new pcs after instructions are removed. `new_pc` maps each old pc, and
+    /// the end of the code, to its new one.
+    pub fn remap(&mut self, new_pc: &[u32]) {
+        let mut entries: Vec<(u32, Span)> = Vec::with_capacity(self.entries.len());
+        for (pc, span) in self.entries.drain(..) {
+            let pc = new_pc[pc as usize];
+            // entries whose code was all removed are replaced by the next one
+            if let Some(last) = entries.last_mut() {
+                if last.0 == pc {
+                    *last = (pc, span);
+                    continue;
+                }
+            }
+            entries.push((pc, span));
+        }
+        entries.dedup_by(|next, prev| next.1 == prev.1);
+        self.entries = entries;
+    }
+
+    pub fn span_at(&self, pc: usize) -> Span {
+        let index = self
+            .entries
+            .partition_point(|(start, _)| *start as usize <= pc);
+        if index == 0 {
+            Span::NONE
+        } else {
+            self.entries[index - 1].1
+        }
+    }
 }
 
 impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
@@ -59,8 +182,9 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
         subs: &'f SubList<'vm>,
         path: &str,
         original_subs: &'f SubList<'vm>,
+        overflow_checks: bool,
     ) -> FunctionBytecode<'vm> {
-        if vm.cli_args.verbose {
+        if vm.options.verbose {
             println!("compiling {}{}", path, original_subs);
             //ir.print();
         }
@@ -75,6 +199,10 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
             loops: Vec::new(),
             loop_breaks: Vec::new(),
             closure: None,
+            overflow_checks,
+            spans: SpanTable::new(ir.source_file),
+            current_span: Span::NONE,
+            debug_locals: Vec::new(),
 
             local_void: Local::new_dummy(vm.common_types().void),
             local_never: Local::new_dummy(vm.common_types().never),
@@ -123,16 +251,43 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
 
         compiler.out_bc.push(Instr::Return);
 
-        if vm.cli_args.verbose {
+        if vm.options.verbose {
             for (i, bc) in compiler.out_bc.iter().enumerate() {
                 println!("  {} {:?}", i, bc);
             }
             println!("<-");
         }
 
+        // parameters and anything else still in scope live until the function returns
+        let end = compiler.out_bc.len() as u32;
+        for local in compiler.debug_locals.iter_mut() {
+            local.end = local.end.min(end);
+        }
+
+        let frame_size = compiler.stack.frame_size();
+
+        // optimized code doesn't keep variables where the debugger expects them
+        if vm.options.bc_opt && !vm.options.debug && !vm.options.dap {
+            let result_size = out_ty.layout().assert_size();
+            bytecode_optimizer::optimize(
+                &mut compiler.out_bc,
+                &mut compiler.spans,
+                &mut compiler.debug_locals,
+                &compiler.stack.drop_leafs,
+                frame_size,
+                result_size,
+                &compiler.stack.allocations,
+            );
+        }
+
         FunctionBytecode {
-            code: compiler.out_bc,
+            code: CompactCode::new(compiler.out_bc),
+            frame_size,
             drops: compiler.stack.drop_leafs,
+            spans: compiler.spans,
+            track_caller: ir.track_caller,
+            locals: compiler.debug_locals,
+            threaded: Default::default(),
         }
     }
 
@@ -142,7 +297,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
         subs: &'f SubList<'vm>,
         root_expr: ExprId,
     ) -> (usize, Option<usize>) {
-        if vm.cli_args.verbose {
+        if vm.options.verbose {
             println!("compiling promoted const");
         }
 
@@ -156,6 +311,10 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
             loops: Vec::new(),
             loop_breaks: Vec::new(),
             closure: None,
+            overflow_checks: false,
+            spans: SpanTable::new(ir.source_file),
+            current_span: Span::NONE,
+            debug_locals: Vec::new(),
 
             local_void: Local::new_dummy(vm.common_types().void),
             local_never: Local::new_dummy(vm.common_types().never),
@@ -165,7 +324,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
         let place = compiler.expr_to_place(root_expr);
         compiler.out_bc.push(Instr::Return);
 
-        if vm.cli_args.verbose {
+        if vm.options.verbose {
             for (i, bc) in compiler.out_bc.iter().enumerate() {
                 println!("  {} {:?}", i, bc);
             }
@@ -175,8 +334,13 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
         let out_ty = compiler.expr_ty(root_expr);
 
         let bc = FunctionBytecode {
-            code: compiler.out_bc,
+            code: CompactCode::new(compiler.out_bc),
+            frame_size: compiler.stack.frame_size(),
             drops: compiler.stack.drop_leafs,
+            spans: compiler.spans,
+            track_caller: false,
+            locals: Vec::new(),
+            threaded: Default::default(),
         };
 
         let mut const_thread = vm.make_thread();
@@ -205,7 +369,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
     }
 
     fn debug<S: Into<String>>(&mut self, f: impl Fn() -> S) {
-        if self.vm.cli_args.verbose {
+        if self.vm.options.verbose {
             let bc = Instr::Debug(Box::new(f().into()));
             self.out_bc.push(bc);
         }
@@ -233,7 +397,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
             self.lower_expr(expr, Some(dest));
         }
 
-        self.stack.pop_scope(scope, &mut self.out_bc);
+        self.pop_scope(scope);
 
         dest
     }
@@ -246,22 +410,93 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                 else_block,
                 ..
             } => {
-                assert!(else_block.is_none());
+                if let Some(else_block) = else_block {
+                    let bool_ty = self.vm.common_types().bool;
+                    let match_result = self.stack.alloc(bool_ty);
 
-                self.match_pattern_expr(*pattern, *init, None);
+                    self.match_pattern_expr(*pattern, *init, Some(match_result.slot));
+                    let check_end_index = self.skip_instr();
+
+                    // the else block always diverges
+                    self.lower_block(else_block, None);
+
+                    self.out_bc[check_end_index] =
+                        Instr::JumpT(-self.get_jump_offset(check_end_index), match_result.slot);
+                } else {
+                    self.match_pattern_expr(*pattern, *init, None);
+                }
             }
             Stmt::Expr(expr) => {
                 let stmt_scope = self.stack.push_scope("stmt");
                 self.lower_expr(*expr, None);
-                self.stack.pop_scope(stmt_scope, &mut self.out_bc);
+                self.pop_scope(stmt_scope);
+            }
+        }
+    }
+
+    fn pop_scope(&mut self, scope: StackScope) {
+        self.stack.pop_scope(scope, &mut self.out_bc);
+
+        // variables stored above the new top of the stack have gone out of scope
+        let pc = self.out_bc.len() as u32;
+        for local in self.debug_locals.iter_mut() {
+            if local.end == u32::MAX && local.slot.index() >= self.stack.top as usize {
+                local.end = pc;
             }
         }
     }
 
     fn lower_expr(&mut self, id: ExprId, dest: Option<Local<'vm>>) -> Local<'vm> {
+        let parent_span = self.enter_span(self.in_func.expr(id).span);
+        let res = self.lower_expr_internal(id, dest);
+        self.set_span(parent_span);
+        res
+    }
+
+    /// Attributes the code emitted from here on to a span. Returns the previous span, to be
+    /// restored when leaving the expression. Unknown spans are attributed to the parent instead.
+    fn enter_span(&mut self, span: Span) -> Span {
+        let parent_span = self.current_span;
+        if !span.is_none() {
+            self.set_span(span);
+        }
+        parent_span
+    }
+
+    fn set_span(&mut self, span: Span) {
+        self.current_span = span;
+        self.spans.set(self.out_bc.len(), span);
+    }
+
+    /// The source position of the code currently being emitted.
+    pub fn current_location(&self) -> (&'vm str, Span) {
+        (self.spans.file, self.current_span)
+    }
+
+    pub fn tracks_caller(&self) -> bool {
+        self.in_func.track_caller
+    }
+
+    fn lower_expr_internal(&mut self, id: ExprId, dest: Option<Local<'vm>>) -> Local<'vm> {
         let expr = self.in_func.expr(id);
         let expr_ty = self.expr_ty(id);
 
+        if let ExprKind::DeRef(boxed) = expr.kind {
+            if self.expr_is_box_deref(id) && expr_ty.drop_info().is_drop() {
+                // moving out of a box, the box must not drop its contents afterwards
+                let boxed = self.lower_expr(boxed, None);
+                let dest = dest.unwrap_or_else(|| self.stack.alloc(expr_ty));
+
+                self.out_bc.push(
+                    bytecode_select::copy_from_ptr(dest.slot, boxed.slot, expr_ty, 0).unwrap(),
+                );
+                self.local_drop_init(dest);
+                self.box_free_moved(boxed);
+
+                return dest;
+            }
+        }
+
         if self.expr_is_place(id) {
             match self.expr_to_place(id) {
                 Place::Local(local_source) => {
@@ -291,6 +526,16 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
             match &expr.kind {
                 ExprKind::Dummy(value) => self.lower_expr(*value, dest),
                 ExprKind::Block(block) => self.lower_block(block, dest),
+                ExprKind::DropTemps(value) => {
+                    // allocate result before entering scope, so only the temporaries are dropped
+                    let dest = dest.unwrap_or_else(|| self.stack.alloc(expr_ty));
+
+                    let scope = self.stack.push_scope("temps");
+                    self.lower_expr(*value, Some(dest));
+                    self.pop_scope(scope);
+
+                    dest
+                }
                 ExprKind::LiteralValue(n) => {
                     let dest = dest.unwrap_or_else(|| self.stack.alloc(expr_ty));
 
@@ -327,7 +572,13 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
 
                     let arg_ty = self.expr_ty(*arg);
 
-                    let ctor = bytecode_select::unary(*op, arg_ty);
+                    let checked_ctor = if self.overflow_checks {
+                        bytecode_select::unary_checked(*op, arg_ty)
+                    } else {
+                        None
+                    };
+
+                    let ctor = checked_ctor.unwrap_or_else(|| bytecode_select::unary(*op, arg_ty));
                     self.out_bc.push(ctor(dest.slot, arg_local.slot));
                     dest
                 }
@@ -356,19 +607,13 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                 ExprKind::Binary(op, lhs, rhs) => {
                     let dest = dest.unwrap_or_else(|| self.stack.alloc(expr_ty));
 
-                    let lhs_local = self.lower_expr(*lhs, None);
-                    let rhs_local = self.lower_expr(*rhs, None);
-
                     let lhs_ty = self.expr_ty(*lhs);
+                    let rhs_ty = self.expr_ty(*rhs);
 
-                    let (ctor, swap) = bytecode_select::binary(*op, lhs_ty);
-                    if swap {
-                        self.out_bc
-                            .push(ctor(dest.slot, rhs_local.slot, lhs_local.slot));
-                    } else {
-                        self.out_bc
-                            .push(ctor(dest.slot, lhs_local.slot, rhs_local.slot));
-                    }
+                    let lhs_local = self.lower_expr(*lhs, None);
+                    let rhs = self.lower_operand(*op, lhs_ty, *rhs);
+
+                    self.binary_op(*op, lhs_ty, rhs_ty, dest.slot, lhs_local.slot, rhs);
                     dest
                 }
                 ExprKind::Assign(lhs, rhs) => {
@@ -389,17 +634,21 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                     dest.unwrap_or_else(|| self.local_void)
                 }
                 ExprKind::AssignOp(op, lhs, rhs) => {
-                    let rhs_local = self.lower_expr(*rhs, None);
-
                     let lhs_ty = self.expr_ty(*lhs);
+                    let rhs_ty = self.expr_ty(*rhs);
 
-                    let (ctor, swap) = bytecode_select::binary(*op, lhs_ty);
-                    assert!(!swap);
+                    let rhs = self.lower_operand(*op, lhs_ty, *rhs);
 
                     match self.expr_to_place(*lhs) {
                         Place::Local(lhs_local) => {
-                            self.out_bc
-                                .push(ctor(lhs_local.slot, lhs_local.slot, rhs_local.slot));
+                            self.binary_op(
+                                *op,
+                                lhs_ty,
+                                rhs_ty,
+                                lhs_local.slot,
+                                lhs_local.slot,
+                                rhs,
+                            );
                         }
                         Place::Ptr(ptr_slot, offset, _) => {
                             let assign_ty = self.expr_ty(*lhs);
@@ -413,7 +662,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                                 )
                                 .unwrap(),
                             );
-                            self.out_bc.push(ctor(tmp_slot, tmp_slot, rhs_local.slot));
+                            self.binary_op(*op, lhs_ty, rhs_ty, tmp_slot, tmp_slot, rhs);
                             self.out_bc.push(
                                 bytecode_select::copy_to_ptr(ptr_slot, tmp_slot, assign_ty, offset)
                                     .unwrap(),
@@ -450,12 +699,33 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                             {
                                 // special case for virtual functions
 
+                                let arg_ty = self.expr_ty(args[0]);
+
+                                // a receiver taken by value can only be moved out of a box
+                                let moved_box = if arg_ty.is_sized() {
+                                    None
+                                } else {
+                                    let ExprKind::DeRef(boxed) = self.in_func.expr(args[0]).kind
+                                    else {
+                                        panic!("unsized receiver is not a box");
+                                    };
+                                    Some(self.lower_expr(boxed, None))
+                                };
+
                                 let (receiver_ptr, vtable_ptr) = {
-                                    let arg_ty = self.expr_ty(args[0]);
-                                    assert!(
-                                        arg_ty.layout().assert_size() == POINTER_SIZE.bytes() * 2
-                                    );
-                                    let receiver = self.lower_expr(args[0], None);
+                                    let receiver = if let Some(moved_box) = moved_box {
+                                        // the box's flags are cleared after the call
+                                        Local {
+                                            drop_id: None,
+                                            ..moved_box
+                                        }
+                                    } else {
+                                        assert!(
+                                            arg_ty.layout().assert_size()
+                                                == POINTER_SIZE.bytes() * 2
+                                        );
+                                        self.lower_expr(args[0], None)
+                                    };
                                     (
                                         receiver,
                                         receiver.slot.offset_by(POINTER_SIZE.bytes() as i32),
@@ -477,12 +747,35 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                                     func_ptr: func_ptr.slot,
                                 });
 
+                                if let Some(moved_box) = moved_box {
+                                    self.box_free_moved(moved_box);
+                                }
+
                                 ret_local
                             } else {
                                 // normal function calls
                                 let ret_local = self.build_call(expr_ty, args, None);
 
-                                let func = func_ref.item.func_mono(&func_ref.subs);
+                                let func = match func_ref.item.get_extern() {
+                                    Some((FunctionAbi::C, _))
+                                        if args.len()
+                                            > func_ref
+                                                .item
+                                                .func_sig(&func_ref.subs)
+                                                .inputs
+                                                .len() =>
+                                    {
+                                        // variadic call, use the types passed at this call site
+                                        let inputs =
+                                            args.iter().map(|arg| self.expr_ty(*arg)).collect();
+                                        let sig = FunctionSig {
+                                            inputs,
+                                            output: expr_ty,
+                                        };
+                                        self.vm.variadic_function(func_ref.item, sig)
+                                    }
+                                    _ => func_ref.item.func_mono(&func_ref.subs),
+                                };
                                 self.out_bc.push(Instr::Call(ret_local.slot, func));
 
                                 ret_local
@@ -501,15 +794,26 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                             self.local_init(dest);
                             dest
                         }
-                    } else if let TypeKind::FunctionPointer(_) = ty.kind() {
+                    } else if let TypeKind::FunctionPointer(sig, abi) = ty.kind() {
                         let ret_local = self.build_call(expr_ty, args, None);
 
-                        let func_ptr = self.lower_expr(*func, None);
+                        if *abi == FunctionAbi::C {
+                            // C function pointers are native code, call them through a thunk
+                            // which loads the pointer from the end of the frame
+                            let func_ptr = self.stack.alloc(ty);
+                            self.lower_expr(*func, Some(func_ptr));
 
-                        self.out_bc.push(Instr::CallPtr {
-                            frame: ret_local.slot,
-                            func_ptr: func_ptr.slot,
-                        });
+                            let ptr_offset = func_ptr.slot.index() - ret_local.slot.index();
+                            let caller = self.vm.c_pointer_caller(sig.clone(), ptr_offset as i32);
+                            self.out_bc.push(Instr::Call(ret_local.slot, caller));
+                        } else {
+                            let func_ptr = self.lower_expr(*func, None);
+
+                            self.out_bc.push(Instr::CallPtr {
+                                frame: ret_local.slot,
+                                func_ptr: func_ptr.slot,
+                            });
+                        }
 
                         let dest = if let Some(dest) = dest {
                             if let Some(instr) =
@@ -533,7 +837,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                     else_opt,
                     ..
                 } => {
-                    let cond_local = self.lower_expr(*cond, None);
+                    let cond = self.lower_cond(*cond);
 
                     let jump_index_1 = self.skip_instr();
 
@@ -545,7 +849,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                         let jump_index_2 = self.skip_instr();
 
                         let jump_offset_1 = -self.get_jump_offset(jump_index_1);
-                        self.out_bc[jump_index_1] = Instr::JumpF(jump_offset_1, cond_local.slot);
+                        self.out_bc[jump_index_1] = cond.jump_f(jump_offset_1);
 
                         self.lower_expr(*else_expr, Some(dest));
 
@@ -558,7 +862,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                         let res = self.lower_expr(*then, dest);
 
                         let jump_offset_1 = -self.get_jump_offset(jump_index_1);
-                        self.out_bc[jump_index_1] = Instr::JumpF(jump_offset_1, cond_local.slot);
+                        self.out_bc[jump_index_1] = cond.jump_f(jump_offset_1);
 
                         res
                     }
@@ -854,7 +1158,6 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                                     let primary_trait =
                                         primary_trait.as_ref().expect("no primary trait");
                                     assert!(!is_dyn_star);
-                                    assert!(primary_trait.subs.list.len() == 0);
 
                                     if let TypeKind::Dynamic {
                                         primary_trait: src_primary_trait,
@@ -866,20 +1169,14 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                                         assert_eq!(src_is_dyn_star, is_dyn_star);
                                         assert_eq!(src_primary_trait.as_ref(), Some(primary_trait));
 
-                                        let dest =
-                                            dest.unwrap_or_else(|| self.stack.alloc(expr_ty));
-
-                                        let source = self.lower_expr(*source, None);
-
-                                        self.out_bc.push(
-                                            bytecode_select::copy(dest.slot, source.slot, expr_ty)
-                                                .unwrap(),
-                                        );
-
-                                        return dest;
+                                        // the pointer is unchanged, lowering it as a move keeps drop flags intact
+                                        return self.lower_expr(*source, dest);
                                     } else {
-                                        let vtable =
-                                            self.vm.find_vtable(primary_trait.item, src_ty);
+                                        let vtable = self.vm.find_vtable(
+                                            primary_trait.item,
+                                            &primary_trait.subs,
+                                            src_ty,
+                                        );
 
                                         vtable as *const _ as usize
                                     }
@@ -918,7 +1215,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
 
                             dest
                         }
-                        PointerCast::MutToConstPointer => {
+                        PointerCast::MutToConstPointer | PointerCast::UnsafeFnPointer => {
                             // a simple copy
                             let dest = dest.unwrap_or_else(|| self.stack.alloc(expr_ty));
 
@@ -936,7 +1233,15 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                             if let Some(func_ref) = src_ty.func_item() {
                                 let dest = dest.unwrap_or_else(|| self.stack.alloc(expr_ty));
 
-                                let func_ptr = func_ref.item.func_mono(&func_ref.subs) as *const _;
+                                let func = func_ref.item.func_mono(&func_ref.subs);
+
+                                let func_ptr = match expr_ty.kind() {
+                                    // C function pointers must be callable from native code
+                                    TypeKind::FunctionPointer(sig, FunctionAbi::C) => {
+                                        self.vm.c_trampoline(func, sig)
+                                    }
+                                    _ => func as *const _ as _,
+                                };
 
                                 self.out_bc.push(bytecode_select::literal(
                                     func_ptr as i128,
@@ -1211,6 +1516,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
             | ExprKind::Static(_) => true,
 
             ExprKind::Block { .. }
+            | ExprKind::DropTemps(_)
             | ExprKind::Tuple { .. }
             | ExprKind::Binary { .. }
             | ExprKind::Unary { .. }
@@ -1240,7 +1546,22 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
         }
     }
 
+    fn expr_is_box_deref(&self, id: ExprId) -> bool {
+        if let ExprKind::DeRef(arg) = self.in_func.expr(id).kind {
+            self.expr_ty(arg).is_box()
+        } else {
+            false
+        }
+    }
+
     fn expr_to_place(&mut self, id: ExprId) -> Place<'vm> {
+        let parent_span = self.enter_span(self.in_func.expr(id).span);
+        let res = self.expr_to_place_internal(id);
+        self.set_span(parent_span);
+        res
+    }
+
+    fn expr_to_place_internal(&mut self, id: ExprId) -> Place<'vm> {
         let expr = self.in_func.expr(id);
 
         if self.expr_is_place(id) {
@@ -1253,8 +1574,22 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                     } else {
                         PointerKind::Fat
                     };
-                    let addr = self.lower_expr(*arg, None);
-                    Place::Ptr(addr.slot, 0, ptr_kind)
+                    let addr = match self.expr_is_place(*arg).then(|| self.expr_to_place(*arg)) {
+                        // a box behind a pointer is only read, it is still owned by the place
+                        Some(Place::Ptr(ptr_slot, offset, _)) => {
+                            let box_ty = self.expr_ty(*arg);
+                            let addr = self.stack.alloc_no_drop(box_ty);
+                            if let Some(instr) =
+                                bytecode_select::copy_from_ptr(addr, ptr_slot, box_ty, offset)
+                            {
+                                self.out_bc.push(instr);
+                            }
+                            addr
+                        }
+                        Some(Place::Local(local)) => local.slot,
+                        None => self.lower_expr(*arg, None).slot,
+                    };
+                    Place::Ptr(addr, 0, ptr_kind)
                 }
                 ExprKind::VarRef(local_id) => {
                     let local_slot = self.find_local(*local_id);
@@ -1435,11 +1770,15 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                     let ptr_ty = ty.ref_to(Mutability::Const);
                     let ptr_size = ptr_ty.layout().assert_size();
 
-                    let static_ptr = self.vm.static_value(static_ref) as i128;
-
                     let ptr_slot = self.stack.alloc_no_drop(ptr_ty);
-                    self.out_bc
-                        .push(bytecode_select::literal(static_ptr, ptr_size, ptr_slot));
+                    if static_ref.item.is_thread_local() {
+                        self.out_bc
+                            .push(Instr::ThreadLocalAddr(ptr_slot, static_ref.item));
+                    } else {
+                        let static_ptr = self.vm.static_value(static_ref, ty) as i128;
+                        self.out_bc
+                            .push(bytecode_select::literal(static_ptr, ptr_size, ptr_slot));
+                    }
 
                     // TODO unsized?
                     assert!(ptr_size == POINTER_SIZE.bytes());
@@ -1519,6 +1858,107 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
         self.apply_subs(ty)
     }
 
+    /// Emit a binary op on primitives, checking for overflow if enabled.
+    fn binary_op(
+        &mut self,
+        op: BinaryOp,
+        lhs_ty: Type<'vm>,
+        rhs_ty: Type<'vm>,
+        out: Slot,
+        lhs: Slot,
+        rhs: Operand,
+    ) {
+        let rhs = match rhs {
+            Operand::Slot(rhs) => rhs,
+            Operand::Imm(imm) => {
+                let size = lhs_ty.layout().assert_size();
+                self.out_bc
+                    .push(bytecode_select::add_imm(size, out, lhs, imm).unwrap());
+                return;
+            }
+        };
+
+        if self.overflow_checks {
+            if let Some(ctor) = bytecode_select::binary_checked(op, lhs_ty) {
+                let rhs = if let BinaryOp::ShiftL | BinaryOp::ShiftR = op {
+                    // checked shifts need the full shift amount, not just the low byte
+                    let u128_ty = self.vm.common_types().u128;
+                    let wide_rhs = self.stack.alloc_no_drop(u128_ty);
+                    let cast_ctor = bytecode_select::cast(rhs_ty, u128_ty);
+                    self.out_bc.push(cast_ctor(wide_rhs, rhs));
+                    wide_rhs
+                } else {
+                    rhs
+                };
+
+                self.out_bc.push(ctor(out, lhs, rhs));
+                return;
+            }
+        }
+
+        let (ctor, swap) = bytecode_select::binary(op, lhs_ty);
+        if swap {
+            self.out_bc.push(ctor(out, rhs, lhs));
+        } else {
+            self.out_bc.push(ctor(out, lhs, rhs));
+        }
+    }
+
+    /// Lowers the right hand side of a binary operation. A literal added or subtracted without
+    /// overflow checks becomes an immediate, when it fits in one.
+    fn lower_operand(&mut self, op: BinaryOp, lhs_ty: Type<'vm>, rhs: ExprId) -> Operand {
+        if let ExprKind::LiteralValue(n) = self.in_func.expr(rhs).kind {
+            let n = match op {
+                BinaryOp::Add => Some(n),
+                BinaryOp::Sub => Some(n.wrapping_neg()),
+                _ => None,
+            };
+            let size = lhs_ty.layout().assert_size();
+            let is_int = matches!(lhs_ty.kind(), TypeKind::Int(..));
+            if let Some(imm) = n.and_then(|n| bytecode_select::literal_imm(n, size)) {
+                if is_int && !self.overflow_checks {
+                    return Operand::Imm(imm);
+                }
+            }
+        }
+        Operand::Slot(self.lower_expr(rhs, None).slot)
+    }
+
+    /// Lowers the condition of a branch. A comparison of integers is left for the branch to
+    /// make, instead of being computed into a bool first.
+    fn lower_cond(&mut self, cond: ExprId) -> Cond {
+        if let ExprKind::Binary(op, lhs, rhs) = self.in_func.expr(cond).kind {
+            let compare = bytecode_select::compare(op, self.expr_ty(lhs));
+            if let Some((size @ (1 | 2 | 4 | 8), compare)) = compare {
+                let lhs_literal = self.literal_imm(lhs, size);
+                let rhs_literal = self.literal_imm(rhs, size);
+
+                let parent_span = self.enter_span(self.in_func.expr(cond).span);
+                // with two literals, one is still put in a slot
+                let lhs = match lhs_literal {
+                    Some(imm) if rhs_literal.is_none() => CompareArg::Imm(imm),
+                    _ => CompareArg::Slot(self.lower_expr(lhs, None).slot),
+                };
+                let rhs = match rhs_literal {
+                    Some(imm) => CompareArg::Imm(imm),
+                    None => CompareArg::Slot(self.lower_expr(rhs, None).slot),
+                };
+                self.set_span(parent_span);
+
+                return Cond::Compare(size, compare, lhs, rhs);
+            }
+        }
+        Cond::Slot(self.lower_expr(cond, None).slot)
+    }
+
+    /// The value of a literal expression, as an immediate of the given size.
+    fn literal_imm(&self, expr: ExprId, size: u32) -> Option<i32> {
+        match self.in_func.expr(expr).kind {
+            ExprKind::LiteralValue(n) => bytecode_select::literal_imm(n, size),
+            _ => None,
+        }
+    }
+
     fn alloc_pattern(&mut self, pat_id: PatternId) -> Local<'vm> {
         let pat = self.in_func.pattern(pat_id);
 
@@ -1553,8 +1993,15 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
         match_result_slot: Option<Slot>,
     ) {
         let (source, can_alias) = if let Some(init_id) = init_id {
-            let is_place = self.expr_is_place(init_id);
-            (self.expr_to_place(init_id), !is_place)
+            let pat = self.in_func.pattern(pat_id);
+            if self.expr_is_box_deref(init_id) && self.pattern_moves_drop(pat) {
+                // moving out of a box, take the whole value so the box can be freed
+                let source = self.lower_expr(init_id, None);
+                (Place::Local(source), true)
+            } else {
+                let is_place = self.expr_is_place(init_id);
+                (self.expr_to_place(init_id), !is_place)
+            }
         } else {
             let slot = self.alloc_pattern(pat_id);
             (Place::Local(slot), true)
@@ -1571,6 +2018,35 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
         }
     }
 
+    /// Does this pattern move any value which needs to be dropped out of its source?
+    fn pattern_moves_drop(&self, pat: &Pattern<'vm>) -> bool {
+        match &pat.kind {
+            PatternKind::LocalBinding {
+                mode, sub_pattern, ..
+            } => {
+                let moves_self =
+                    *mode == BindingMode::Value && self.apply_subs(pat.ty).drop_info().is_drop();
+                let moves_sub = sub_pattern
+                    .map(|sub_pattern| self.pattern_moves_drop(self.in_func.pattern(sub_pattern)))
+                    .unwrap_or(false);
+                moves_self || moves_sub
+            }
+            PatternKind::Struct { fields } | PatternKind::Enum { fields, .. } => fields
+                .iter()
+                .any(|field| self.pattern_moves_drop(self.in_func.pattern(field.pattern))),
+            PatternKind::Or { options } => options
+                .iter()
+                .any(|option| self.pattern_moves_drop(self.in_func.pattern(*option))),
+            PatternKind::Slice { start, mid, end } => start
+                .iter()
+                .chain(mid.iter())
+                .chain(end.iter())
+                .any(|sub_pattern| self.pattern_moves_drop(self.in_func.pattern(*sub_pattern))),
+            // a deref pattern can't move out of its source
+            _ => false,
+        }
+    }
+
     /// If can_alias is false, we must copy values from the source. Otherwise we are free to re-use locals.
     /// The returned value indicates whether the pattern is refutable.
     /// If not, match_result_slot will NOT be written to, and the caller must assume the match was successful
@@ -1584,6 +2060,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
         match &pat.kind {
             PatternKind::LocalBinding {
                 local_id,
+                name,
                 mode,
                 sub_pattern,
             } => {
@@ -1659,6 +2136,8 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                         }*/
                     }
                 }
+                self.add_debug_local(name, *local_id);
+
                 if let Some(sub_pattern) = sub_pattern {
                     // copy values if there are possible aliases
                     let sub_pattern = self.in_func.pattern(*sub_pattern);
@@ -1734,6 +2213,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                     let fake_pattern = Pattern {
                         kind: PatternKind::LiteralValue(disc_val),
                         ty: discriminant_ty,
+                        span: pat.span,
                     };
                     self.match_pattern_internal(
                         &fake_pattern,
@@ -1744,6 +2224,18 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                     jump_gaps.push(self.skip_instr());
                 }
 
+                // Enum locals are drop leafs, so their fields can't be tracked individually.
+                // Moving any field out of one kills the whole enum, leaking the other fields.
+                let moved_enum = match source {
+                    Place::Local(source_local)
+                        if source_local.drop_id.is_some() && self.pattern_moves_drop(pat) =>
+                    {
+                        Some(source_local)
+                    }
+                    _ => None,
+                };
+                let can_alias = can_alias && moved_enum.is_none();
+
                 for field in fields {
                     let field_pattern = self.in_func.pattern(field.pattern);
                     let field_source =
@@ -1761,6 +2253,10 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                     }
                 }
 
+                if let Some(moved_enum) = moved_enum {
+                    self.local_forget(moved_enum);
+                }
+
                 // cut out unnecessary jumps
                 if let Some(last_gap) = jump_gaps.last() {
                     if *last_gap == self.out_bc.len() - 1 {
@@ -2217,7 +2713,12 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
 
                             match sub_pat.kind {
                                 PatternKind::Hole => (), // do nothing
-                                PatternKind::LocalBinding { local_id, mode, .. } => {
+                                PatternKind::LocalBinding {
+                                    local_id,
+                                    name,
+                                    mode,
+                                    ..
+                                } => {
                                     assert!(mode == BindingMode::Ref);
 
                                     let sub_ty = self.apply_subs(sub_pat.ty);
@@ -2246,6 +2747,7 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                                         len_slot,
                                         -len_sub,
                                     ));
+                                    self.add_debug_local(name, local_id);
                                 }
                                 _ => println!("? {:?}", sub_pat.kind),
                             }
@@ -2263,9 +2765,9 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
                             for (i, pat_id) in end.iter().enumerate() {
                                 let sub_pat = self.in_func.pattern(*pat_id);
 
-                                let i = i - end.len();
+                                let i = i as i32 - end.len() as i32;
 
-                                let offset = i as i32 * elem_size as i32;
+                                let offset = i * elem_size as i32;
                                 let refutable = self.match_pattern_internal(
                                     sub_pat,
                                     Place::Ptr(end_slot, offset, PointerKind::Thin),
@@ -2325,6 +2827,29 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
         local
     }
 
+    /// Records a variable's name for debuggers, once it has been bound.
+    fn add_debug_local(&mut self, name: &'vm str, local_id: u32) {
+        if name.is_empty() {
+            return;
+        }
+
+        let local = self.find_local(local_id);
+        // or-patterns bind the same variable once for each option
+        let pc = self.out_bc.len() as u32;
+        let exists = self.debug_locals.iter().any(|debug_local| {
+            debug_local.slot == local.slot && debug_local.name == name && debug_local.end >= pc
+        });
+        if !exists {
+            self.debug_locals.push(DebugLocal {
+                name,
+                slot: local.slot,
+                ty: local.ty,
+                start: pc,
+                end: u32::MAX,
+            });
+        }
+    }
+
     fn assert_local_undef(&self, local: u32) {
         for (id, _) in &self.locals {
             if *id == local {
@@ -2388,6 +2913,25 @@ impl<'vm, 'f> BytecodeCompiler<'vm, 'f> {
         }
     }
 
+    /// After its contents are moved out, a box is dead but still owns its allocation.
+    /// Free it without dropping the contents again.
+    fn box_free_moved(&mut self, boxed: Local<'vm>) {
+        self.local_forget(boxed);
+
+        if let Some(drop_fn) = self.vm.find_drop(boxed.ty) {
+            let TypeKind::Adt(box_item) = boxed.ty.kind() else {
+                panic!("box is not an adt");
+            };
+            let drop_fn = drop_fn.func_mono(&box_item.subs);
+
+            let ref_ty = boxed.ty.ref_to(Mutability::Mut);
+            self.stack.align_for_call();
+            let slot_ref = self.stack.alloc_no_drop(ref_ty);
+            self.out_bc.push(Instr::SlotAddr(slot_ref, boxed.slot));
+            self.out_bc.push(Instr::Call(slot_ref, drop_fn));
+        }
+    }
+
     // assert values are dead
     // mark values as live
     fn local_init(&mut self, local: Local<'vm>) {
@@ -2488,9 +3032,12 @@ impl PointerKind {
 pub struct CompilerStack<'vm> {
     entries: Vec<StackEntry>,
     top: u32,
+    max_top: u32,
     drop_info: Vec<CompilerDropInfo>,
     drop_leafs: Vec<(Slot, DropGlue<'vm>)>,
     first_drop: DropBit,
+    /// The base and size of every allocation, including those whose scopes have ended.
+    allocations: Vec<(u32, u32)>,
 }
 
 struct StackScope {
@@ -2502,18 +3049,35 @@ struct StackScope {
 
 impl Drop for StackScope {
     fn drop(&mut self) {
-        panic!("stack scope was not returned to the stack!");
+        if !(std::thread::panicking() && ABANDON_SCOPES.get()) {
+            panic!("stack scope was not returned to the stack!");
+        }
     }
 }
 
+thread_local! {
+    static ABANDON_SCOPES: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f`, catching a compile failure inside it. Stack scopes are abandoned when compilation
+/// fails part way through, so their usual check is skipped while unwinding out of `f`.
+pub fn catch_compile_failure<R>(f: impl FnOnce() -> R) -> std::thread::Result<R> {
+    let old = ABANDON_SCOPES.replace(true);
+    let res = std::panic::catch_unwind(AssertUnwindSafe(f));
+    ABANDON_SCOPES.set(old);
+    res
+}
+
 impl<'vm> CompilerStack<'vm> {
     pub fn new() -> Self {
         Self {
             entries: vec![],
             top: 0,
+            max_top: 0,
             drop_info: vec![],
             drop_leafs: vec![],
             first_drop: DropBit::new(0),
+            allocations: vec![],
         }
     }
 
@@ -2524,8 +3088,10 @@ impl<'vm> CompilerStack<'vm> {
 
         let drop_id = match drop_info {
             DropInfo::Branch { fields, .. } => Some(self.register_drop_branch(slot, fields)),
-            DropInfo::Enum { glue, fields } => panic!("enum drop"),
-            DropInfo::Leaf(glue) => Some(self.register_drop_leaf(slot, *glue)),
+            // enums are always dropped as a whole, see `match_pattern_internal`
+            DropInfo::Leaf(glue) | DropInfo::Enum { glue, .. } => {
+                Some(self.register_drop_leaf(slot, *glue))
+            }
             DropInfo::None => None,
         };
 
@@ -2546,10 +3112,17 @@ impl<'vm> CompilerStack<'vm> {
         let size = layout.assert_size();
 
         self.entries.push(StackEntry { base, size });
+        self.allocations.push((base, size));
         self.top += size;
+        self.max_top = self.max_top.max(self.top);
         Slot::new(base)
     }
 
+    /// The most stack used at any point, which is the size of the function's frame.
+    pub fn frame_size(&self) -> u32 {
+        self.max_top
+    }
+
     pub fn align(&mut self, align: u32) {
         self.top = crate::abi::align(self.top, align);
     }
@@ -2635,8 +3208,14 @@ impl<'vm> CompilerStack<'vm> {
                 let drop_info = field.ty.drop_info();
 
                 let field_id = match drop_info {
-                    DropInfo::Leaf(field_glue) => self.register_drop_leaf(field_slot, *field_glue),
-                    _ => panic!("non-trivial branch"),
+                    DropInfo::Leaf(field_glue)
+                    | DropInfo::Enum {
+                        glue: field_glue, ..
+                    } => self.register_drop_leaf(field_slot, *field_glue),
+                    DropInfo::Branch {
+                        fields: sub_fields, ..
+                    } => self.register_drop_branch(field_slot, sub_fields),
+                    DropInfo::None => panic!("field is not drop"),
                 };
 
                 // this is very dumb, but should be correct
@@ -2695,6 +3274,10 @@ impl<'vm> Local<'vm> {
         }
     }
 
+    pub fn ty(&self) -> Type<'vm> {
+        self.ty
+    }
+
     pub fn get_field(&self, variant: VariantIndex, field: u32, stack: &CompilerStack<'vm>) -> Self {
         let offset = self.ty.layout().field_offsets.get(variant)[field as usize];
 
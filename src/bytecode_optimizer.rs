@@ -0,0 +1,1399 @@
+//! A peephole optimizer for the bytecode compiler's output. The compiler emits very literal code,
+//! moving values through temporaries and jumping to jumps. This cleans that up without looking
+//! beyond single functions:
+//!
+//! 1. Copies and constants are propagated forward within each basic block, and operations on
+//!    constants are folded. Conditional jumps on constants become unconditional.
+//! 2. Jumps to jumps are threaded, and code which can't be reached is removed.
+//! 3. Stores which are never read are removed, and values computed into a temporary only to be
+//!    moved somewhere else are computed in place.
+//!
+//! Common sequences are fused into superinstructions while propagating, like a comparison
+//! followed by a branch on its result. The instructions they replace are left for the last phase
+//! to remove once nothing reads their results.
+//!
+//! Pointers into the frame can only be made by taking the address of a slot. Any stack allocation
+//! which holds such a slot is "pinned": its bytes may be read or written by anything which uses
+//! pointers, including calls and the drop glue run when a frame is unwound. Drop flags are never
+//! touched, so values are dropped exactly as they were before.
+
+use paste::paste;
+
+use crate::{
+    abi::POINTER_SIZE,
+    bytecode_compiler::{DebugLocal, SpanTable},
+    bytecode_select::{self, Compare, CompareArg},
+    types::DropGlue,
+    vm::instr::{Instr, Slot},
+};
+
+/// Optimizes a function's bytecode in place, and updates its spans and variables to match.
+/// `drops` are the values in the frame with drop glue, `result_size` is the size of the return
+/// value at the start of the frame, and `allocations` holds every slot the compiler allocated, with
+/// its size.
+pub fn optimize<'vm>(
+    code: &mut Vec<Instr<'vm>>,
+    spans: &mut SpanTable<'vm>,
+    locals: &mut [DebugLocal<'vm>],
+    drops: &[(Slot, DropGlue<'vm>)],
+    frame_size: u32,
+    result_size: u32,
+    allocations: &[(u32, u32)],
+) {
+    let in_frame = code.iter_mut().all(|instr| {
+        let ops = operands(instr);
+        let out = ops
+            .out
+            .iter()
+            .map(|(slot, size)| (slot.index() as u32, *size));
+        let args = ops
+            .args
+            .iter()
+            .flatten()
+            .map(|(slot, size)| (slot.index() as u32, *size));
+        out.chain(args)
+            .all(|(start, size)| start + size <= frame_size)
+    });
+    if !in_frame {
+        // not produced by the compiler in the usual way, leave it alone
+        return;
+    }
+
+    let pinned = pinned_bytes(code, drops, frame_size, allocations);
+    let mut opt = Optimizer {
+        code: std::mem::take(code),
+        frame_size,
+        result_size,
+        pinned,
+    };
+
+    opt.propagate();
+    opt.thread_jumps();
+    opt.remove_unreachable();
+    opt.remove_dead_stores();
+    // removing code may leave jumps to the next instruction
+    opt.thread_jumps();
+
+    *code = compact(opt.code, spans, locals);
+}
+
+struct Optimizer<'vm> {
+    code: Vec<Instr<'vm>>,
+    frame_size: u32,
+    result_size: u32,
+    pinned: ByteSet,
+}
+
+impl<'vm> Optimizer<'vm> {
+    /// Forward copy and constant propagation, within basic blocks.
+    fn propagate(&mut self) {
+        let leaders = self.leaders();
+        let mut facts = Facts::default();
+
+        for (pc, &leader) in leaders.iter().enumerate() {
+            if leader {
+                facts.clear();
+            }
+
+            let mut ops = operands(&mut self.code[pc]);
+            if ops.effect == Effect::Unknown {
+                facts.clear();
+                continue;
+            }
+
+            let mut values = [None; 3];
+            for (arg, value) in ops.args.iter_mut().zip(&mut values) {
+                if let Some((slot, size)) = arg {
+                    let start = slot.index() as u32;
+                    if let Some(src) = facts.copy_source(start, *size) {
+                        **slot = Slot::new(src);
+                    }
+                    *value = facts.constant(slot.index() as u32, *size);
+                }
+            }
+
+            let effect = ops.effect;
+            let out = ops
+                .out
+                .as_ref()
+                .map(|(slot, size)| (slot.index() as u32, *size));
+            let out_read = ops.out_read;
+            let arg_count = ops.args.iter().flatten().count();
+            let all_const = arg_count > 0 && values[..arg_count].iter().all(Option::is_some);
+
+            match (effect, out) {
+                (Effect::Pure, Some((out, size))) if all_const && !out_read => {
+                    let a = values[0].unwrap();
+                    let b = values[1].unwrap_or(0);
+                    if let Some(res) = fold(&self.code[pc], a, b) {
+                        self.code[pc] = constant(Slot::new(out), size, res);
+                    }
+                }
+                (Effect::Jump, _)
+                    if all_const
+                        && matches!(self.code[pc], Instr::JumpT(..) | Instr::JumpF(..)) =>
+                {
+                    let (offset, jump_if) = match self.code[pc] {
+                        Instr::JumpT(offset, _) => (offset, true),
+                        Instr::JumpF(offset, _) => (offset, false),
+                        _ => unreachable!(),
+                    };
+                    self.code[pc] = if (values[0].unwrap() != 0) == jump_if {
+                        Instr::Jump(offset)
+                    } else {
+                        Instr::Skipped
+                    };
+                }
+                _ => (),
+            }
+
+            if let Some(fused) = self.fuse(pc, &leaders, &facts) {
+                self.code[pc] = fused;
+            }
+
+            if let Some((out, size)) = out {
+                facts.kill(out, size);
+            }
+            match effect {
+                Effect::Memory => facts.kill_pinned(&self.pinned),
+                Effect::Call(frame) => {
+                    facts.kill(frame, self.frame_size - frame);
+                    facts.kill_pinned(&self.pinned);
+                }
+                _ => (),
+            }
+
+            if let Some(value) = const_value(&self.code[pc]) {
+                let (out, size) = out.unwrap();
+                facts.push(Fact::Const {
+                    slot: out,
+                    size,
+                    value: mask(value, size),
+                });
+            } else if is_move(&self.code[pc]) {
+                let (dst, size) = out.unwrap();
+                let ops = operands(&mut self.code[pc]);
+                let src = ops.args[0].as_ref().unwrap().0.index() as u32;
+                if !overlaps(dst, src, size) {
+                    facts.push(Fact::Copy { dst, src, size });
+                }
+            }
+        }
+    }
+
+    /// A superinstruction which can replace the one at `pc`, given the facts known before it.
+    fn fuse(&self, pc: usize, leaders: &[bool], facts: &Facts) -> Option<Instr<'vm>> {
+        match self.code[pc] {
+            Instr::JumpF(offset, cond) => self.fuse_branch(pc, leaders, facts, offset, cond, false),
+            Instr::JumpT(offset, cond) => self.fuse_branch(pc, leaders, facts, offset, cond, true),
+            ref instr => {
+                if let Some((size, compare, lhs, rhs, offset)) =
+                    bytecode_select::split_compare_jump(instr)
+                {
+                    let load_pc = pc.checked_sub(1).filter(|_| !leaders[pc]);
+                    let branch = CompareBranch {
+                        size,
+                        compare,
+                        out: None,
+                        offset,
+                    };
+                    return self.fuse_compare_jump(load_pc, facts, branch, lhs, rhs);
+                }
+                self.fuse_add(pc, facts)
+            }
+        }
+    }
+
+    /// Turns a branch on the comparison just before it into a compare-and-branch. The comparison
+    /// is left for dead store removal.
+    fn fuse_branch(
+        &self,
+        pc: usize,
+        leaders: &[bool],
+        facts: &Facts,
+        offset: i32,
+        cond: Slot,
+        jump_if: bool,
+    ) -> Option<Instr<'vm>> {
+        if leaders[pc] {
+            return None;
+        }
+        let (size, compare, out, lhs, rhs) = bytecode_select::split_compare(&self.code[pc - 1])?;
+        let out = out.index() as u32;
+        if out != cond.index() as u32
+            || overlaps_range(out, 1, lhs.index() as u32, size)
+            || overlaps_range(out, 1, rhs.index() as u32, size)
+        {
+            return None;
+        }
+        // the fused instructions jump when the comparison is false
+        let compare = if jump_if { compare.negate() } else { compare };
+
+        let load_pc = pc.checked_sub(2).filter(|_| !leaders[pc - 1]);
+        let branch = CompareBranch {
+            size,
+            compare,
+            out: Some(out),
+            offset,
+        };
+        let (lhs, rhs) = (CompareArg::Slot(lhs), CompareArg::Slot(rhs));
+        self.fuse_compare_jump(load_pc, facts, branch, lhs, rhs)
+    }
+
+    /// Picks the compare-and-branch for a comparison of `lhs` and `rhs`. Constant operands become
+    /// immediates, and an operand loaded at `load_pc` is read from its pointer instead, leaving the
+    /// load for dead store removal.
+    fn fuse_compare_jump(
+        &self,
+        load_pc: Option<usize>,
+        facts: &Facts,
+        branch: CompareBranch,
+        lhs: CompareArg,
+        rhs: CompareArg,
+    ) -> Option<Instr<'vm>> {
+        let CompareBranch {
+            size,
+            compare,
+            out,
+            offset,
+        } = branch;
+
+        let imm = |arg| match arg {
+            CompareArg::Slot(slot) => facts
+                .constant(slot.index() as u32, size)
+                .and_then(|value| immediate(value, size))
+                .map(CompareArg::Imm),
+            _ => None,
+        };
+        let field = |arg| {
+            let CompareArg::Slot(slot) = arg else {
+                return None;
+            };
+            let (tmp, ptr, field) = load(&self.code[load_pc?], size)?;
+            let ptr = ptr.index() as u32;
+            // the pointer and what it points to must still be the same at the jump
+            let unchanged = !overlaps_range(slot.index() as u32, size, ptr, POINTER_SIZE.bytes())
+                && out.map_or(true, |out| {
+                    !overlaps_range(out, 1, ptr, POINTER_SIZE.bytes())
+                        && !self.pinned.intersects(out, 1)
+                });
+            let loaded = tmp == slot;
+            (unchanged && loaded).then(|| CompareArg::Field(Slot::new(ptr), field))
+        };
+
+        [
+            (
+                imm(lhs).or_else(|| field(lhs)),
+                imm(rhs).or_else(|| field(rhs)),
+            ),
+            (imm(lhs), imm(rhs)),
+            (None, None),
+        ]
+        .into_iter()
+        .find_map(|(a, b)| {
+            let (a, b) = (a.unwrap_or(lhs), b.unwrap_or(rhs));
+            bytecode_select::compare_jump(size, compare, a, b, offset)
+        })
+    }
+
+    /// Turns additions and subtractions of a constant into additions of an immediate.
+    fn fuse_add(&self, pc: usize, facts: &Facts) -> Option<Instr<'vm>> {
+        let (size, out, a, b, sub) = match self.code[pc] {
+            Instr::I8_Add(out, a, b) => (1, out, a, b, false),
+            Instr::I16_Add(out, a, b) => (2, out, a, b, false),
+            Instr::I32_Add(out, a, b) => (4, out, a, b, false),
+            Instr::I64_Add(out, a, b) => (8, out, a, b, false),
+            Instr::I8_Sub(out, a, b) => (1, out, a, b, true),
+            Instr::I16_Sub(out, a, b) => (2, out, a, b, true),
+            Instr::I32_Sub(out, a, b) => (4, out, a, b, true),
+            Instr::I64_Sub(out, a, b) => (8, out, a, b, true),
+            _ => return None,
+        };
+        let constant = |slot: Slot| facts.constant(slot.index() as u32, size);
+
+        let (src, value) = match (constant(a), constant(b)) {
+            (_, Some(value)) if sub => (a, mask(value.wrapping_neg(), size)),
+            (_, Some(value)) => (a, value),
+            (Some(value), None) if !sub => (b, value),
+            _ => return None,
+        };
+        bytecode_select::add_imm(size, out, src, immediate(value, size)?)
+    }
+
+    /// Points jumps to where they finally end up, and removes jumps to the next instruction.
+    fn thread_jumps(&mut self) {
+        for pc in 0..self.code.len() {
+            let Some(offset) = self.code[pc].jump_offset() else {
+                continue;
+            };
+            let target = self.final_target((pc as isize + offset as isize) as usize);
+            *self.code[pc].jump_offset_mut().unwrap() = (target as isize - pc as isize) as i32;
+
+            if target == self.skip_removed(pc + 1) {
+                self.code[pc] = Instr::Skipped;
+            }
+        }
+    }
+
+    fn final_target(&self, mut pc: usize) -> usize {
+        // bounded, so loops made only of jumps end
+        for _ in 0..16 {
+            pc = self.skip_removed(pc);
+            if pc == self.code.len() {
+                break;
+            }
+            match self.code[pc] {
+                Instr::Jump(offset) => pc = (pc as isize + offset as isize) as usize,
+                _ => break,
+            }
+        }
+        pc
+    }
+
+    fn skip_removed(&self, mut pc: usize) -> usize {
+        while pc < self.code.len() && matches!(self.code[pc], Instr::Skipped) {
+            pc += 1;
+        }
+        pc
+    }
+
+    fn remove_unreachable(&mut self) {
+        let mut reachable = vec![false; self.code.len()];
+        let mut pending = vec![0];
+        while let Some(pc) = pending.pop() {
+            if pc >= self.code.len() || reachable[pc] {
+                continue;
+            }
+            reachable[pc] = true;
+            pending.extend(successors(pc, &self.code[pc]).into_iter().flatten());
+        }
+
+        for (instr, reachable) in self.code.iter_mut().zip(reachable) {
+            if !reachable {
+                *instr = Instr::Skipped;
+            }
+        }
+    }
+
+    /// Removes pure instructions whose results are never read, using the liveness of each byte of
+    /// the frame. Moves out of a temporary which was just written are folded into the write.
+    fn remove_dead_stores(&mut self) {
+        let leaders = self.leaders();
+        let mut blocks = Vec::new();
+        for (pc, &leader) in leaders.iter().enumerate() {
+            if leader {
+                blocks.push(pc..pc + 1);
+            } else {
+                blocks.last_mut().unwrap().end = pc + 1;
+            }
+        }
+
+        let mut block_of = vec![0; self.code.len()];
+        for (index, block) in blocks.iter().enumerate() {
+            for pc in block.clone() {
+                block_of[pc] = index;
+            }
+        }
+
+        // the bytes each block reads before writing, and the bytes it writes
+        let mut gen_kill = Vec::new();
+        for block in &blocks {
+            let mut gen = ByteSet::new(self.frame_size);
+            let mut kill = ByteSet::new(self.frame_size);
+            for pc in block.clone().rev() {
+                self.step_back(pc, &mut gen);
+                if let Some((out, size)) = must_write(&mut self.code[pc]) {
+                    kill.insert(out, size);
+                }
+            }
+            gen_kill.push((gen, kill));
+        }
+
+        let succs: Vec<Vec<usize>> = blocks
+            .iter()
+            .map(|block| {
+                let last = block.end - 1;
+                successors(last, &self.code[last])
+                    .into_iter()
+                    .flatten()
+                    .filter(|pc| *pc < self.code.len())
+                    .map(|pc| block_of[pc])
+                    .collect()
+            })
+            .collect();
+
+        let mut live_in = vec![ByteSet::new(self.frame_size); blocks.len()];
+        let mut live_out = vec![ByteSet::new(self.frame_size); blocks.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for index in (0..blocks.len()).rev() {
+                let mut out = ByteSet::new(self.frame_size);
+                for succ in &succs[index] {
+                    out.union(&live_in[*succ]);
+                }
+
+                let (gen, kill) = &gen_kill[index];
+                let mut live = out.clone();
+                live.subtract(kill);
+                live.union(gen);
+
+                if live != live_in[index] {
+                    live_in[index] = live;
+                    changed = true;
+                }
+                live_out[index] = out;
+            }
+        }
+
+        for (block, mut live) in blocks.into_iter().zip(live_out) {
+            for pc in block.clone().rev() {
+                if self.is_dead(pc, &live) || self.fold_move(block.start, pc, &live) {
+                    self.code[pc] = Instr::Skipped;
+                } else {
+                    self.step_back(pc, &mut live);
+                }
+            }
+        }
+    }
+
+    /// Updates the bytes live after an instruction to those live before it.
+    fn step_back(&mut self, pc: usize, live: &mut ByteSet) {
+        let ops = operands(&mut self.code[pc]);
+        if ops.effect == Effect::Unknown {
+            live.fill();
+            return;
+        }
+
+        if let Some((slot, size)) = &ops.out {
+            live.remove(slot.index() as u32, *size);
+            if ops.out_read {
+                live.insert(slot.index() as u32, *size);
+            }
+        }
+        for (slot, size) in ops.args.iter().flatten() {
+            live.insert(slot.index() as u32, *size);
+        }
+
+        match ops.effect {
+            Effect::Panics | Effect::Load | Effect::Memory => live.union(&self.pinned),
+            Effect::Call(frame) => {
+                live.insert(frame, self.frame_size - frame);
+                live.union(&self.pinned);
+            }
+            Effect::Return => {
+                live.insert(0, self.result_size);
+                live.union(&self.pinned);
+            }
+            _ => (),
+        }
+    }
+
+    fn is_dead(&mut self, pc: usize, live: &ByteSet) -> bool {
+        let ops = operands(&mut self.code[pc]);
+        match (ops.effect, &ops.out) {
+            (Effect::Pure | Effect::Load, Some((slot, size))) => {
+                !live.intersects(slot.index() as u32, *size)
+            }
+            _ => false,
+        }
+    }
+
+    /// Turns `tmp = op(..); dst = tmp` into `dst = op(..)` when `tmp` is not read afterwards.
+    /// Returns true if the move at `pc` can be removed.
+    fn fold_move(&mut self, block_start: usize, pc: usize, live: &ByteSet) -> bool {
+        if !is_move(&self.code[pc]) {
+            return false;
+        }
+        let ops = operands(&mut self.code[pc]);
+        let (dst, size) = ops
+            .out
+            .as_ref()
+            .map(|(slot, size)| (slot.index() as u32, *size))
+            .unwrap();
+        let src = ops.args[0].as_ref().unwrap().0.index() as u32;
+        if overlaps(dst, src, size) || live.intersects(src, size) {
+            return false;
+        }
+
+        let mut prev = pc;
+        loop {
+            if prev == block_start {
+                return false;
+            }
+            prev -= 1;
+            if !matches!(self.code[prev], Instr::Skipped) {
+                break;
+            }
+        }
+
+        let ops = operands(&mut self.code[prev]);
+        if !matches!(ops.effect, Effect::Pure | Effect::Panics) || ops.out_read {
+            return false;
+        }
+        let reads_dst = ops
+            .args
+            .iter()
+            .flatten()
+            .any(|(slot, arg_size)| overlaps_range(dst, size, slot.index() as u32, *arg_size));
+        match ops.out {
+            Some((slot, out_size))
+                if slot.index() as u32 == src && out_size == size && !reads_dst =>
+            {
+                *slot = Slot::new(dst);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Instructions which start a basic block: the entry, jump targets and anything after a jump.
+    fn leaders(&self) -> Vec<bool> {
+        let mut leaders = vec![false; self.code.len() + 1];
+        leaders[0] = true;
+        for (pc, instr) in self.code.iter().enumerate() {
+            let succs = successors(pc, instr);
+            if succs != [Some(pc + 1), None] {
+                leaders[pc + 1] = true;
+                for succ in succs.into_iter().flatten() {
+                    if succ < leaders.len() {
+                        leaders[succ] = true;
+                    }
+                }
+            }
+        }
+        leaders.truncate(self.code.len());
+        leaders
+    }
+}
+
+/// Removes the instructions marked as skipped, fixing up jumps, spans and debug info.
+fn compact<'vm>(
+    code: Vec<Instr<'vm>>,
+    spans: &mut SpanTable<'vm>,
+    locals: &mut [DebugLocal<'vm>],
+) -> Vec<Instr<'vm>> {
+    // maps old pcs to new ones, removed instructions map to the next one kept
+    let mut new_pc = Vec::with_capacity(code.len() + 1);
+    let mut count = 0u32;
+    for instr in &code {
+        new_pc.push(count);
+        if !matches!(instr, Instr::Skipped) {
+            count += 1;
+        }
+    }
+    new_pc.push(count);
+
+    let code = code
+        .into_iter()
+        .enumerate()
+        .filter(|(_, instr)| !matches!(instr, Instr::Skipped))
+        .map(|(pc, mut instr)| {
+            if let Some(offset) = instr.jump_offset_mut() {
+                let target = (pc as isize + *offset as isize) as usize;
+                *offset = new_pc[target] as i32 - new_pc[pc] as i32;
+            }
+            instr
+        })
+        .collect();
+
+    spans.remap(&new_pc);
+    for local in locals {
+        local.start = new_pc[local.start as usize];
+        local.end = new_pc[local.end as usize];
+    }
+    code
+}
+
+/// Bytes of the frame which pointers may reach: any allocation whose address is taken, and
+/// values with drop glue, which is run on a pointer to them.
+fn pinned_bytes(
+    code: &[Instr],
+    drops: &[(Slot, DropGlue)],
+    frame_size: u32,
+    allocations: &[(u32, u32)],
+) -> ByteSet {
+    let mut pinned = ByteSet::new(frame_size);
+    let mut pin = |slot: Slot| {
+        let slot = slot.index() as u32;
+        let containing = allocations
+            .iter()
+            .filter(|(base, size)| *base <= slot && (slot < base + size || *base == slot));
+        match containing.clone().map(|(base, _)| *base).min() {
+            Some(start) => {
+                let end = containing.map(|(base, size)| base + size).max().unwrap();
+                pinned.insert(start, end - start);
+            }
+            // not allocated in the usual way, assume the worst
+            None => pinned.insert(slot, frame_size.saturating_sub(slot)),
+        }
+    };
+
+    for instr in code {
+        match instr {
+            Instr::SlotAddr(_, arg) | Instr::SlotAddrOffset { arg, .. } => pin(*arg),
+            _ => (),
+        }
+    }
+    for (slot, _) in drops {
+        pin(*slot);
+    }
+    pinned
+}
+
+/// A compare-and-branch being fused, apart from its operands.
+#[derive(Clone, Copy)]
+struct CompareBranch {
+    size: u32,
+    compare: Compare,
+    /// The bool the comparison would have been written to, when fusing a separate comparison.
+    out: Option<u32>,
+    offset: i32,
+}
+
+/// Facts known about the frame at some point within a basic block.
+#[derive(Default)]
+struct Facts(Vec<Fact>);
+
+#[derive(Clone, Copy)]
+enum Fact {
+    /// The bytes at `dst` are a copy of those at `src`.
+    Copy { dst: u32, src: u32, size: u32 },
+    /// The bytes at `slot` hold a constant.
+    Const { slot: u32, size: u32, value: u128 },
+}
+
+impl Facts {
+    /// Enough for straight-line code, while keeping lookups cheap.
+    const LIMIT: usize = 32;
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    fn push(&mut self, fact: Fact) {
+        if self.0.len() == Self::LIMIT {
+            self.0.remove(0);
+        }
+        self.0.push(fact);
+    }
+
+    /// Where a read of these bytes can be taken from instead, if they were copied.
+    fn copy_source(&self, start: u32, size: u32) -> Option<u32> {
+        self.0.iter().rev().find_map(|fact| match *fact {
+            Fact::Copy {
+                dst,
+                src,
+                size: copy_size,
+            } if dst <= start && start + size <= dst + copy_size => Some(src + (start - dst)),
+            _ => None,
+        })
+    }
+
+    /// The value of these bytes, if they are a constant or its low bytes, like a shift amount
+    /// read from a wider integer.
+    fn constant(&self, start: u32, size: u32) -> Option<u128> {
+        self.0.iter().rev().find_map(|fact| match *fact {
+            Fact::Const {
+                slot,
+                size: const_size,
+                value,
+            } if slot == start && size <= const_size => Some(mask(value, size)),
+            _ => None,
+        })
+    }
+
+    /// Forgets anything which depends on bytes that are written.
+    fn kill(&mut self, start: u32, size: u32) {
+        self.0.retain(|fact| match *fact {
+            Fact::Copy {
+                dst,
+                src,
+                size: copy_size,
+            } => {
+                !overlaps_range(start, size, dst, copy_size)
+                    && !overlaps_range(start, size, src, copy_size)
+            }
+            Fact::Const {
+                slot,
+                size: const_size,
+                ..
+            } => !overlaps_range(start, size, slot, const_size),
+        });
+    }
+
+    fn kill_pinned(&mut self, pinned: &ByteSet) {
+        self.0.retain(|fact| match *fact {
+            Fact::Copy { dst, src, size } => {
+                !pinned.intersects(dst, size) && !pinned.intersects(src, size)
+            }
+            Fact::Const { slot, size, .. } => !pinned.intersects(slot, size),
+        });
+    }
+}
+
+/// A set of bytes in the frame.
+#[derive(Clone, PartialEq)]
+struct ByteSet(Vec<u64>);
+
+impl ByteSet {
+    fn new(size: u32) -> Self {
+        Self(vec![0; (size as usize + 63) / 64])
+    }
+
+    fn fill(&mut self) {
+        self.0.fill(!0);
+    }
+
+    fn insert(&mut self, start: u32, size: u32) {
+        self.update(start, size, |word, mask| *word |= mask);
+    }
+
+    fn remove(&mut self, start: u32, size: u32) {
+        self.update(start, size, |word, mask| *word &= !mask);
+    }
+
+    fn intersects(&self, start: u32, size: u32) -> bool {
+        let mut res = false;
+        for_words(start, size, |index, mask| res |= self.0[index] & mask != 0);
+        res
+    }
+
+    fn union(&mut self, other: &ByteSet) {
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            *word |= other;
+        }
+    }
+
+    fn subtract(&mut self, other: &ByteSet) {
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            *word &= !other;
+        }
+    }
+
+    fn update(&mut self, start: u32, size: u32, f: impl Fn(&mut u64, u64)) {
+        for_words(start, size, |index, mask| f(&mut self.0[index], mask));
+    }
+}
+
+/// Calls `f` with each word index and bit mask covering a range of bytes.
+fn for_words(start: u32, size: u32, mut f: impl FnMut(usize, u64)) {
+    let (start, end) = (start as usize, (start + size) as usize);
+    let mut pos = start;
+    while pos < end {
+        let index = pos / 64;
+        let bit = pos % 64;
+        let count = (64 - bit).min(end - pos);
+        let mask = if count == 64 {
+            !0
+        } else {
+            ((1u64 << count) - 1) << bit
+        };
+        f(index, mask);
+        pos += count;
+    }
+}
+
+fn overlaps(a: u32, b: u32, size: u32) -> bool {
+    overlaps_range(a, size, b, size)
+}
+
+fn overlaps_range(a: u32, a_size: u32, b: u32, b_size: u32) -> bool {
+    a < b + b_size && b < a + a_size
+}
+
+/// The pcs which may run after an instruction.
+fn successors(pc: usize, instr: &Instr) -> [Option<usize>; 2] {
+    let target = |offset: i32| Some((pc as isize + offset as isize) as usize);
+    match instr {
+        Instr::Jump(offset) => [target(*offset), None],
+        Instr::Return | Instr::Abort | Instr::Error(_) => [None, None],
+        instr => [Some(pc + 1), instr.jump_offset().and_then(target)],
+    }
+}
+
+fn must_write(instr: &mut Instr) -> Option<(u32, u32)> {
+    let ops = operands(instr);
+    match ops.effect {
+        Effect::Unknown => None,
+        _ => ops.out.map(|(slot, size)| (slot.index() as u32, size)),
+    }
+}
+
+fn is_move(instr: &Instr) -> bool {
+    matches!(
+        instr,
+        Instr::MovSS1(..)
+            | Instr::MovSS2(..)
+            | Instr::MovSS4(..)
+            | Instr::MovSS8(..)
+            | Instr::MovSS16(..)
+            | Instr::MovSS1N(..)
+            | Instr::MovSS2N(..)
+            | Instr::MovSS4N(..)
+            | Instr::MovSS8N(..)
+            | Instr::MovSS16N(..)
+    )
+}
+
+/// The output, pointer and offset of a load of `size` bytes.
+fn load(instr: &Instr, size: u32) -> Option<(Slot, Slot, i16)> {
+    let (out, ptr, offset) = match (instr, size) {
+        (Instr::MovSP1(out, ptr, offset), 1)
+        | (Instr::MovSP2(out, ptr, offset), 2)
+        | (Instr::MovSP4(out, ptr, offset), 4)
+        | (Instr::MovSP8(out, ptr, offset), 8) => (*out, *ptr, *offset),
+        _ => return None,
+    };
+    Some((out, ptr, offset.try_into().ok()?))
+}
+
+/// A constant as an immediate operand, which is sign-extended to its size.
+fn immediate(value: u128, size: u32) -> Option<i32> {
+    sign_extend(value, size * 8).try_into().ok()
+}
+
+fn const_value(instr: &Instr) -> Option<u128> {
+    match instr {
+        Instr::I8_Const(_, n) => Some(*n as u128),
+        Instr::I16_Const(_, n) => Some(*n as u128),
+        Instr::I32_Const(_, n) => Some(*n as u128),
+        Instr::I64_Const(_, n) => Some(*n as u128),
+        Instr::I128_Const(_, n) => Some(*n as u128),
+        _ => None,
+    }
+}
+
+fn constant<'vm>(slot: Slot, size: u32, value: u128) -> Instr<'vm> {
+    match size {
+        1 => Instr::I8_Const(slot, value as i8),
+        2 => Instr::I16_Const(slot, value as i16),
+        4 => Instr::I32_Const(slot, value as i32),
+        8 => Instr::I64_Const(slot, value as i64),
+        16 => Instr::I128_Const(slot, value as i128),
+        _ => panic!("no constant of size {}", size),
+    }
+}
+
+fn mask(value: u128, size: u32) -> u128 {
+    if size >= 16 {
+        value
+    } else {
+        value & ((1 << (size * 8)) - 1)
+    }
+}
+
+fn sign_extend(value: u128, bits: u32) -> i128 {
+    let shift = 128 - bits;
+    ((value << shift) as i128) >> shift
+}
+
+/// Evaluates an instruction whose arguments are the constants `a` and `b`, if it can be.
+/// The result is not yet truncated to the size of the output.
+fn fold(instr: &Instr, a: u128, b: u128) -> Option<u128> {
+    macro_rules! fold_int {
+        ($($ty:ident $bits:literal),*) => {
+            paste! {
+                match instr {
+                    $(
+                        Instr::[<$ty _Neg>](..) => Some(a.wrapping_neg()),
+                        Instr::[<$ty _Not>](..) => Some(!a),
+                        Instr::[<$ty _Add>](..) => Some(a.wrapping_add(b)),
+                        Instr::[<$ty _Sub>](..) => Some(a.wrapping_sub(b)),
+                        Instr::[<$ty _Mul>](..) => Some(a.wrapping_mul(b)),
+                        Instr::[<$ty _Or>](..) => Some(a | b),
+                        Instr::[<$ty _And>](..) => Some(a & b),
+                        Instr::[<$ty _Xor>](..) => Some(a ^ b),
+                        Instr::[<$ty _Eq>](..) => Some((a == b) as u128),
+                        Instr::[<$ty _NotEq>](..) => Some((a != b) as u128),
+                        Instr::[<$ty _S_Lt>](..) => {
+                            Some((sign_extend(a, $bits) < sign_extend(b, $bits)) as u128)
+                        }
+                        Instr::[<$ty _S_LtEq>](..) => {
+                            Some((sign_extend(a, $bits) <= sign_extend(b, $bits)) as u128)
+                        }
+                        Instr::[<$ty _U_Lt>](..) => Some((a < b) as u128),
+                        Instr::[<$ty _U_LtEq>](..) => Some((a <= b) as u128),
+                        Instr::[<$ty _ShiftL>](..) => Some(a << (b % $bits)),
+                        Instr::[<$ty _S_ShiftR>](..) => {
+                            Some((sign_extend(a, $bits) >> (b % $bits)) as u128)
+                        }
+                        Instr::[<$ty _U_ShiftR>](..) => Some(a >> (b % $bits)),
+                    )*
+
+                    Instr::I16_S_Widen_8(..)
+                    | Instr::I32_S_Widen_8(..)
+                    | Instr::I64_S_Widen_8(..)
+                    | Instr::I128_S_Widen_8(..) => Some(sign_extend(a, 8) as u128),
+                    Instr::I32_S_Widen_16(..)
+                    | Instr::I64_S_Widen_16(..)
+                    | Instr::I128_S_Widen_16(..) => Some(sign_extend(a, 16) as u128),
+                    Instr::I64_S_Widen_32(..) | Instr::I128_S_Widen_32(..) => {
+                        Some(sign_extend(a, 32) as u128)
+                    }
+                    Instr::I128_S_Widen_64(..) => Some(sign_extend(a, 64) as u128),
+                    Instr::I16_U_Widen_8(..)
+                    | Instr::I32_U_Widen_8(..)
+                    | Instr::I32_U_Widen_16(..)
+                    | Instr::I64_U_Widen_8(..)
+                    | Instr::I64_U_Widen_16(..)
+                    | Instr::I64_U_Widen_32(..)
+                    | Instr::I128_U_Widen_8(..)
+                    | Instr::I128_U_Widen_16(..)
+                    | Instr::I128_U_Widen_32(..)
+                    | Instr::I128_U_Widen_64(..) => Some(a),
+
+                    Instr::Bool_Not(..) => Some(a ^ 1),
+                    Instr::MovSS1(..)
+                    | Instr::MovSS2(..)
+                    | Instr::MovSS4(..)
+                    | Instr::MovSS8(..)
+                    | Instr::MovSS16(..) => Some(a),
+                    _ => None,
+                }
+            }
+        };
+    }
+
+    fold_int!(I8 8, I16 16, I32 32, I64 64, I128 128)
+}
+
+/// How an instruction affects the frame, beyond its operands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Effect {
+    /// Only reads its arguments and writes its output. Can be removed if the output is unused.
+    Pure,
+    /// Like `Pure`, but may panic, which runs the drop glue of the frame's values.
+    Panics,
+    /// Reads memory through pointers, which may point to pinned bytes. Can be removed if the
+    /// output is unused.
+    Load,
+    /// Reads or writes memory through pointers, which may point to pinned bytes.
+    Memory,
+    /// Calls a function whose frame starts at this offset. It may use the rest of our frame, and
+    /// pinned bytes through pointers.
+    Call(u32),
+    Jump,
+    Return,
+    /// Only touches drop flags, or nothing at all.
+    Inert,
+    /// May read or write anything.
+    Unknown,
+}
+
+/// The slots an instruction uses, so they can be inspected and replaced.
+struct Operands<'a> {
+    effect: Effect,
+    /// The slot written and its size.
+    out: Option<(&'a mut Slot, u32)>,
+    /// Whether the output is also read.
+    out_read: bool,
+    /// The slots read and their sizes.
+    args: [Option<(&'a mut Slot, u32)>; 3],
+}
+
+impl<'a> Operands<'a> {
+    fn new(effect: Effect) -> Self {
+        Self {
+            effect,
+            out: None,
+            out_read: false,
+            args: [None, None, None],
+        }
+    }
+
+    fn out(mut self, slot: &'a mut Slot, size: u32) -> Self {
+        self.out = Some((slot, size));
+        self
+    }
+
+    fn out_read(mut self) -> Self {
+        self.out_read = true;
+        self
+    }
+
+    fn arg(mut self, slot: &'a mut Slot, size: u32) -> Self {
+        let free = self.args.iter_mut().find(|arg| arg.is_none()).unwrap();
+        *free = Some((slot, size));
+        self
+    }
+}
+
+fn operands<'a>(instr: &'a mut Instr) -> Operands<'a> {
+    use Effect::*;
+
+    macro_rules! int_operands {
+        ($($ty:ident $size:literal),*) => {
+            paste! {
+                match instr {
+                    $(
+                        Instr::[<$ty _Const>](out, _) => Operands::new(Pure).out(out, $size),
+                        Instr::[<$ty _Neg>](out, x)
+                        | Instr::[<$ty _Not>](out, x)
+                        | Instr::[<$ty _PopCount>](out, x)
+                        | Instr::[<$ty _LeadingZeros>](out, x)
+                        | Instr::[<$ty _TrailingZeros>](out, x)
+                        | Instr::[<$ty _ReverseBits>](out, x) => {
+                            Operands::new(Pure).out(out, $size).arg(x, $size)
+                        }
+                        Instr::[<$ty _Add>](out, a, b)
+                        | Instr::[<$ty _Sub>](out, a, b)
+                        | Instr::[<$ty _Mul>](out, a, b)
+                        | Instr::[<$ty _Or>](out, a, b)
+                        | Instr::[<$ty _And>](out, a, b)
+                        | Instr::[<$ty _Xor>](out, a, b)
+                        | Instr::[<$ty _RotateLeft>](out, a, b)
+                        | Instr::[<$ty _RotateRight>](out, a, b)
+                        | Instr::[<$ty _S_SatAdd>](out, a, b)
+                        | Instr::[<$ty _U_SatAdd>](out, a, b)
+                        | Instr::[<$ty _S_SatSub>](out, a, b)
+                        | Instr::[<$ty _U_SatSub>](out, a, b) => {
+                            Operands::new(Pure).out(out, $size).arg(a, $size).arg(b, $size)
+                        }
+                        Instr::[<$ty _Eq>](out, a, b)
+                        | Instr::[<$ty _NotEq>](out, a, b)
+                        | Instr::[<$ty _S_Lt>](out, a, b)
+                        | Instr::[<$ty _S_LtEq>](out, a, b)
+                        | Instr::[<$ty _U_Lt>](out, a, b)
+                        | Instr::[<$ty _U_LtEq>](out, a, b) => {
+                            Operands::new(Pure).out(out, 1).arg(a, $size).arg(b, $size)
+                        }
+                        Instr::[<$ty _ShiftL>](out, a, b)
+                        | Instr::[<$ty _S_ShiftR>](out, a, b)
+                        | Instr::[<$ty _U_ShiftR>](out, a, b) => {
+                            Operands::new(Pure).out(out, $size).arg(a, $size).arg(b, 1)
+                        }
+                        Instr::[<$ty _S_Div>](out, a, b)
+                        | Instr::[<$ty _S_Rem>](out, a, b)
+                        | Instr::[<$ty _U_Div>](out, a, b)
+                        | Instr::[<$ty _U_Rem>](out, a, b)
+                        | Instr::[<$ty _S_CheckedAdd>](out, a, b)
+                        | Instr::[<$ty _U_CheckedAdd>](out, a, b)
+                        | Instr::[<$ty _S_CheckedSub>](out, a, b)
+                        | Instr::[<$ty _U_CheckedSub>](out, a, b)
+                        | Instr::[<$ty _S_CheckedMul>](out, a, b)
+                        | Instr::[<$ty _U_CheckedMul>](out, a, b) => {
+                            Operands::new(Panics).out(out, $size).arg(a, $size).arg(b, $size)
+                        }
+                        Instr::[<$ty _CheckedNeg>](out, x) => {
+                            Operands::new(Panics).out(out, $size).arg(x, $size)
+                        }
+                        // the shift amount is widened to u128
+                        Instr::[<$ty _CheckedShiftL>](out, a, b)
+                        | Instr::[<$ty _S_CheckedShiftR>](out, a, b)
+                        | Instr::[<$ty _U_CheckedShiftR>](out, a, b) => {
+                            Operands::new(Panics).out(out, $size).arg(a, $size).arg(b, 16)
+                        }
+                    )*
+                    instr => fused_operands(instr),
+                }
+            }
+        };
+    }
+
+    int_operands!(I8 1, I16 2, I32 4, I64 8, I128 16)
+}
+
+fn fused_operands<'a>(instr: &'a mut Instr) -> Operands<'a> {
+    use Effect::*;
+
+    macro_rules! fused_operands {
+        ($($ty:ident $size:literal),*) => {
+            paste! {
+                match instr {
+                    $(
+                        Instr::[<$ty _EqJumpF>](a, b, _)
+                        | Instr::[<$ty _NotEqJumpF>](a, b, _)
+                        | Instr::[<$ty _S_LtJumpF>](a, b, _)
+                        | Instr::[<$ty _S_LtEqJumpF>](a, b, _)
+                        | Instr::[<$ty _U_LtJumpF>](a, b, _)
+                        | Instr::[<$ty _U_LtEqJumpF>](a, b, _) => {
+                            Operands::new(Jump).arg(a, $size).arg(b, $size)
+                        }
+                        Instr::[<$ty _EqImmJumpF>](a, _, _)
+                        | Instr::[<$ty _NotEqImmJumpF>](a, _, _)
+                        | Instr::[<$ty _S_LtImmJumpF>](a, _, _)
+                        | Instr::[<$ty _S_LtEqImmJumpF>](a, _, _)
+                        | Instr::[<$ty _S_GtImmJumpF>](a, _, _)
+                        | Instr::[<$ty _S_GtEqImmJumpF>](a, _, _)
+                        | Instr::[<$ty _U_LtImmJumpF>](a, _, _)
+                        | Instr::[<$ty _U_LtEqImmJumpF>](a, _, _)
+                        | Instr::[<$ty _U_GtImmJumpF>](a, _, _)
+                        | Instr::[<$ty _U_GtEqImmJumpF>](a, _, _) => Operands::new(Jump).arg(a, $size),
+                        // jumps too, but reading memory is what matters for liveness
+                        Instr::[<$ty _EqFieldJumpF>](_, ptr, b, _)
+                        | Instr::[<$ty _NotEqFieldJumpF>](_, ptr, b, _)
+                        | Instr::[<$ty _S_LtFieldJumpF>](_, ptr, b, _)
+                        | Instr::[<$ty _S_LtEqFieldJumpF>](_, ptr, b, _)
+                        | Instr::[<$ty _S_GtFieldJumpF>](_, ptr, b, _)
+                        | Instr::[<$ty _S_GtEqFieldJumpF>](_, ptr, b, _)
+                        | Instr::[<$ty _U_LtFieldJumpF>](_, ptr, b, _)
+                        | Instr::[<$ty _U_LtEqFieldJumpF>](_, ptr, b, _)
+                        | Instr::[<$ty _U_GtFieldJumpF>](_, ptr, b, _)
+                        | Instr::[<$ty _U_GtEqFieldJumpF>](_, ptr, b, _) => {
+                            Operands::new(Load).arg(ptr, POINTER_SIZE.bytes()).arg(b, $size)
+                        }
+                        Instr::[<$ty _AddImm>](out, a, _) => Operands::new(Pure).out(out, $size).arg(a, $size),
+                    )*
+                    instr => other_operands(instr),
+                }
+            }
+        };
+    }
+
+    fused_operands!(I8 1, I16 2, I32 4, I64 8)
+}
+
+fn other_operands<'a>(instr: &'a mut Instr) -> Operands<'a> {
+    use Effect::*;
+
+    macro_rules! float_operands {
+        ($($ty:ident $size:literal),*) => {
+            paste! {
+                match instr {
+                    $(
+                        Instr::[<$ty _Neg>](out, x) => Operands::new(Pure).out(out, $size).arg(x, $size),
+                        Instr::[<$ty _Add>](out, a, b)
+                        | Instr::[<$ty _Sub>](out, a, b)
+                        | Instr::[<$ty _Mul>](out, a, b)
+                        | Instr::[<$ty _Div>](out, a, b)
+                        | Instr::[<$ty _Rem>](out, a, b)
+                        | Instr::[<$ty _Min>](out, a, b)
+                        | Instr::[<$ty _Max>](out, a, b) => {
+                            Operands::new(Pure).out(out, $size).arg(a, $size).arg(b, $size)
+                        }
+                        Instr::[<$ty _Eq>](out, a, b)
+                        | Instr::[<$ty _NotEq>](out, a, b)
+                        | Instr::[<$ty _Lt>](out, a, b)
+                        | Instr::[<$ty _LtEq>](out, a, b)
+                        | Instr::[<$ty _Gt>](out, a, b)
+                        | Instr::[<$ty _GtEq>](out, a, b) => {
+                            Operands::new(Pure).out(out, 1).arg(a, $size).arg(b, $size)
+                        }
+                    )*
+                    instr => conversion_operands(instr),
+                }
+            }
+        };
+    }
+
+    float_operands!(F32 4, F64 8)
+}
+
+fn conversion_operands<'a>(instr: &'a mut Instr) -> Operands<'a> {
+    fn unary<'a>(
+        out: &'a mut Slot,
+        out_size: u32,
+        src: &'a mut Slot,
+        src_size: u32,
+    ) -> Operands<'a> {
+        Operands::new(Effect::Pure)
+            .out(out, out_size)
+            .arg(src, src_size)
+    }
+
+    match instr {
+        Instr::Bool_Not(out, src) => unary(out, 1, src, 1),
+
+        Instr::MovSS1(out, src) => unary(out, 1, src, 1),
+        Instr::MovSS2(out, src) => unary(out, 2, src, 2),
+        Instr::MovSS4(out, src) => unary(out, 4, src, 4),
+        Instr::MovSS8(out, src) => unary(out, 8, src, 8),
+        Instr::MovSS16(out, src) => unary(out, 16, src, 16),
+
+        Instr::I16_S_Widen_8(out, src) | Instr::I16_U_Widen_8(out, src) => unary(out, 2, src, 1),
+        Instr::I32_S_Widen_8(out, src) | Instr::I32_U_Widen_8(out, src) => unary(out, 4, src, 1),
+        Instr::I32_S_Widen_16(out, src) | Instr::I32_U_Widen_16(out, src) => unary(out, 4, src, 2),
+        Instr::I64_S_Widen_8(out, src) | Instr::I64_U_Widen_8(out, src) => unary(out, 8, src, 1),
+        Instr::I64_S_Widen_16(out, src) | Instr::I64_U_Widen_16(out, src) => unary(out, 8, src, 2),
+        Instr::I64_S_Widen_32(out, src) | Instr::I64_U_Widen_32(out, src) => unary(out, 8, src, 4),
+        Instr::I128_S_Widen_8(out, src) | Instr::I128_U_Widen_8(out, src) => unary(out, 16, src, 1),
+        Instr::I128_S_Widen_16(out, src) | Instr::I128_U_Widen_16(out, src) => {
+            unary(out, 16, src, 2)
+        }
+        Instr::I128_S_Widen_32(out, src) | Instr::I128_U_Widen_32(out, src) => {
+            unary(out, 16, src, 4)
+        }
+        Instr::I128_S_Widen_64(out, src) | Instr::I128_U_Widen_64(out, src) => {
+            unary(out, 16, src, 8)
+        }
+
+        Instr::F32_From_F64(out, src) => unary(out, 4, src, 8),
+        Instr::F32_From_I8_S(out, src) | Instr::F32_From_I8_U(out, src) => unary(out, 4, src, 1),
+        Instr::F32_From_I16_S(out, src) | Instr::F32_From_I16_U(out, src) => unary(out, 4, src, 2),
+        Instr::F32_From_I32_S(out, src) | Instr::F32_From_I32_U(out, src) => unary(out, 4, src, 4),
+        Instr::F32_From_I64_S(out, src) | Instr::F32_From_I64_U(out, src) => unary(out, 4, src, 8),
+        Instr::F32_From_I128_S(out, src) | Instr::F32_From_I128_U(out, src) => {
+            unary(out, 4, src, 16)
+        }
+        Instr::F32_Into_I8_S(out, src) | Instr::F32_Into_I8_U(out, src) => unary(out, 1, src, 4),
+        Instr::F32_Into_I16_S(out, src) | Instr::F32_Into_I16_U(out, src) => unary(out, 2, src, 4),
+        Instr::F32_Into_I32_S(out, src) | Instr::F32_Into_I32_U(out, src) => unary(out, 4, src, 4),
+        Instr::F32_Into_I64_S(out, src) | Instr::F32_Into_I64_U(out, src) => unary(out, 8, src, 4),
+        Instr::F32_Into_I128_S(out, src) | Instr::F32_Into_I128_U(out, src) => {
+            unary(out, 16, src, 4)
+        }
+
+        Instr::F64_From_F32(out, src) => unary(out, 8, src, 4),
+        Instr::F64_From_I8_S(out, src) | Instr::F64_From_I8_U(out, src) => unary(out, 8, src, 1),
+        Instr::F64_From_I16_S(out, src) | Instr::F64_From_I16_U(out, src) => unary(out, 8, src, 2),
+        Instr::F64_From_I32_S(out, src) | Instr::F64_From_I32_U(out, src) => unary(out, 8, src, 4),
+        Instr::F64_From_I64_S(out, src) | Instr::F64_From_I64_U(out, src) => unary(out, 8, src, 8),
+        Instr::F64_From_I128_S(out, src) | Instr::F64_From_I128_U(out, src) => {
+            unary(out, 8, src, 16)
+        }
+        Instr::F64_Into_I8_S(out, src) | Instr::F64_Into_I8_U(out, src) => unary(out, 1, src, 8),
+        Instr::F64_Into_I16_S(out, src) | Instr::F64_Into_I16_U(out, src) => unary(out, 2, src, 8),
+        Instr::F64_Into_I32_S(out, src) | Instr::F64_Into_I32_U(out, src) => unary(out, 4, src, 8),
+        Instr::F64_Into_I64_S(out, src) | Instr::F64_Into_I64_U(out, src) => unary(out, 8, src, 8),
+        Instr::F64_Into_I128_S(out, src) | Instr::F64_Into_I128_U(out, src) => {
+            unary(out, 16, src, 8)
+        }
+
+        instr => memory_operands(instr),
+    }
+}
+
+fn memory_operands<'a>(instr: &'a mut Instr) -> Operands<'a> {
+    use Effect::*;
+
+    match instr {
+        Instr::MovSS1N(out, src, n) => Operands::new(Pure).out(out, *n).arg(src, *n),
+        Instr::MovSS2N(out, src, n) => Operands::new(Pure).out(out, *n * 2).arg(src, *n * 2),
+        Instr::MovSS4N(out, src, n) => Operands::new(Pure).out(out, *n * 4).arg(src, *n * 4),
+        Instr::MovSS8N(out, src, n) => Operands::new(Pure).out(out, *n * 8).arg(src, *n * 8),
+        Instr::MovSS16N(out, src, n) => Operands::new(Pure).out(out, *n * 16).arg(src, *n * 16),
+
+        Instr::MovSP1(out, ptr, _) => Operands::new(Load)
+            .out(out, 1)
+            .arg(ptr, POINTER_SIZE.bytes()),
+        Instr::MovSP2(out, ptr, _) => Operands::new(Load)
+            .out(out, 2)
+            .arg(ptr, POINTER_SIZE.bytes()),
+        Instr::MovSP4(out, ptr, _) => Operands::new(Load)
+            .out(out, 4)
+            .arg(ptr, POINTER_SIZE.bytes()),
+        Instr::MovSP8(out, ptr, _) => Operands::new(Load)
+            .out(out, 8)
+            .arg(ptr, POINTER_SIZE.bytes()),
+        Instr::MovSP16(out, ptr, _) => Operands::new(Load)
+            .out(out, 16)
+            .arg(ptr, POINTER_SIZE.bytes()),
+        Instr::MovSP1N(out, ptr, _, n) => Operands::new(Load)
+            .out(out, *n as u32)
+            .arg(ptr, POINTER_SIZE.bytes()),
+        Instr::MovSP2N(out, ptr, _, n) => Operands::new(Load)
+            .out(out, *n as u32 * 2)
+            .arg(ptr, POINTER_SIZE.bytes()),
+        Instr::MovSP4N(out, ptr, _, n) => Operands::new(Load)
+            .out(out, *n as u32 * 4)
+            .arg(ptr, POINTER_SIZE.bytes()),
+        Instr::MovSP8N(out, ptr, _, n) => Operands::new(Load)
+            .out(out, *n as u32 * 8)
+            .arg(ptr, POINTER_SIZE.bytes()),
+        Instr::MovSP16N(out, ptr, _, n) => Operands::new(Load)
+            .out(out, *n as u32 * 16)
+            .arg(ptr, POINTER_SIZE.bytes()),
+
+        Instr::MovPS1(ptr, src, _) => Operands::new(Memory)
+            .arg(ptr, POINTER_SIZE.bytes())
+            .arg(src, 1),
+        Instr::MovPS2(ptr, src, _) => Operands::new(Memory)
+            .arg(ptr, POINTER_SIZE.bytes())
+            .arg(src, 2),
+        Instr::MovPS4(ptr, src, _) => Operands::new(Memory)
+            .arg(ptr, POINTER_SIZE.bytes())
+            .arg(src, 4),
+        Instr::MovPS8(ptr, src, _) => Operands::new(Memory)
+            .arg(ptr, POINTER_SIZE.bytes())
+            .arg(src, 8),
+        Instr::MovPS16(ptr, src, _) => Operands::new(Memory)
+            .arg(ptr, POINTER_SIZE.bytes())
+            .arg(src, 16),
+        Instr::MovPS1N(ptr, src, _, n) => Operands::new(Memory)
+            .arg(ptr, POINTER_SIZE.bytes())
+            .arg(src, *n as u32),
+        Instr::MovPS2N(ptr, src, _, n) => Operands::new(Memory)
+            .arg(ptr, POINTER_SIZE.bytes())
+            .arg(src, *n as u32 * 2),
+        Instr::MovPS4N(ptr, src, _, n) => Operands::new(Memory)
+            .arg(ptr, POINTER_SIZE.bytes())
+            .arg(src, *n as u32 * 4),
+        Instr::MovPS8N(ptr, src, _, n) => Operands::new(Memory)
+            .arg(ptr, POINTER_SIZE.bytes())
+            .arg(src, *n as u32 * 8),
+        Instr::MovPS16N(ptr, src, _, n) => Operands::new(Memory)
+            .arg(ptr, POINTER_SIZE.bytes())
+            .arg(src, *n as u32 * 16),
+
+        Instr::MemCopy(src, dst, count) => Operands::new(Memory)
+            .arg(src, POINTER_SIZE.bytes())
+            .arg(dst, POINTER_SIZE.bytes())
+            .arg(count, POINTER_SIZE.bytes()),
+        Instr::MemCompare(out, a, b) => Operands::new(Load)
+            .out(out, 1)
+            .arg(a, POINTER_SIZE.bytes() * 2)
+            .arg(b, POINTER_SIZE.bytes() * 2),
+        Instr::WriteBytes {
+            dst, val, count, ..
+        } => Operands::new(Memory)
+            .arg(dst, POINTER_SIZE.bytes())
+            .arg(val, 1)
+            .arg(count, POINTER_SIZE.bytes()),
+        Instr::Alloc { out, .. } => Operands::new(Memory).out(out, POINTER_SIZE.bytes()),
+
+        // the address of a slot is taken without reading it
+        Instr::SlotAddr(out, _) => Operands::new(Pure).out(out, POINTER_SIZE.bytes()),
+        Instr::SlotAddrOffset { out, offset, .. } => Operands::new(Pure)
+            .out(out, POINTER_SIZE.bytes())
+            .arg(offset, POINTER_SIZE.bytes()),
+        Instr::PointerOffset2(out, src, _) => Operands::new(Pure)
+            .out(out, POINTER_SIZE.bytes())
+            .arg(src, POINTER_SIZE.bytes()),
+        Instr::PointerOffset3(out, src, _) => Operands::new(Pure)
+            .out(out, POINTER_SIZE.bytes())
+            .out_read()
+            .arg(src, POINTER_SIZE.bytes()),
+        Instr::IndexCalc { arg_out, .. } => Operands::new(Panics)
+            .out(arg_out, POINTER_SIZE.bytes())
+            .out_read(),
+        Instr::IndexCalcDyn {
+            arg_out,
+            elem_count,
+            ..
+        } => Operands::new(Panics)
+            .out(arg_out, POINTER_SIZE.bytes())
+            .out_read()
+            .arg(elem_count, POINTER_SIZE.bytes()),
+        Instr::IndexCalcEndPointer { out, slice, .. } => Operands::new(Pure)
+            .out(out, POINTER_SIZE.bytes())
+            .arg(slice, POINTER_SIZE.bytes() * 2),
+        Instr::VTableFunc(out, vtable, _) => Operands::new(Panics)
+            .out(out, POINTER_SIZE.bytes())
+            .arg(vtable, POINTER_SIZE.bytes()),
+        Instr::VTableSize(out, vtable) | Instr::VTableAlign(out, vtable) => Operands::new(Pure)
+            .out(out, POINTER_SIZE.bytes())
+            .arg(vtable, POINTER_SIZE.bytes()),
+
+        Instr::Jump(_) => Operands::new(Jump),
+        Instr::JumpF(_, cond) | Instr::JumpT(_, cond) => Operands::new(Jump).arg(cond, 1),
+
+        Instr::Call(frame, _) => Operands::new(Call(frame.index() as u32)),
+        Instr::CallPtr { frame, func_ptr } => {
+            Operands::new(Call(frame.index() as u32)).arg(func_ptr, POINTER_SIZE.bytes())
+        }
+        Instr::VTableDrop { frame, arg } => {
+            Operands::new(Call(frame.index() as u32)).arg(arg, POINTER_SIZE.bytes() * 2)
+        }
+        Instr::CatchUnwind { out, frame, .. } => {
+            Operands::new(Call(frame.index() as u32)).out(out, 4)
+        }
+
+        // glue is run on a pointer to the value
+        Instr::LocalDrop(_) | Instr::LocalDropInit(_) => Operands::new(Memory),
+        Instr::LocalInit(_) | Instr::LocalMove(_) | Instr::Skipped => Operands::new(Inert),
+        Instr::Return => Operands::new(Return),
+
+        _ => Operands::new(Unknown),
+    }
+}
@@ -1,5 +1,7 @@
 // Just a few utility functions for picking the right bytecode instructions
 
+use paste::paste;
+
 use crate::abi::POINTER_SIZE;
 use crate::ir::{BinaryOp, UnaryOp};
 use crate::types::{IntSign, Type, TypeKind};
@@ -11,7 +13,7 @@ pub fn literal<'vm>(n: i128, size: u32, slot: Slot) -> Instr<'vm> {
         2 => Instr::I16_Const(slot, n as i16),
         4 => Instr::I32_Const(slot, n as i32),
         8 => Instr::I64_Const(slot, n as i64),
-        16 => Instr::I128_Const(slot, Box::new(n)),
+        16 => Instr::I128_Const(slot, n),
         _ => panic!("int size {}", size),
     }
 }
@@ -388,6 +390,39 @@ pub fn binary<'vm>(op: BinaryOp, ty: Type) -> (fn(Slot, Slot, Slot) -> Instr<'vm
                 _ => panic!(),
             }
         }
+        (BinaryOp::NotEq, TypeKind::Ptr(..), _) => {
+            assert_eq!(size, POINTER_SIZE.bytes());
+            match size {
+                4 => Instr::I32_NotEq,
+                8 => Instr::I64_NotEq,
+                _ => panic!(),
+            }
+        }
+        (BinaryOp::Lt, TypeKind::Ptr(..), _) => {
+            assert_eq!(size, POINTER_SIZE.bytes());
+            match size {
+                4 => Instr::I32_U_Lt,
+                8 => Instr::I64_U_Lt,
+                _ => panic!(),
+            }
+        }
+        (BinaryOp::LtEq, TypeKind::Ptr(..), _) => {
+            assert_eq!(size, POINTER_SIZE.bytes());
+            match size {
+                4 => Instr::I32_U_LtEq,
+                8 => Instr::I64_U_LtEq,
+                _ => panic!(),
+            }
+        }
+        (BinaryOp::Gt, TypeKind::Ptr(..), _) => {
+            swap = true;
+            assert_eq!(size, POINTER_SIZE.bytes());
+            match size {
+                4 => Instr::I32_U_Lt,
+                8 => Instr::I64_U_Lt,
+                _ => panic!(),
+            }
+        }
 
         _ => panic!("no binary op: {:?} {:?}", op, ty),
     };
@@ -395,6 +430,353 @@ pub fn binary<'vm>(op: BinaryOp, ty: Type) -> (fn(Slot, Slot, Slot) -> Instr<'vm
     (ctor, swap)
 }
 
+/// Unary ops that panic on overflow. Returns None if the op can not overflow.
+pub fn unary_checked<'vm>(op: UnaryOp, ty: Type) -> Option<fn(Slot, Slot) -> Instr<'vm>> {
+    let size = ty.layout().assert_size();
+    Some(match (op, ty.kind(), size) {
+        (UnaryOp::Neg, TypeKind::Int(_, IntSign::Signed), 1) => Instr::I8_CheckedNeg,
+        (UnaryOp::Neg, TypeKind::Int(_, IntSign::Signed), 2) => Instr::I16_CheckedNeg,
+        (UnaryOp::Neg, TypeKind::Int(_, IntSign::Signed), 4) => Instr::I32_CheckedNeg,
+        (UnaryOp::Neg, TypeKind::Int(_, IntSign::Signed), 8) => Instr::I64_CheckedNeg,
+        (UnaryOp::Neg, TypeKind::Int(_, IntSign::Signed), 16) => Instr::I128_CheckedNeg,
+
+        _ => return None,
+    })
+}
+
+/// Binary ops that panic on overflow. Returns None if the op can not overflow.
+/// The RHS of shifts must be widened to u128.
+pub fn binary_checked<'vm>(op: BinaryOp, ty: Type) -> Option<fn(Slot, Slot, Slot) -> Instr<'vm>> {
+    let size = ty.layout().assert_size();
+    Some(match (op, ty.kind(), size) {
+        (BinaryOp::Add, TypeKind::Int(_, IntSign::Signed), 1) => Instr::I8_S_CheckedAdd,
+        (BinaryOp::Add, TypeKind::Int(_, IntSign::Signed), 2) => Instr::I16_S_CheckedAdd,
+        (BinaryOp::Add, TypeKind::Int(_, IntSign::Signed), 4) => Instr::I32_S_CheckedAdd,
+        (BinaryOp::Add, TypeKind::Int(_, IntSign::Signed), 8) => Instr::I64_S_CheckedAdd,
+        (BinaryOp::Add, TypeKind::Int(_, IntSign::Signed), 16) => Instr::I128_S_CheckedAdd,
+        (BinaryOp::Add, TypeKind::Int(_, IntSign::Unsigned), 1) => Instr::I8_U_CheckedAdd,
+        (BinaryOp::Add, TypeKind::Int(_, IntSign::Unsigned), 2) => Instr::I16_U_CheckedAdd,
+        (BinaryOp::Add, TypeKind::Int(_, IntSign::Unsigned), 4) => Instr::I32_U_CheckedAdd,
+        (BinaryOp::Add, TypeKind::Int(_, IntSign::Unsigned), 8) => Instr::I64_U_CheckedAdd,
+        (BinaryOp::Add, TypeKind::Int(_, IntSign::Unsigned), 16) => Instr::I128_U_CheckedAdd,
+
+        (BinaryOp::Sub, TypeKind::Int(_, IntSign::Signed), 1) => Instr::I8_S_CheckedSub,
+        (BinaryOp::Sub, TypeKind::Int(_, IntSign::Signed), 2) => Instr::I16_S_CheckedSub,
+        (BinaryOp::Sub, TypeKind::Int(_, IntSign::Signed), 4) => Instr::I32_S_CheckedSub,
+        (BinaryOp::Sub, TypeKind::Int(_, IntSign::Signed), 8) => Instr::I64_S_CheckedSub,
+        (BinaryOp::Sub, TypeKind::Int(_, IntSign::Signed), 16) => Instr::I128_S_CheckedSub,
+        (BinaryOp::Sub, TypeKind::Int(_, IntSign::Unsigned), 1) => Instr::I8_U_CheckedSub,
+        (BinaryOp::Sub, TypeKind::Int(_, IntSign::Unsigned), 2) => Instr::I16_U_CheckedSub,
+        (BinaryOp::Sub, TypeKind::Int(_, IntSign::Unsigned), 4) => Instr::I32_U_CheckedSub,
+        (BinaryOp::Sub, TypeKind::Int(_, IntSign::Unsigned), 8) => Instr::I64_U_CheckedSub,
+        (BinaryOp::Sub, TypeKind::Int(_, IntSign::Unsigned), 16) => Instr::I128_U_CheckedSub,
+
+        (BinaryOp::Mul, TypeKind::Int(_, IntSign::Signed), 1) => Instr::I8_S_CheckedMul,
+        (BinaryOp::Mul, TypeKind::Int(_, IntSign::Signed), 2) => Instr::I16_S_CheckedMul,
+        (BinaryOp::Mul, TypeKind::Int(_, IntSign::Signed), 4) => Instr::I32_S_CheckedMul,
+        (BinaryOp::Mul, TypeKind::Int(_, IntSign::Signed), 8) => Instr::I64_S_CheckedMul,
+        (BinaryOp::Mul, TypeKind::Int(_, IntSign::Signed), 16) => Instr::I128_S_CheckedMul,
+        (BinaryOp::Mul, TypeKind::Int(_, IntSign::Unsigned), 1) => Instr::I8_U_CheckedMul,
+        (BinaryOp::Mul, TypeKind::Int(_, IntSign::Unsigned), 2) => Instr::I16_U_CheckedMul,
+        (BinaryOp::Mul, TypeKind::Int(_, IntSign::Unsigned), 4) => Instr::I32_U_CheckedMul,
+        (BinaryOp::Mul, TypeKind::Int(_, IntSign::Unsigned), 8) => Instr::I64_U_CheckedMul,
+        (BinaryOp::Mul, TypeKind::Int(_, IntSign::Unsigned), 16) => Instr::I128_U_CheckedMul,
+
+        (BinaryOp::ShiftL, TypeKind::Int(..), 1) => Instr::I8_CheckedShiftL,
+        (BinaryOp::ShiftL, TypeKind::Int(..), 2) => Instr::I16_CheckedShiftL,
+        (BinaryOp::ShiftL, TypeKind::Int(..), 4) => Instr::I32_CheckedShiftL,
+        (BinaryOp::ShiftL, TypeKind::Int(..), 8) => Instr::I64_CheckedShiftL,
+        (BinaryOp::ShiftL, TypeKind::Int(..), 16) => Instr::I128_CheckedShiftL,
+
+        (BinaryOp::ShiftR, TypeKind::Int(_, IntSign::Signed), 1) => Instr::I8_S_CheckedShiftR,
+        (BinaryOp::ShiftR, TypeKind::Int(_, IntSign::Signed), 2) => Instr::I16_S_CheckedShiftR,
+        (BinaryOp::ShiftR, TypeKind::Int(_, IntSign::Signed), 4) => Instr::I32_S_CheckedShiftR,
+        (BinaryOp::ShiftR, TypeKind::Int(_, IntSign::Signed), 8) => Instr::I64_S_CheckedShiftR,
+        (BinaryOp::ShiftR, TypeKind::Int(_, IntSign::Signed), 16) => Instr::I128_S_CheckedShiftR,
+        (BinaryOp::ShiftR, TypeKind::Int(_, IntSign::Unsigned), 1) => Instr::I8_U_CheckedShiftR,
+        (BinaryOp::ShiftR, TypeKind::Int(_, IntSign::Unsigned), 2) => Instr::I16_U_CheckedShiftR,
+        (BinaryOp::ShiftR, TypeKind::Int(_, IntSign::Unsigned), 4) => Instr::I32_U_CheckedShiftR,
+        (BinaryOp::ShiftR, TypeKind::Int(_, IntSign::Unsigned), 8) => Instr::I64_U_CheckedShiftR,
+        (BinaryOp::ShiftR, TypeKind::Int(_, IntSign::Unsigned), 16) => Instr::I128_U_CheckedShiftR,
+
+        _ => return None,
+    })
+}
+
+/// An integer comparison, which compare-and-branch superinstructions are specialized for.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compare {
+    Eq,
+    NotEq,
+    S_Lt,
+    S_LtEq,
+    S_Gt,
+    S_GtEq,
+    U_Lt,
+    U_LtEq,
+    U_Gt,
+    U_GtEq,
+}
+
+impl Compare {
+    /// The comparison which holds exactly when this one does not.
+    pub fn negate(self) -> Self {
+        match self {
+            Compare::Eq => Compare::NotEq,
+            Compare::NotEq => Compare::Eq,
+            Compare::S_Lt => Compare::S_GtEq,
+            Compare::S_LtEq => Compare::S_Gt,
+            Compare::S_Gt => Compare::S_LtEq,
+            Compare::S_GtEq => Compare::S_Lt,
+            Compare::U_Lt => Compare::U_GtEq,
+            Compare::U_LtEq => Compare::U_Gt,
+            Compare::U_Gt => Compare::U_LtEq,
+            Compare::U_GtEq => Compare::U_Lt,
+        }
+    }
+
+    /// The same comparison with its operands swapped, `a < b` becomes `b > a`.
+    pub fn swap(self) -> Self {
+        match self {
+            Compare::Eq => Compare::Eq,
+            Compare::NotEq => Compare::NotEq,
+            Compare::S_Lt => Compare::S_Gt,
+            Compare::S_LtEq => Compare::S_GtEq,
+            Compare::S_Gt => Compare::S_Lt,
+            Compare::S_GtEq => Compare::S_LtEq,
+            Compare::U_Lt => Compare::U_Gt,
+            Compare::U_LtEq => Compare::U_GtEq,
+            Compare::U_Gt => Compare::U_Lt,
+            Compare::U_GtEq => Compare::U_LtEq,
+        }
+    }
+}
+
+/// The comparison a binary operator performs on integers of this type, with their size.
+pub fn compare(op: BinaryOp, ty: Type) -> Option<(u32, Compare)> {
+    let signed = match ty.kind() {
+        TypeKind::Int(_, sign) => *sign == IntSign::Signed,
+        TypeKind::Bool | TypeKind::Char => false,
+        _ => return None,
+    };
+    let compare = match (op, signed) {
+        (BinaryOp::Eq, _) => Compare::Eq,
+        (BinaryOp::NotEq, _) => Compare::NotEq,
+        (BinaryOp::Lt, true) => Compare::S_Lt,
+        (BinaryOp::LtEq, true) => Compare::S_LtEq,
+        (BinaryOp::Gt, true) => Compare::S_Gt,
+        (BinaryOp::GtEq, true) => Compare::S_GtEq,
+        (BinaryOp::Lt, false) => Compare::U_Lt,
+        (BinaryOp::LtEq, false) => Compare::U_LtEq,
+        (BinaryOp::Gt, false) => Compare::U_Gt,
+        (BinaryOp::GtEq, false) => Compare::U_GtEq,
+        _ => return None,
+    };
+    Some((ty.layout().assert_size(), compare))
+}
+
+/// A literal as the immediate of a superinstruction of the given size, if it fits in one.
+pub fn literal_imm(n: i128, size: u32) -> Option<i32> {
+    match size {
+        1 => Some(n as i8 as i32),
+        2 => Some(n as i16 as i32),
+        4 => Some(n as i32),
+        8 => (n as i64).try_into().ok(),
+        _ => None,
+    }
+}
+
+/// An operand of a fused comparison.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareArg {
+    Slot(Slot),
+    Imm(i32),
+    /// Read through the pointer in a slot, at an offset.
+    Field(Slot, i16),
+}
+
+/// Splits an integer comparison into its size, comparison, output and operands.
+pub fn split_compare(instr: &Instr) -> Option<(u32, Compare, Slot, Slot, Slot)> {
+    macro_rules! split {
+        ($($ty:ident $size:literal),*) => {
+            paste! {
+                Some(match instr {
+                    $(
+                        Instr::[<$ty _Eq>](out, a, b) => ($size, Compare::Eq, *out, *a, *b),
+                        Instr::[<$ty _NotEq>](out, a, b) => ($size, Compare::NotEq, *out, *a, *b),
+                        Instr::[<$ty _S_Lt>](out, a, b) => ($size, Compare::S_Lt, *out, *a, *b),
+                        Instr::[<$ty _S_LtEq>](out, a, b) => ($size, Compare::S_LtEq, *out, *a, *b),
+                        Instr::[<$ty _U_Lt>](out, a, b) => ($size, Compare::U_Lt, *out, *a, *b),
+                        Instr::[<$ty _U_LtEq>](out, a, b) => ($size, Compare::U_LtEq, *out, *a, *b),
+                    )*
+                    _ => return None,
+                })
+            }
+        };
+    }
+
+    split!(I8 1, I16 2, I32 4, I64 8, I128 16)
+}
+
+/// A superinstruction which jumps by `offset` unless `lhs <compare> rhs`. Returns None if there
+/// is none for these operands. Immediates are sign-extended to the size of the comparison.
+pub fn compare_jump<'vm>(
+    size: u32,
+    compare: Compare,
+    lhs: CompareArg,
+    rhs: CompareArg,
+    offset: i32,
+) -> Option<Instr<'vm>> {
+    use CompareArg::*;
+
+    // fused instructions take slots before immediates, fields before slots, and lt before gt
+    let (compare, lhs, rhs) = match (compare, lhs, rhs) {
+        (_, Imm(_), Slot(_)) | (_, Slot(_), Field(..)) => (compare.swap(), rhs, lhs),
+        (Compare::S_Gt | Compare::S_GtEq | Compare::U_Gt | Compare::U_GtEq, Slot(_), Slot(_)) => {
+            (compare.swap(), rhs, lhs)
+        }
+        _ => (compare, lhs, rhs),
+    };
+
+    macro_rules! select {
+        ($($ty:ident $size:literal),*) => {
+            paste! {
+                Some(match (size, compare, lhs, rhs) {
+                    $(
+                        ($size, Compare::Eq, Slot(a), Slot(b)) => Instr::[<$ty _EqJumpF>](a, b, offset),
+                        ($size, Compare::NotEq, Slot(a), Slot(b)) => Instr::[<$ty _NotEqJumpF>](a, b, offset),
+                        ($size, Compare::S_Lt, Slot(a), Slot(b)) => Instr::[<$ty _S_LtJumpF>](a, b, offset),
+                        ($size, Compare::S_LtEq, Slot(a), Slot(b)) => Instr::[<$ty _S_LtEqJumpF>](a, b, offset),
+                        ($size, Compare::U_Lt, Slot(a), Slot(b)) => Instr::[<$ty _U_LtJumpF>](a, b, offset),
+                        ($size, Compare::U_LtEq, Slot(a), Slot(b)) => Instr::[<$ty _U_LtEqJumpF>](a, b, offset),
+                        ($size, Compare::Eq, Slot(a), Imm(b)) => Instr::[<$ty _EqImmJumpF>](a, b, offset),
+                        ($size, Compare::NotEq, Slot(a), Imm(b)) => Instr::[<$ty _NotEqImmJumpF>](a, b, offset),
+                        ($size, Compare::S_Lt, Slot(a), Imm(b)) => Instr::[<$ty _S_LtImmJumpF>](a, b, offset),
+                        ($size, Compare::S_LtEq, Slot(a), Imm(b)) => Instr::[<$ty _S_LtEqImmJumpF>](a, b, offset),
+                        ($size, Compare::S_Gt, Slot(a), Imm(b)) => Instr::[<$ty _S_GtImmJumpF>](a, b, offset),
+                        ($size, Compare::S_GtEq, Slot(a), Imm(b)) => Instr::[<$ty _S_GtEqImmJumpF>](a, b, offset),
+                        ($size, Compare::U_Lt, Slot(a), Imm(b)) => Instr::[<$ty _U_LtImmJumpF>](a, b, offset),
+                        ($size, Compare::U_LtEq, Slot(a), Imm(b)) => Instr::[<$ty _U_LtEqImmJumpF>](a, b, offset),
+                        ($size, Compare::U_Gt, Slot(a), Imm(b)) => Instr::[<$ty _U_GtImmJumpF>](a, b, offset),
+                        ($size, Compare::U_GtEq, Slot(a), Imm(b)) => Instr::[<$ty _U_GtEqImmJumpF>](a, b, offset),
+                        ($size, Compare::Eq, Field(p, f), Slot(b)) => {
+                            Instr::[<$ty _EqFieldJumpF>](f, p, b, offset)
+                        }
+                        ($size, Compare::NotEq, Field(p, f), Slot(b)) => {
+                            Instr::[<$ty _NotEqFieldJumpF>](f, p, b, offset)
+                        }
+                        ($size, Compare::S_Lt, Field(p, f), Slot(b)) => {
+                            Instr::[<$ty _S_LtFieldJumpF>](f, p, b, offset)
+                        }
+                        ($size, Compare::S_LtEq, Field(p, f), Slot(b)) => {
+                            Instr::[<$ty _S_LtEqFieldJumpF>](f, p, b, offset)
+                        }
+                        ($size, Compare::S_Gt, Field(p, f), Slot(b)) => {
+                            Instr::[<$ty _S_GtFieldJumpF>](f, p, b, offset)
+                        }
+                        ($size, Compare::S_GtEq, Field(p, f), Slot(b)) => {
+                            Instr::[<$ty _S_GtEqFieldJumpF>](f, p, b, offset)
+                        }
+                        ($size, Compare::U_Lt, Field(p, f), Slot(b)) => {
+                            Instr::[<$ty _U_LtFieldJumpF>](f, p, b, offset)
+                        }
+                        ($size, Compare::U_LtEq, Field(p, f), Slot(b)) => {
+                            Instr::[<$ty _U_LtEqFieldJumpF>](f, p, b, offset)
+                        }
+                        ($size, Compare::U_Gt, Field(p, f), Slot(b)) => {
+                            Instr::[<$ty _U_GtFieldJumpF>](f, p, b, offset)
+                        }
+                        ($size, Compare::U_GtEq, Field(p, f), Slot(b)) => {
+                            Instr::[<$ty _U_GtEqFieldJumpF>](f, p, b, offset)
+                        }
+                    )*
+                    _ => return None,
+                })
+            }
+        };
+    }
+
+    select!(I8 1, I16 2, I32 4, I64 8)
+}
+
+/// Splits a compare-and-branch superinstruction into its size, comparison, operands and offset.
+pub fn split_compare_jump(instr: &Instr) -> Option<(u32, Compare, CompareArg, CompareArg, i32)> {
+    use CompareArg::*;
+
+    macro_rules! split {
+        ($($ty:ident $size:literal),*) => {
+            paste! {
+                match instr {
+                    $(
+                        Instr::[<$ty _EqJumpF>](a, b, offset) => ($size, Compare::Eq, Slot(*a), Slot(*b), offset),
+                        Instr::[<$ty _NotEqJumpF>](a, b, offset) => ($size, Compare::NotEq, Slot(*a), Slot(*b), offset),
+                        Instr::[<$ty _S_LtJumpF>](a, b, offset) => ($size, Compare::S_Lt, Slot(*a), Slot(*b), offset),
+                        Instr::[<$ty _S_LtEqJumpF>](a, b, offset) => ($size, Compare::S_LtEq, Slot(*a), Slot(*b), offset),
+                        Instr::[<$ty _U_LtJumpF>](a, b, offset) => ($size, Compare::U_Lt, Slot(*a), Slot(*b), offset),
+                        Instr::[<$ty _U_LtEqJumpF>](a, b, offset) => ($size, Compare::U_LtEq, Slot(*a), Slot(*b), offset),
+                        Instr::[<$ty _EqImmJumpF>](a, b, offset) => ($size, Compare::Eq, Slot(*a), Imm(*b), offset),
+                        Instr::[<$ty _NotEqImmJumpF>](a, b, offset) => ($size, Compare::NotEq, Slot(*a), Imm(*b), offset),
+                        Instr::[<$ty _S_LtImmJumpF>](a, b, offset) => ($size, Compare::S_Lt, Slot(*a), Imm(*b), offset),
+                        Instr::[<$ty _S_LtEqImmJumpF>](a, b, offset) => ($size, Compare::S_LtEq, Slot(*a), Imm(*b), offset),
+                        Instr::[<$ty _S_GtImmJumpF>](a, b, offset) => ($size, Compare::S_Gt, Slot(*a), Imm(*b), offset),
+                        Instr::[<$ty _S_GtEqImmJumpF>](a, b, offset) => ($size, Compare::S_GtEq, Slot(*a), Imm(*b), offset),
+                        Instr::[<$ty _U_LtImmJumpF>](a, b, offset) => ($size, Compare::U_Lt, Slot(*a), Imm(*b), offset),
+                        Instr::[<$ty _U_LtEqImmJumpF>](a, b, offset) => ($size, Compare::U_LtEq, Slot(*a), Imm(*b), offset),
+                        Instr::[<$ty _U_GtImmJumpF>](a, b, offset) => ($size, Compare::U_Gt, Slot(*a), Imm(*b), offset),
+                        Instr::[<$ty _U_GtEqImmJumpF>](a, b, offset) => ($size, Compare::U_GtEq, Slot(*a), Imm(*b), offset),
+                        Instr::[<$ty _EqFieldJumpF>](f, p, b, offset) => {
+                            ($size, Compare::Eq, Field(*p, *f), Slot(*b), offset)
+                        }
+                        Instr::[<$ty _NotEqFieldJumpF>](f, p, b, offset) => {
+                            ($size, Compare::NotEq, Field(*p, *f), Slot(*b), offset)
+                        }
+                        Instr::[<$ty _S_LtFieldJumpF>](f, p, b, offset) => {
+                            ($size, Compare::S_Lt, Field(*p, *f), Slot(*b), offset)
+                        }
+                        Instr::[<$ty _S_LtEqFieldJumpF>](f, p, b, offset) => {
+                            ($size, Compare::S_LtEq, Field(*p, *f), Slot(*b), offset)
+                        }
+                        Instr::[<$ty _S_GtFieldJumpF>](f, p, b, offset) => {
+                            ($size, Compare::S_Gt, Field(*p, *f), Slot(*b), offset)
+                        }
+                        Instr::[<$ty _S_GtEqFieldJumpF>](f, p, b, offset) => {
+                            ($size, Compare::S_GtEq, Field(*p, *f), Slot(*b), offset)
+                        }
+                        Instr::[<$ty _U_LtFieldJumpF>](f, p, b, offset) => {
+                            ($size, Compare::U_Lt, Field(*p, *f), Slot(*b), offset)
+                        }
+                        Instr::[<$ty _U_LtEqFieldJumpF>](f, p, b, offset) => {
+                            ($size, Compare::U_LtEq, Field(*p, *f), Slot(*b), offset)
+                        }
+                        Instr::[<$ty _U_GtFieldJumpF>](f, p, b, offset) => {
+                            ($size, Compare::U_Gt, Field(*p, *f), Slot(*b), offset)
+                        }
+                        Instr::[<$ty _U_GtEqFieldJumpF>](f, p, b, offset) => {
+                            ($size, Compare::U_GtEq, Field(*p, *f), Slot(*b), offset)
+                        }
+                    )*
+                    _ => return None,
+                }
+            }
+        };
+    }
+
+    let (size, compare, lhs, rhs, offset) = split!(I8 1, I16 2, I32 4, I64 8);
+    Some((size, compare, lhs, rhs, *offset))
+}
+
+/// Adds an immediate, which is sign-extended to the size of the addition.
+pub fn add_imm<'vm>(size: u32, out: Slot, src: Slot, imm: i32) -> Option<Instr<'vm>> {
+    Some(match size {
+        1 => Instr::I8_AddImm(out, src, imm),
+        2 => Instr::I16_AddImm(out, src, imm),
+        4 => Instr::I32_AddImm(out, src, imm),
+        8 => Instr::I64_AddImm(out, src, imm),
+        _ => return None,
+    })
+}
+
 pub fn cast<'vm>(arg_ty: Type, res_ty: Type) -> fn(Slot, Slot) -> Instr<'vm> {
     let arg_size = arg_ty.layout().assert_size();
     let res_size = res_ty.layout().assert_size();
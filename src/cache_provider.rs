@@ -98,6 +98,13 @@ impl<'vm> CrateProvider<'vm> for CacheProvider<'vm> {
         items.array.get(id.index())
     }
 
+    fn all_items(&self) -> Vec<&'vm Item<'vm>> {
+        let items = self.read_context.items.get().unwrap();
+        (0..items.array.len())
+            .map(|index| *items.array.get(index))
+            .collect()
+    }
+
     fn item_by_path(&self, path: &ItemPath<'vm>) -> Option<&'vm Item<'vm>> {
         let items = self.read_context.items.get().unwrap();
         items.get(path).copied()
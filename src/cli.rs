@@ -1,13 +1,27 @@
-use std::ffi::OsString;
+use std::{ffi::OsString, path::PathBuf};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use skitter::Options;
 
 /// Simple program to greet a person
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct CliArgs {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// The rust file to run.
-    pub file_name: OsString,
+    #[clap(required_unless_present_any = ["manifest_path", "dap"])]
+    pub file_name: Option<OsString>,
+
+    /// Run the binary of a cargo package instead of a single file.
+    #[clap(long)]
+    pub manifest_path: Option<PathBuf>,
+
+    /// Arguments for the program being run, after `--`.
+    #[clap(last = true)]
+    pub program_args: Vec<OsString>,
 
     /// Used to run tests. Pass a directory as file_name.
     #[clap(long)]
@@ -25,10 +39,31 @@ pub struct CliArgs {
     #[clap(long)]
     pub save: bool,
 
+    /// Do not print compiler warnings for the program being run.
+    #[clap(long)]
+    pub no_warnings: bool,
+
+    /// Emulate rustc's debug profile: enable overflow checks and `cfg(debug_assertions)` for the program being run.
+    #[clap(long)]
+    pub debug_profile: bool,
+
+    /// Load a shared library to resolve extern functions from. A bare name like `z` loads `libz.so`.
+    #[clap(long = "link-lib")]
+    pub link_libs: Vec<String>,
+
     /// Compile bytecode to machine code.
     #[clap(long, short)]
     pub jit: bool,
 
+    /// Run bytecode as the compiler emits it, without optimizing it. Useful to bisect bugs.
+    #[clap(long)]
+    pub no_bc_opt: bool,
+
+    /// Run bytecode decoded from its compact form, instead of the compact form itself. Useful to
+    /// bisect bugs, and to measure what the encoding gains.
+    #[clap(long)]
+    pub no_compact: bool,
+
     /// Continue the same action in a loop, forever, or until an error is encountered.
     #[clap(long)]
     pub debug_repeat: bool,
@@ -40,4 +75,103 @@ pub struct CliArgs {
     /// Log the arguments and return value of every function call.
     #[clap(long)]
     pub debug_trace_calls: bool,
+
+    /// Size of each thread's interpreter stack, in bytes. Accepts a K, M or G suffix.
+    #[clap(long, value_parser = parse_size, default_value = "1M")]
+    pub stack_size: usize,
+
+    /// Grow the interpreter stack instead of failing with a stack overflow.
+    #[clap(long)]
+    pub growable_stack: bool,
+
+    /// Debug the program with breakpoints and stepping. Type `help` at the prompt for commands.
+    #[clap(long)]
+    pub debug: bool,
+
+    /// Print the bytecode of each function whose path matches a pattern, like `::main` or
+    /// `::vec::*`, when it is compiled.
+    #[clap(long, value_name = "PATTERN")]
+    pub dump_bytecode: Option<String>,
+
+    /// Print the calls, self time and inclusive time of the most expensive functions after running.
+    #[clap(long)]
+    pub profile_functions: bool,
+
+    /// Profile functions like `--profile-functions`, and write every stack to a file, in the
+    /// collapsed format read by flamegraph tools.
+    #[clap(long, value_name = "FILE")]
+    pub profile_stacks: Option<PathBuf>,
+
+    /// Print how many times each kind of instruction was run. Needs skitter to be built with
+    /// `--features profile-opcodes`. The debugger's loop doesn't count instructions.
+    #[clap(long, conflicts_with_all = ["debug", "dap"])]
+    pub profile_opcodes: bool,
+
+    /// Profile opcodes like `--profile-opcodes`, and also count pairs of consecutive instructions.
+    #[clap(long, conflicts_with_all = ["debug", "dap"])]
+    pub profile_opcode_pairs: bool,
+
+    /// Serve the Debug Adapter Protocol on stdin and stdout, for debugging from an editor. The
+    /// program to run is given by the editor's launch request.
+    #[clap(long, conflicts_with_all = ["test", "debug"])]
+    pub dap: bool,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Print the bytecode of the non-generic functions in a crate. The crate's IR is read from the
+    /// cache if it was saved there with `--save`.
+    Disasm {
+        /// A source file, or an internal crate like `@core`.
+        crate_path: OsString,
+
+        /// Only show functions whose path matches this pattern, where `*` matches anything.
+        #[clap(default_value = "*")]
+        pattern: String,
+    },
+}
+
+impl CliArgs {
+    /// The engine options selected by these arguments.
+    pub fn options(&self) -> Options {
+        Options {
+            verbose: self.verbose,
+            save: self.save,
+            no_warnings: self.no_warnings,
+            debug_profile: self.debug_profile,
+            link_libs: self.link_libs.clone(),
+            jit: self.jit,
+            bc_opt: !self.no_bc_opt,
+            compact: !self.no_compact,
+            debug_local_impls: self.debug_local_impls,
+            debug_trace_calls: self.debug_trace_calls,
+            stack_size: self.stack_size,
+            growable_stack: self.growable_stack,
+            debug: self.debug,
+            dump_bytecode: self.dump_bytecode.clone(),
+            profile_functions: self.profile_functions || self.profile_stacks.is_some(),
+            profile_opcodes: self.profile_opcodes || self.profile_opcode_pairs,
+            profile_opcode_pairs: self.profile_opcode_pairs,
+            dap: false,
+        }
+    }
+}
+
+/// Parses a size like `4096`, `64K` or `8M`.
+fn parse_size(arg: &str) -> Result<usize, String> {
+    let (digits, scale) = match arg.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&arg[..arg.len() - 1], 1 << 10),
+        Some('M') => (&arg[..arg.len() - 1], 1 << 20),
+        Some('G') => (&arg[..arg.len() - 1], 1 << 30),
+        _ => (arg, 1),
+    };
+
+    let size: usize = digits
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", arg))?;
+    if size == 0 {
+        return Err("size must be greater than zero".to_owned());
+    }
+    size.checked_mul(scale)
+        .ok_or_else(|| format!("size '{}' is too large", arg))
 }
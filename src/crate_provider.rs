@@ -10,6 +10,9 @@ pub trait CrateProvider<'vm>: Send + Sync + 'vm {
     /// Find an item by id. Generally used to retrieve local items.
     fn item_by_id(&self, id: ItemId) -> &'vm Item<'vm>;
 
+    /// Every item in the crate, in no particular order.
+    fn all_items(&self) -> Vec<&'vm Item<'vm>>;
+
     /// Find an item by path. Generally used for external items.
     fn item_by_path(&self, path: &ItemPath<'vm>) -> Option<&'vm Item<'vm>>;
 
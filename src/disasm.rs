@@ -0,0 +1,139 @@
+use std::fmt::Write;
+
+use crate::{
+    bytecode_compiler::{DebugLocal, FunctionBytecode},
+    vm::instr::{Instr, Slot},
+};
+
+/// Renders a function's bytecode for people to read. Each instruction is shown with its pc and,
+/// for jumps, the pc they go to. Slots which hold a named variable are annotated with its name
+/// and type. The function's locals and drop table follow the code.
+pub fn disassemble(name: &str, bc: &FunctionBytecode) -> String {
+    let mut out = String::new();
+    _ = writeln!(out, "fn {} (frame {} bytes)", name, bc.frame_size);
+
+    let mut line = 0;
+    for (pc, instr) in bc.code.instrs().iter().enumerate() {
+        let span = bc.spans.span_at(pc);
+        if !span.is_none() && span.line != line {
+            line = span.line;
+            _ = writeln!(out, "  -- {}:{}:{}", bc.spans.file, span.line, span.col);
+        }
+
+        let text = annotate_slots(&format!("{:?}", instr), |slot| {
+            describe_slot(bc, pc as u32, slot)
+        });
+        _ = write!(out, "  {:>5}: {}", pc, text);
+        if let Some(target) = jump_target(pc, instr) {
+            _ = write!(out, "  -> {}", target);
+        }
+        out.push('\n');
+    }
+
+    if !bc.locals.is_empty() {
+        _ = writeln!(out, "  locals:");
+        for local in &bc.locals {
+            _ = writeln!(
+                out,
+                "    [{}] {}: {}  pc {}..{}",
+                local.slot.index(),
+                local.name,
+                local.ty,
+                local.start,
+                local.end
+            );
+        }
+    }
+
+    if !bc.drops.is_empty() {
+        _ = writeln!(out, "  drops:");
+        for (index, (slot, glue)) in bc.drops.iter().enumerate() {
+            _ = writeln!(
+                out,
+                "    {:>3}: {} {}",
+                index,
+                describe_slot(bc, u32::MAX, *slot),
+                glue.function().debug_name()
+            );
+        }
+    }
+
+    out
+}
+
+/// Whether a path matches a pattern, where `*` matches any run of characters and `?` any one.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // the last `*` seen, and the text position it currently matches up to
+    let mut star = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn jump_target(pc: usize, instr: &Instr) -> Option<isize> {
+    instr
+        .jump_offset()
+        .map(|offset| pc as isize + offset as isize)
+}
+
+/// Replaces each `Slot(n)` in an instruction's debug output.
+fn annotate_slots(text: &str, describe: impl Fn(Slot) -> String) -> String {
+    const PREFIX: &str = "Slot(";
+
+    let mut res = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(PREFIX) {
+        let after = &rest[start + PREFIX.len()..];
+        let Some(end) = after.find(')') else {
+            break;
+        };
+        let Ok(index) = after[..end].parse::<u32>() else {
+            res.push_str(&rest[..start + PREFIX.len()]);
+            rest = after;
+            continue;
+        };
+        res.push_str(&rest[..start]);
+        res.push_str(&describe(Slot::new(index)));
+        rest = &after[end + 1..];
+    }
+    res.push_str(rest);
+    res
+}
+
+/// A slot's offset, with the variable it belongs to if any. `pc` selects the variables in scope,
+/// `u32::MAX` accepts any variable.
+fn describe_slot(bc: &FunctionBytecode, pc: u32, slot: Slot) -> String {
+    let index = slot.index();
+    let in_scope = |local: &&DebugLocal| pc == u32::MAX || (local.start <= pc && pc < local.end);
+
+    let local = bc.locals.iter().rev().filter(in_scope).find(|local| {
+        let start = local.slot.index();
+        let size = local.ty.layout().maybe_size.unwrap_or(0) as usize;
+        index == start || (start < index && index < start + size)
+    });
+
+    match local {
+        Some(local) if local.slot.index() == index => {
+            format!("[{} {}: {}]", index, local.name, local.ty)
+        }
+        Some(local) => format!("[{} {}+{}]", index, local.name, index - local.slot.index()),
+        None => format!("[{}]", index),
+    }
+}
@@ -0,0 +1,820 @@
+use std::{
+    ffi::{CString, OsStr, OsString},
+    fmt::{self, Write},
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    sync::Once,
+};
+
+use rustc_span::edition::Edition;
+
+use crate::{
+    bytecode_compiler::catch_compile_failure,
+    disasm::{disassemble, glob_matches},
+    items::{AssocValue, CrateId, ExternCrate, ItemPath},
+    manifest::read_manifest,
+    rustc_worker::RustCWorkerConfig,
+    types::{IntSign, ItemWithSubs, Sub, SubList, Type, TypeKind},
+    variants::VariantIndex,
+    vm::{Function, StackOverflow, VMPanic, VMThread, VM},
+    CratePath, FunctionAbi, NativeFunc,
+};
+
+/// Settings for an [`Engine`], shared by every crate it loads.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Print debug information.
+    pub verbose: bool,
+
+    /// Save the IR of each loaded crate to the cache, then exit the process.
+    pub save: bool,
+
+    /// Do not print compiler warnings for loaded crates.
+    pub no_warnings: bool,
+
+    /// Emulate rustc's debug profile: enable overflow checks and `cfg(debug_assertions)` for loaded crates.
+    pub debug_profile: bool,
+
+    /// Shared libraries to resolve extern functions from. A bare name like `z` loads `libz.so`.
+    pub link_libs: Vec<String>,
+
+    /// Compile bytecode to machine code.
+    pub jit: bool,
+
+    /// Optimize bytecode after compiling it. Ignored while debugging, so that variables can
+    /// always be inspected.
+    pub bc_opt: bool,
+
+    /// Run bytecode from its compact encoding. When disabled, the interpreter decodes it into
+    /// `Instr`s and runs those, like the debugger does.
+    pub compact: bool,
+
+    /// Lookup local inherent impls instead of using a fast path.
+    pub debug_local_impls: bool,
+
+    /// Log the arguments and return value of every function call.
+    pub debug_trace_calls: bool,
+
+    /// Size of each thread's interpreter stack, in bytes.
+    pub stack_size: usize,
+
+    /// Continue on a new stack segment when a thread's stack runs out, instead of failing.
+    pub growable_stack: bool,
+
+    /// Run a command-line debugger on stdin, which stops when `main` is entered.
+    /// JIT-compiled code can't be debugged.
+    pub debug: bool,
+
+    /// Record the calls and time spent in each function, which are reported by the functions
+    /// of [`profiler`](crate::profiler).
+    pub profile_functions: bool,
+
+    /// Count the instructions run by the interpreter, which are reported by
+    /// [`profiler::opcode_profile_log`](crate::profiler). Needs the `profile-opcodes` feature,
+    /// and takes priority over `debug`.
+    pub profile_opcodes: bool,
+
+    /// Also count pairs of consecutive instructions, when counting instructions.
+    pub profile_opcode_pairs: bool,
+
+    /// Print the bytecode of each function whose path matches this pattern as it is compiled.
+    /// `*` matches any run of characters, like `::vec::*`.
+    pub dump_bytecode: Option<String>,
+
+    /// Debug over the Debug Adapter Protocol instead of the command line. Set by
+    /// [`run_dap_server`](crate::run_dap_server), which speaks the protocol on stdin and stdout.
+    pub dap: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            verbose: false,
+            save: false,
+            no_warnings: false,
+            debug_profile: false,
+            link_libs: Vec::new(),
+            jit: false,
+            bc_opt: true,
+            compact: true,
+            debug_local_impls: false,
+            debug_trace_calls: false,
+            stack_size: DEFAULT_STACK_SIZE,
+            growable_stack: false,
+            debug: false,
+            dump_bytecode: None,
+            profile_functions: false,
+            profile_opcodes: false,
+            profile_opcode_pairs: false,
+            dap: false,
+        }
+    }
+}
+
+/// 1 MiB, smaller than the main thread of a compiled program.
+pub const DEFAULT_STACK_SIZE: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be loaded.
+    Load(String),
+    /// No function exists at the given path.
+    NotFound(String),
+    /// The host types do not match the function's signature.
+    Signature(String),
+    /// The interpreted code panicked.
+    Panic,
+    /// The interpreted code ran out of stack. Holds the message and a backtrace.
+    StackOverflow(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Load(msg) => write!(f, "failed to load: {}", msg),
+            Error::NotFound(path) => write!(f, "no function found at {}", path),
+            Error::Signature(msg) => write!(f, "signature mismatch: {}", msg),
+            Error::Panic => write!(f, "interpreted code panicked"),
+            Error::StackOverflow(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Runs Rust code from a host program.
+///
+/// Loaded crates are linked against core, alloc and std from the cache. Functions are
+/// looked up by their path within a loaded crate, like `math::add`.
+///
+/// The engine's memory is never freed, so it is best to create one per process.
+pub struct Engine {
+    vm: &'static VM<'static>,
+    crates: Vec<CrateId>,
+    /// std's thread info for the main thread can only be set once.
+    std_main_thread: Once,
+}
+
+impl Engine {
+    pub fn new(options: Options) -> Self {
+        let vm = VM::new_static(options);
+        // HACK: this must be initialized ASAP so common types have correct persist IDs
+        vm.common_types();
+
+        Self {
+            vm,
+            crates: Vec::new(),
+            std_main_thread: Once::new(),
+        }
+    }
+
+    pub(crate) fn vm(&self) -> &'static VM<'static> {
+        self.vm
+    }
+
+    /// Load a crate from a source file. Internal crates can be loaded by name, like `@core`.
+    pub fn load_file(&mut self, path: impl AsRef<OsStr>) -> Result<(), Error> {
+        let path = path.as_ref();
+
+        let is_internal = path.to_str().is_some_and(|path| path.starts_with('@'));
+        if !is_internal && !Path::new(path).is_file() {
+            return Err(Error::Load(format!(
+                "no such file: {}",
+                path.to_string_lossy()
+            )));
+        }
+
+        self.load(RustCWorkerConfig {
+            crate_path: CratePath::new(path),
+            extern_crates: vec![],
+            save_file: self.vm.options.save,
+            edition: Edition::Edition2021,
+            crate_cfg: vec![],
+            extern_metadata: vec![],
+            metadata_out: None,
+        })?;
+        Ok(())
+    }
+
+    /// Load a crate from a string of source code.
+    pub fn load_source(&mut self, source: &str) -> Result<(), Error> {
+        self.load(RustCWorkerConfig {
+            crate_path: CratePath::from_source("script", source.to_owned()),
+            extern_crates: vec![],
+            save_file: self.vm.options.save,
+            edition: Edition::Edition2021,
+            crate_cfg: vec![],
+            extern_metadata: vec![],
+            metadata_out: None,
+        })?;
+        Ok(())
+    }
+
+    /// Load a cargo package and its dependencies, which must already be on disk.
+    pub fn load_manifest(&mut self, manifest_path: impl AsRef<Path>) -> Result<(), Error> {
+        let manifest = read_manifest(manifest_path.as_ref()).map_err(Error::Load)?;
+
+        std::fs::create_dir_all(&manifest.out_dir)
+            .map_err(|err| Error::Load(format!("failed to create output directory: {}", err)))?;
+
+        let crate_count = manifest.crates.len();
+        let mut loaded: Vec<(ExternCrate, PathBuf)> = Vec::new();
+
+        for (i, krate) in manifest.crates.into_iter().enumerate() {
+            let mut extern_crates = Vec::new();
+            let mut extern_metadata = Vec::new();
+            for (dep_name, dep_index) in krate.deps {
+                let (dep_crate, dep_metadata) = &loaded[dep_index];
+                // items are mapped to crates by their real name, rustc imports them by the dependency name
+                extern_crates.push(ExternCrate {
+                    name: dep_crate.name.clone(),
+                    id: dep_crate.id,
+                });
+                extern_metadata.push((dep_name, dep_metadata.clone()));
+            }
+
+            // only dependencies need metadata, for type-checking the crates which use them
+            let metadata_path = manifest
+                .out_dir
+                .join(format!("lib{}-{}.rmeta", krate.name, i));
+            let metadata_out = if i + 1 < crate_count {
+                Some(metadata_path.clone())
+            } else {
+                None
+            };
+
+            let id = self.load(RustCWorkerConfig {
+                crate_path: CratePath::with_name(&krate.src_path, &krate.name),
+                extern_crates,
+                save_file: false,
+                edition: krate.edition,
+                crate_cfg: krate.crate_cfg,
+                extern_metadata,
+                metadata_out,
+            })?;
+
+            let extern_crate = ExternCrate {
+                name: krate.name,
+                id,
+            };
+            loaded.push((extern_crate, metadata_path));
+        }
+
+        Ok(())
+    }
+
+    /// Add a crate, linked against core, alloc and std as appropriate.
+    fn load(&mut self, mut config: RustCWorkerConfig) -> Result<CrateId, Error> {
+        let vm = self.vm;
+        let crate_path = &config.crate_path;
+
+        // internal crates are shared with the crates linked against them, unless being saved
+        if crate_path.is_internal() && !config.save_file {
+            let crate_id = get_lib(vm, &crate_path.name).id;
+            self.crates.push(crate_id);
+            return Ok(crate_id);
+        }
+
+        let no_core = crate_path.is_core();
+        let no_alloc = crate_path.is_alloc() || crate_path.is_core();
+        let no_std = crate_path.is_internal();
+
+        let mut extern_crates = Vec::new();
+        if !no_core {
+            extern_crates.push(get_lib(vm, "core"));
+        }
+        if !no_alloc {
+            extern_crates.push(get_lib(vm, "alloc"));
+        }
+        if !no_std {
+            extern_crates.push(get_lib(vm, "std"));
+        }
+        extern_crates.append(&mut config.extern_crates);
+        config.extern_crates = extern_crates;
+
+        let crate_id = vm.add_provider_auto(config).map_err(Error::Load)?;
+
+        self.crates.push(crate_id);
+        Ok(crate_id)
+    }
+
+    /// Compiles every function in the loaded crates whose path matches `pattern`, and renders its
+    /// bytecode. Generic functions are skipped, since they can only be compiled for some given
+    /// type arguments. A function which fails to compile is reported in place of its bytecode.
+    pub fn disassemble(&self, pattern: &str) -> String {
+        let mut items: Vec<_> = self
+            .crates
+            .iter()
+            .flat_map(|id| self.vm.crate_provider(*id).all_items())
+            .filter(|item| item.is_function() && glob_matches(pattern, item.path.as_string()))
+            .collect();
+        items.sort_by_key(|item| item.path.as_string());
+
+        let mut out = String::new();
+        for item in items {
+            let res = catch_compile_failure(|| {
+                if !item.is_mono_function() {
+                    return None;
+                }
+                let func = item.func_mono(&SubList::empty());
+                Some(disassemble(&func.debug_name(), func.bytecode()))
+            });
+            match res {
+                Ok(Some(text)) => out.push_str(&text),
+                Ok(None) => continue,
+                Err(err) => {
+                    let msg = err
+                        .downcast_ref::<String>()
+                        .map(String::as_str)
+                        .or_else(|| err.downcast_ref::<&str>().copied())
+                        .unwrap_or("unknown error");
+                    _ = writeln!(
+                        out,
+                        "fn {} failed to compile: {}",
+                        item.path.as_string(),
+                        msg
+                    );
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Provide a native function for extern items with the given ABI and symbol name.
+    /// Must be registered before the items are first used.
+    pub fn register_extern(&self, abi: FunctionAbi, symbol: &str, native: NativeFunc) {
+        self.vm.register_extern(abi, symbol, native);
+    }
+
+    /// Provide a static for extern items with the given ABI and symbol name.
+    pub fn register_extern_static(&self, abi: FunctionAbi, symbol: &str, ptr: *const u8) {
+        self.vm.register_extern_static(abi, symbol, ptr);
+    }
+
+    /// Call a function which takes no generic parameters. Arguments and the return
+    /// value are copied according to the interpreted types' layouts.
+    pub fn call<A: Args, R: Value>(&self, path: &str, args: A) -> Result<R, Error> {
+        let (func, inputs, output) = self.function(path)?;
+
+        let args = args.raw_values();
+        if args.len() != inputs.len() {
+            return Err(Error::Signature(format!(
+                "{} takes {} arguments, {} were provided",
+                path,
+                inputs.len(),
+                args.len()
+            )));
+        }
+        for (i, (arg, ty)) in args.iter().zip(&inputs).enumerate() {
+            if !value_matches(arg.kind, arg.layout, *ty) {
+                return Err(Error::Signature(format!(
+                    "argument {} of {} has type {}",
+                    i, path, ty
+                )));
+            }
+        }
+        if !value_matches(R::KIND, std::alloc::Layout::new::<R>(), output) {
+            return Err(Error::Signature(format!(
+                "{} returns type {}",
+                path, output
+            )));
+        }
+
+        let mut thread = self.vm.make_thread();
+        let frame = thread.root_frame();
+
+        // the return value is followed by each argument, aligned to its type
+        let mut offset = output.layout().assert_size() as usize;
+        for (arg, ty) in args.iter().zip(&inputs) {
+            let layout = ty.layout();
+            offset = crate::abi::align(offset as u32, layout.align) as usize;
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    arg.bytes.as_ptr(),
+                    frame.add(offset),
+                    arg.bytes.len(),
+                );
+            }
+            offset += arg.bytes.len();
+        }
+
+        call_catch(&mut thread, func, frame)?;
+
+        Ok(unsafe { (frame as *const R).read() })
+    }
+
+    /// Run the `main` function of the last loaded crate which has one. `program_args` become
+    /// the arguments seen by `std::env::args`, starting with the program name.
+    ///
+    /// Returns the exit code reported by main's return type, through the `Termination` trait.
+    pub fn run_main(&self, program_args: &[OsString]) -> Result<i32, Error> {
+        let main_path = ItemPath::main();
+
+        let main_item = self
+            .crates
+            .iter()
+            .rev()
+            .find_map(|id| self.vm.crate_provider(*id).item_by_path(&main_path))
+            .ok_or_else(|| Error::NotFound("main".to_owned()))?;
+
+        let subs = SubList { list: Vec::new() };
+        let main_fn = main_item.func_mono(&subs);
+        let output = main_item.func_sig(&subs).output;
+
+        let mut thread = self.vm.make_thread();
+
+        if self.vm.std_crate.get().is_some() {
+            init_std_args(self.vm, &mut thread, program_args);
+            self.std_main_thread
+                .call_once(|| init_std_main_thread(self.vm, &mut thread));
+        }
+
+        let frame = thread.root_frame();
+        call_catch(&mut thread, main_fn, frame)?;
+
+        self.report(&mut thread, output, frame)
+    }
+
+    /// Call `Termination::report` on the value main returned, which is at the start of `frame`.
+    fn report(
+        &self,
+        thread: &mut VMThread<'static>,
+        output: Type<'static>,
+        frame: *mut u8,
+    ) -> Result<i32, Error> {
+        let returns_unit = matches!(output.kind(), TypeKind::Tuple(fields) if fields.is_empty());
+        let Some(std_id) = self.vm.std_crate.get() else {
+            return Ok(0);
+        };
+        if returns_unit {
+            return Ok(0);
+        }
+
+        let report_item = self
+            .vm
+            .crate_provider(*std_id)
+            .item_by_path(&ItemPath::for_value("::process::Termination::report"))
+            .expect("missing Termination::report");
+        let report_subs = SubList {
+            list: vec![Sub::Type(output)],
+        };
+        let report_fn = report_item.func_mono(&report_subs);
+        let exit_code_ty = report_item.func_sig(&report_subs).output;
+        assert!(exit_code_ty.layout().assert_size() == 1);
+
+        let output_layout = output.layout();
+        let arg_offset = crate::abi::align(1, output_layout.align) as usize;
+        unsafe {
+            std::ptr::copy(
+                frame,
+                frame.add(arg_offset),
+                output_layout.assert_size() as usize,
+            );
+        }
+
+        call_catch(thread, report_fn, frame)?;
+
+        // `ExitCode` wraps the status byte on unix
+        let exit_code = unsafe { *frame };
+        Ok(exit_code as i32)
+    }
+
+    fn function(
+        &self,
+        path: &str,
+    ) -> Result<
+        (
+            &'static Function<'static>,
+            Vec<Type<'static>>,
+            Type<'static>,
+        ),
+        Error,
+    > {
+        let item_path = ItemPath::for_value(self.vm.alloc_path(&format!("::{}", path)));
+
+        let item = self
+            .crates
+            .iter()
+            .find_map(|id| self.vm.crate_provider(*id).item_by_path(&item_path))
+            .filter(|item| item.is_function())
+            .ok_or_else(|| Error::NotFound(path.to_owned()))?;
+
+        let subs = SubList { list: Vec::new() };
+        let sig = item.func_sig(&subs);
+
+        Ok((item.func_mono(&subs), sig.inputs, sig.output))
+    }
+}
+
+/// Run a function, turning a panic of the interpreted code which unwinds out of it into an error.
+/// Host panics, like a bug in a builtin, keep unwinding.
+fn call_catch<'vm>(
+    thread: &mut VMThread<'vm>,
+    func: &'vm Function<'vm>,
+    frame: *mut u8,
+) -> Result<(), Error> {
+    let call_depth = thread.call_stack().depth();
+    std::panic::catch_unwind(AssertUnwindSafe(|| thread.call(func, frame))).map_err(|payload| {
+        thread.call_stack().truncate(call_depth);
+        if payload.is::<VMPanic>() {
+            return Error::Panic;
+        }
+        match payload.downcast::<StackOverflow>() {
+            Ok(overflow) => Error::StackOverflow(overflow.0),
+            Err(payload) => std::panic::resume_unwind(payload),
+        }
+    })
+}
+
+/// The kinds of host values which can cross into interpreted code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Int,
+    Uint,
+    Float,
+    Bool,
+    Char,
+    Unit,
+    Pointer,
+}
+
+/// A host type which can be passed to or returned from an interpreted function.
+///
+/// # Safety
+///
+/// Values are copied bytewise, `KIND` must describe the type's representation.
+pub unsafe trait Value: Copy {
+    const KIND: ValueKind;
+}
+
+macro_rules! impl_value {
+    ($kind:ident: $($ty:ty),*) => {
+        $(unsafe impl Value for $ty {
+            const KIND: ValueKind = ValueKind::$kind;
+        })*
+    };
+}
+
+impl_value!(Int: i8, i16, i32, i64, i128, isize);
+impl_value!(Uint: u8, u16, u32, u64, u128, usize);
+impl_value!(Float: f32, f64);
+impl_value!(Bool: bool);
+impl_value!(Char: char);
+impl_value!(Unit: ());
+
+unsafe impl<T> Value for *const T {
+    const KIND: ValueKind = ValueKind::Pointer;
+}
+
+unsafe impl<T> Value for *mut T {
+    const KIND: ValueKind = ValueKind::Pointer;
+}
+
+fn value_matches(kind: ValueKind, host_layout: std::alloc::Layout, ty: Type) -> bool {
+    let ty_kind = match ty.kind() {
+        TypeKind::Int(_, IntSign::Signed) => ValueKind::Int,
+        TypeKind::Int(_, IntSign::Unsigned) => ValueKind::Uint,
+        TypeKind::Float(_) => ValueKind::Float,
+        TypeKind::Bool => ValueKind::Bool,
+        TypeKind::Char => ValueKind::Char,
+        TypeKind::Tuple(fields) if fields.is_empty() => ValueKind::Unit,
+        TypeKind::Ptr(..) => ValueKind::Pointer,
+        _ => return false,
+    };
+
+    let layout = ty.layout();
+    ty_kind == kind
+        && layout.maybe_size == Some(host_layout.size() as u32)
+        && layout.align == host_layout.align() as u32
+}
+
+/// A value passed as an argument, with its representation erased.
+#[doc(hidden)]
+pub struct RawValue<'a> {
+    kind: ValueKind,
+    layout: std::alloc::Layout,
+    bytes: &'a [u8],
+}
+
+impl<'a> RawValue<'a> {
+    fn new<T: Value>(value: &'a T) -> Self {
+        let layout = std::alloc::Layout::new::<T>();
+        let bytes =
+            unsafe { std::slice::from_raw_parts(value as *const T as *const u8, layout.size()) };
+
+        Self {
+            kind: T::KIND,
+            layout,
+            bytes,
+        }
+    }
+}
+
+/// Tuples of [`Value`]s, used as the arguments of [`Engine::call`].
+pub trait Args {
+    #[doc(hidden)]
+    fn raw_values(&self) -> Vec<RawValue>;
+}
+
+macro_rules! impl_args {
+    ($($name:ident),*) => {
+        impl<$($name: Value),*> Args for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn raw_values(&self) -> Vec<RawValue> {
+                let ($($name,)*) = self;
+                vec![$(RawValue::new($name)),*]
+            }
+        }
+    };
+}
+
+impl_args!();
+impl_args!(A);
+impl_args!(A, B);
+impl_args!(A, B, C);
+impl_args!(A, B, C, D);
+impl_args!(A, B, C, D, E);
+impl_args!(A, B, C, D, E, F);
+
+fn get_lib(vm: &'static VM, name: &str) -> ExternCrate {
+    let id = match name {
+        "core" => {
+            if let Some(id) = vm.core_crate.get() {
+                *id
+            } else {
+                let crate_path = CratePath::new(OsStr::new("@core"));
+
+                let id = vm
+                    .add_provider_auto(RustCWorkerConfig {
+                        crate_path,
+                        extern_crates: vec![],
+                        save_file: false,
+                        edition: Edition::Edition2021,
+                        crate_cfg: vec![],
+                        extern_metadata: vec![],
+                        metadata_out: None,
+                    })
+                    .expect("failed to load internal crate");
+
+                vm.core_crate.set(id).unwrap();
+
+                id
+            }
+        }
+        "alloc" => {
+            if let Some(id) = vm.alloc_crate.get() {
+                *id
+            } else {
+                let crate_path = CratePath::new(OsStr::new("@alloc"));
+
+                let id = vm
+                    .add_provider_auto(RustCWorkerConfig {
+                        crate_path,
+                        extern_crates: vec![get_lib(vm, "core")],
+                        save_file: false,
+                        edition: Edition::Edition2021,
+                        crate_cfg: vec![],
+                        extern_metadata: vec![],
+                        metadata_out: None,
+                    })
+                    .expect("failed to load internal crate");
+
+                vm.alloc_crate.set(id).unwrap();
+
+                id
+            }
+        }
+        "std" => {
+            if let Some(id) = vm.std_crate.get() {
+                *id
+            } else {
+                let crate_path = CratePath::new(OsStr::new("@std"));
+
+                let id = vm
+                    .add_provider_auto(RustCWorkerConfig {
+                        crate_path,
+                        extern_crates: vec![get_lib(vm, "core"), get_lib(vm, "alloc")],
+                        save_file: false,
+                        edition: Edition::Edition2021,
+                        crate_cfg: vec![],
+                        extern_metadata: vec![],
+                        metadata_out: None,
+                    })
+                    .expect("failed to load internal crate");
+
+                vm.std_crate.set(id).unwrap();
+
+                id
+            }
+        }
+        _ => panic!("lib = {}", name),
+    };
+
+    ExternCrate {
+        id,
+        name: name.to_owned(),
+    }
+}
+
+/// std normally receives argc and argv through `.init_array`, which never runs for interpreted programs.
+fn init_std_args(vm: &'static VM, thread: &mut VMThread<'static>, program_args: &[OsString]) {
+    use std::os::unix::ffi::OsStrExt;
+
+    let std_id = *vm.std_crate.get().expect("no std crate");
+
+    let init_item = vm
+        .crate_provider(std_id)
+        .item_by_path(&ItemPath::for_value("::sys::unix::args::imp::really_init"))
+        .expect("missing std args init");
+
+    // the strings live as long as the program
+    let mut argv: Vec<*const u8> = program_args
+        .iter()
+        .map(|arg| {
+            let arg = CString::new(arg.as_bytes()).expect("argument contains nul");
+            arg.into_raw() as *const u8
+        })
+        .collect();
+    let argc = argv.len() as isize;
+    argv.push(std::ptr::null());
+    let argv = argv.leak().as_ptr();
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct InitArgs {
+        argc: isize,
+        argv: *const *const u8,
+    }
+
+    let init_fn = init_item.func_mono(&SubList { list: Vec::new() });
+    thread.call_root_with_args(init_fn, InitArgs { argc, argv });
+}
+
+/// Name the current thread `main`, like std's runtime does before calling main. Otherwise std
+/// creates an unnamed `Thread` the first time the program asks for the current one.
+fn init_std_main_thread(vm: &'static VM, thread: &mut VMThread<'static>) {
+    let std_id = *vm.std_crate.get().expect("no std crate");
+    let std_items = vm.crate_provider(std_id);
+    let subs = SubList { list: Vec::new() };
+
+    let thread_ty = vm.ty_adt(ItemWithSubs {
+        item: std_items
+            .item_by_path(&ItemPath::for_type("::thread::Thread"))
+            .expect("missing Thread"),
+        subs: subs.clone(),
+    });
+    let new_fn = {
+        let key = format!(
+            "{}::new",
+            thread_ty.impl_key().expect("no impl key for Thread")
+        );
+        let Some(AssocValue::Item(new_id)) = std_items.inherent_impl(&key, thread_ty) else {
+            panic!("missing Thread::new");
+        };
+        std_items.item_by_id(new_id)
+    };
+    let set_item = std_items
+        .item_by_path(&ItemPath::for_value("::sys_common::thread_info::set"))
+        .expect("missing std thread info");
+
+    // the CString owns its bytes, with a trailing nul
+    let name = b"main\0";
+    let name_ptr = unsafe {
+        let ptr = vm.alloc_bytes(name.len(), 1);
+        std::ptr::copy_nonoverlapping(name.as_ptr(), ptr, name.len());
+        ptr
+    };
+    let some = VariantIndex::new(1);
+
+    // new(name: Option<CString>) -> Thread
+    let new_sig = new_fn.func_sig(&subs);
+    let name_ty = new_sig.inputs[0];
+    unsafe {
+        let name_arg = thread.stack_ptr().add(new_sig.input_offsets()[0] as usize);
+        name_ty.write_discriminant(some, name_arg);
+        let (cstring_offset, _) = name_ty.field(some, 0);
+        let bytes = std::ptr::slice_from_raw_parts(name_ptr, name.len());
+        std::ptr::write(
+            name_arg.add(cstring_offset as usize) as *mut *const [u8],
+            bytes,
+        );
+    }
+    thread.call_root(new_fn.func_mono(&subs));
+    let main_thread = thread.copy_result(0, thread_ty.layout().assert_size() as usize);
+
+    // set(stack_guard: Option<Guard>, thread: Thread)
+    let set_sig = set_item.func_sig(&subs);
+    let input_offsets = set_sig.input_offsets();
+    unsafe {
+        let frame = thread.stack_ptr();
+        let none = VariantIndex::new(0);
+        set_sig.inputs[0].write_discriminant(none, frame.add(input_offsets[0] as usize));
+        std::ptr::copy_nonoverlapping(
+            main_thread.as_ptr(),
+            frame.add(input_offsets[1] as usize),
+            main_thread.len(),
+        );
+    }
+    thread.call_root(set_item.func_mono(&subs));
+}
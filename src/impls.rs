@@ -67,7 +67,7 @@ fn get_internal_crates(vm: &VM) -> [Option<CrateId>; 3] {
     [
         vm.core_crate.get().copied(),
         vm.alloc_crate.get().copied(),
-        None, // TODO STD
+        vm.std_crate.get().copied(),
     ]
 }
 
@@ -83,12 +83,19 @@ fn find_source_crate(ty: Type) -> ImplLocation {
 
         TypeKind::Dynamic { primary_trait, .. } => {
             let primary_trait = primary_trait.as_ref().expect("no primary trait, fallback to core?");
-            ImplLocation::Crate(primary_trait.item.crate_id)
+            let crate_id = primary_trait.item.crate_id;
+            // internal crates can add incoherent impls to each other's trait objects (`impl dyn Error`)
+            if get_internal_crates(ty.vm()).contains(&Some(crate_id)) {
+                ImplLocation::Internal
+            } else {
+                ImplLocation::Crate(crate_id)
+            }
         }
 
         TypeKind::Ref(child, _) => find_source_crate(*child),
 
         TypeKind::Bool
+        | TypeKind::Never
         | TypeKind::Char
         | TypeKind::Ptr(..) // TODO is this correct?
         | TypeKind::Closure(..) // TODO is this correct?
@@ -548,11 +555,13 @@ impl<'vm> ImplBounds<'vm> {
             (
                 TypeKind::Dynamic {
                     primary_trait: lhs_trait,
+                    projections: lhs_projections,
                     auto_traits: lhs_auto,
                     is_dyn_star: lhs_star,
                 },
                 TypeKind::Dynamic {
                     primary_trait: rhs_trait,
+                    projections: rhs_projections,
                     auto_traits: rhs_auto,
                     is_dyn_star: rhs_star,
                 },
@@ -566,6 +575,18 @@ impl<'vm> ImplBounds<'vm> {
                 if lhs_trait.is_some() != rhs_trait.is_some() {
                     return false;
                 }
+                if lhs_projections.len() != rhs_projections.len() {
+                    return false;
+                }
+                for ((lhs_assoc, lhs_ty), (rhs_assoc, rhs_ty)) in
+                    lhs_projections.iter().zip(rhs_projections)
+                {
+                    if lhs_assoc.item != rhs_assoc.item
+                        || !Self::match_types(*lhs_ty, *rhs_ty, res_map)
+                    {
+                        return false;
+                    }
+                }
                 if let (Some(lhs), Some(rhs)) = (lhs_trait, rhs_trait) {
                     (lhs.item == rhs.item) && Self::match_subs(&lhs.subs, &rhs.subs, res_map)
                 } else {
@@ -29,10 +29,11 @@ impl<'vm> IRFunction<'vm> {
             | ExprKind::Field { lhs: child, .. }
             | ExprKind::Index { lhs: child, .. } => self.is_const_alloc(child),
 
-            ExprKind::VarRef(..) | ExprKind::UpVar(_) => false,
+            ExprKind::VarRef(..) | ExprKind::UpVar(_) | ExprKind::Static(_) => false,
 
             ExprKind::Cast(_) => false,
             ExprKind::Call { .. } => false,
+            ExprKind::Block(_) | ExprKind::DropTemps(_) => false,
 
             _ => panic!("is_const_alloc {:?} / {}", expr.kind, expr.ty),
         }
@@ -47,7 +48,8 @@ impl<'vm> IRFunction<'vm> {
             ExprKind::LiteralValue(_) => ConstStatus::CanPromote,
             ExprKind::LiteralVoid => ConstStatus::Not, // ???
 
-            ExprKind::LiteralBytes(_) => ConstStatus::Explicit,
+            // the value is a pointer to constant data, which can be copied into a promoted const
+            ExprKind::LiteralBytes(_) => ConstStatus::CanPromote,
 
             ExprKind::Ref(child, mutability) => {
                 if mutability == Mutability::Const && self.const_status(child).is_const() {
@@ -81,7 +83,10 @@ impl<'vm> IRFunction<'vm> {
                 }
             }
 
-            ExprKind::Field { lhs: child, .. } | ExprKind::Cast(child) | ExprKind::DeRef(child) => {
+            ExprKind::Field { lhs: child, .. }
+            | ExprKind::Cast(child)
+            | ExprKind::PointerCast(child, _)
+            | ExprKind::DeRef(child) => {
                 if self.const_status(child).is_const() {
                     ConstStatus::CanPromote
                 } else {
@@ -104,9 +109,8 @@ impl<'vm> IRFunction<'vm> {
                     self.const_status(lhs).is_const() && self.const_status(rhs).is_const();
 
                 if args_const {
-                    // i have no recollection of what this means
-                    // arg types should always be primitives in our IR -- otherwise we would have a function?
-                    panic!("todo arg types must be primitive")
+                    // arg types should always be primitives in our IR -- otherwise we would have a function
+                    ConstStatus::CanPromote
                 } else {
                     ConstStatus::Not
                 }
@@ -114,6 +118,11 @@ impl<'vm> IRFunction<'vm> {
 
             ExprKind::Call { .. } => ConstStatus::Not,
 
+            // control flow is never promoted
+            ExprKind::If { .. } | ExprKind::Match { .. } | ExprKind::Loop(..) => ConstStatus::Not,
+
+            ExprKind::DropTemps(child) => self.const_status(child),
+
             ExprKind::Block(ref block) => {
                 if let Some(res) = block.result {
                     if self.const_status(res).is_const() {
@@ -1,6 +1,9 @@
 use rustc_hir as hir;
 use rustc_hir::def_id::LocalDefId;
+use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
 use rustc_middle::ty::TypeckResults;
+use rustc_span::symbol::sym;
+use rustc_span::BytePos;
 
 use std::{str::FromStr, sync::Arc};
 
@@ -14,7 +17,7 @@ use crate::{
 use super::{
     BinaryOp, BindingMode, Block, Expr, ExprId, ExprKind, FieldPattern, IRFunction,
     IRFunctionBuilder, IRKind, LogicOp, LoopId, MatchArm, Pattern, PatternId, PatternKind,
-    PointerCast, Stmt, UnaryOp, UpVar,
+    PointerCast, Span, Stmt, UnaryOp, UpVar,
 };
 
 /// Converts rust IR to skitter IR.
@@ -23,6 +26,8 @@ use super::{
 pub struct IRFunctionConverter<'vm, 'tcx, 'a> {
     ctx: &'a RustCContext<'vm, 'tcx>,
     func_id: LocalDefId,
+    /// Start of the file the function's body is in. Spans into other files are dropped.
+    file_start: BytePos,
     types: &'tcx TypeckResults<'tcx>,
     loops: Vec<(hir::HirId, LoopId)>,
     builder: IRFunctionBuilder<'vm>,
@@ -42,6 +47,8 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
         let opaque_types = types
             .concrete_opaque_types
             .iter()
+            // type alias impl traits are revealed when converting types
+            .filter(|(key, _)| !ctx.tcx.is_type_alias_impl_trait(key.def_id))
             .map(|(key, val)| {
                 let (item, full_path) =
                     ctx.vm
@@ -58,9 +65,14 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
             })
             .collect();
 
+        let source_map = ctx.tcx.sess.source_map();
+        let body_span = outermost_span(body.value.span);
+        let source_file = source_map.lookup_source_file(body_span.lo());
+
         let mut converter = Self {
             ctx,
             func_id,
+            file_start: source_file.start_pos,
             types,
             loops: vec![],
             builder: Default::default(),
@@ -74,9 +86,39 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
 
         let root_expr = converter.expr(body.value);
 
-        converter
+        let mut ir = converter
             .builder
-            .finish(root_expr, ir_kind, params, opaque_types)
+            .finish(root_expr, ir_kind, params, opaque_types);
+
+        let file_name = source_file
+            .name
+            .prefer_remapped_unconditionaly()
+            .to_string_lossy();
+        ir.source_file = ctx.vm.alloc_path(&file_name);
+
+        // closures have attributes of their own, const blocks don't
+        let owner = ctx.tcx.hir().body_owner_def_id(body.id());
+        ir.track_caller = ir_kind == IRKind::Function
+            && ctx.tcx.def_kind(owner).has_codegen_attrs()
+            && ctx
+                .tcx
+                .codegen_fn_attrs(owner)
+                .flags
+                .contains(CodegenFnAttrFlags::TRACK_CALLER);
+
+        ir
+    }
+
+    fn span(&self, span: rustc_span::Span) -> Span {
+        let span = outermost_span(span);
+        let loc = self.ctx.tcx.sess.source_map().lookup_char_pos(span.lo());
+        if loc.file.start_pos != self.file_start {
+            return Span::NONE;
+        }
+        Span {
+            line: loc.line as u32,
+            col: loc.col_display as u32 + 1,
+        }
     }
 
     /// Convert binary op from rust IR. Does not handle logical ops.
@@ -108,6 +150,11 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
     fn expr(&mut self, expr: &hir::Expr) -> ExprId {
         let rs_ty = self.types.expr_ty(expr);
         let ty = self.ctx.type_from_rustc(rs_ty);
+        // like rustc, method calls are located at the method name rather than the receiver
+        let span = match expr.kind {
+            hir::ExprKind::MethodCall(segment, ..) => self.span(segment.ident.span),
+            _ => self.span(expr.span),
+        };
 
         let expr_kind = match expr.kind {
             hir::ExprKind::Lit(_) => self.expr_literal(expr, ty, false),
@@ -119,6 +166,16 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                 };
                 ExprKind::Ref(self.expr(arg), mutability)
             }
+            // fold negative literals like rustc does, so `-128i8` does not trip overflow checks
+            hir::ExprKind::Unary(
+                hir::UnOp::Neg,
+                hir::Expr {
+                    kind: hir::ExprKind::Lit(_),
+                    ..
+                },
+            ) if self.types.type_dependent_def_id(expr.hir_id).is_none() => {
+                self.expr_literal(expr, ty, false)
+            }
             hir::ExprKind::Unary(op, arg) => {
                 let arg = self.expr(arg);
 
@@ -130,10 +187,11 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                     let func = self.builder.add_expr(Expr {
                         kind: ExprKind::LiteralVoid,
                         ty: func_ty,
+                        span,
                     });
 
                     if let hir::UnOp::Deref = op {
-                        self.expr_deref_overload(func, vec![arg], ty)
+                        self.expr_deref_overload(func, vec![arg], ty, span)
                     } else {
                         ExprKind::Call {
                             func,
@@ -169,6 +227,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                             let func = self.builder.add_expr(Expr {
                                 kind: ExprKind::LiteralVoid,
                                 ty: func_ty,
+                                span,
                             });
 
                             ExprKind::Call {
@@ -195,6 +254,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                     let func = self.builder.add_expr(Expr {
                         kind: ExprKind::LiteralVoid,
                         ty: func_ty,
+                        span,
                     });
 
                     ExprKind::Call {
@@ -212,7 +272,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
 
                 ExprKind::Assign(lhs, rhs)
             }
-            hir::ExprKind::Index(lhs, index, _) => {
+            hir::ExprKind::Index(lhs, index, brackets_span) => {
                 let lhs = self.expr(lhs);
                 let index = self.expr(index);
 
@@ -221,12 +281,15 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                     let func_item = self.ctx.vm.types.def_from_rustc(func_did, subs, &self.ctx);
                     let func_ty = self.ctx.vm.ty_func_def(func_item);
 
+                    // like rustc, calls to `Index::index` are located at the brackets
+                    let call_span = self.span(brackets_span);
                     let func = self.builder.add_expr(Expr {
                         kind: ExprKind::LiteralVoid,
                         ty: func_ty,
+                        span: call_span,
                     });
 
-                    self.expr_deref_overload(func, vec![lhs, index], ty)
+                    self.expr_deref_overload(func, vec![lhs, index], ty, call_span)
                 } else {
                     ExprKind::Index { lhs, index }
                 }
@@ -254,11 +317,13 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                     let arg_tup = self.builder.add_expr(Expr {
                         kind: ExprKind::Tuple(args),
                         ty: arg_tup_ty,
+                        span,
                     });
 
                     let func = self.builder.add_expr(Expr {
                         kind: ExprKind::LiteralVoid,
                         ty: func_ty,
+                        span,
                     });
 
                     ExprKind::Call {
@@ -292,6 +357,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                 let func = self.builder.add_expr(Expr {
                     kind: ExprKind::LiteralVoid,
                     ty: method_ty,
+                    span,
                 });
 
                 ExprKind::Call {
@@ -484,33 +550,35 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
 
                 ExprKind::ConstBlock(Arc::new(const_body))
             }
-            hir::ExprKind::DropTemps(e) => {
-                // TODO TODO TODO
-                // this may pose an issue when implementing destructors / drop
-                return self.expr(e);
-            }
+            hir::ExprKind::DropTemps(e) => ExprKind::DropTemps(self.expr(e)),
             hir::ExprKind::Closure(closure) => {
                 let body = self.ctx.tcx.hir().body(closure.body);
                 let types = self.ctx.tcx.typeck_body(closure.body);
 
+                let closure_def_id = closure.def_id;
                 let captures: Vec<_> = self
                     .types
-                    .closure_min_captures_flattened(closure.def_id)
+                    .closure_min_captures_flattened(closure_def_id)
                     .collect();
 
                 let capture_exprs: Vec<_> = captures
                     .iter()
-                    .map(|capture| self.expr_capture(capture))
+                    .map(|capture| self.expr_capture(capture, span))
                     .collect();
 
                 let closure = self
                     .ctx
                     .vm
                     .types
-                    .closure_from_rustc(closure.def_id.into(), self.ctx);
+                    .closure_from_rustc(closure_def_id.into(), self.ctx);
 
                 let mut ir =
                     IRFunctionConverter::run(self.ctx, self.func_id, body, types, IRKind::Function);
+                // closures have their own attributes, like the ones summing integers in core
+                ir.inherit_overflow_checks = self
+                    .ctx
+                    .tcx
+                    .has_attr(closure_def_id, sym::rustc_inherit_overflow_checks);
                 replace_captures(&mut ir, &captures);
 
                 let abstract_sig = self.ctx.vm.types.closure_sig_from_rustc(rs_ty, self.ctx);
@@ -547,6 +615,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
         let mut expr_id = self.builder.add_expr(Expr {
             kind: expr_kind,
             ty,
+            span,
         });
 
         // Keep track of the input types to adjustments so deref methods can be built easily.
@@ -563,6 +632,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                     expr_id = self.builder.add_expr(Expr {
                         kind: ExprKind::Dummy(expr_id),
                         ty: adjust_ty,
+                        span,
                     });
                 }
                 Adjust::Deref(overloaded) => {
@@ -573,6 +643,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                         let func = self.builder.add_expr(Expr {
                             kind: ExprKind::LiteralVoid,
                             ty: func_ty,
+                            span,
                         });
 
                         // for whatever reason, the rustc adjustments contain an extra deref
@@ -582,17 +653,20 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                         let arg = self.builder.add_expr(Expr {
                             kind: ExprKind::Ref(expr_id, Mutability::Const),
                             ty: adjust_ty_in.ref_to(Mutability::Const),
+                            span,
                         });
 
-                        let kind = self.expr_deref_overload(func, vec![arg], adjust_ty);
+                        let kind = self.expr_deref_overload(func, vec![arg], adjust_ty, span);
                         expr_id = self.builder.add_expr(Expr {
                             kind,
                             ty: adjust_ty,
+                            span,
                         });
                     } else {
                         expr_id = self.builder.add_expr(Expr {
                             kind: ExprKind::DeRef(expr_id),
                             ty: adjust_ty,
+                            span,
                         });
                     }
                 }
@@ -600,6 +674,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                     expr_id = self.builder.add_expr(Expr {
                         kind: ExprKind::Ref(expr_id, Mutability::Const),
                         ty: adjust_ty,
+                        span,
                     });
                 }
                 Adjust::Pointer(ptr_cast) => {
@@ -616,6 +691,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                     expr_id = self.builder.add_expr(Expr {
                         kind: ExprKind::PointerCast(expr_id, ptr_cast),
                         ty: adjust_ty,
+                        span,
                     });
                 }
                 _ => panic!("todo adjust {:?}", adjust),
@@ -633,6 +709,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
         func: ExprId,
         args: Vec<ExprId>,
         res_ty: Type<'vm>,
+        span: Span,
     ) -> ExprKind<'vm> {
         // WARNING: ref mutability not necessarily correct
         // hopefully not an issue as the type shouldn't
@@ -642,6 +719,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
         let res = self.builder.add_expr(Expr {
             kind: ExprKind::Call { func, args },
             ty: ref_ty,
+            span,
         });
 
         ExprKind::DeRef(res)
@@ -655,7 +733,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                     LitKind::Int(n, _) => {
                         let mut n = n as i128;
                         if negative {
-                            n = -n;
+                            n = n.wrapping_neg();
                         }
                         ExprKind::LiteralValue(n)
                     }
@@ -712,7 +790,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
         }
     }
 
-    fn expr_capture(&mut self, cap: &rustc_middle::ty::CapturedPlace<'tcx>) -> ExprId {
+    fn expr_capture(&mut self, cap: &rustc_middle::ty::CapturedPlace<'tcx>, span: Span) -> ExprId {
         let mut base_ty = self.ctx.type_from_rustc(cap.place.base_ty);
 
         let mut base = if let rustc_middle::hir::place::PlaceBase::Upvar(upvar) = cap.place.base {
@@ -721,6 +799,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
             self.builder.add_expr(Expr {
                 kind: ExprKind::VarRef(local_id),
                 ty: base_ty,
+                span,
             })
         } else {
             panic!("cannot handle capture base {:?}", cap.place.base);
@@ -743,6 +822,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
             base = self.builder.add_expr(Expr {
                 kind: new_expr,
                 ty: base_ty,
+                span,
             });
         }
 
@@ -755,6 +835,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                     base = self.builder.add_expr(Expr {
                         kind: ExprKind::Ref(base, Mutability::Const),
                         ty: base_ty,
+                        span,
                     });
                 }
                 UpvarCapture::ByRef(BorrowKind::UniqueImmBorrow)
@@ -763,6 +844,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                     base = self.builder.add_expr(Expr {
                         kind: ExprKind::Ref(base, Mutability::Mut),
                         ty: base_ty,
+                        span,
                     });
                 }
                 UpvarCapture::ByValue => (),
@@ -809,9 +891,10 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
     fn pattern(&mut self, pat: &hir::Pat) -> PatternId {
         let rs_ty = self.types.pat_ty(pat);
         let ty = self.ctx.type_from_rustc(rs_ty);
+        let span = self.span(pat.span);
 
         let pattern_kind = match pat.kind {
-            hir::PatKind::Binding(_, hir_id, _, sub_pattern) => {
+            hir::PatKind::Binding(_, hir_id, ident, sub_pattern) => {
                 let all_binding_modes = self.types.pat_binding_modes();
                 let mode = all_binding_modes.get(pat.hir_id).unwrap();
 
@@ -829,6 +912,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
 
                 PatternKind::LocalBinding {
                     local_id,
+                    name: self.ctx.vm.alloc_path(ident.as_str()),
                     mode,
                     sub_pattern,
                 }
@@ -886,10 +970,16 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
 
                 // It's not easy to get a variant from a ctor so we do some annoying stuff.
                 let res = self.types.qpath_res(&struct_path, pat.hir_id);
-                let def = res.def_id();
-                let ctor_item = self.ctx.vm.types.def_from_rustc(def, &[], &self.ctx).item;
+                let variant_index = if let hir::def::Res::SelfCtor(_) = res {
+                    // `Self(..)` can only refer to a struct
+                    VariantIndex::new(0)
+                } else {
+                    let def = res.def_id();
+                    let ctor_item = self.ctx.vm.types.def_from_rustc(def, &[], self.ctx).item;
 
-                let (_, variant_index) = ctor_item.ctor_info().unwrap();
+                    let (_, variant_index) = ctor_item.ctor_info().unwrap();
+                    variant_index
+                };
 
                 let tup_size = adt_info.variant_fields.get(variant_index).len();
 
@@ -1032,6 +1122,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
         let mut res_pat = self.builder.add_pattern(Pattern {
             kind: pattern_kind,
             ty,
+            span,
         });
 
         let all_adjust = self.types.pat_adjustments();
@@ -1043,6 +1134,7 @@ impl<'vm, 'tcx, 'a> IRFunctionConverter<'vm, 'tcx, 'a> {
                         sub_pattern: res_pat,
                     },
                     ty: adjust_ty,
+                    span,
                 });
             }
         }
@@ -1150,6 +1242,11 @@ fn match_capture(
     }
 }
 
+/// Code expanded from macros is attributed to the outermost macro call.
+fn outermost_span(span: rustc_span::Span) -> rustc_span::Span {
+    rustc_span::hygiene::walk_chain(span, rustc_span::SyntaxContext::root())
+}
+
 fn is_capture_by_ref(capture: &rustc_middle::ty::CapturedPlace) -> bool {
     use rustc_middle::ty::UpvarCapture;
     match capture.info.capture_kind {
@@ -1,6 +1,7 @@
 use crate::{
     closure::FnTrait,
-    ir::{BindingMode, Expr, ExprKind, IRFunctionBuilder, Pattern, PatternKind},
+    ir::{BindingMode, Expr, ExprKind, IRFunctionBuilder, Pattern, PatternKind, Span},
+    items::FunctionSig,
     types::{Mutability, Type, TypeKind},
     variants::VariantIndex,
 };
@@ -32,10 +33,12 @@ pub fn glue_for_fn_trait<'vm>(
             builder.add_pattern(Pattern {
                 kind: PatternKind::LocalBinding {
                     local_id: i as u32,
+                    name: "",
                     mode: BindingMode::Value,
                     sub_pattern: None,
                 },
                 ty: *ty,
+                span: Span::NONE,
             })
         })
         .collect();
@@ -48,6 +51,7 @@ pub fn glue_for_fn_trait<'vm>(
     let mut func_expr = builder.add_expr(Expr {
         kind: ExprKind::VarRef(0),
         ty: self_ty,
+        span: Span::NONE,
     });
 
     match kind {
@@ -55,6 +59,7 @@ pub fn glue_for_fn_trait<'vm>(
             func_expr = builder.add_expr(Expr {
                 kind: ExprKind::DeRef(func_expr),
                 ty: func_ty,
+                span: Span::NONE,
             });
         }
         FnTrait::FnOnce => (),
@@ -63,6 +68,7 @@ pub fn glue_for_fn_trait<'vm>(
     let tuple_expr = builder.add_expr(Expr {
         kind: ExprKind::VarRef(1),
         ty: args_ty,
+        span: Span::NONE,
     });
 
     let mut call_args = Vec::new();
@@ -74,6 +80,7 @@ pub fn glue_for_fn_trait<'vm>(
                 field: i as u32,
             },
             ty: *arg_ty,
+            span: Span::NONE,
         }));
     }
 
@@ -83,6 +90,7 @@ pub fn glue_for_fn_trait<'vm>(
             args: call_args,
         },
         ty: res_ty,
+        span: Span::NONE,
     });
 
     builder.finish(root_expr, IRKind::Function, params, vec![])
@@ -112,10 +120,12 @@ pub fn glue_for_ctor<'vm>(
             builder.add_pattern(Pattern {
                 kind: PatternKind::LocalBinding {
                     local_id: i as u32,
+                    name: "",
                     mode: BindingMode::Value,
                     sub_pattern: None,
                 },
                 ty,
+                span: Span::NONE,
             })
         })
         .collect();
@@ -127,6 +137,7 @@ pub fn glue_for_ctor<'vm>(
             let expr_id = builder.add_expr(Expr {
                 kind: ExprKind::VarRef(i as u32),
                 ty: builder.pattern(*pat_id).ty,
+                span: Span::NONE,
             });
             (i as u32, expr_id)
         })
@@ -139,7 +150,52 @@ pub fn glue_for_ctor<'vm>(
             rest: None,
         },
         ty: adt_ty,
+        span: Span::NONE,
     });
 
     builder.finish(struct_expr, ir_kind, params, vec![])
 }
+
+/// Used for constants in crates without source, which rustc evaluates for us.
+pub fn glue_for_literal(ty: Type<'_>, n: i128) -> IRFunction<'_> {
+    let mut builder = IRFunctionBuilder::default();
+
+    let root_expr = builder.add_expr(Expr {
+        kind: ExprKind::LiteralValue(n),
+        ty,
+        span: Span::NONE,
+    });
+
+    builder.finish(root_expr, IRKind::Constant, vec![], vec![])
+}
+
+/// Foreign functions have no body, their IR only carries the signature. It is never compiled.
+pub fn glue_for_foreign_fn(sig: FunctionSig<'_>) -> IRFunction<'_> {
+    let mut builder = IRFunctionBuilder::default();
+
+    let params = sig
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, ty)| {
+            builder.add_pattern(Pattern {
+                kind: PatternKind::LocalBinding {
+                    local_id: i as u32,
+                    name: "",
+                    mode: BindingMode::Value,
+                    sub_pattern: None,
+                },
+                ty: *ty,
+                span: Span::NONE,
+            })
+        })
+        .collect();
+
+    let root_expr = builder.add_expr(Expr {
+        kind: ExprKind::LiteralVoid,
+        ty: sig.output,
+        span: Span::NONE,
+    });
+
+    builder.finish(root_expr, IRKind::Function, params, vec![])
+}
@@ -62,6 +62,9 @@ impl<'vm> IRFunctionBuilder<'vm> {
             exprs: self.exprs,
             patterns: self.patterns,
             opaque_types,
+            inherit_overflow_checks: false,
+            source_file: "",
+            track_caller: false,
         }
     }
 }
@@ -75,6 +78,13 @@ pub struct IRFunction<'vm> {
     pub root_expr: ExprId,
     pub params: Vec<PatternId>,
     pub opaque_types: Vec<OpaqueTypeMapping<'vm>>,
+    /// Whether the function is `#[rustc_inherit_overflow_checks]`, and checks for overflow when
+    /// the program being run does, even if its crate doesn't.
+    pub inherit_overflow_checks: bool,
+    /// The file every span in the function points into, empty for generated functions.
+    pub source_file: &'vm str,
+    /// Whether the function is `#[track_caller]`, and reports its caller's location.
+    pub track_caller: bool,
     exprs: Vec<Expr<'vm>>,
     patterns: Vec<Pattern<'vm>>,
 }
@@ -101,6 +111,16 @@ impl<'vm> IRFunction<'vm> {
         &self.patterns[id.0 as usize]
     }
 
+    /// Whether the function's code depends on generic parameters, so it can't be compiled without
+    /// substitutions.
+    pub fn is_generic(&self) -> bool {
+        let sig_concrete =
+            self.sig.inputs.iter().all(|ty| ty.is_concrete()) && self.sig.output.is_concrete();
+        !sig_concrete
+            || self.exprs.iter().any(|expr| !expr.ty.is_concrete())
+            || self.patterns.iter().any(|pat| !pat.ty.is_concrete())
+    }
+
     // deliberately given this name to avoid accidental clones
     pub fn clone_ir(&self) -> Self {
         Self {
@@ -112,12 +132,19 @@ impl<'vm> IRFunction<'vm> {
             exprs: self.exprs.clone(),
             patterns: self.patterns.clone(),
             opaque_types: self.opaque_types.clone(),
+            inherit_overflow_checks: self.inherit_overflow_checks,
+            source_file: self.source_file,
+            track_caller: self.track_caller,
         }
     }
 
     pub fn insert_pattern(&mut self, kind: PatternKind<'vm>, ty: Type<'vm>) -> PatternId {
         let index = self.patterns.len();
-        self.patterns.push(Pattern { kind, ty });
+        self.patterns.push(Pattern {
+            kind,
+            ty,
+            span: Span::NONE,
+        });
         PatternId(index as u32)
     }
 
@@ -130,7 +157,7 @@ impl<'vm> IRFunction<'vm> {
     }
 
     pub fn const_eval(&self, vm: &'vm VM<'vm>, subs: &SubList<'vm>) -> (Vec<u8>, Type<'vm>) {
-        let bc = BytecodeCompiler::compile(vm, self, subs, "<const block>", subs);
+        let bc = BytecodeCompiler::compile(vm, self, subs, "<const block>", subs, false);
 
         let mut eval_thread = vm.make_thread();
         eval_thread.run_bytecode_root(&bc);
@@ -177,12 +204,32 @@ pub enum Stmt {
 pub struct Expr<'vm> {
     pub kind: ExprKind<'vm>,
     pub ty: Type<'vm>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Persist)]
 pub struct Pattern<'vm> {
     pub kind: PatternKind<'vm>,
     pub ty: Type<'vm>,
+    pub span: Span,
+}
+
+/// Where an expression or pattern starts in the function's `source_file`. Code expanded from
+/// macros points at the outermost macro call, like rustc's `caller_location`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Persist)]
+pub struct Span {
+    /// Starts at 1. Zero if the location is unknown.
+    pub line: u32,
+    /// Starts at 1, counted in characters.
+    pub col: u32,
+}
+
+impl Span {
+    pub const NONE: Span = Span { line: 0, col: 0 };
+
+    pub fn is_none(&self) -> bool {
+        self.line == 0
+    }
 }
 
 #[derive(Debug, Clone, Persist)]
@@ -204,6 +251,9 @@ pub enum ExprKind<'vm> {
 
     Block(Block),
     ConstBlock(Arc<IRFunction<'vm>>),
+    /// Drops the temporaries made while evaluating the expression as soon as it finishes, like
+    /// the borrows in an `if` condition.
+    DropTemps(ExprId),
 
     /// Variable reference
     VarRef(u32),
@@ -291,6 +341,8 @@ pub enum ExprKind<'vm> {
 pub enum PatternKind<'vm> {
     LocalBinding {
         local_id: u32,
+        /// The variable's name, for debuggers.
+        name: &'vm str,
         mode: BindingMode,
         sub_pattern: Option<PatternId>,
     },
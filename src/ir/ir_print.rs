@@ -109,6 +109,11 @@ impl<'vm> IRFunction<'vm> {
                 self.print_expr(rhs, indent);
                 print!(" )");
             }
+            ExprKind::DropTemps(child) => {
+                print!("drop_temps( ");
+                self.print_expr(child, indent);
+                print!(" )");
+            }
             ExprKind::Cast(child) => {
                 print!("( ");
                 self.print_expr(child, indent);
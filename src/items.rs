@@ -5,6 +5,7 @@ use std::{
 };
 
 use crate::{
+    abi::align,
     builtins::{BuiltinAdt, BuiltinTrait},
     bytecode_compiler::BytecodeCompiler,
     closure::{Closure, ClosureRef},
@@ -273,11 +274,13 @@ impl<'vm> Persist<'vm> for Item<'vm> {
                 ir,
                 closures,
                 extern_name,
+                thread_local,
                 ..
             } => {
                 writer.write_byte('s' as u8);
 
                 extern_name.persist_write(writer);
+                thread_local.persist_write(writer);
 
                 let ir_block = write_item_ir(ir, closures, writer);
 
@@ -372,12 +375,14 @@ impl<'vm> Persist<'vm> for Item<'vm> {
             }
             's' => {
                 let extern_name = Persist::persist_read(reader);
+                let thread_local = Persist::persist_read(reader);
 
                 ir = reader.read_byte_slice();
                 ItemKind::Static {
                     ir: Default::default(),
                     value_ptr: Default::default(),
                     extern_name,
+                    thread_local,
                     closures: Default::default(),
                 }
             }
@@ -437,7 +442,7 @@ impl<'vm> Persist<'vm> for AssocValue<'vm> {
     }
 }
 
-#[derive(PartialEq, Persist, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Persist, Debug, Copy, Clone)]
 pub enum FunctionAbi {
     Rust,          // "default" calling convention -- presumably won't be called from c
     RustIntrinsic, // intrinsics which are inlined at compile-time
@@ -447,6 +452,21 @@ pub enum FunctionAbi {
     Unadjusted,        // I have no clue what this is.
 }
 
+impl FunctionAbi {
+    pub fn from_rustc(abi: rustc_target::spec::abi::Abi) -> Self {
+        use rustc_target::spec::abi::Abi;
+        match abi {
+            // "rust-call" only changes how arguments are tupled, which we handle separately
+            Abi::Rust | Abi::RustCall => FunctionAbi::Rust,
+            Abi::RustIntrinsic => FunctionAbi::RustIntrinsic,
+            Abi::C { .. } => FunctionAbi::C,
+            Abi::PlatformIntrinsic => FunctionAbi::PlatformIntrinsic,
+            Abi::Unadjusted => FunctionAbi::Unadjusted,
+            _ => panic!("abi? {:?}", abi),
+        }
+    }
+}
+
 ///
 /// `virtual_info` is attached to each item appearing in a trait declaration,
 /// and is used to resolve concrete implementations of those items.
@@ -475,6 +495,8 @@ pub enum ItemKind<'vm> {
         ir: Mutex<Option<Arc<IRFunction<'vm>>>>,
         value_ptr: OnceLock<usize>,
         extern_name: OnceLock<(FunctionAbi, String)>,
+        /// `#[thread_local]` statics get a copy of `value_ptr` in each thread.
+        thread_local: bool,
         closures: Mutex<AHashMap<&'vm str, ClosureRef<'vm>>>,
     },
     AssociatedType {
@@ -517,6 +539,21 @@ impl<'vm> FunctionSig<'vm> {
 
         Self { inputs, output }
     }
+
+    /// Offsets of the arguments in a call frame, which holds the result followed by each argument.
+    pub fn input_offsets(&self) -> Vec<u32> {
+        let mut offset = self.output.layout().assert_size();
+        self.inputs
+            .iter()
+            .map(|ty| {
+                let layout = ty.layout();
+                offset = align(offset, layout.align);
+                let input_offset = offset;
+                offset += layout.assert_size();
+                input_offset
+            })
+            .collect()
+    }
 }
 
 #[derive(Persist)]
@@ -545,6 +582,8 @@ pub enum DiscriminantSource<'vm> {
     Explicit(Arc<IRFunction<'vm>>),
     Next,
     None,
+    /// Used for crates without source.
+    Value(i128),
 }
 
 impl<'vm> AdtInfo<'vm> {
@@ -574,6 +613,7 @@ impl<'vm> AdtInfo<'vm> {
 
                             Discriminant::from_bytes(&bytes, ty)
                         }
+                        DiscriminantSource::Value(val) => Discriminant::new(*val),
                         DiscriminantSource::Next => next,
                         DiscriminantSource::None => panic!("no discriminant"),
                     };
@@ -758,12 +798,13 @@ impl<'vm> ItemKind<'vm> {
         }
     }
 
-    pub fn new_static() -> Self {
+    pub fn new_static(thread_local: bool) -> Self {
         Self::Static {
             ir: Default::default(),
             value_ptr: Default::default(),
             closures: Default::default(),
             extern_name: OnceLock::new(),
+            thread_local,
         }
     }
 
@@ -773,6 +814,7 @@ impl<'vm> ItemKind<'vm> {
             value_ptr: Default::default(),
             closures: Default::default(),
             extern_name: (abi, name).into(),
+            thread_local: false,
         }
     }
 
@@ -840,6 +882,19 @@ impl<'vm> Item<'vm> {
         }
     }
 
+    /// Whether this is a function which can be compiled without substitutions: it has IR, is not
+    /// a trait member, and has no generic parameters.
+    pub fn is_mono_function(&self) -> bool {
+        match &self.kind {
+            ItemKind::Function {
+                virtual_info: None,
+                has_ir: true,
+                ..
+            } => !self.ir(&SubList::empty()).0.is_generic(),
+            _ => false,
+        }
+    }
+
     pub fn ir_kind(&self) -> Option<IRKind> {
         match self.kind {
             ItemKind::Function { .. } => Some(IRKind::Function),
@@ -976,22 +1031,27 @@ impl<'vm> Item<'vm> {
             panic!("item kind mismatch");
         };
 
-        let mut mono_values = mono_values.lock().unwrap();
-        let result_val = mono_values.entry(subs.clone()).or_insert_with(|| {
-            let (ir, new_subs) = self.ir(subs);
+        if let Some(result_val) = mono_values.lock().unwrap().get(subs) {
+            return result_val;
+        }
 
-            let bc =
-                BytecodeCompiler::compile(self.vm, &ir, &new_subs, self.path.as_string(), subs);
+        // evaluated without holding the lock: a constant can use the same item with other subs,
+        // like `InPlaceIterable::MERGE_BY` of an adapter, which is the one of the iterator it wraps
+        let (ir, new_subs) = self.ir(subs);
 
-            let mut eval_thread = self.vm.make_thread();
-            eval_thread.run_bytecode_root(&bc);
-            let ty = ir.sig.output; // todo sub?
+        let bc =
+            BytecodeCompiler::compile(self.vm, &ir, &new_subs, self.path.as_string(), subs, false);
 
-            let eval_bytes = eval_thread.copy_result(0, ty.layout().assert_size() as usize);
-            self.vm.alloc_constant(eval_bytes)
-        });
+        let mut eval_thread = self.vm.make_thread();
+        eval_thread.run_bytecode_root(&bc);
+        let ty = ir.sig.output; // todo sub?
+
+        let eval_bytes = eval_thread.copy_result(0, ty.layout().assert_size() as usize);
+        let result_val = self.vm.alloc_constant(eval_bytes);
 
-        result_val
+        // if another thread evaluated it first, use its value
+        let mut mono_values = mono_values.lock().unwrap();
+        mono_values.entry(subs.clone()).or_insert(result_val)
     }
 
     // unlike with constants, we should probably ALWAYS be allocating here, even for small values
@@ -1005,8 +1065,14 @@ impl<'vm> Item<'vm> {
         let result_val = value_ptr.get_or_init(|| {
             let (ir, new_subs) = self.ir(subs);
 
-            let bc =
-                BytecodeCompiler::compile(self.vm, &ir, &new_subs, self.path.as_string(), subs);
+            let bc = BytecodeCompiler::compile(
+                self.vm,
+                &ir,
+                &new_subs,
+                self.path.as_string(),
+                subs,
+                false,
+            );
 
             let mut eval_thread = self.vm.make_thread();
             eval_thread.run_bytecode_root(&bc);
@@ -1019,6 +1085,13 @@ impl<'vm> Item<'vm> {
         *result_val as _
     }
 
+    pub fn is_thread_local(&self) -> bool {
+        let ItemKind::Static { thread_local, .. } = &self.kind else {
+            panic!("item kind mismatch");
+        };
+        *thread_local
+    }
+
     pub fn ctor_info(&self) -> Option<(ItemId, VariantIndex)> {
         match &self.kind {
             ItemKind::Function { ctor_for, .. } => ctor_for.clone(),
@@ -1130,6 +1203,30 @@ impl<'vm> Item<'vm> {
         results
     }
 
+    /// Find the item declaring a trait member, used for provided methods that impls don't override.
+    pub fn trait_member(&self, member_index: u32) -> Option<&'vm Item<'vm>> {
+        let ItemKind::Trait {
+            assoc_value_map, ..
+        } = &self.kind
+        else {
+            panic!("item kind mismatch");
+        };
+
+        let (ident, _) = assoc_value_map
+            .get()
+            .unwrap()
+            .iter()
+            .find(|(_, index)| **index == member_index)?;
+
+        let path = self
+            .vm
+            .alloc_path(&format!("{}::{}", self.path.as_string(), ident.as_string()));
+
+        self.vm
+            .crate_provider(self.crate_id)
+            .item_by_path(&ItemPath::for_value(path))
+    }
+
     pub fn resolve_associated_ty(&self, subs: &SubList<'vm>) -> Type<'vm> {
         let ItemKind::AssociatedType { virtual_info } = &self.kind else {
             panic!("item kind mismatch");
@@ -1138,19 +1235,35 @@ impl<'vm> Item<'vm> {
         let crate_items = self.vm.crate_provider(self.crate_id);
         let trait_item = crate_items.item_by_id(virtual_info.trait_id);
 
-        if let TraitImplResult::Static(trait_impl) = trait_item.find_trait_impl(subs) {
-            let res_val = &trait_impl.assoc_values[virtual_info.member_index as usize];
+        match trait_item.find_trait_impl(subs) {
+            TraitImplResult::Static(trait_impl) => {
+                let res_val = &trait_impl.assoc_values[virtual_info.member_index as usize];
 
-            if let Some(AssocValue::Type(ty)) = res_val {
-                ty.sub(&trait_impl.impl_subs)
-            } else {
-                panic!("failed to find associated type")
+                if let Some(AssocValue::Type(ty)) = res_val {
+                    ty.sub(&trait_impl.impl_subs)
+                } else {
+                    panic!("failed to find associated type")
+                }
+            }
+            TraitImplResult::Dynamic => {
+                // trait objects carry their associated types with them
+                let self_ty = subs.list[0].assert_ty();
+                let TypeKind::Dynamic { projections, .. } = self_ty.kind() else {
+                    panic!("dynamic impl for non-dyn type");
+                };
+
+                projections
+                    .iter()
+                    .find(|(assoc_ty, _)| assoc_ty.item == self)
+                    .map(|(_, ty)| *ty)
+                    .expect("failed to find associated type on dyn")
+            }
+            _ => {
+                panic!(
+                    "failed to resolve impl for associated type: {:?}{}",
+                    self, subs
+                );
             }
-        } else {
-            panic!(
-                "failed to resolve impl for associated type: {:?}{}",
-                self, subs
-            );
         }
     }
 
@@ -1264,6 +1377,11 @@ pub fn path_from_rustc<'vm>(
                 }
                 result.push_str("::");
                 result.push_str(sym.as_str());
+                // items with the same name in different blocks of the same function
+                if elem.disambiguator != 0 {
+                    write!(result, "#{}", elem.disambiguator).unwrap();
+                    is_debug = true;
+                }
             }
             DefPathData::Impl => {
                 result.push_str("::{impl}");
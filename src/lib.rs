@@ -0,0 +1,182 @@
+#![allow(dead_code)]
+#![feature(rustc_private)]
+#![feature(extract_if)]
+#![feature(lazy_cell)]
+
+extern crate rustc_abi;
+extern crate rustc_ast;
+extern crate rustc_ast_pretty;
+extern crate rustc_driver;
+extern crate rustc_error_codes;
+extern crate rustc_errors;
+extern crate rustc_feature;
+extern crate rustc_hash;
+extern crate rustc_hir;
+extern crate rustc_interface;
+extern crate rustc_metadata;
+extern crate rustc_middle;
+extern crate rustc_mir_dataflow;
+extern crate rustc_session;
+extern crate rustc_span;
+extern crate rustc_target;
+
+mod vm;
+
+mod abi;
+mod builtins;
+mod bytecode_compiler;
+mod bytecode_optimizer;
+mod bytecode_select;
+mod cache_provider;
+mod closure;
+mod crate_provider;
+mod disasm;
+mod engine;
+mod impls;
+mod ir;
+mod items;
+mod lazy_collections;
+mod manifest;
+mod persist;
+mod persist_header;
+pub mod profiler;
+mod rustc_worker;
+mod simple_jit;
+mod types;
+mod value_debug;
+mod variants;
+
+pub use engine::{Args, Engine, Error, Options, Value, ValueKind};
+pub use items::FunctionAbi;
+pub use vm::{print_backtrace, run_dap_server, NativeArgs, NativeFunc, VMThread};
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process,
+    sync::LazyLock,
+};
+
+static SYSROOT: LazyLock<PathBuf> = LazyLock::new(|| {
+    let out = process::Command::new("rustc")
+        .arg("--print=sysroot")
+        .current_dir(".")
+        .output()
+        .unwrap();
+
+    std::str::from_utf8(&out.stdout)
+        .expect("bad sysroot")
+        .trim()
+        .into()
+});
+
+#[derive(Debug)]
+pub struct CratePath {
+    pub name: String,
+    path: Option<PathBuf>,
+    /// Source code provided directly by the host, instead of read from a file.
+    source: Option<String>,
+}
+
+impl CratePath {
+    pub fn new(path: &OsStr) -> Self {
+        if let Some(path) = path.to_str() {
+            if let Some(name) = path.strip_prefix('@') {
+                let name = name.to_owned();
+
+                return CratePath {
+                    name,
+                    path: None,
+                    source: None,
+                };
+            }
+        }
+
+        let path = Path::new(path).canonicalize().expect("invalid path (1)");
+        let name = path
+            .file_stem()
+            .expect("invalid path (2)")
+            .to_str()
+            .expect("invalid path (3)")
+            .to_owned();
+
+        CratePath {
+            name,
+            path: Some(path),
+            source: None,
+        }
+    }
+
+    /// A crate whose name does not come from its root file, like those in cargo projects.
+    pub fn with_name(path: &Path, name: &str) -> Self {
+        let path = path.canonicalize().expect("invalid path (1)");
+
+        CratePath {
+            name: name.to_owned(),
+            path: Some(path),
+            source: None,
+        }
+    }
+
+    pub fn from_source(name: &str, source: String) -> Self {
+        CratePath {
+            name: name.to_owned(),
+            path: None,
+            source: Some(source),
+        }
+    }
+
+    pub fn source_path(&self) -> PathBuf {
+        if let Some(path) = &self.path {
+            path.clone()
+        } else if self.source.is_some() {
+            format!("<{}>", self.name).into()
+        } else {
+            let mut path = SYSROOT.clone();
+            path.push(format!(
+                "lib/rustlib/src/rust/library/{}/src/lib.rs",
+                self.name
+            ));
+            path
+        }
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn is_internal(&self) -> bool {
+        self.path.is_none() && self.source.is_none()
+    }
+
+    pub fn is_core(&self) -> bool {
+        self.is_internal() && self.name == "core"
+    }
+
+    pub fn is_alloc(&self) -> bool {
+        self.is_internal() && self.name == "alloc"
+    }
+
+    pub fn is_std(&self) -> bool {
+        self.is_internal() && self.name == "std"
+    }
+
+    pub fn cache_path(&self) -> PathBuf {
+        use base64::Engine;
+
+        let mut hash = md5::Context::new();
+
+        // do not hash the the source path for internal crates
+        if let Some(path) = &self.path {
+            hash.consume(path.to_string_lossy().as_bytes());
+        }
+        if let Some(source) = &self.source {
+            hash.consume(source.as_bytes());
+        }
+
+        let hash = hash.compute();
+        let hash = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(*hash);
+
+        format!("./cache/{}-{}", self.name, hash).into()
+    }
+}
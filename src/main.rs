@@ -1,64 +1,12 @@
 #![allow(dead_code)]
-#![feature(rustc_private)]
-#![feature(extract_if)]
-#![feature(lazy_cell)]
 
-extern crate rustc_abi;
-extern crate rustc_ast;
-extern crate rustc_ast_pretty;
-extern crate rustc_driver;
-extern crate rustc_error_codes;
-extern crate rustc_errors;
-extern crate rustc_feature;
-extern crate rustc_hash;
-extern crate rustc_hir;
-extern crate rustc_interface;
-extern crate rustc_metadata;
-extern crate rustc_middle;
-extern crate rustc_mir_dataflow;
-extern crate rustc_session;
-extern crate rustc_span;
-extern crate rustc_target;
-
-mod vm;
-
-mod abi;
-mod builtins;
-mod bytecode_compiler;
-mod bytecode_select;
-mod cache_provider;
 mod cli;
-mod closure;
-mod crate_provider;
-mod impls;
-mod ir;
-mod items;
-mod lazy_collections;
-mod persist;
-mod persist_header;
-mod profiler;
-mod rustc_worker;
-mod simple_jit;
 mod test;
-mod types;
-mod value_debug;
-mod variants;
 
-use std::{
-    cell::LazyCell,
-    ffi::{OsStr, OsString},
-    path::{Path, PathBuf},
-    process,
-};
+use std::{ffi::OsString, path::Path, process};
 
 use clap::Parser;
-use types::SubList;
-use vm::VM;
-
-use crate::{
-    items::{ExternCrate, ItemPath},
-    rustc_worker::RustCWorkerConfig,
-};
+use skitter::{print_backtrace, profiler, run_dap_server, Engine, Error};
 
 // seems neutral or slower than the system allocator (wsl), todo more tests
 //use mimalloc::MiMalloc;
@@ -66,10 +14,20 @@ use crate::{
 //static GLOBAL: MiMalloc = MiMalloc;
 
 fn main() {
-    set_panic_handler();
-
     let args = cli::CliArgs::parse();
 
+    if let Some(cli::Command::Disasm {
+        crate_path,
+        pattern,
+    }) = &args.command
+    {
+        // keeps the default panic handler, so functions which fail to compile don't end the process
+        disasm(&args, crate_path, pattern);
+        return;
+    }
+
+    set_panic_handler();
+
     profiler::profile("top", || {
         if args.debug_repeat {
             loop {
@@ -86,10 +44,11 @@ fn main() {
 }
 
 fn run(args: &cli::CliArgs) {
-    let file_name = Path::new(&args.file_name);
-
     if args.test {
-        let mut global_args = Vec::new();
+        let dir_name = args.file_name.as_ref().expect("no test directory");
+
+        // compiler warnings would show up in the stderr we compare against
+        let mut global_args = vec![OsString::from("--no-warnings")];
 
         if args.debug_local_impls {
             global_args.push(OsString::from("--debug-local-impls"));
@@ -98,89 +57,74 @@ fn run(args: &cli::CliArgs) {
             global_args.push(OsString::from("--jit"));
         }
 
-        test::test(file_name, global_args);
+        test::test(Path::new(dir_name), global_args);
     }
 
-    let vm: &VM = Box::leak(Box::new(VM::new(args)));
-    // HACK: this must be initialized ASAP so common types have correct persist IDs
-    vm.common_types();
-
-    let mut extern_crates = Vec::new();
-
-    let crate_path = CratePath::new(&args.file_name);
-
-    let no_core = crate_path.is_core();
-    let no_alloc = crate_path.is_alloc() || crate_path.is_core();
-
-    if !no_core {
-        extern_crates.push(get_lib(vm, "core"));
-    }
-    if !no_alloc {
-        extern_crates.push(get_lib(vm, "alloc"));
+    if (args.profile_opcodes || args.profile_opcode_pairs) && !profiler::OPCODE_PROFILING {
+        eprintln!("opcode profiling needs skitter to be built with `--features profile-opcodes`");
+        process::exit(1);
     }
 
-    let main_crate = vm.add_provider_auto(RustCWorkerConfig {
-        crate_path,
-        extern_crates,
-        save_file: args.save,
-    });
-
-    let main_path = ItemPath::main();
-
-    let main_item = vm
-        .crate_provider(main_crate)
-        .item_by_path(&main_path)
-        .expect("no main found");
+    if args.dap {
+        process::exit(run_dap_server(args.options()));
+    }
 
-    let main_fn = main_item.func_mono(&SubList { list: Vec::new() });
+    let mut engine = Engine::new(args.options());
 
-    let mut thread = vm.make_thread();
-    thread.call_root(&main_fn);
-}
+    let (res, program_name) = if let Some(manifest_path) = &args.manifest_path {
+        let res = engine.load_manifest(manifest_path);
+        (res, manifest_path.clone().into_os_string())
+    } else {
+        let file_name = args.file_name.clone().expect("no file to run");
+        (engine.load_file(&file_name), file_name)
+    };
 
-fn get_lib(vm: &'static VM, name: &str) -> ExternCrate {
-    let id = match name {
-        "core" => {
-            if let Some(id) = vm.core_crate.get() {
-                *id
-            } else {
-                let crate_path = CratePath::new(OsStr::new("@core"));
+    if let Err(err) = res {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
 
-                let id = vm.add_provider_auto(RustCWorkerConfig {
-                    crate_path,
-                    extern_crates: vec![],
-                    save_file: false,
-                });
+    let mut program_args = vec![program_name];
+    program_args.extend(args.program_args.iter().cloned());
 
-                vm.core_crate.set(id).unwrap();
+    let res = engine.run_main(&program_args);
 
-                id
-            }
+    if args.profile_functions {
+        profiler::function_profile_log(50);
+    }
+    #[cfg(feature = "profile-opcodes")]
+    if args.profile_opcodes || args.profile_opcode_pairs {
+        profiler::opcode_profile_log(50);
+    }
+    if let Some(path) = &args.profile_stacks {
+        if let Err(err) = profiler::write_collapsed_stacks(path) {
+            eprintln!("failed to write {}: {}", path.display(), err);
         }
-        "alloc" => {
-            if let Some(id) = vm.alloc_crate.get() {
-                *id
-            } else {
-                let crate_path = CratePath::new(OsStr::new("@alloc"));
-
-                let id = vm.add_provider_auto(RustCWorkerConfig {
-                    crate_path,
-                    extern_crates: vec![get_lib(vm, "core")],
-                    save_file: false,
-                });
-
-                vm.alloc_crate.set(id).unwrap();
+    }
 
-                id
-            }
+    match res {
+        Ok(0) => (),
+        Ok(code) => process::exit(code),
+        // if a panic unwinds out of main, exit with the same status as a compiled program
+        Err(Error::Panic) => process::exit(101),
+        // compiled programs are killed by a signal on stack overflow, abort does the same
+        Err(err @ Error::StackOverflow(_)) => {
+            eprintln!("{}", err);
+            process::abort();
         }
-        _ => panic!("lib = {}", name),
-    };
+        Err(err) => panic!("{}", err),
+    }
+}
 
-    ExternCrate {
-        id,
-        name: name.to_owned(),
+fn disasm(args: &cli::CliArgs, crate_path: &OsString, pattern: &str) {
+    let mut engine = Engine::new(args.options());
+    if let Err(err) = engine.load_file(crate_path) {
+        eprintln!("{}", err);
+        process::exit(1);
     }
+    // functions which fail to compile are reported in the listing
+    std::panic::set_hook(Box::new(|_| {}));
+    print!("{}", engine.disassemble(pattern));
 }
 
 /// When any thread panics, close the process.
@@ -188,91 +132,8 @@ fn set_panic_handler() {
     let orig_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |panic_info| {
         orig_hook(panic_info);
+        // skitter's own backtrace says little about where the interpreted program was
+        print_backtrace();
         process::exit(1);
     }));
 }
-
-const SYSROOT: LazyCell<PathBuf> = LazyCell::new(|| {
-    let out = process::Command::new("rustc")
-        .arg("--print=sysroot")
-        .current_dir(".")
-        .output()
-        .unwrap();
-
-    std::str::from_utf8(&out.stdout)
-        .expect("bad sysroot")
-        .trim()
-        .into()
-});
-
-#[derive(Debug)]
-pub struct CratePath {
-    pub name: String,
-    path: Option<PathBuf>,
-}
-
-impl CratePath {
-    pub fn new(path: &OsStr) -> Self {
-        if let Some(path) = path.to_str() {
-            if path.starts_with('@') {
-                let name = path[1..].to_owned();
-
-                return CratePath { name, path: None };
-            }
-        }
-
-        let path = Path::new(path).canonicalize().expect("invalid path (1)");
-        let name = path
-            .file_stem()
-            .expect("invalid path (2)")
-            .to_str()
-            .expect("invalid path (3)")
-            .to_owned();
-
-        CratePath {
-            name,
-            path: Some(path),
-        }
-    }
-
-    pub fn source_path(&self) -> PathBuf {
-        if let Some(path) = &self.path {
-            path.clone()
-        } else {
-            let mut path = SYSROOT.clone();
-            path.push(format!(
-                "lib/rustlib/src/rust/library/{}/src/lib.rs",
-                self.name
-            ));
-            path
-        }
-    }
-
-    pub fn is_internal(&self) -> bool {
-        self.path.is_none()
-    }
-
-    pub fn is_core(&self) -> bool {
-        self.is_internal() && self.name == "core"
-    }
-
-    pub fn is_alloc(&self) -> bool {
-        self.is_internal() && self.name == "alloc"
-    }
-
-    pub fn cache_path(&self) -> PathBuf {
-        use base64::Engine;
-
-        let mut hash = md5::Context::new();
-
-        // do not hash the the source path for internal crates
-        if let Some(path) = &self.path {
-            hash.consume(path.to_string_lossy().as_bytes());
-        }
-
-        let hash = hash.compute();
-        let hash = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(*hash);
-
-        format!("./cache/{}-{}", self.name, hash).into()
-    }
-}
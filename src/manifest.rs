@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+
+use ahash::AHashMap;
+use cargo_metadata::{DependencyKind, Metadata, MetadataCommand, Package, PackageId, Target};
+use rustc_span::edition::Edition;
+
+/// A crate in a cargo project, with everything needed to pass it to rustc.
+pub struct ManifestCrate {
+    pub name: String,
+    pub src_path: PathBuf,
+    pub edition: Edition,
+    /// Enabled features, as `--cfg` strings.
+    pub crate_cfg: Vec<String>,
+    /// Dependencies, by the name they are imported with, as indices of earlier crates.
+    pub deps: Vec<(String, usize)>,
+}
+
+/// The crates of a cargo project in dependency order. The last crate is the package's binary
+/// (or library, if it has no binary).
+pub struct Manifest {
+    pub crates: Vec<ManifestCrate>,
+    /// A directory for build artifacts, under cargo's target directory.
+    pub out_dir: PathBuf,
+}
+
+/// Read a package and its dependencies with `cargo metadata`. Dependencies must already be on
+/// disk: build scripts are not run, and proc-macro crates are not supported.
+pub fn read_manifest(manifest_path: &Path) -> Result<Manifest, String> {
+    let metadata = MetadataCommand::new()
+        .manifest_path(manifest_path)
+        .other_options(vec![
+            "--offline".to_owned(),
+            "--filter-platform".to_owned(),
+            rustc_session::config::host_triple().to_owned(),
+        ])
+        .exec()
+        .map_err(|err| err.to_string())?;
+
+    let resolve = metadata.resolve.as_ref().ok_or("no dependency graph")?;
+    let root_id = resolve
+        .root
+        .as_ref()
+        .ok_or("workspace manifests are not supported, pick a package")?;
+
+    let mut reader = ManifestReader {
+        metadata: &metadata,
+        crates: Vec::new(),
+        lib_indices: AHashMap::new(),
+    };
+
+    let root = reader.package(root_id);
+    let lib_index = reader.lib_crate(root_id)?;
+
+    if let Some(bin) = root.targets.iter().find(|t| t.is_bin()) {
+        let mut deps = reader.deps(root_id)?;
+        if let Some(lib_index) = lib_index {
+            let lib_name = reader.crates[lib_index].name.clone();
+            deps.push((lib_name, lib_index));
+        }
+        reader.push_crate(bin, root_id, deps);
+    } else if lib_index.is_none() {
+        return Err(format!("package {} has no library or binary", root.name));
+    }
+
+    Ok(Manifest {
+        crates: reader.crates,
+        out_dir: metadata.target_directory.join("skitter").into(),
+    })
+}
+
+struct ManifestReader<'a> {
+    metadata: &'a Metadata,
+    crates: Vec<ManifestCrate>,
+    lib_indices: AHashMap<&'a PackageId, Option<usize>>,
+}
+
+impl<'a> ManifestReader<'a> {
+    fn package(&self, id: &PackageId) -> &'a Package {
+        &self.metadata[id]
+    }
+
+    /// Add a package's library and its dependencies, returning its index.
+    fn lib_crate(&mut self, id: &'a PackageId) -> Result<Option<usize>, String> {
+        if let Some(index) = self.lib_indices.get(id) {
+            return Ok(*index);
+        }
+
+        let package = self.package(id);
+
+        if package
+            .targets
+            .iter()
+            .any(|t| t.kind.iter().any(|k| k == "proc-macro"))
+        {
+            return Err(format!(
+                "{} is a proc-macro crate, which is not supported",
+                package.name
+            ));
+        }
+
+        let lib = package.targets.iter().find(|t| {
+            t.kind
+                .iter()
+                .any(|k| matches!(k.as_str(), "lib" | "rlib" | "dylib"))
+        });
+
+        let index = if let Some(lib) = lib {
+            let deps = self.deps(id)?;
+            Some(self.push_crate(lib, id, deps))
+        } else {
+            None
+        };
+
+        self.lib_indices.insert(id, index);
+        Ok(index)
+    }
+
+    /// The normal dependencies of a package, adding any which have not been seen yet.
+    fn deps(&mut self, id: &'a PackageId) -> Result<Vec<(String, usize)>, String> {
+        let resolve = self.metadata.resolve.as_ref().unwrap();
+        let node = resolve
+            .nodes
+            .iter()
+            .find(|node| &node.id == id)
+            .ok_or("missing package in dependency graph")?;
+
+        let mut deps = Vec::new();
+        for dep in &node.deps {
+            let is_normal = dep
+                .dep_kinds
+                .iter()
+                .any(|info| info.kind == DependencyKind::Normal);
+            if !is_normal {
+                continue;
+            }
+
+            if let Some(index) = self.lib_crate(&dep.pkg)? {
+                deps.push((dep.name.clone(), index));
+            }
+        }
+        Ok(deps)
+    }
+
+    fn push_crate(&mut self, target: &Target, id: &PackageId, deps: Vec<(String, usize)>) -> usize {
+        let resolve = self.metadata.resolve.as_ref().unwrap();
+        let features = resolve
+            .nodes
+            .iter()
+            .find(|node| &node.id == id)
+            .map(|node| node.features.as_slice())
+            .unwrap_or_default();
+
+        let edition = target
+            .edition
+            .as_str()
+            .parse()
+            .unwrap_or(Edition::Edition2021);
+
+        self.crates.push(ManifestCrate {
+            name: target.name.replace('-', "_"),
+            src_path: target.src_path.clone().into(),
+            edition,
+            crate_cfg: features
+                .iter()
+                .map(|feature| format!("feature={:?}", feature))
+                .collect(),
+            deps,
+        });
+
+        self.crates.len() - 1
+    }
+}
@@ -1,11 +1,19 @@
 use ahash::AHashMap;
 
 use std::{
+    cell::RefCell,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
     sync::LazyLock,
-    sync::RwLock,
+    sync::{Mutex, RwLock},
     time::{Duration, Instant},
 };
 
+#[cfg(feature = "profile-opcodes")]
+use crate::vm::instr::opcode;
+use crate::vm::Function;
+
 static PROFILER_TABLE: LazyLock<RwLock<AHashMap<&'static str, Duration>>> =
     LazyLock::new(|| RwLock::new(AHashMap::new()));
 
@@ -28,3 +36,444 @@ pub fn profile_log() {
         println!("    {:20} {:?}", name, t);
     }
 }
+
+/// Calls and time per function, recorded with `--profile-functions`. Each thread records its own
+/// call tree, which is merged into this when the thread exits.
+static FUNCTION_PROFILE: LazyLock<Mutex<FunctionProfile>> = LazyLock::new(Default::default);
+
+thread_local! {
+    static THREAD_PROFILE: RefCell<ThreadProfile> = RefCell::new(ThreadProfile::new());
+}
+
+#[derive(Default)]
+struct FunctionProfile {
+    /// Keyed by function name, which includes its substitutions.
+    functions: AHashMap<String, FunctionStats>,
+    /// Self time of each distinct stack, keyed by its function names joined with `;`.
+    stacks: AHashMap<String, Duration>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct FunctionStats {
+    calls: u64,
+    /// Time until returning, not counting recursive calls twice.
+    inclusive: Duration,
+    /// Time not spent in calls to other functions.
+    exclusive: Duration,
+    /// Calls which have not returned yet.
+    active: u32,
+}
+
+struct ThreadProfile {
+    /// The call tree, starting with a root node that has no function.
+    nodes: Vec<CallNode>,
+    /// The calls which have not returned yet, innermost last.
+    frames: Vec<ActiveCall>,
+    /// Keyed by function address.
+    functions: AHashMap<usize, FunctionStats>,
+}
+
+struct CallNode {
+    func: *const Function<'static>,
+    parent: usize,
+    children: AHashMap<usize, usize>,
+    exclusive: Duration,
+}
+
+struct ActiveCall {
+    node: usize,
+    start: Instant,
+    /// Inclusive time of the calls this one made.
+    child_time: Duration,
+}
+
+impl ThreadProfile {
+    fn new() -> Self {
+        Self {
+            nodes: vec![CallNode {
+                func: std::ptr::null(),
+                parent: 0,
+                children: AHashMap::new(),
+                exclusive: Duration::ZERO,
+            }],
+            frames: Vec::new(),
+            functions: AHashMap::new(),
+        }
+    }
+
+    fn enter(&mut self, func: &Function) -> usize {
+        let depth = self.frames.len();
+        let parent = self.frames.last().map_or(0, |frame| frame.node);
+        let key = func as *const Function as usize;
+
+        let node = match self.nodes[parent].children.get(&key) {
+            Some(node) => *node,
+            None => {
+                let node = self.nodes.len();
+                self.nodes.push(CallNode {
+                    func: key as *const Function,
+                    parent,
+                    children: AHashMap::new(),
+                    exclusive: Duration::ZERO,
+                });
+                self.nodes[parent].children.insert(key, node);
+                node
+            }
+        };
+
+        let stats = self.functions.entry(key).or_default();
+        stats.calls += 1;
+        stats.active += 1;
+
+        self.frames.push(ActiveCall {
+            node,
+            start: Instant::now(),
+            child_time: Duration::ZERO,
+        });
+        depth
+    }
+
+    /// Ends the call at `depth`, and any calls above it which were unwound by a panic.
+    fn exit(&mut self, depth: usize) {
+        let now = Instant::now();
+        while self.frames.len() > depth {
+            let frame = self.frames.pop().unwrap();
+            let elapsed = now - frame.start;
+            let exclusive = elapsed.saturating_sub(frame.child_time);
+
+            let node = &mut self.nodes[frame.node];
+            node.exclusive += exclusive;
+
+            let stats = self.functions.get_mut(&(node.func as usize)).unwrap();
+            stats.exclusive += exclusive;
+            stats.active -= 1;
+            if stats.active == 0 {
+                stats.inclusive += elapsed;
+            }
+
+            if let Some(parent) = self.frames.last_mut() {
+                parent.child_time += elapsed;
+            }
+        }
+    }
+
+    /// Moves everything recorded so far into the global profile.
+    fn merge_into(&mut self, profile: &mut FunctionProfile) {
+        // functions are arena allocated, and outlive any thread calling them
+        let name = |func: *const Function| unsafe { (*func).debug_name() };
+
+        for (func, stats) in self.functions.drain() {
+            let total = profile
+                .functions
+                .entry(name(func as *const Function))
+                .or_default();
+            total.calls += stats.calls;
+            total.inclusive += stats.inclusive;
+            total.exclusive += stats.exclusive;
+        }
+
+        let mut names = Vec::with_capacity(self.nodes.len());
+        names.push(String::new());
+        for index in 1..self.nodes.len() {
+            let node = &self.nodes[index];
+            // flamegraph tools split stacks on `;`, which can appear in array types
+            let func_name = name(node.func).replace(';', ":");
+            let stack = if node.parent == 0 {
+                func_name
+            } else {
+                format!("{};{}", names[node.parent], func_name)
+            };
+            if !node.exclusive.is_zero() {
+                *profile.stacks.entry(stack.clone()).or_default() += node.exclusive;
+            }
+            names.push(stack);
+        }
+
+        self.nodes.truncate(1);
+        self.nodes[0].children.clear();
+    }
+}
+
+impl Drop for ThreadProfile {
+    fn drop(&mut self) {
+        if self.nodes.len() > 1 {
+            self.merge_into(&mut FUNCTION_PROFILE.lock().unwrap());
+        }
+    }
+}
+
+/// Records a call to `func` on the current thread. Returns a depth to pass to
+/// `profile_function_exit` when it returns.
+pub(crate) fn profile_function_enter(func: &Function) -> usize {
+    THREAD_PROFILE.with(|profile| profile.borrow_mut().enter(func))
+}
+
+pub(crate) fn profile_function_exit(depth: usize) {
+    THREAD_PROFILE.with(|profile| profile.borrow_mut().exit(depth));
+}
+
+/// Merges the current thread's function profile, which must have no calls in progress.
+fn flush_function_profile() -> std::sync::MutexGuard<'static, FunctionProfile> {
+    let mut profile = FUNCTION_PROFILE.lock().unwrap();
+    THREAD_PROFILE.with(|thread| thread.borrow_mut().merge_into(&mut profile));
+    profile
+}
+
+/// Prints the functions recorded by `--profile-functions` to stderr, by self time, most first.
+pub fn function_profile_log(limit: usize) {
+    let profile = flush_function_profile();
+
+    let mut functions: Vec<_> = profile.functions.iter().collect();
+    functions.sort_by(|(_, a), (_, b)| b.exclusive.cmp(&a.exclusive));
+    let total: Duration = functions.iter().map(|(_, stats)| stats.exclusive).sum();
+    let calls: u64 = functions.iter().map(|(_, stats)| stats.calls).sum();
+
+    eprintln!("FUNCTION PROFILE: {:.3?} in {} calls", total, calls);
+    eprintln!(
+        "    {:>10} {:>12} {:>7} {:>12}  function",
+        "calls", "self", "", "inclusive"
+    );
+    for (name, stats) in functions.iter().take(limit) {
+        let percent = stats.exclusive.as_secs_f64() / total.as_secs_f64().max(1e-9) * 100.0;
+        eprintln!(
+            "    {:>10} {:>12.3?} {:>6.2}% {:>12.3?}  {}",
+            stats.calls, stats.exclusive, percent, stats.inclusive, name
+        );
+    }
+    if functions.len() > limit {
+        eprintln!("    ... {} more", functions.len() - limit);
+    }
+}
+
+/// Writes the stacks recorded by `--profile-functions` in the collapsed format read by
+/// flamegraph tools: one line per stack, with its self time in microseconds.
+pub fn write_collapsed_stacks(path: &Path) -> std::io::Result<()> {
+    let profile = flush_function_profile();
+
+    let mut stacks: Vec<_> = profile.stacks.iter().collect();
+    stacks.sort();
+
+    let mut out = BufWriter::new(File::create(path)?);
+    for (stack, time) in stacks {
+        let micros = time.as_micros();
+        if micros > 0 {
+            writeln!(out, "{} {}", stack, micros)?;
+        }
+    }
+    out.flush()
+}
+
+/// Whether skitter was built with the `profile-opcodes` feature, which `--profile-opcodes` needs.
+pub const OPCODE_PROFILING: bool = cfg!(feature = "profile-opcodes");
+
+/// Opcodes are below this, so pairs of them fit in a flat table.
+#[cfg(feature = "profile-opcodes")]
+const OPCODE_LIMIT: usize = 1 << 10;
+
+/// Instructions run with `--profile-opcodes`. Merged from each thread's counts when it exits.
+#[cfg(feature = "profile-opcodes")]
+static OPCODE_PROFILE: LazyLock<Mutex<OpcodeProfile>> = LazyLock::new(Default::default);
+
+#[cfg(feature = "profile-opcodes")]
+thread_local! {
+    static THREAD_OPCODES: RefCell<OpcodeCounts> = RefCell::new(OpcodeCounts::default());
+}
+
+#[cfg(feature = "profile-opcodes")]
+#[derive(Default)]
+struct OpcodeProfile {
+    counts: AHashMap<u16, u64>,
+    pairs: AHashMap<(u16, u16), u64>,
+    /// Variant names, filled in when an opcode is first run.
+    names: AHashMap<u16, String>,
+}
+
+#[cfg(feature = "profile-opcodes")]
+#[derive(Default)]
+struct OpcodeCounts {
+    counts: Vec<u64>,
+    /// Indexed by the previous opcode times `OPCODE_LIMIT` plus the current one. Allocated when
+    /// pairs are first counted.
+    pairs: Vec<u64>,
+    prev: usize,
+}
+
+#[cfg(feature = "profile-opcodes")]
+impl OpcodeCounts {
+    fn record(&mut self, op: u16, pairs: bool) {
+        let op = op as usize;
+
+        if op >= self.counts.len() {
+            self.counts.resize(op + 1, 0);
+        }
+        if self.counts[op] == 0 {
+            name_opcode(op as u16);
+        }
+        self.counts[op] += 1;
+
+        if pairs {
+            if self.pairs.is_empty() {
+                self.pairs = vec![0; OPCODE_LIMIT * OPCODE_LIMIT];
+            }
+            self.pairs[self.prev * OPCODE_LIMIT + op] += 1;
+            self.prev = op;
+        }
+    }
+
+    fn merge_into(&mut self, profile: &mut OpcodeProfile) {
+        for (op, count) in self.counts.iter_mut().enumerate() {
+            if *count > 0 {
+                *profile.counts.entry(op as u16).or_default() += *count;
+                *count = 0;
+            }
+        }
+        for (index, count) in self.pairs.iter_mut().enumerate() {
+            if *count > 0 {
+                let pair = ((index / OPCODE_LIMIT) as u16, (index % OPCODE_LIMIT) as u16);
+                *profile.pairs.entry(pair).or_default() += *count;
+                *count = 0;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "profile-opcodes")]
+impl Drop for OpcodeCounts {
+    fn drop(&mut self) {
+        self.merge_into(&mut OPCODE_PROFILE.lock().unwrap());
+    }
+}
+
+#[cfg(feature = "profile-opcodes")]
+#[cold]
+fn name_opcode(op: u16) {
+    let mut profile = OPCODE_PROFILE.lock().unwrap();
+    profile
+        .names
+        .entry(op)
+        .or_insert_with(|| opcode::NAMES[op as usize].to_owned());
+}
+
+/// Counts an instruction about to be run on the current thread by its opcode, and optionally the
+/// pair it forms with the one run before it.
+#[cfg(feature = "profile-opcodes")]
+pub(crate) fn profile_opcode(op: u16, pairs: bool) {
+    THREAD_OPCODES.with(|counts| counts.borrow_mut().record(op, pairs));
+}
+
+/// Prints the opcodes run with `--profile-opcodes` to stderr, most run first, followed by the
+/// pairs of consecutive opcodes if they were counted.
+#[cfg(feature = "profile-opcodes")]
+pub fn opcode_profile_log(limit: usize) {
+    let mut profile = OPCODE_PROFILE.lock().unwrap();
+    THREAD_OPCODES.with(|counts| counts.borrow_mut().merge_into(&mut profile));
+
+    for line in opcode_profile_lines(&profile, limit) {
+        eprintln!("{}", line);
+    }
+}
+
+/// Ties are broken by opcode, so the output doesn't depend on hash map order.
+#[cfg(feature = "profile-opcodes")]
+fn opcode_profile_lines(profile: &OpcodeProfile, limit: usize) -> Vec<String> {
+    let name = |op: &u16| profile.names.get(op).map_or("?", |name| name.as_str());
+    let total: u64 = profile.counts.values().sum();
+    let percent = |count: u64| count as f64 / total.max(1) as f64 * 100.0;
+    let mut lines = Vec::new();
+
+    let mut counts: Vec<_> = profile.counts.iter().collect();
+    counts.sort_by(|(op_a, a), (op_b, b)| b.cmp(a).then(op_a.cmp(op_b)));
+    lines.push(format!("OPCODE PROFILE: {} instructions", total));
+    for (op, count) in counts.iter().take(limit) {
+        lines.push(format!(
+            "    {:>14} {:>6.2}%  {}",
+            count,
+            percent(**count),
+            name(op)
+        ));
+    }
+    if counts.len() > limit {
+        lines.push(format!("    ... {} more", counts.len() - limit));
+    }
+
+    if profile.pairs.is_empty() {
+        return lines;
+    }
+    let mut pairs: Vec<_> = profile.pairs.iter().collect();
+    pairs.sort_by(|(pair_a, a), (pair_b, b)| b.cmp(a).then(pair_a.cmp(pair_b)));
+    lines.push("OPCODE PAIRS:".to_owned());
+    for ((first, second), count) in pairs.iter().take(limit) {
+        lines.push(format!(
+            "    {:>14} {:>6.2}%  {} -> {}",
+            count,
+            percent(**count),
+            name(first),
+            name(second)
+        ));
+    }
+    if pairs.len() > limit {
+        lines.push(format!("    ... {} more", pairs.len() - limit));
+    }
+    lines
+}
+
+#[cfg(all(test, feature = "profile-opcodes"))]
+mod tests {
+    use super::*;
+
+    fn profile(counts: &[(u16, &str, u64)], pairs: &[((u16, u16), u64)]) -> OpcodeProfile {
+        OpcodeProfile {
+            counts: counts.iter().map(|(op, _, count)| (*op, *count)).collect(),
+            pairs: pairs.iter().copied().collect(),
+            names: counts
+                .iter()
+                .map(|(op, name, _)| (*op, name.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn opcodes_sorted_by_count() {
+        let profile = profile(
+            &[
+                (3, "Call", 10),
+                (1, "MovSS4", 70),
+                (2, "Jump", 10),
+                (5, "Return", 10),
+            ],
+            &[],
+        );
+
+        assert_eq!(
+            opcode_profile_lines(&profile, 3),
+            [
+                "OPCODE PROFILE: 100 instructions",
+                "                70  70.00%  MovSS4",
+                "                10  10.00%  Jump",
+                "                10  10.00%  Call",
+                "    ... 1 more",
+            ]
+        );
+    }
+
+    #[test]
+    fn pairs_follow_opcodes() {
+        let profile = profile(
+            &[(1, "MovSS4", 3), (2, "Jump", 1)],
+            &[((1, 2), 1), ((1, 1), 2), ((0, 1), 1)],
+        );
+
+        assert_eq!(
+            opcode_profile_lines(&profile, 10),
+            [
+                "OPCODE PROFILE: 4 instructions",
+                "                 3  75.00%  MovSS4",
+                "                 1  25.00%  Jump",
+                "OPCODE PAIRS:",
+                "                 2  50.00%  MovSS4 -> MovSS4",
+                "                 1  25.00%  ? -> MovSS4",
+                "                 1  25.00%  MovSS4 -> Jump",
+            ]
+        );
+    }
+}
@@ -1,10 +1,11 @@
 use std::{
+    path::PathBuf,
     rc::Rc,
     sync::{Arc, Barrier, Mutex, OnceLock},
     time::Instant,
 };
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use rustc_hir::def_id::{DefId, LocalDefId};
 use rustc_hir::AssocItemKind;
 use rustc_hir::ItemKind as HirItemKind;
@@ -12,16 +13,22 @@ use rustc_hir::VariantData;
 use rustc_middle::hir::map::Map as HirMap;
 use rustc_middle::ty::{ImplSubject, Ty, TyCtxt};
 use rustc_session::config;
+use rustc_span::edition::Edition;
+use rustc_span::symbol::sym;
 
 use crate::{
     builtins::{BuiltinAdt, BuiltinTrait},
     crate_provider::{CrateProvider, TraitImpl},
     impls::{ImplBounds, ImplTable, ImplTableSimple},
-    ir::{converter::IRFunctionConverter, IRFunction, IRKind},
+    ir::{
+        converter::IRFunctionConverter,
+        glue_builder::{glue_for_foreign_fn, glue_for_literal},
+        IRFunction, IRKind,
+    },
     items::{
         ident_from_rustc, path_from_rustc, AdtInfo, AdtKind, AssocValue, BoundKind, CrateId,
-        DiscriminantSource, EnumInfo, ExternCrate, FunctionAbi, GenericCounts, Item, ItemId,
-        ItemKind, ItemPath,
+        DiscriminantSource, EnumInfo, ExternCrate, FunctionAbi, FunctionSig, GenericCounts, Item,
+        ItemId, ItemKind, ItemPath,
     },
     lazy_collections::{LazyArray, LazyTable},
     persist::{Persist, PersistWriteContext, PersistWriter},
@@ -29,7 +36,7 @@ use crate::{
     types::{Sub, SubList, Type},
     variants::{VariantIndex, Variants},
     vm::VM,
-    CratePath,
+    CratePath, SYSROOT,
 };
 
 /////////////////////////
@@ -88,6 +95,13 @@ pub struct RustCWorkerConfig {
     pub crate_path: CratePath,
     pub extern_crates: Vec<ExternCrate>,
     pub save_file: bool,
+    pub edition: Edition,
+    /// Extra `--cfg` options, like `feature="std"`.
+    pub crate_cfg: Vec<String>,
+    /// Metadata files for dependencies outside the sysroot, by the name they are imported with.
+    pub extern_metadata: Vec<(String, PathBuf)>,
+    /// Write this crate's metadata here, so crates which depend on it can be type-checked.
+    pub metadata_out: Option<PathBuf>,
 }
 
 impl RustCWorkerConfig {
@@ -99,8 +113,80 @@ impl RustCWorkerConfig {
     }
 }
 
+/// The dependencies of std, which are only available as rlibs in the sysroot.
+/// rustc's own dependencies are shipped as rmeta files, so only rlibs are considered.
+fn std_externs() -> config::Externs {
+    const STD_DEPS: &[&str] = &[
+        "core",
+        "alloc",
+        "libc",
+        "cfg_if",
+        "compiler_builtins",
+        "hashbrown",
+        "std_detect",
+        "rustc_demangle",
+        "unwind",
+        "panic_abort",
+    ];
+
+    let lib_dir = SYSROOT
+        .join("lib/rustlib")
+        .join(config::host_triple())
+        .join("lib");
+    let lib_files: Vec<_> = std::fs::read_dir(lib_dir)
+        .expect("failed to read sysroot")
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+
+    let paths: Vec<_> = STD_DEPS
+        .iter()
+        .map(|name| {
+            let prefix = format!("lib{}-", name);
+            let path = lib_files
+                .iter()
+                .find(|path| {
+                    let file_name = path.file_name().unwrap().to_string_lossy();
+                    file_name.starts_with(&prefix) && file_name.ends_with(".rlib")
+                })
+                .unwrap_or_else(|| panic!("failed to find std dependency: {}", name));
+
+            (name.to_string(), path.clone())
+        })
+        .collect();
+
+    exact_path_externs(&paths)
+}
+
+/// Externs which are passed to rustc as `--extern name=path`.
+fn exact_path_externs(extern_paths: &[(String, PathBuf)]) -> config::Externs {
+    let mut externs = std::collections::BTreeMap::new();
+    for (name, path) in extern_paths {
+        let mut paths = std::collections::BTreeSet::new();
+        paths.insert(rustc_session::utils::CanonicalizedPath::new(path));
+
+        externs.insert(
+            name.clone(),
+            config::ExternEntry {
+                location: config::ExternLocation::ExactPaths(paths),
+                is_private_dep: false,
+                add_prelude: true,
+                nounused_dep: true,
+                force: false,
+            },
+        );
+    }
+
+    config::Externs::new(externs)
+}
+
 impl<'vm> RustCWorker<'vm> {
-    pub fn new(worker_config: RustCWorkerConfig, vm: &'vm VM<'vm>, this_crate: CrateId) -> Self
+    /// Starts rustc on the crate, failing if it doesn't compile.
+    pub fn new(
+        worker_config: RustCWorkerConfig,
+        vm: &'vm VM<'vm>,
+        this_crate: CrateId,
+    ) -> Result<Self, String>
     where
         'vm: 'static,
     {
@@ -113,6 +199,55 @@ impl<'vm> RustCWorker<'vm> {
             config::SwitchWithOptPath::Disabled
         };
 
+        let lint_cap = if vm.options.no_warnings {
+            Some(rustc_session::lint::Level::Allow)
+        } else {
+            None
+        };
+
+        // internal crates keep their defaults, so cached IR does not depend on the profile
+        let (overflow_checks, debug_assertions) = if worker_config.crate_path.is_internal() {
+            (false, config::Options::default().debug_assertions)
+        } else {
+            (vm.options.debug_profile, vm.options.debug_profile)
+        };
+
+        // std is normally built by cargo, which passes its dependencies and build script output
+        let (externs, crate_cfg) = if worker_config.crate_path.is_std() {
+            std::env::set_var("STD_ENV_ARCH", std::env::consts::ARCH);
+            (std_externs(), vec!["backtrace_in_libstd".to_owned()])
+        } else {
+            (
+                exact_path_externs(&worker_config.extern_metadata),
+                worker_config.crate_cfg.clone(),
+            )
+        };
+
+        // like cargo, tell apart crates with the same name, such as a package's library and binary
+        let metadata = if worker_config.crate_path.is_internal() {
+            vec![]
+        } else {
+            let source_path = worker_config.crate_path.source_path();
+            vec![source_path.to_string_lossy().into_owned()]
+        };
+
+        let input = if let Some(source) = worker_config.crate_path.source() {
+            config::Input::Str {
+                name: rustc_span::FileName::Custom(worker_config.crate_path.name.clone()),
+                input: source.to_owned(),
+            }
+        } else {
+            config::Input::File(worker_config.crate_path.source_path())
+        };
+
+        // like rustc, show paths relative to where we were run, in diagnostics and panic locations
+        let remap_path_prefix = if worker_config.crate_path.is_internal() {
+            vec![]
+        } else {
+            let cwd = std::env::current_dir().expect("no current dir");
+            vec![(cwd, PathBuf::new())]
+        };
+
         let config = rustc_interface::Config {
             opts: config::Options {
                 crate_name: Some(worker_config.crate_path.name.clone()),
@@ -120,20 +255,27 @@ impl<'vm> RustCWorker<'vm> {
                 // otherwise rustc assumes we're building a binary and
                 // demands an allocator, panic handler, etc.
                 crate_types: vec![config::CrateType::Rlib],
-                edition: rustc_span::edition::Edition::Edition2021,
+                edition: worker_config.edition,
                 unstable_features: rustc_feature::UnstableFeatures::Cheat,
                 cg: config::CodegenOptions {
-                    overflow_checks: Some(false),
+                    overflow_checks: Some(overflow_checks),
+                    metadata,
                     ..config::CodegenOptions::default()
                 },
                 unstable_opts: config::UnstableOptions {
                     self_profile,
+                    // std's dependencies come from the sysroot, which is only allowed for std itself
+                    force_unstable_if_unmarked: worker_config.crate_path.is_std(),
                     ..config::UnstableOptions::default()
                 },
+                lint_cap,
+                debug_assertions,
+                externs,
+                remap_path_prefix,
                 ..config::Options::default()
             },
-            input: config::Input::File(worker_config.crate_path.source_path()),
-            crate_cfg: Default::default(),
+            input,
+            crate_cfg,
             crate_check_cfg: Default::default(),
             // (Some(Mode::Std), "backtrace_in_libstd", None),
             output_dir: None,
@@ -153,10 +295,33 @@ impl<'vm> RustCWorker<'vm> {
             ice_file: None,
         };
 
+        // the crate is checked before anything is loaded from it, and dependent crates can't be
+        // compiled until its metadata is written
+        let (ready_sender, ready_recv) = std::sync::mpsc::channel::<()>();
+        let crate_name = worker_config.crate_path.name.clone();
+
         std::thread::spawn(move || {
             rustc_interface::run_compiler(config, |compiler| {
                 compiler.enter(move |queries| {
-                    queries.global_ctxt().unwrap().enter(|tcx| {
+                    let Ok(mut gcx) = queries.global_ctxt() else {
+                        return;
+                    };
+                    gcx.enter(|tcx| {
+                        // internal crates are known to compile, and checking them is slow.
+                        // Others must also be fully resolved before metadata freezes the crate
+                        // store.
+                        if !worker_config.crate_path.is_internal() {
+                            let res = tcx.analysis(());
+                            if res.is_err() || tcx.sess.has_errors().is_some() {
+                                return;
+                            }
+                        }
+
+                        if let Some(path) = &worker_config.metadata_out {
+                            rustc_metadata::encode_metadata(tcx, path);
+                        }
+                        ready_sender.send(()).unwrap();
+
                         let ctx = RustCContext::new(vm, tcx, this_crate, worker_config);
 
                         loop {
@@ -168,10 +333,15 @@ impl<'vm> RustCWorker<'vm> {
             });
         });
 
-        RustCWorker {
+        // rustc has already printed the errors, if the worker stopped
+        if ready_recv.recv().is_err() {
+            return Err(format!("could not compile `{}`", crate_name));
+        }
+
+        Ok(RustCWorker {
             sender: Mutex::new(sender),
             items: Default::default(),
-        }
+        })
     }
 
     fn call<T, F>(&self, func: F) -> Arc<WorkerResult<T>>
@@ -212,6 +382,10 @@ impl<'vm> CrateProvider<'vm> for RustCWorker<'vm> {
         items.items[item_id.index()].item
     }
 
+    fn all_items(&self) -> Vec<&'vm Item<'vm>> {
+        self.items().items.iter().map(|item| item.item).collect()
+    }
+
     fn item_by_path(&self, path: &ItemPath) -> Option<&'vm Item<'vm>> {
         let items = self.items();
         items.item_by_path(path)
@@ -254,19 +428,43 @@ impl<'vm> CrateProvider<'vm> for RustCWorker<'vm> {
 
 fn build_ir<'vm, 'tcx>(
     ctx: &RustCContext<'vm, 'tcx>,
-    did: LocalDefId,
+    did: DefId,
     ir_kind: IRKind,
 ) -> Option<Arc<IRFunction<'vm>>> {
     let hir = ctx.tcx.hir();
 
+    // foreign functions have no body, but calling them requires their signature
+    if ir_kind == IRKind::Function && ctx.tcx.is_foreign_item(did) {
+        let rs_sig = ctx.tcx.fn_sig(did).instantiate_identity().skip_binder();
+        let sig = FunctionSig::from_rustc(&rs_sig, ctx);
+        return Some(Arc::new(glue_for_foreign_fn(sig)));
+    }
+
+    // items absorbed from bundled crates have no source, but scalar constants can be evaluated
+    if ctx.is_bundled(did) {
+        use rustc_hir::def::DefKind;
+        if !matches!(ctx.tcx.def_kind(did), DefKind::Const | DefKind::AssocConst) {
+            return None;
+        }
+        let const_val = ctx.tcx.const_eval_poly(did).ok()?;
+        let scalar = const_val.try_to_scalar_int()?;
+        let n = scalar.to_bits(scalar.size()).unwrap() as i128;
+
+        let ty = ctx.type_from_rustc(ctx.tcx.type_of(did).instantiate_identity());
+        return Some(Arc::new(glue_for_literal(ty, n)));
+    }
+
+    let did = did.as_local()?;
+
     if let Some(body_id) = hir.maybe_body_owned_by(did) {
         let body = hir.body(body_id);
 
         let types = ctx.tcx.typeck(did);
 
-        Some(Arc::new(IRFunctionConverter::run(
-            ctx, did, body, types, ir_kind,
-        )))
+        let mut ir = IRFunctionConverter::run(ctx, did, body, types, ir_kind);
+        ir.inherit_overflow_checks = ctx.tcx.has_attr(did, sym::rustc_inherit_overflow_checks);
+
+        Some(Arc::new(ir))
     } else {
         None
     }
@@ -280,6 +478,8 @@ pub struct RustCContext<'vm, 'tcx> {
 
     extern_crates: Vec<ExternCrate>,
     extern_crate_id_cache: Mutex<AHashMap<rustc_span::def_id::CrateNum, CrateId>>,
+    /// Crates without source whose items are indexed into this crate. See `index_bundled_crate`.
+    bundled_crates: Vec<rustc_span::def_id::CrateNum>,
 }
 
 impl<'vm, 'tcx> RustCContext<'vm, 'tcx> {
@@ -295,7 +495,7 @@ impl<'vm, 'tcx> RustCContext<'vm, 'tcx> {
 
         let t = Instant::now();
         let hir_items = hir.items();
-        if vm.cli_args.verbose {
+        if vm.options.verbose {
             println!("rustc hir items took {:?}", t.elapsed());
         }
 
@@ -331,7 +531,8 @@ impl<'vm, 'tcx> RustCContext<'vm, 'tcx> {
                 }
                 HirItemKind::Static(..) => {
                     let item_path = path_from_rustc(&hir.def_path(local_id), vm);
-                    let kind = ItemKind::new_static();
+                    let thread_local = tcx.is_thread_local_static(local_id.to_def_id());
+                    let kind = ItemKind::new_static(thread_local);
 
                     items.index_item(kind, item_path, local_id, vm);
                 }
@@ -482,25 +683,22 @@ impl<'vm, 'tcx> RustCContext<'vm, 'tcx> {
 
                         let item = hir.foreign_item(item.id);
 
-                        use rustc_target::spec::abi::Abi;
-                        let abi = match abi {
-                            Abi::Rust => FunctionAbi::Rust,
-                            Abi::RustIntrinsic => FunctionAbi::RustIntrinsic,
-                            Abi::C { .. } => FunctionAbi::C,
-                            Abi::PlatformIntrinsic => FunctionAbi::PlatformIntrinsic,
-                            Abi::Unadjusted => FunctionAbi::Unadjusted,
-                            _ => panic!("abi? {:?}", abi),
-                        };
+                        let abi = FunctionAbi::from_rustc(abi);
 
                         use rustc_hir::ForeignItemKind;
+                        let link_name = tcx
+                            .codegen_fn_attrs(local_id)
+                            .link_name
+                            .unwrap_or(item.ident.name);
+
                         match item.kind {
                             ForeignItemKind::Fn(..) => {
-                                let ident = item.ident.as_str().to_owned();
+                                let ident = link_name.as_str().to_owned();
                                 let kind = ItemKind::new_function_extern(abi, ident);
                                 items.index_item(kind, item_path, local_id, vm);
                             }
                             ForeignItemKind::Static(_, _) => {
-                                let ident = item.ident.as_str().to_owned();
+                                let ident = link_name.as_str().to_owned();
                                 let kind = ItemKind::new_static_extern(abi, ident);
                                 items.index_item(kind, item_path, local_id, vm);
                             }
@@ -514,12 +712,34 @@ impl<'vm, 'tcx> RustCContext<'vm, 'tcx> {
             }
         }
 
+        // std's dependencies are only available as rlibs, so std provides their items itself
+        let bundled_crates = if worker_config.crate_path.is_std() {
+            let bundled_crates: Vec<_> = tcx
+                .crates(())
+                .iter()
+                .copied()
+                .filter(|crate_num| {
+                    let crate_name = tcx.crate_name(*crate_num);
+                    worker_config.find_extern(crate_name.as_str()).is_none()
+                })
+                .collect();
+
+            for crate_num in &bundled_crates {
+                index_bundled_crate(tcx, *crate_num, &mut items, &mut adt_ids, vm);
+            }
+
+            bundled_crates
+        } else {
+            vec![]
+        };
+
         let ctx = RustCContext {
             vm,
             tcx,
             items: Arc::new(items),
             extern_crates: worker_config.extern_crates,
             extern_crate_id_cache: Default::default(),
+            bundled_crates,
         };
 
         // fill ADT fields
@@ -543,19 +763,25 @@ impl<'vm, 'tcx> RustCContext<'vm, 'tcx> {
                     use rustc_middle::ty::VariantDiscr;
 
                     match variant.discr {
-                        VariantDiscr::Explicit(did) => {
-                            let did = if did.krate == rustc_hir::def_id::LOCAL_CRATE {
-                                rustc_hir::def_id::LocalDefId {
-                                    local_def_index: did.index,
-                                }
-                            } else {
-                                panic!("variant has non-local did");
-                            };
-
+                        VariantDiscr::Explicit(did) if did.is_local() => {
                             let ir = build_ir(&ctx, did, IRKind::Constant)
                                 .expect("missing discriminant");
                             DiscriminantSource::Explicit(ir)
                         }
+                        VariantDiscr::Explicit(_) => {
+                            // bundled crates have no source, use rustc's value instead
+                            let discr = adt_def.discriminant_for_variant(
+                                tcx,
+                                rustc_target::abi::VariantIdx::from_usize(i),
+                            );
+                            let val = if discr.ty.is_signed() {
+                                let size = discr.ty.primitive_size(tcx);
+                                size.sign_extend(discr.val) as i128
+                            } else {
+                                discr.val as i128
+                            };
+                            DiscriminantSource::Value(val)
+                        }
                         VariantDiscr::Relative(_) => DiscriminantSource::Next,
                     }
                 } else {
@@ -807,7 +1033,7 @@ impl<'vm, 'tcx> RustCContext<'vm, 'tcx> {
             }
         }
 
-        if vm.cli_args.verbose {
+        if vm.options.verbose {
             println!("item aggregation took {:?}", t.elapsed());
             println!("n = {}", ctx.items.items.len());
         }
@@ -896,26 +1122,24 @@ impl<'vm, 'tcx> RustCContext<'vm, 'tcx> {
         }
     }
 
+    /// Is this item from a crate we only have metadata for?
+    pub fn is_bundled(&self, did: DefId) -> bool {
+        self.bundled_crates.contains(&did.krate)
+    }
+
     pub fn type_from_rustc(&self, ty: Ty<'tcx>) -> Type<'vm> {
         self.vm.types.type_from_rustc(ty, self)
     }
 
     pub fn item_by_did(&self, did: DefId) -> Option<&'vm Item<'vm>> {
-        if did.krate == rustc_hir::def_id::LOCAL_CRATE {
-            let local_did = rustc_hir::def_id::LocalDefId {
-                local_def_index: did.index,
-            };
-            self.items
-                .map_defs
-                .get(&local_did)
-                .map(|item_id| self.items.items[item_id.index()].item)
-        } else {
-            None
-        }
+        self.items
+            .map_defs
+            .get(&did)
+            .map(|item_id| self.items.items[item_id.index()].item)
     }
 
     pub fn find_crate_id(&self, crate_num: rustc_span::def_id::CrateNum) -> CrateId {
-        if crate_num == rustc_hir::def_id::LOCAL_CRATE {
+        if crate_num == rustc_hir::def_id::LOCAL_CRATE || self.bundled_crates.contains(&crate_num) {
             self.items.crate_id
         } else {
             let mut cache = self.extern_crate_id_cache.lock().unwrap();
@@ -957,16 +1181,135 @@ impl<'vm, 'tcx> RustCContext<'vm, 'tcx> {
     }
 }
 
+/// Index the public interface of a crate we only have metadata for: types, traits, and
+/// functions. None of these items have IR, so only extern functions and statics can
+/// actually be used at runtime. Impls are skipped entirely.
+fn index_bundled_crate<'vm>(
+    tcx: TyCtxt<'_>,
+    crate_num: rustc_span::def_id::CrateNum,
+    items: &mut RustCItems<'vm>,
+    adt_ids: &mut Vec<ItemId>,
+    vm: &'vm VM<'vm>,
+) {
+    use rustc_hir::def::{CtorKind, DefKind, Res};
+    use rustc_middle::ty::AssocKind;
+
+    // prefix paths with the crate name, they would otherwise collide with the crate's own items
+    let crate_name = tcx.crate_name(crate_num);
+    let bundled_path = |did: DefId| {
+        let path = path_from_rustc(&tcx.def_path(did), vm);
+        ItemPath::for_debug(vm.alloc_path(&format!("::{}{}", crate_name, path.as_string())))
+    };
+    let link_name = |did: DefId| {
+        let attrs = tcx.codegen_fn_attrs(did);
+        attrs
+            .link_name
+            .unwrap_or_else(|| tcx.item_name(did))
+            .to_string()
+    };
+
+    let mut mod_queue = vec![crate_num.as_def_id()];
+    let mut seen = AHashSet::new();
+
+    while let Some(mod_did) = mod_queue.pop() {
+        for child in tcx.module_children(mod_did) {
+            let Res::Def(def_kind, did) = child.res else {
+                continue;
+            };
+            // skip re-exports
+            if did.krate != crate_num || !seen.insert(did) {
+                continue;
+            }
+
+            match def_kind {
+                DefKind::Mod => mod_queue.push(did),
+                DefKind::Fn => {
+                    let kind = if tcx.is_foreign_item(did) {
+                        let abi = FunctionAbi::from_rustc(tcx.fn_sig(did).skip_binder().abi());
+                        ItemKind::new_function_extern(abi, link_name(did))
+                    } else {
+                        ItemKind::new_function()
+                    };
+                    items.index_item(kind, bundled_path(did), did, vm);
+                }
+                DefKind::Static(_) => {
+                    let kind = if tcx.is_foreign_item(did) {
+                        ItemKind::new_static_extern(FunctionAbi::C, link_name(did))
+                    } else {
+                        ItemKind::new_static(tcx.is_thread_local_static(did))
+                    };
+                    items.index_item(kind, bundled_path(did), did, vm);
+                }
+                DefKind::Const => {
+                    items.index_item(ItemKind::new_const(), bundled_path(did), did, vm);
+                }
+                DefKind::Struct | DefKind::Union | DefKind::Enum => {
+                    let adt_id = items.index_item(ItemKind::new_adt(), bundled_path(did), did, vm);
+                    adt_ids.push(adt_id);
+
+                    let adt_def = tcx.adt_def(did);
+                    for (index, variant) in adt_def.variants().iter().enumerate() {
+                        if let Some((ctor_kind, ctor_did)) = variant.ctor {
+                            let variant = VariantIndex::new(index as u32);
+                            let kind = match ctor_kind {
+                                CtorKind::Fn => ItemKind::new_function_ctor(adt_id, variant),
+                                CtorKind::Const => ItemKind::new_const_ctor(adt_id, variant),
+                            };
+                            items.index_item(kind, bundled_path(ctor_did), ctor_did, vm);
+                        }
+                    }
+
+                    // inherent impl members are found by def id, so they are never looked up
+                    for impl_did in tcx.inherent_impls(did) {
+                        for assoc_did in tcx.associated_item_def_ids(impl_did) {
+                            let kind = match tcx.associated_item(*assoc_did).kind {
+                                AssocKind::Fn => ItemKind::new_function(),
+                                AssocKind::Const => ItemKind::new_const(),
+                                AssocKind::Type => continue,
+                            };
+                            items.index_item(kind, bundled_path(*assoc_did), *assoc_did, vm);
+                        }
+                    }
+                }
+                DefKind::Trait => {
+                    let trait_id =
+                        items.index_item(ItemKind::new_trait(), bundled_path(did), did, vm);
+
+                    let mut assoc_value_map = AHashMap::new();
+                    for (id_in_trait, assoc_did) in
+                        tcx.associated_item_def_ids(did).iter().enumerate()
+                    {
+                        let id_in_trait = id_in_trait as u32;
+                        let item_ident = ident_from_rustc(&tcx.def_path(*assoc_did), vm);
+                        assoc_value_map.insert(item_ident, id_in_trait);
+
+                        let kind = match tcx.associated_item(*assoc_did).kind {
+                            AssocKind::Fn => ItemKind::new_function_virtual(trait_id, id_in_trait),
+                            AssocKind::Type => ItemKind::new_associated_type(trait_id, id_in_trait),
+                            AssocKind::Const => ItemKind::new_const_virtual(trait_id, id_in_trait),
+                        };
+                        items.index_item(kind, bundled_path(*assoc_did), *assoc_did, vm);
+                    }
+
+                    let trait_item = items.items[trait_id.index()].item;
+                    trait_item.trait_set_assoc_value_map(assoc_value_map);
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
 struct RustCItems<'vm> {
     crate_id: CrateId,
     items: Vec<RustCItem<'vm>>,
     map_paths: AHashMap<ItemPath<'vm>, ItemId>,
-    map_defs: AHashMap<LocalDefId, ItemId>,
+    map_defs: AHashMap<DefId, ItemId>,
     impls: OnceLock<ImplTableSimple<'vm>>,
 }
 
 struct RustCItem<'vm> {
-    did: LocalDefId,
+    did: DefId,
     item: &'vm Item<'vm>,
 }
 
@@ -985,9 +1328,10 @@ impl<'vm> RustCItems<'vm> {
         &mut self,
         kind: ItemKind<'vm>,
         path: ItemPath<'vm>,
-        did: LocalDefId,
+        did: impl Into<DefId>,
         vm: &'vm VM<'vm>,
     ) -> ItemId {
+        let did = did.into();
         let item_id = ItemId::new(self.items.len() as u32);
 
         let item = vm.alloc_item(Item::new(vm, self.crate_id, item_id, path, kind));
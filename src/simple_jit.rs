@@ -1,7 +1,10 @@
+use std::sync::atomic::Ordering;
+
 use dynasmrt::{dynasm, DynamicLabel, DynasmApi, DynasmLabelApi};
 
 use crate::{
     bytecode_compiler::FunctionBytecode,
+    bytecode_select::{self, Compare, CompareArg},
     vm::{instr::Instr, Function, NativeFunc, VMThread},
 };
 
@@ -25,11 +28,12 @@ pub fn compile<'vm>(bytecode: &'vm FunctionBytecode<'vm>) -> Result<NativeFunc,
 
     let labels: Vec<_> = bytecode
         .code
+        .instrs()
         .iter()
         .map(|_| ops.new_dynamic_label())
         .collect();
 
-    for (pc, (bc, label)) in bytecode.code.iter().zip(&labels).enumerate() {
+    for (pc, (bc, label)) in bytecode.code.instrs().iter().zip(&labels).enumerate() {
         dynasm!(ops
             ; =>*label
         );
@@ -78,6 +82,20 @@ pub fn compile<'vm>(bytecode: &'vm FunctionBytecode<'vm>) -> Result<NativeFunc,
                     ; mov [rdi + dst.index() as i32], rax
                 );
             }
+            Instr::I32_AddImm(dst, src, n) => {
+                dynasm!(ops
+                    ; mov eax, [rdi + src.index() as i32]
+                    ; add eax, *n
+                    ; mov [rdi + dst.index() as i32], eax
+                );
+            }
+            Instr::I64_AddImm(dst, src, n) => {
+                dynasm!(ops
+                    ; mov rax, [rdi + src.index() as i32]
+                    ; add rax, *n
+                    ; mov [rdi + dst.index() as i32], rax
+                );
+            }
             Instr::I32_Sub(dst, lhs, rhs) => {
                 dynasm!(ops
                     ; mov eax, [rdi + lhs.index() as i32]
@@ -356,6 +374,7 @@ pub fn compile<'vm>(bytecode: &'vm FunctionBytecode<'vm>) -> Result<NativeFunc,
                     ; mov rdi, [rsp + 8]
                 );
 
+                // FIXME: jit code has no unwind info, panics can't unwind through it
                 // self = rdi
                 //VMThread::call(&mut self, func, stack_offset)
                 // self.call(func,stack_offset + base.index() as u32);
@@ -397,7 +416,226 @@ pub fn compile<'vm>(bytecode: &'vm FunctionBytecode<'vm>) -> Result<NativeFunc,
                     ; mov [rdi + dst.index() as i32 + 1], al
                 );
             }
-            _ => return Err(format!("nyi: {:?}", bc)),
+            Instr::I32_AtomicLoad(_, out, ptr) => {
+                // plain loads are acquire on x86, and seqcst since seqcst stores use xchg
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov eax, [rcx]
+                    ; mov [rdi + out.index() as i32], eax
+                );
+            }
+            Instr::I32_AtomicStore(ordering, ptr, val) => {
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov eax, [rdi + val.index() as i32]
+                );
+                if *ordering == Ordering::SeqCst {
+                    dynasm!(ops
+                        ; xchg [rcx], eax
+                    );
+                } else {
+                    dynasm!(ops
+                        ; mov [rcx], eax
+                    );
+                }
+            }
+            Instr::I32_AtomicXchg(_, out, ptr, val) => {
+                // xchg with memory is implicitly locked
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov eax, [rdi + val.index() as i32]
+                    ; xchg [rcx], eax
+                    ; mov [rdi + out.index() as i32], eax
+                );
+            }
+            Instr::I32_AtomicAdd(_, out, ptr, val) => {
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov eax, [rdi + val.index() as i32]
+                    ; lock xadd [rcx], eax
+                    ; mov [rdi + out.index() as i32], eax
+                );
+            }
+            Instr::I32_AtomicSub(_, out, ptr, val) => {
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov eax, [rdi + val.index() as i32]
+                    ; neg eax
+                    ; lock xadd [rcx], eax
+                    ; mov [rdi + out.index() as i32], eax
+                );
+            }
+            Instr::I32_AtomicCxchg(_, _, out, ptr, val)
+            | Instr::I32_AtomicCxchgWeak(_, _, out, ptr, val) => {
+                // the expected value is in the output slot
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov eax, [rdi + out.index() as i32]
+                    ; mov edx, [rdi + val.index() as i32]
+                    ; lock cmpxchg [rcx], edx
+                    ; mov [rdi + out.index() as i32], eax
+                    ; setz dl
+                    ; mov [rdi + out.index() as i32 + 4], dl
+                );
+            }
+            Instr::I32_AtomicAnd(_, out, ptr, val) => {
+                // no fetch-and instruction, retry with cmpxchg
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov eax, [rcx]
+                    ; retry:
+                    ; mov edx, eax
+                    ; and edx, [rdi + val.index() as i32]
+                    ; lock cmpxchg [rcx], edx
+                    ; jnz <retry
+                    ; mov [rdi + out.index() as i32], eax
+                );
+            }
+            Instr::I32_AtomicOr(_, out, ptr, val) => {
+                // no fetch-or instruction, retry with cmpxchg
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov eax, [rcx]
+                    ; retry:
+                    ; mov edx, eax
+                    ; or edx, [rdi + val.index() as i32]
+                    ; lock cmpxchg [rcx], edx
+                    ; jnz <retry
+                    ; mov [rdi + out.index() as i32], eax
+                );
+            }
+            Instr::I32_AtomicXor(_, out, ptr, val) => {
+                // no fetch-xor instruction, retry with cmpxchg
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov eax, [rcx]
+                    ; retry:
+                    ; mov edx, eax
+                    ; xor edx, [rdi + val.index() as i32]
+                    ; lock cmpxchg [rcx], edx
+                    ; jnz <retry
+                    ; mov [rdi + out.index() as i32], eax
+                );
+            }
+            Instr::I64_AtomicLoad(_, out, ptr) => {
+                // plain loads are acquire on x86, and seqcst since seqcst stores use xchg
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov rax, [rcx]
+                    ; mov [rdi + out.index() as i32], rax
+                );
+            }
+            Instr::I64_AtomicStore(ordering, ptr, val) => {
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov rax, [rdi + val.index() as i32]
+                );
+                if *ordering == Ordering::SeqCst {
+                    dynasm!(ops
+                        ; xchg [rcx], rax
+                    );
+                } else {
+                    dynasm!(ops
+                        ; mov [rcx], rax
+                    );
+                }
+            }
+            Instr::I64_AtomicXchg(_, out, ptr, val) => {
+                // xchg with memory is implicitly locked
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov rax, [rdi + val.index() as i32]
+                    ; xchg [rcx], rax
+                    ; mov [rdi + out.index() as i32], rax
+                );
+            }
+            Instr::I64_AtomicAdd(_, out, ptr, val) => {
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov rax, [rdi + val.index() as i32]
+                    ; lock xadd [rcx], rax
+                    ; mov [rdi + out.index() as i32], rax
+                );
+            }
+            Instr::I64_AtomicSub(_, out, ptr, val) => {
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov rax, [rdi + val.index() as i32]
+                    ; neg rax
+                    ; lock xadd [rcx], rax
+                    ; mov [rdi + out.index() as i32], rax
+                );
+            }
+            Instr::I64_AtomicCxchg(_, _, out, ptr, val)
+            | Instr::I64_AtomicCxchgWeak(_, _, out, ptr, val) => {
+                // the expected value is in the output slot
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov rax, [rdi + out.index() as i32]
+                    ; mov rdx, [rdi + val.index() as i32]
+                    ; lock cmpxchg [rcx], rdx
+                    ; mov [rdi + out.index() as i32], rax
+                    ; setz dl
+                    ; mov [rdi + out.index() as i32 + 8], dl
+                );
+            }
+            Instr::I64_AtomicAnd(_, out, ptr, val) => {
+                // no fetch-and instruction, retry with cmpxchg
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov rax, [rcx]
+                    ; retry:
+                    ; mov rdx, rax
+                    ; and rdx, [rdi + val.index() as i32]
+                    ; lock cmpxchg [rcx], rdx
+                    ; jnz <retry
+                    ; mov [rdi + out.index() as i32], rax
+                );
+            }
+            Instr::I64_AtomicOr(_, out, ptr, val) => {
+                // no fetch-or instruction, retry with cmpxchg
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov rax, [rcx]
+                    ; retry:
+                    ; mov rdx, rax
+                    ; or rdx, [rdi + val.index() as i32]
+                    ; lock cmpxchg [rcx], rdx
+                    ; jnz <retry
+                    ; mov [rdi + out.index() as i32], rax
+                );
+            }
+            Instr::I64_AtomicXor(_, out, ptr, val) => {
+                // no fetch-xor instruction, retry with cmpxchg
+                dynasm!(ops
+                    ; mov rcx, [rdi + ptr.index() as i32]
+                    ; mov rax, [rcx]
+                    ; retry:
+                    ; mov rdx, rax
+                    ; xor rdx, [rdi + val.index() as i32]
+                    ; lock cmpxchg [rcx], rdx
+                    ; jnz <retry
+                    ; mov [rdi + out.index() as i32], rax
+                );
+            }
+            Instr::AtomicFence(ordering) => {
+                // other fences only restrict compiler reordering, which the jit does not do
+                if *ordering == Ordering::SeqCst {
+                    dynasm!(ops
+                        ; mfence
+                    );
+                }
+            }
+            Instr::CompilerFence(_) => (),
+            _ => {
+                let Some((size, compare, lhs, rhs, offset)) =
+                    bytecode_select::split_compare_jump(bc)
+                else {
+                    return Err(format!("nyi: {:?}", bc));
+                };
+                let target = labels[(pc as i32 + offset) as usize];
+                compare_jump(&mut ops, size, compare, lhs, rhs, target)?;
+            }
         }
     }
 
@@ -421,3 +659,119 @@ pub fn compile<'vm>(bytecode: &'vm FunctionBytecode<'vm>) -> Result<NativeFunc,
 
     unsafe { Ok(std::mem::transmute(ptr)) }
 }
+
+/// Compares two operands, jumping to `target` unless the comparison holds.
+fn compare_jump(
+    ops: &mut dynasmrt::x64::Assembler,
+    size: u32,
+    compare: Compare,
+    lhs: CompareArg,
+    rhs: CompareArg,
+    target: DynamicLabel,
+) -> Result<(), String> {
+    match (size, lhs) {
+        (1, CompareArg::Slot(slot)) => {
+            dynasm!(ops
+                ; mov al, [rdi + slot.index() as i32]
+            );
+        }
+        (2, CompareArg::Slot(slot)) => {
+            dynasm!(ops
+                ; mov ax, [rdi + slot.index() as i32]
+            );
+        }
+        (4, CompareArg::Slot(slot)) => {
+            dynasm!(ops
+                ; mov eax, [rdi + slot.index() as i32]
+            );
+        }
+        (8, CompareArg::Slot(slot)) => {
+            dynasm!(ops
+                ; mov rax, [rdi + slot.index() as i32]
+            );
+        }
+        (1, CompareArg::Field(ptr, field)) => {
+            dynasm!(ops
+                ; mov rcx, [rdi + ptr.index() as i32]
+                ; mov al, [rcx + field as i32]
+            );
+        }
+        (2, CompareArg::Field(ptr, field)) => {
+            dynasm!(ops
+                ; mov rcx, [rdi + ptr.index() as i32]
+                ; mov ax, [rcx + field as i32]
+            );
+        }
+        (4, CompareArg::Field(ptr, field)) => {
+            dynasm!(ops
+                ; mov rcx, [rdi + ptr.index() as i32]
+                ; mov eax, [rcx + field as i32]
+            );
+        }
+        (8, CompareArg::Field(ptr, field)) => {
+            dynasm!(ops
+                ; mov rcx, [rdi + ptr.index() as i32]
+                ; mov rax, [rcx + field as i32]
+            );
+        }
+        _ => return Err(format!("nyi: {}-byte {:?}", size, compare)),
+    }
+    match (size, rhs) {
+        (1, CompareArg::Slot(slot)) => {
+            dynasm!(ops
+                ; cmp al, [rdi + slot.index() as i32]
+            );
+        }
+        (2, CompareArg::Slot(slot)) => {
+            dynasm!(ops
+                ; cmp ax, [rdi + slot.index() as i32]
+            );
+        }
+        (4, CompareArg::Slot(slot)) => {
+            dynasm!(ops
+                ; cmp eax, [rdi + slot.index() as i32]
+            );
+        }
+        (8, CompareArg::Slot(slot)) => {
+            dynasm!(ops
+                ; cmp rax, [rdi + slot.index() as i32]
+            );
+        }
+        // only the low bytes of the immediate matter, as it is sign-extended to the operand size
+        (1, CompareArg::Imm(n)) => {
+            dynasm!(ops
+                ; cmp al, n as i8
+            );
+        }
+        (2, CompareArg::Imm(n)) => {
+            dynasm!(ops
+                ; cmp ax, n as i16
+            );
+        }
+        (4, CompareArg::Imm(n)) => {
+            dynasm!(ops
+                ; cmp eax, n
+            );
+        }
+        // the immediate is sign-extended, like in the interpreter
+        (8, CompareArg::Imm(n)) => {
+            dynasm!(ops
+                ; cmp rax, n
+            );
+        }
+        _ => return Err(format!("nyi: {:?} rhs", rhs)),
+    }
+    match compare {
+        Compare::Eq => dynasm!(ops ; jne =>target),
+        Compare::NotEq => dynasm!(ops ; je =>target),
+        Compare::S_Lt => dynasm!(ops ; jge =>target),
+        Compare::S_LtEq => dynasm!(ops ; jg =>target),
+        Compare::S_Gt => dynasm!(ops ; jle =>target),
+        Compare::S_GtEq => dynasm!(ops ; jl =>target),
+        Compare::U_Lt => dynasm!(ops ; jae =>target),
+        Compare::U_LtEq => dynasm!(ops ; ja =>target),
+        Compare::U_Gt => dynasm!(ops ; jbe =>target),
+        Compare::U_GtEq => dynasm!(ops ; jb =>target),
+    }
+    Ok(())
+}
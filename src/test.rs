@@ -2,8 +2,9 @@ use std::{
     collections::VecDeque,
     error::Error,
     ffi::{OsStr, OsString},
+    fs::File,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
     sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
@@ -62,23 +63,27 @@ pub fn test(dir_name: &Path, global_args: Vec<OsString>) -> ! {
                                     state.time_skitter_total += res.time_skitter;
                                 }
 
-                                let percent = format!("{:.1}%", res.fraction * 100.0);
-
-                                let speedup_str = if res.fraction < 0.1 {
-                                    percent.green()
-                                } else if res.fraction < 1.0 {
-                                    percent.yellow()
+                                if let Some(fraction) = res.fraction {
+                                    let percent = format!("{:.1}%", fraction * 100.0);
+
+                                    let speedup_str = if fraction < 0.1 {
+                                        percent.green()
+                                    } else if fraction < 1.0 {
+                                        percent.yellow()
+                                    } else {
+                                        percent.red()
+                                    };
+
+                                    format!(
+                                        "{} {} ({:?} / {:?})",
+                                        "GOOD:".green(),
+                                        speedup_str,
+                                        res.time_skitter,
+                                        res.time_rustc
+                                    )
                                 } else {
-                                    percent.red()
-                                };
-
-                                format!(
-                                    "{} {} ({:?} / {:?})",
-                                    "GOOD:".green(),
-                                    speedup_str,
-                                    res.time_skitter,
-                                    res.time_rustc
-                                )
+                                    format!("{} ({:?})", "GOOD:".green(), res.time_skitter)
+                                }
                             }
                         };
 
@@ -146,6 +151,14 @@ pub fn test(dir_name: &Path, global_args: Vec<OsString>) -> ! {
 struct TestInfo {
     file: PathBuf,
     args: Vec<OsString>,
+    /// Input for skitter, from `<test>.stdin`.
+    stdin: Option<PathBuf>,
+    /// Output expected from skitter, from `<test>.stdout`. Tests of skitter's own tools, like the
+    /// debugger, have nothing to compare with rustc.
+    expected: Option<PathBuf>,
+    /// Stderr expected from skitter, from `<test>.stderr`, checked instead of rustc's. For tests
+    /// where rustc prints a backtrace, like panics during cleanup.
+    expected_stderr: Option<PathBuf>,
 }
 
 fn gather_tests(dir_name: &Path, files: &mut Vec<TestInfo>) {
@@ -168,6 +181,19 @@ fn gather_tests(dir_name: &Path, files: &mut Vec<TestInfo>) {
         vec![]
     };
 
+    // a cargo package is a single test, including its dependencies
+    let manifest = dir_path.join("Cargo.toml");
+    if manifest.is_file() {
+        files.push(TestInfo {
+            file: manifest,
+            args,
+            stdin: None,
+            expected: None,
+            expected_stderr: None,
+        });
+        return;
+    }
+
     let read_dir = std::fs::read_dir(dir_name).expect("failed to read test directory");
     for entry in read_dir {
         if let Ok(entry) = entry {
@@ -182,7 +208,14 @@ fn gather_tests(dir_name: &Path, files: &mut Vec<TestInfo>) {
                     } else if file_ty.is_file() {
                         if file_name.ends_with(".rs") {
                             let file = dir_path.join(file_name);
+                            let with_extension = |extension| {
+                                let path = file.with_extension(extension);
+                                path.is_file().then_some(path)
+                            };
                             files.push(TestInfo {
+                                stdin: with_extension("stdin"),
+                                expected: with_extension("stdout"),
+                                expected_stderr: with_extension("stderr"),
                                 file,
                                 args: args.clone(),
                             });
@@ -199,7 +232,8 @@ struct TestResult {
     time_rustc_exec: Duration,
     time_rustc: Duration,
     time_skitter: Duration,
-    fraction: f64,
+    /// Time taken by skitter relative to rustc, unless the test has no rustc run.
+    fraction: Option<f64>,
 }
 
 const ERROR_CHARS: usize = 80;
@@ -209,18 +243,50 @@ fn run_test(
     bin_name: &Path,
     global_args: &[OsString],
 ) -> Result<TestResult, String> {
+    if let Some(expected) = &test_info.expected {
+        return run_expected_test(test_info, expected, global_args);
+    }
+
+    let is_cargo = test_info.file.ends_with("Cargo.toml");
+    let (skitter_args, program_args) = split_args(test_info);
+
+    let mut target_dir = bin_name.as_os_str().to_owned();
+    target_dir.push("_target");
+
+    // cargo packages are built in release mode, which matches skitter's defaults
+    let cargo_args = |command: &'static str| -> Vec<&OsStr> {
+        vec![
+            OsStr::new(command),
+            OsStr::new("--release"),
+            OsStr::new("--quiet"),
+            OsStr::new("--manifest-path"),
+            test_info.file.as_os_str(),
+            OsStr::new("--target-dir"),
+            &target_dir,
+        ]
+    };
+
     // Rust compile
     let t = Instant::now();
     {
         let fail = || Err(String::from("rustc compile failed"));
 
-        let cmd_res = Command::new("rustc")
-            .arg(&test_info.file)
-            .arg("-o")
-            .arg(bin_name)
-            .arg("-C")
-            .arg("overflow-checks=off")
-            .output();
+        let debug_profile = skitter_args.iter().any(|arg| *arg == "--debug-profile");
+        let switch = if debug_profile { "on" } else { "off" };
+
+        let cmd_res = if is_cargo {
+            Command::new("cargo").args(cargo_args("build")).output()
+        } else {
+            Command::new("rustc")
+                .arg(&test_info.file)
+                .arg("-o")
+                .arg(bin_name)
+                .arg("-C")
+                .arg(format!("overflow-checks={}", switch))
+                .arg("-C")
+                .arg(format!("debug-assertions={}", switch))
+                .output()
+        };
 
         if let Ok(cmd_res) = cmd_res {
             if !cmd_res.status.success() {
@@ -236,17 +302,21 @@ fn run_test(
     let time_rustc_compile = t.elapsed();
 
     // Rust execute
+    // tests are allowed to fail, we check that skitter fails in the same way
     let t = Instant::now();
     let rustc_out = {
         let fail = || Err(String::from("rustc exec failed"));
 
-        let cmd_res = Command::new(bin_name).output();
+        let cmd_res = if is_cargo {
+            let mut args = cargo_args("run");
+            args.push(OsStr::new("--"));
+            args.extend(&program_args);
+            time_command(Path::new("cargo"), &args, test_info.stdin.as_deref())
+        } else {
+            time_command(bin_name, &program_args, test_info.stdin.as_deref())
+        };
         if let Ok(cmd_res) = cmd_res {
-            if !cmd_res.status.success() {
-                return fail();
-            } else {
-                cmd_res.stdout
-            }
+            cmd_res
         } else {
             return fail();
         }
@@ -258,22 +328,12 @@ fn run_test(
         let fail = || Err(String::from("skitter failed"));
 
         let program = std::env::current_exe().expect("failed to get skitter path");
+        let args = skitter_command_args(test_info, global_args);
 
-        let mut args = Vec::new();
-        args.push(test_info.file.as_os_str());
-
-        for arg in global_args {
-            args.push(arg);
-        }
-
-        for arg in test_info.args.iter() {
-            args.push(arg);
-        }
-
-        let cmd_res = time_command(&program, &args);
+        let cmd_res = time_command(&program, &args, test_info.stdin.as_deref());
 
         if let Ok(cmd_res) = cmd_res {
-            if !cmd_res.success {
+            if cmd_res.status != rustc_out.status && !cmd_res.success {
                 let skitter_err =
                     std::str::from_utf8(&cmd_res.stderr).expect("failed to read stderr as utf8");
 
@@ -291,15 +351,23 @@ fn run_test(
 
                 return Err(format!("skitter failed ( {} )", skitter_err));
             } else {
-                (cmd_res.stdout, cmd_res.time)
+                let time = cmd_res.time;
+                (cmd_res, time)
             }
         } else {
             return fail();
         }
     };
 
-    if rustc_out != skitter_out {
+    if rustc_out.stdout != skitter_out.stdout {
         Err("output mismatch".into())
+    } else if rustc_out.status != skitter_out.status {
+        Err(format!(
+            "exit status mismatch ( {:?} / {:?} )",
+            skitter_out.status, rustc_out.status
+        ))
+    } else if !stderr_matches(test_info, &rustc_out.stderr, &skitter_out.stderr)? {
+        Err("stderr mismatch".into())
     } else {
         let time_rustc = time_rustc_compile + time_rustc_exec;
         Ok(TestResult {
@@ -307,30 +375,275 @@ fn run_test(
             time_rustc_exec,
             time_rustc,
             time_skitter,
-            fraction: time_skitter.as_secs_f64() / time_rustc.as_secs_f64(),
+            fraction: Some(time_skitter.as_secs_f64() / time_rustc.as_secs_f64()),
+        })
+    }
+}
+
+/// Compares skitter's stderr with the test's `.stderr` file if it has one, or with rustc's.
+fn stderr_matches(test_info: &TestInfo, rustc: &[u8], skitter: &[u8]) -> Result<bool, String> {
+    if let Some(expected) = &test_info.expected_stderr {
+        let expected = std::fs::read_to_string(expected).map_err(|err| err.to_string())?;
+        Ok(output_matches(&String::from_utf8_lossy(skitter), &expected))
+    } else {
+        Ok(rustc == skitter)
+    }
+}
+
+/// Runs skitter alone, and compares its output with the test's `.stdout` file.
+fn run_expected_test(
+    test_info: &TestInfo,
+    expected: &Path,
+    global_args: &[OsString],
+) -> Result<TestResult, String> {
+    let expected = std::fs::read_to_string(expected).map_err(|err| err.to_string())?;
+
+    let program = std::env::current_exe().expect("failed to get skitter path");
+    let args = skitter_command_args(test_info, global_args);
+
+    let cmd_res = if args.iter().any(|arg| *arg == "--dap") {
+        let Some(script) = &test_info.stdin else {
+            return Err("debug adapter test without a .stdin script".into());
+        };
+        dap_session(&program, &args, script)
+    } else {
+        time_command(&program, &args, test_info.stdin.as_deref())
+    };
+    let cmd_res = match cmd_res {
+        Ok(cmd_res) => cmd_res,
+        Err(err) => return Err(format!("skitter failed: {}", err)),
+    };
+
+    let stdout = String::from_utf8_lossy(&cmd_res.stdout);
+    if !cmd_res.success {
+        Err(format!("exit status {:?}", cmd_res.status))
+    } else if !output_matches(&stdout, &expected) {
+        Err("output mismatch".into())
+    } else {
+        Ok(TestResult {
+            time_rustc_compile: Duration::ZERO,
+            time_rustc_exec: Duration::ZERO,
+            time_rustc: Duration::ZERO,
+            time_skitter: cmd_res.time,
+            fraction: None,
         })
     }
 }
 
+/// Runs a `--dap` test. Each line of its script is a request, as JSON without `seq` and `type`,
+/// which is sent before waiting for its response, or `wait <event>`. The output is every message
+/// skitter sent, one per line and without `seq` or the working directory in paths, so it can be
+/// matched like a program's output.
+fn dap_session(
+    cmd_name: &Path,
+    args: &[&OsStr],
+    script: &Path,
+) -> Result<TimeResult, Box<dyn Error>> {
+    use serde_json::Value;
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    fn read_message(output: &mut impl BufRead, transcript: &mut String) -> Option<Value> {
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            if output.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+        let mut body = vec![0; length?];
+        output.read_exact(&mut body).ok()?;
+        let mut message: Value = serde_json::from_slice(&body).ok()?;
+        message.as_object_mut()?.remove("seq");
+        *transcript += &message.to_string();
+        transcript.push('\n');
+        Some(message)
+    }
+
+    let script = std::fs::read_to_string(script)?;
+
+    let t = std::time::Instant::now();
+    let mut child = Command::new(cmd_name)
+        .args(args)
+        .env("RUST_BACKTRACE", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut input = child.stdin.take().unwrap();
+    let mut output = BufReader::new(child.stdout.take().unwrap());
+
+    let mut transcript = String::new();
+    let mut seq = 0;
+    for line in script.lines() {
+        if let Some(event) = line.strip_prefix("wait ") {
+            loop {
+                let message = read_message(&mut output, &mut transcript)
+                    .ok_or_else(|| format!("skitter exited waiting for {}", event))?;
+                if message["type"] == "event" && message["event"] == event {
+                    break;
+                }
+            }
+        } else {
+            seq += 1;
+            let mut request: Value = serde_json::from_str(line)?;
+            request["seq"] = seq.into();
+            request["type"] = "request".into();
+            let body = request.to_string();
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+            input.flush()?;
+            loop {
+                let message = read_message(&mut output, &mut transcript)
+                    .ok_or_else(|| format!("skitter exited waiting for `{}`", line))?;
+                if message["type"] == "response" && message["request_seq"] == seq {
+                    break;
+                }
+            }
+        }
+    }
+    drop(input);
+    while read_message(&mut output, &mut transcript).is_some() {}
+
+    let mut stderr = Vec::new();
+    child.stderr.take().unwrap().read_to_end(&mut stderr)?;
+    let status = child.wait()?;
+    let time = t.elapsed();
+
+    // source paths are absolute
+    let dir = format!("{}/", std::env::current_dir()?.display());
+    Ok(TimeResult {
+        success: status.success(),
+        status: status.code(),
+        stdout: transcript.replace(&dir, "").into_bytes(),
+        stderr,
+        time,
+    })
+}
+
+/// Splits a test's arguments into skitter's and the program's, which follow `--`.
+fn split_args(test_info: &TestInfo) -> (Vec<&OsStr>, Vec<&OsStr>) {
+    match test_info.args.iter().position(|arg| arg == "--") {
+        Some(split) => (
+            test_info.args[..split]
+                .iter()
+                .map(|a| a.as_os_str())
+                .collect(),
+            test_info.args[split + 1..]
+                .iter()
+                .map(|a| a.as_os_str())
+                .collect(),
+        ),
+        None => (
+            test_info.args.iter().map(|a| a.as_os_str()).collect(),
+            vec![],
+        ),
+    }
+}
+
+fn skitter_command_args<'a>(
+    test_info: &'a TestInfo,
+    global_args: &'a [OsString],
+) -> Vec<&'a OsStr> {
+    let (skitter_args, program_args) = split_args(test_info);
+
+    let mut args = Vec::new();
+    if test_info.file.ends_with("Cargo.toml") {
+        args.push(OsStr::new("--manifest-path"));
+    }
+    args.push(test_info.file.as_os_str());
+
+    for arg in global_args {
+        args.push(arg);
+    }
+
+    for arg in skitter_args {
+        args.push(arg);
+    }
+
+    args.push(OsStr::new("--"));
+    for arg in program_args {
+        args.push(arg);
+    }
+    args
+}
+
+/// Compares output with an expected output, where `{n}` stands for any number and a line of
+/// `...` for any number of lines.
+fn output_matches(output: &str, expected: &str) -> bool {
+    fn line_matches(line: &str, pattern: &str) -> bool {
+        let mut pieces = pattern.split("{n}");
+        let Some(mut rest) = line.strip_prefix(pieces.next().unwrap()) else {
+            return false;
+        };
+        for piece in pieces {
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if digits == 0 {
+                return false;
+            }
+            let Some(after) = rest[digits..].strip_prefix(piece) else {
+                return false;
+            };
+            rest = after;
+        }
+        rest.is_empty()
+    }
+
+    fn lines_match(lines: &[&str], patterns: &[&str]) -> bool {
+        match patterns.split_first() {
+            None => lines.is_empty(),
+            Some((&"...", patterns)) => {
+                (0..=lines.len()).any(|skip| lines_match(&lines[skip..], patterns))
+            }
+            Some((pattern, patterns)) => match lines.split_first() {
+                Some((line, lines)) => line_matches(line, pattern) && lines_match(lines, patterns),
+                None => false,
+            },
+        }
+    }
+
+    let lines: Vec<_> = output.lines().collect();
+    let patterns: Vec<_> = expected.lines().collect();
+    lines_match(&lines, &patterns)
+}
+
 struct TimeResult {
     success: bool,
+    /// Exit code, or None if the process was killed by a signal.
+    status: Option<i32>,
     stdout: Vec<u8>,
     stderr: Vec<u8>,
     time: Duration,
 }
 
-fn time_command(cmd_name: &Path, args: &[&OsStr]) -> Result<TimeResult, Box<dyn Error>> {
+fn time_command(
+    cmd_name: &Path,
+    args: &[&OsStr],
+    stdin: Option<&Path>,
+) -> Result<TimeResult, Box<dyn Error>> {
     let mut cmd = Command::new(cmd_name);
     cmd.args(args);
+    if let Some(stdin) = stdin {
+        cmd.stdin(File::open(stdin)?);
+    }
+    // panic messages must not depend on the environment
+    cmd.env("RUST_BACKTRACE", "0");
 
     let t = std::time::Instant::now();
     let output = cmd.output()?;
     let time = t.elapsed();
 
     let success = output.status.success();
+    let status = output.status.code();
 
     Ok(TimeResult {
         success,
+        status,
         stdout: output.stdout,
         stderr: output.stderr,
         time,
@@ -343,7 +656,7 @@ fn get_base_overhead() -> Duration {
     let n = 5;
 
     let sum: Duration = (0..n)
-        .map(|_| time_command(&program, &[]).unwrap().time)
+        .map(|_| time_command(&program, &[], None).unwrap().time)
         .sum();
     sum / n
 }
@@ -1,7 +1,7 @@
 use crate::{
     abi::POINTER_SIZE,
     builtins::BuiltinAdt,
-    bytecode_compiler::FunctionBytecode,
+    bytecode_compiler::{FunctionBytecode, SpanTable},
     bytecode_select,
     ir::BinaryOp,
     items::{AdtInfo, AdtKind, Item},
@@ -9,7 +9,7 @@ use crate::{
     variants::{Discriminant, VariantIndex, Variants},
     vm::{
         instr::{Instr, Slot},
-        Function, FunctionSource, VM,
+        CompactCode, Function, FunctionSource, VM,
     },
 };
 
@@ -158,11 +158,14 @@ impl<'vm> Type<'vm> {
                 | TypeKind::Ptr(..)
                 | TypeKind::FunctionPointer(..)
                 | TypeKind::FunctionDef(..)
+                | TypeKind::StringSlice
+                | TypeKind::Foreign(..)
                 | TypeKind::Never => DropInfo::None,
 
                 TypeKind::Array(child, len) => {
-                    if child.drop_info().is_drop() {
-                        let len = len.get_value() as u32;
+                    let len = len.get_value() as u32;
+                    // an empty array has nothing to drop, even if its elements do
+                    if len > 0 && child.drop_info().is_drop() {
                         DropInfo::Leaf(DropGlue::for_array(vm, *child, Some(len)))
                     } else {
                         DropInfo::None
@@ -190,6 +193,8 @@ impl<'vm> Type<'vm> {
                         "tuple",
                     )
                 }
+                // dropped through the vtable, which may or may not have glue
+                TypeKind::Dynamic { .. } => DropInfo::Leaf(DropGlue::for_dyn(vm)),
                 TypeKind::Closure(closure, subs) => {
                     let env = closure.env(subs);
                     env.drop_info().clone()
@@ -341,8 +346,14 @@ impl<'vm> DropGlue<'vm> {
         code.push(Instr::Return);
 
         let bc = FunctionBytecode {
-            code,
+            code: CompactCode::new(code),
             drops: Vec::new(),
+            // every call takes a single pointer
+            frame_size: member_slot.index() as u32 + POINTER_SIZE.bytes(),
+            spans: SpanTable::default(),
+            track_caller: false,
+            locals: Vec::new(),
+            threaded: Default::default(),
         };
 
         let bc = vm.alloc_bytecode(bc);
@@ -412,8 +423,13 @@ impl<'vm> DropGlue<'vm> {
         code.push(Instr::Return);
 
         let bc = FunctionBytecode {
-            code,
+            code: CompactCode::new(code),
             drops: Vec::new(),
+            frame_size: elem_slot.index() as u32 + POINTER_SIZE.bytes(),
+            spans: SpanTable::default(),
+            track_caller: false,
+            locals: Vec::new(),
+            threaded: Default::default(),
         };
 
         let bc = vm.alloc_bytecode(bc);
@@ -423,6 +439,37 @@ impl<'vm> DropGlue<'vm> {
         Self(vm.alloc_function(FunctionSource::RawBytecode(bc, name), SubList::empty()))
     }
 
+    /// Builds drop glue for a trait object, which calls the glue in its vtable.
+    pub fn for_dyn(vm: &'vm VM<'vm>) -> Self {
+        let self_slot = Slot::new(0);
+        let frame_slot = Slot::new(POINTER_SIZE.bytes() * 2);
+        assert!(frame_slot.has_call_align());
+
+        let code = vec![
+            Instr::VTableDrop {
+                frame: frame_slot,
+                arg: self_slot,
+            },
+            Instr::Return,
+        ];
+
+        let bc = FunctionBytecode {
+            code: CompactCode::new(code),
+            drops: Vec::new(),
+            frame_size: frame_slot.index() as u32 + POINTER_SIZE.bytes(),
+            spans: SpanTable::default(),
+            track_caller: false,
+            locals: Vec::new(),
+            threaded: Default::default(),
+        };
+
+        let bc = vm.alloc_bytecode(bc);
+
+        let name = vm.alloc_path("<drop dyn>");
+
+        Self(vm.alloc_function(FunctionSource::RawBytecode(bc, name), SubList::empty()))
+    }
+
     pub fn function(&self) -> &'vm Function<'vm> {
         self.0
     }
@@ -65,6 +65,12 @@ impl Layout {
                 align: 1,
                 field_offsets: Variants::empty(),
             },
+            // the real alignment is only known at run-time, from the vtable
+            TypeKind::Dynamic { .. } => Layout {
+                maybe_size: None,
+                align: 1,
+                field_offsets: Variants::empty(),
+            },
 
             TypeKind::Ref(ref_ty, _) | TypeKind::Ptr(ref_ty, _) => {
                 let ptr_size = POINTER_SIZE.bytes();
@@ -79,7 +85,7 @@ impl Layout {
                     }
                 }
             }
-            TypeKind::FunctionPointer(_) => {
+            TypeKind::FunctionPointer(..) => {
                 let ptr_size = POINTER_SIZE.bytes();
                 Layout::simple(ptr_size)
             }
@@ -62,7 +62,18 @@ impl<'vm> Display for Sub<'vm> {
         match self {
             Sub::Type(ty) => write!(f, "{}", ty),
             Sub::Lifetime => write!(f, "'_"),
-            Sub::Const(ty, c) => write!(f, "<const {} {:?}>", ty, c),
+            Sub::Const(ty, c) => match c {
+                ConstGeneric::Value(n) => match ty.kind() {
+                    TypeKind::Bool => write!(f, "{}", *n != 0),
+                    TypeKind::Char => match char::from_u32(*n as u32) {
+                        Some(c) => write!(f, "{:?}", c),
+                        None => write!(f, "{}", n),
+                    },
+                    _ => write!(f, "{}", n),
+                },
+                ConstGeneric::Param(n) => write!(f, "{{param#{}}}", n),
+                ConstGeneric::Error | ConstGeneric::Unknown => write!(f, "_"),
+            },
         }
     }
 }
@@ -171,7 +182,7 @@ impl<'vm> Display for SubList<'vm> {
         write!(f, "<")?;
         for (i, sub) in self.list.iter().enumerate() {
             if i > 0 {
-                write!(f, ",")?;
+                write!(f, ", ")?;
             }
             write!(f, "{}", sub)?;
         }
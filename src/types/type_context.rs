@@ -9,7 +9,7 @@ use rustc_middle::ty::{
 use crate::{
     closure::{ClosureRef, ClosureSig},
     impls::find_inherent_impl_crate,
-    items::{parent_def_from_rustc, path_from_rustc, AssocValue, FunctionSig},
+    items::{parent_def_from_rustc, path_from_rustc, AssocValue, FunctionAbi, FunctionSig},
     rustc_worker::RustCContext,
     types::{AutoTraitSet, ConstGeneric, Sub},
     vm::VM,
@@ -26,6 +26,12 @@ pub struct TypeContext<'vm> {
     arena: Arena<InternedType<'vm>>,
 }
 
+impl<'vm> Default for TypeContext<'vm> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'vm> TypeContext<'vm> {
     pub fn new() -> Self {
         TypeContext {
@@ -114,13 +120,29 @@ impl<'vm> TypeContext<'vm> {
                     TypeKind::AssociatedType(item_with_subs)
                 }
                 AliasKind::Opaque => {
+                    // type alias impl trait, there is no single function we can get the
+                    // concrete type from, but rustc can find it for us
+                    if ctx.tcx.is_type_alias_impl_trait(alias_ty.def_id) {
+                        let ty = ctx
+                            .tcx
+                            .type_of(alias_ty.def_id)
+                            .instantiate(ctx.tcx, alias_ty.args);
+                        return self.type_from_rustc(ty, ctx);
+                    }
+
                     let (parent_item, sub_id) =
                         self.opaque_type_from_rustc(alias_ty.def_id, alias_ty.args, ctx);
 
                     TypeKind::Opaque(parent_item, sub_id)
                 }
                 AliasKind::Inherent => panic!("inherent alias?"),
-                AliasKind::Weak => panic!("weak alias?"),
+                AliasKind::Weak => {
+                    let ty = ctx
+                        .tcx
+                        .type_of(alias_ty.def_id)
+                        .instantiate(ctx.tcx, alias_ty.args);
+                    return self.type_from_rustc(ty, ctx);
+                }
             },
             TyKind::Foreign(did) => {
                 let def_path = ctx.tcx.def_path(*did);
@@ -138,6 +160,7 @@ impl<'vm> TypeContext<'vm> {
                 };
 
                 let mut primary_trait = None;
+                let mut projections = Vec::new();
                 let mut auto_traits = AutoTraitSet::EMPTY;
 
                 for item in list.iter() {
@@ -151,8 +174,12 @@ impl<'vm> TypeContext<'vm> {
 
                             primary_trait = Some(item);
                         }
-                        ExistentialPredicate::Projection(_) => {
-                            println!("todo projection on dyn???");
+                        ExistentialPredicate::Projection(projection) => {
+                            let assoc_ty =
+                                self.def_from_rustc(projection.def_id, projection.args, ctx);
+                            let ty = projection.term.ty().expect("const projection on dyn");
+
+                            projections.push((assoc_ty, self.type_from_rustc(ty, ctx)));
                         }
                         ExistentialPredicate::AutoTrait(did) => {
                             let def_path = ctx.tcx.def_path(did);
@@ -160,6 +187,11 @@ impl<'vm> TypeContext<'vm> {
                             let add_trait = match path.as_string() {
                                 "::marker::Send" => AutoTraitSet::SEND,
                                 "::marker::Sync" => AutoTraitSet::SYNC,
+                                "::marker::Unpin" => AutoTraitSet::UNPIN,
+                                "::panic::unwind_safe::UnwindSafe" => AutoTraitSet::UNWIND_SAFE,
+                                "::panic::unwind_safe::RefUnwindSafe" => {
+                                    AutoTraitSet::REF_UNWIND_SAFE
+                                }
                                 _ => panic!("auto trait {}", path.as_string()),
                             };
                             auto_traits.add(add_trait);
@@ -169,13 +201,14 @@ impl<'vm> TypeContext<'vm> {
 
                 TypeKind::Dynamic {
                     primary_trait,
+                    projections,
                     auto_traits,
                     is_dyn_star,
                 }
             }
             TyKind::FnPtr(rs_sig) => {
                 let sig = FunctionSig::from_rustc(&rs_sig.skip_binder(), ctx);
-                TypeKind::FunctionPointer(sig)
+                TypeKind::FunctionPointer(sig, FunctionAbi::from_rustc(rs_sig.abi()))
             }
             TyKind::Closure(did, subs) => {
                 let closure = self.closure_from_rustc(*did, ctx);
@@ -223,6 +256,7 @@ impl<'vm> TypeContext<'vm> {
         let ty = self.type_from_rustc(c.ty(), ctx);
 
         use rustc_middle::ty::ConstKind;
+        use rustc_middle::ty::ParamEnv;
         use rustc_middle::ty::ValTree;
 
         let kind = match c.kind() {
@@ -234,7 +268,11 @@ impl<'vm> TypeContext<'vm> {
                 ConstGeneric::Value(n as i128)
             }
             ConstKind::Param(n) => ConstGeneric::Param(n.index),
-            ConstKind::Unevaluated(..) => ConstGeneric::Error,
+            // constants which don't depend on generics, like `BLOCK_CAP` in std's channels
+            ConstKind::Unevaluated(..) => match c.try_eval_bits(ctx.tcx, ParamEnv::reveal_all()) {
+                Some(n) => ConstGeneric::Value(n as i128),
+                None => ConstGeneric::Error,
+            },
             _ => panic!("lower const {:?}", c.kind()),
         };
 
@@ -247,7 +285,7 @@ impl<'vm> TypeContext<'vm> {
         args: &[GenericArg<'tcx>],
         ctx: &RustCContext<'vm, 'tcx>,
     ) -> ItemWithSubs<'vm> {
-        if ctx.vm.cli_args.debug_local_impls && did.krate == rustc_hir::def_id::LOCAL_CRATE {
+        if ctx.vm.options.debug_local_impls && did.krate == rustc_hir::def_id::LOCAL_CRATE {
             if let Some(parent_impl) = ctx.tcx.impl_of_method(did) {
                 let subject = ctx.tcx.impl_subject(parent_impl).skip_binder();
                 if let ImplSubject::Inherent(ty) = subject {
@@ -276,7 +314,7 @@ impl<'vm> TypeContext<'vm> {
             let def_path = ctx.tcx.def_path(did);
             let item_path = path_from_rustc(&def_path, ctx.vm);
             if item_path.can_lookup() {
-                if did.krate == rustc_hir::def_id::LOCAL_CRATE {
+                if did.krate == rustc_hir::def_id::LOCAL_CRATE || ctx.is_bundled(did) {
                     panic!("attempt to find local item by path: {:?}", item_path);
                 }
                 let trait_crate_id = ctx.find_crate_id(did.krate);
@@ -313,7 +351,7 @@ impl<'vm> TypeContext<'vm> {
                             }
                         }
 
-                        panic!("failed to find inherent impl {:?}", did);
+                        panic!("failed to find inherent impl {:?} {} {}", did, full_key, ty);
                     }
                     ImplSubject::Trait(_) => {
                         panic!("todo trait ref");
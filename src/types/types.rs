@@ -1,8 +1,10 @@
 use std::fmt::Display;
 
 use crate::{
+    builtins::BuiltinAdt,
     closure::ClosureRef,
-    items::{AdtInfo, CrateId, FunctionSig, Item, ItemId},
+    items::{AdtInfo, CrateId, FunctionAbi, FunctionSig, Item, ItemId},
+    variants::VariantIndex,
     vm::VM,
 };
 
@@ -40,12 +42,14 @@ pub enum TypeKind<'vm> {
     Foreign(CrateId, &'vm str),
 
     Opaque(ItemWithSubs<'vm>, &'vm str),
-    FunctionPointer(FunctionSig<'vm>),
+    FunctionPointer(FunctionSig<'vm>, FunctionAbi),
     Closure(ClosureRef<'vm>, SubList<'vm>),
 
     // not properly implemented yet
     Dynamic {
         primary_trait: Option<ItemWithSubs<'vm>>,
+        /// Associated types fixed by the trait object, like `Output` in `dyn FnOnce() -> T`.
+        projections: Vec<(ItemWithSubs<'vm>, Type<'vm>)>,
         auto_traits: AutoTraitSet,
         is_dyn_star: bool,
     },
@@ -90,7 +94,11 @@ pub struct ItemWithSubs<'vm> {
 
 impl<'vm> Display for ItemWithSubs<'vm> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}", self.item.path.as_string(), self.subs)
+        write!(f, "{}", self.item.path.as_string())?;
+        if !self.subs.list.is_empty() {
+            write!(f, "{}", self.subs)?;
+        }
+        Ok(())
     }
 }
 
@@ -103,6 +111,11 @@ impl<'vm> Type<'vm> {
         self.1
     }
 
+    /// Types are interned, so this is unique to the type for as long as the VM lives.
+    pub fn unique_id(&self) -> usize {
+        self.0 as *const _ as usize
+    }
+
     pub fn layout(&self) -> &'vm Layout {
         self.0
             .layout
@@ -249,22 +262,44 @@ impl<'vm> Type<'vm> {
 
                 vm.types.intern(TypeKind::Closure(*closure, new_subs), vm)
             }
-            TypeKind::FunctionPointer(sig) => {
+            TypeKind::FunctionPointer(sig, abi) => {
                 let new_sig = sig.sub(subs);
 
-                vm.types.intern(TypeKind::FunctionPointer(new_sig), vm)
+                vm.types
+                    .intern(TypeKind::FunctionPointer(new_sig, *abi), vm)
             }
 
             TypeKind::Dynamic {
                 primary_trait,
+                projections,
+                auto_traits,
                 is_dyn_star,
-                ..
             } => {
-                let primary_trait = primary_trait.as_ref().expect("no primary trait");
-                assert!(!is_dyn_star);
-                assert!(primary_trait.subs.list.len() == 0);
+                let primary_trait = primary_trait.as_ref().map(|primary_trait| ItemWithSubs {
+                    item: primary_trait.item,
+                    subs: primary_trait.subs.sub(subs),
+                });
 
-                *self
+                let projections = projections
+                    .iter()
+                    .map(|(assoc_ty, ty)| {
+                        let assoc_ty = ItemWithSubs {
+                            item: assoc_ty.item,
+                            subs: assoc_ty.subs.sub(subs),
+                        };
+                        (assoc_ty, ty.sub(subs))
+                    })
+                    .collect();
+
+                vm.types.intern(
+                    TypeKind::Dynamic {
+                        primary_trait,
+                        projections,
+                        auto_traits: *auto_traits,
+                        is_dyn_star: *is_dyn_star,
+                    },
+                    vm,
+                )
             }
 
             TypeKind::Opaque(item_with_subs, full_path) => {
@@ -312,31 +347,38 @@ impl<'vm> Type<'vm> {
             | TypeKind::Float(..)
             | TypeKind::Bool
             | TypeKind::Char
-            | TypeKind::StringSlice => true,
+            | TypeKind::StringSlice
+            | TypeKind::Never
+            | TypeKind::Foreign(..) => true,
             TypeKind::Tuple(children) => children.iter().all(|child| child.is_concrete()),
             TypeKind::Adt(adt) => adt.subs.is_concrete(),
             TypeKind::FunctionDef(fun) => fun.subs.is_concrete(),
             TypeKind::Closure(_, subs) => subs.is_concrete(),
-            TypeKind::Dynamic { primary_trait, .. } => {
-                if let Some(primary_trait) = primary_trait {
+            TypeKind::Dynamic {
+                primary_trait,
+                projections,
+                ..
+            } => {
+                let trait_concrete = if let Some(primary_trait) = primary_trait {
                     primary_trait.subs.is_concrete()
                 } else {
                     true
-                }
+                };
+                trait_concrete && projections.iter().all(|(_, ty)| ty.is_concrete())
             }
 
             TypeKind::Ref(child, _) | TypeKind::Ptr(child, _) | TypeKind::Slice(child) => {
                 child.is_concrete()
             }
 
-            TypeKind::FunctionPointer(sig) => {
+            TypeKind::FunctionPointer(sig, _) => {
                 let args_concrete = sig.inputs.iter().all(|arg| arg.is_concrete());
                 args_concrete && sig.output.is_concrete()
             }
             TypeKind::Array(child, size) => child.is_concrete() && size.is_concrete(),
             TypeKind::Param(_) | TypeKind::Unknown => false,
             TypeKind::AssociatedType(_) => false,
-            _ => panic!("is concrete? {}", self),
+            TypeKind::Opaque(item, _) => item.subs.is_concrete(),
         }
     }
 
@@ -346,9 +388,10 @@ impl<'vm> Type<'vm> {
             | TypeKind::Float(..)
             | TypeKind::Bool
             | TypeKind::Char
+            | TypeKind::Never
             | TypeKind::StringSlice
             | TypeKind::FunctionDef(_)
-            | TypeKind::FunctionPointer(_) => false,
+            | TypeKind::FunctionPointer(..) => false,
             TypeKind::Tuple(children) => children.iter().any(|child| child.is_interior_mut()),
             TypeKind::Closure(closure, subs) => {
                 let env = closure.env(subs);
@@ -418,6 +461,28 @@ impl<'vm> Type<'vm> {
         info
     }
 
+    /// The offset and type of a field in a variant of an ADT.
+    pub fn field(&self, variant: VariantIndex, index: usize) -> (u32, Type<'vm>) {
+        let TypeKind::Adt(adt) = self.kind() else {
+            panic!("field: not an adt");
+        };
+        let field_ty = adt.item.adt_info().variant_fields.get(variant)[index].sub(&adt.subs);
+        let offset = self.layout().field_offsets.get(variant)[index];
+
+        (offset, field_ty)
+    }
+
+    /// Write the discriminant of an enum variant, which is stored at the start of the value.
+    pub unsafe fn write_discriminant(&self, variant: VariantIndex, ptr: *mut u8) {
+        let info = self.adt_info();
+        let disc_ty = info.enum_info().expect("not an enum").discriminant_internal;
+        let disc = info.variant_discriminants(self.vm()).get(variant);
+        let value = disc.value().expect("no discriminant value");
+
+        let size = disc_ty.layout().assert_size() as usize;
+        std::ptr::copy_nonoverlapping(value.to_le_bytes().as_ptr(), ptr, size);
+    }
+
     pub fn func_item(&self) -> Option<ItemWithSubs<'vm>> {
         match self.kind() {
             TypeKind::FunctionDef(item) => Some(item.clone()),
@@ -425,6 +490,15 @@ impl<'vm> Type<'vm> {
         }
     }
 
+    /// Is this `Box<T>`, the builtin owning pointer?
+    pub fn is_box(&self) -> bool {
+        if let TypeKind::Adt(item) = self.kind() {
+            item.item.adt_is_builtin(BuiltinAdt::Box)
+        } else {
+            false
+        }
+    }
+
     /// Attempts to re-interpret this type as a reference or pointer, and get the referenced type.
     ///
     /// DO NOT USE if you just want to get the referenced type from a reference or pointer.
@@ -468,26 +542,40 @@ impl<'vm> std::cmp::PartialEq for Type<'vm> {
 
 impl<'vm> std::cmp::Eq for Type<'vm> {}
 
+// pretty printing for types, as rustc writes them, except that paths have no crate name
 impl<'vm> Display for Type<'vm> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.kind() {
-            TypeKind::Adt(item_with_subs) | TypeKind::FunctionDef(item_with_subs) => {
-                write!(f, "{}", item_with_subs.item.path.as_string())?;
-                if item_with_subs.subs.list.len() > 0 {
-                    write!(f, "{}", item_with_subs.subs)?;
-                }
-                Ok(())
+            TypeKind::Adt(item_with_subs)
+            | TypeKind::FunctionDef(item_with_subs)
+            | TypeKind::AssociatedType(item_with_subs)
+            | TypeKind::Opaque(item_with_subs, _) => {
+                write!(f, "{}", item_with_subs)
             }
+            TypeKind::Foreign(_, path) => write!(f, "{}", path),
+            // closures have no name, but their path says where they are defined
+            TypeKind::Closure(closure, _) => write!(f, "{}", closure.def_full_path),
             TypeKind::Tuple(children) => {
                 write!(f, "(")?;
                 for (i, ty) in children.iter().enumerate() {
                     if i > 0 {
-                        write!(f, ",")?;
+                        write!(f, ", ")?;
                     }
                     write!(f, "{}", ty)?;
                 }
+                if children.len() == 1 {
+                    write!(f, ",")?;
+                }
                 write!(f, ")")
             }
+            TypeKind::Array(child, len) => {
+                write!(f, "[{}; ", child)?;
+                match len {
+                    ConstGeneric::Value(n) => write!(f, "{}]", n),
+                    ConstGeneric::Param(n) => write!(f, "{{param#{}}}]", n),
+                    ConstGeneric::Error | ConstGeneric::Unknown => write!(f, "_]"),
+                }
+            }
             TypeKind::Slice(child) => {
                 write!(f, "[{}]", child)
             }
@@ -510,6 +598,9 @@ impl<'vm> Display for Type<'vm> {
                 FloatWidth::F32 => write!(f, "f32"),
                 FloatWidth::F64 => write!(f, "f64"),
             },
+            TypeKind::Char => write!(f, "char"),
+            TypeKind::Bool => write!(f, "bool"),
+            TypeKind::Never => write!(f, "!"),
             TypeKind::StringSlice => {
                 write!(f, "str")
             }
@@ -521,22 +612,121 @@ impl<'vm> Display for Type<'vm> {
                 Mutability::Const => write!(f, "*const {}", ty),
                 Mutability::Mut => write!(f, "*mut {}", ty),
             },
-            _ => {
-                write!(f, "{:?}", self)
+            TypeKind::FunctionPointer(sig, abi) => {
+                match abi {
+                    FunctionAbi::Rust => (),
+                    FunctionAbi::C => write!(f, "extern \"C\" ")?,
+                    _ => write!(f, "extern \"{:?}\" ", abi)?,
+                }
+                write!(f, "fn(")?;
+                for (i, ty) in sig.inputs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", ty)?;
+                }
+                write!(f, ")")?;
+                if !matches!(sig.output.kind(), TypeKind::Tuple(fields) if fields.is_empty()) {
+                    write!(f, " -> {}", sig.output)?;
+                }
+                Ok(())
             }
+            TypeKind::Dynamic {
+                primary_trait,
+                projections,
+                auto_traits,
+                is_dyn_star,
+            } => {
+                write!(f, "{}", if *is_dyn_star { "dyn*" } else { "dyn" })?;
+                let mut first = true;
+                if let Some(primary_trait) = primary_trait {
+                    let path = primary_trait.item.path.as_string();
+                    write!(f, " {}", path)?;
+                    let fn_args = match primary_trait.subs.list.as_slice() {
+                        [Sub::Type(args)] if is_fn_trait(path) => match args.kind() {
+                            TypeKind::Tuple(args) => Some(args),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if let Some(args) = fn_args {
+                        // sugar for the `Fn` traits, like `Fn(&str) -> bool`
+                        write!(f, "(")?;
+                        for (i, ty) in args.iter().enumerate() {
+                            if i > 0 {
+                                write!(f, ", ")?;
+                            }
+                            write!(f, "{}", ty)?;
+                        }
+                        write!(f, ")")?;
+                        for (_, ty) in projections {
+                            if !matches!(ty.kind(), TypeKind::Tuple(fields) if fields.is_empty()) {
+                                write!(f, " -> {}", ty)?;
+                            }
+                        }
+                    } else {
+                        let mut any_args = false;
+                        for sub in &primary_trait.subs.list {
+                            write!(f, "{}{}", if any_args { ", " } else { "<" }, sub)?;
+                            any_args = true;
+                        }
+                        for (assoc_ty, ty) in projections {
+                            let path = assoc_ty.item.path.as_string();
+                            let name = path.rsplit("::").next().unwrap();
+                            write!(f, "{}{} = {}", if any_args { ", " } else { "<" }, name, ty)?;
+                            any_args = true;
+                        }
+                        if any_args {
+                            write!(f, ">")?;
+                        }
+                    }
+                    first = false;
+                }
+                for name in auto_traits.paths() {
+                    write!(f, "{}{}", if first { " " } else { " + " }, name)?;
+                    first = false;
+                }
+                Ok(())
+            }
+            TypeKind::Param(n) => write!(f, "{{param#{}}}", n),
+            TypeKind::Unknown => write!(f, "_"),
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Persist)]
+fn is_fn_trait(path: &str) -> bool {
+    matches!(
+        path,
+        "::ops::function::Fn" | "::ops::function::FnMut" | "::ops::function::FnOnce"
+    )
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Persist)]
 pub struct AutoTraitSet(u16);
 
 impl AutoTraitSet {
     pub const EMPTY: AutoTraitSet = AutoTraitSet(0);
     pub const SEND: AutoTraitSet = AutoTraitSet(1);
     pub const SYNC: AutoTraitSet = AutoTraitSet(2);
+    pub const UNPIN: AutoTraitSet = AutoTraitSet(4);
+    pub const UNWIND_SAFE: AutoTraitSet = AutoTraitSet(8);
+    pub const REF_UNWIND_SAFE: AutoTraitSet = AutoTraitSet(16);
 
     pub fn add(&mut self, other: Self) {
         self.0 |= other.0;
     }
+
+    /// The paths of the traits in the set.
+    pub fn paths(self) -> impl Iterator<Item = &'static str> {
+        [
+            (Self::SEND, "::marker::Send"),
+            (Self::SYNC, "::marker::Sync"),
+            (Self::UNPIN, "::marker::Unpin"),
+            (Self::UNWIND_SAFE, "::panic::unwind_safe::UnwindSafe"),
+            (Self::REF_UNWIND_SAFE, "::panic::unwind_safe::RefUnwindSafe"),
+        ]
+        .into_iter()
+        .filter(move |(set, _)| self.0 & set.0 != 0)
+        .map(|(_, path)| path)
+    }
 }
@@ -1,3 +1,5 @@
+use std::fmt::Write;
+
 use crate::{
     abi::POINTER_SIZE,
     items::AdtKind,
@@ -6,26 +8,37 @@ use crate::{
 };
 
 /// A debug utility for printing values at runtime.
-pub unsafe fn print_value<'vm>(ty: Type<'vm>, ptr: *const u8, meta: usize) {
+pub unsafe fn print_value(ty: Type, ptr: *const u8, meta: usize) {
+    print!("{}", format_value(ty, ptr, meta));
+}
+
+/// Renders a value the same way `print_value` prints it.
+pub unsafe fn format_value(ty: Type, ptr: *const u8, meta: usize) -> String {
+    let mut out = String::new();
+    write_value(&mut out, ty, ptr, meta);
+    out
+}
+
+unsafe fn write_value(out: &mut String, ty: Type, ptr: *const u8, meta: usize) {
     match ty.kind() {
         TypeKind::Ref(ref_ty, _) => {
             let ref_ptr: *const u8 = std::ptr::read(ptr as _);
             if ref_ty.is_sized() {
-                print!("&({:?}) ", ref_ptr);
-                print_value(*ref_ty, ref_ptr, 0);
+                _ = write!(out, "&({:?}) ", ref_ptr);
+                write_value(out, *ref_ty, ref_ptr, 0);
             } else {
                 let meta: usize = std::ptr::read(ptr.offset(POINTER_SIZE.bytes() as _) as _);
-                print!("&({:?},0x{:x}) ", ref_ptr, meta);
-                print_value(*ref_ty, ref_ptr, meta);
+                _ = write!(out, "&({:?},0x{:x}) ", ref_ptr, meta);
+                write_value(out, *ref_ty, ref_ptr, meta);
             }
         }
         TypeKind::Ptr(ref_ty, _) => {
             let ref_ptr: *const u8 = std::ptr::read(ptr as _);
             if ref_ty.is_sized() {
-                print!("*({:?})", ref_ptr);
+                _ = write!(out, "*({:?})", ref_ptr);
             } else {
                 let meta: usize = std::ptr::read(ptr.offset(POINTER_SIZE.bytes() as _) as _);
-                print!("*({:?},0x{:x})", ref_ptr, meta);
+                _ = write!(out, "*({:?},0x{:x})", ref_ptr, meta);
             }
         }
 
@@ -35,38 +48,20 @@ pub unsafe fn print_value<'vm>(ty: Type<'vm>, ptr: *const u8, meta: usize) {
 
             let variant = match &adt_info.kind {
                 AdtKind::Struct => {
-                    print!("struct{{ ");
+                    _ = write!(out, "struct{{ ");
                     Some(VariantIndex::new(0))
                 }
                 AdtKind::Enum(e) => {
                     let disc = e.discriminant_internal;
 
-                    print!("enum(");
-                    print_value(disc, ptr, 0);
-                    print!("){{ ");
-
-                    let disc = match disc.kind() {
-                        TypeKind::Int(IntWidth::I32, IntSign::Unsigned) => {
-                            let n: u32 = std::ptr::read(ptr as _);
-                            Discriminant::new(n as _)
-                        }
-                        TypeKind::Int(IntWidth::I32, IntSign::Signed) => {
-                            let n: i32 = std::ptr::read(ptr as _);
-                            Discriminant::new(n as _)
-                        }
-                        _ => {
-                            panic!("can't read discriminant {}",disc);
-                        }
-                    };
-
-                    let variant = adt_info
-                        .index_for_discriminant(ty.vm(), disc)
-                        .expect("no variant index found");
-
-                    Some(variant)
+                    _ = write!(out, "enum(");
+                    write_value(out, disc, ptr, 0);
+                    _ = write!(out, "){{ ");
+
+                    Some(enum_variant(ty, disc, ptr))
                 }
                 AdtKind::Union => {
-                    print!("union{{ ?");
+                    _ = write!(out, "union{{ ?");
                     None
                 }
             };
@@ -82,21 +77,21 @@ pub unsafe fn print_value<'vm>(ty: Type<'vm>, ptr: *const u8, meta: usize) {
                 {
                     let field_ty = ty.sub(&item_ref.subs);
                     if i != 0 {
-                        print!(" , ");
+                        _ = write!(out, " , ");
                     }
 
                     let field_ptr = ptr.offset(*offset as isize);
-                    print_value(field_ty, field_ptr, 0);
+                    write_value(out, field_ty, field_ptr, 0);
                 }
             }
-            print!(" }}");
+            _ = write!(out, " }}");
         }
         TypeKind::Tuple(children) => {
             if children.len() == 0 {
-                print!("()");
+                _ = write!(out, "()");
             } else {
                 let tup_layout = ty.layout();
-                print!("( ");
+                _ = write!(out, "( ");
 
                 for (i, (ty, offset)) in children
                     .iter()
@@ -104,56 +99,117 @@ pub unsafe fn print_value<'vm>(ty: Type<'vm>, ptr: *const u8, meta: usize) {
                     .enumerate()
                 {
                     if i != 0 {
-                        print!(" , ");
+                        _ = write!(out, " , ");
                     }
 
                     let field_ptr = ptr.offset(*offset as isize);
-                    print_value(*ty, field_ptr, 0);
+                    write_value(out, *ty, field_ptr, 0);
                 }
-                print!(" )");
+                _ = write!(out, " )");
             }
         }
 
         TypeKind::StringSlice => {
             let byte_slice = std::slice::from_raw_parts(ptr, meta);
             let string = std::str::from_utf8(byte_slice).expect("bar str");
-            print!("{:?}", string);
+            _ = write!(out, "{:?}", string);
         }
         TypeKind::Slice(child_ty) => {
-            print!("[ ");
+            _ = write!(out, "[ ");
             let child_size = child_ty.layout().assert_size();
             for i in 0..meta {
                 if i != 0 {
-                    print!(" , ");
+                    _ = write!(out, " , ");
                 }
                 let child_offset = child_size as isize * i as isize;
 
-                print_value(*child_ty, ptr.offset(child_offset), 0);
+                write_value(out, *child_ty, ptr.offset(child_offset), 0);
             }
-            print!(" ]");
+            _ = write!(out, " ]");
         }
 
-        TypeKind::Int(IntWidth::I32, IntSign::Signed) => print_raw::<i32>(ptr),
-        TypeKind::Int(IntWidth::I64, IntSign::Signed) => print_raw::<i64>(ptr),
+        TypeKind::Int(IntWidth::I8, IntSign::Signed) => write_raw::<i8>(out, ptr),
+        TypeKind::Int(IntWidth::I16, IntSign::Signed) => write_raw::<i16>(out, ptr),
+        TypeKind::Int(IntWidth::I32, IntSign::Signed) => write_raw::<i32>(out, ptr),
+        TypeKind::Int(IntWidth::I64, IntSign::Signed) => write_raw::<i64>(out, ptr),
+        TypeKind::Int(IntWidth::I128, IntSign::Signed) => write_raw::<i128>(out, ptr),
+        TypeKind::Int(IntWidth::ISize, IntSign::Signed) => write_raw::<isize>(out, ptr),
 
-        TypeKind::Int(IntWidth::I8, IntSign::Unsigned) => print_raw::<u8>(ptr),
-        TypeKind::Int(IntWidth::I32, IntSign::Unsigned) => print_raw::<u32>(ptr),
-        TypeKind::Int(IntWidth::ISize, IntSign::Unsigned) => print_raw::<usize>(ptr),
+        TypeKind::Int(IntWidth::I8, IntSign::Unsigned) => write_raw::<u8>(out, ptr),
+        TypeKind::Int(IntWidth::I16, IntSign::Unsigned) => write_raw::<u16>(out, ptr),
+        TypeKind::Int(IntWidth::I32, IntSign::Unsigned) => write_raw::<u32>(out, ptr),
+        TypeKind::Int(IntWidth::I64, IntSign::Unsigned) => write_raw::<u64>(out, ptr),
+        TypeKind::Int(IntWidth::I128, IntSign::Unsigned) => write_raw::<u128>(out, ptr),
+        TypeKind::Int(IntWidth::ISize, IntSign::Unsigned) => write_raw::<usize>(out, ptr),
 
-        TypeKind::Float(FloatWidth::F32) => print_raw::<f32>(ptr),
+        TypeKind::Float(FloatWidth::F32) => write_raw::<f32>(out, ptr),
+        TypeKind::Float(FloatWidth::F64) => write_raw::<f64>(out, ptr),
 
-        TypeKind::Bool => print_raw::<bool>(ptr),
+        TypeKind::Bool => write_raw::<bool>(out, ptr),
         TypeKind::Char => {
-            print!("'");
-            print_raw::<char>(ptr);
-            print!("'");
+            _ = write!(out, "'");
+            write_raw::<char>(out, ptr);
+            _ = write!(out, "'");
+        }
+
+        _ => _ = write!(out, "NYI={}", ty),
+    }
+}
+
+/// The parts of a value which a debugger can expand: the fields of a struct, tuple or enum
+/// variant, named by their index, or the target of a reference, named `*`.
+pub unsafe fn value_fields(ty: Type<'_>, ptr: *const u8) -> Vec<(String, Type<'_>, *const u8)> {
+    match ty.kind() {
+        TypeKind::Ref(ref_ty, _) if ref_ty.is_sized() => {
+            let ref_ptr: *const u8 = std::ptr::read(ptr as _);
+            vec![("*".to_owned(), *ref_ty, ref_ptr)]
         }
+        TypeKind::Adt(item_ref) => {
+            let adt_info = item_ref.item.adt_info();
+            let variant = match &adt_info.kind {
+                AdtKind::Struct => VariantIndex::new(0),
+                AdtKind::Enum(e) => enum_variant(ty, e.discriminant_internal, ptr),
+                AdtKind::Union => return Vec::new(),
+            };
 
-        _ => print!("NYI={}", ty),
+            adt_info
+                .variant_fields
+                .get(variant)
+                .iter()
+                .zip(ty.layout().field_offsets.get(variant))
+                .enumerate()
+                .map(|(i, (field_ty, offset))| {
+                    let field_ty = field_ty.sub(&item_ref.subs);
+                    (i.to_string(), field_ty, ptr.offset(*offset as isize))
+                })
+                .collect()
+        }
+        TypeKind::Tuple(children) => children
+            .iter()
+            .zip(ty.layout().field_offsets.assert_single())
+            .enumerate()
+            .map(|(i, (field_ty, offset))| (i.to_string(), *field_ty, ptr.offset(*offset as isize)))
+            .collect(),
+        _ => Vec::new(),
     }
 }
 
-unsafe fn print_raw<T: std::fmt::Display>(ptr: *const u8) {
+unsafe fn enum_variant(ty: Type, disc_ty: Type, ptr: *const u8) -> VariantIndex {
+    let disc_size = disc_ty.layout().assert_size() as usize;
+    let disc_bytes = std::slice::from_raw_parts(ptr, disc_size);
+    let disc = Discriminant::from_bytes(disc_bytes, disc_ty);
+
+    let TypeKind::Adt(item_ref) = ty.kind() else {
+        panic!("not an enum: {}", ty);
+    };
+    item_ref
+        .item
+        .adt_info()
+        .index_for_discriminant(ty.vm(), disc)
+        .expect("no variant index found")
+}
+
+unsafe fn write_raw<T: std::fmt::Display>(out: &mut String, ptr: *const u8) {
     let n: T = std::ptr::read(ptr as _);
-    print!("{}", n);
+    _ = write!(out, "{}", n);
 }
@@ -2,7 +2,7 @@ use skitter_macro::Persist;
 
 use crate::{
     persist::Persist,
-    types::{IntSign, IntWidth, Type, TypeKind},
+    types::{IntSign, Type, TypeKind},
 };
 
 #[derive(Copy, Clone, Eq, PartialEq, Persist, Debug)]
@@ -32,16 +32,19 @@ impl Discriminant {
     }
 
     pub fn from_bytes(bytes: &[u8], ty: Type) -> Self {
-        let val = match ty.kind() {
-            TypeKind::Int(IntWidth::I8, IntSign::Signed) => {
-                i8::from_le_bytes(bytes.try_into().unwrap()) as i128
-            }
-            TypeKind::Int(IntWidth::ISize, IntSign::Signed) => {
-                isize::from_le_bytes(bytes.try_into().unwrap()) as i128
-            }
-            _ => panic!("discriminant from bytes {:?} {} -> ???", bytes, ty),
+        let TypeKind::Int(_, sign) = ty.kind() else {
+            panic!("discriminant from bytes {:?} {} -> ???", bytes, ty);
         };
 
+        // sign- or zero-extend to 128 bits
+        let fill = match sign {
+            IntSign::Signed if bytes.last().copied().unwrap_or(0) & 0x80 != 0 => 0xFF,
+            _ => 0,
+        };
+        let mut full_bytes = [fill; 16];
+        full_bytes[..bytes.len()].copy_from_slice(bytes);
+        let val = i128::from_le_bytes(full_bytes);
+
         //eprintln!("discriminant from bytes {:?} {} -> {}",bytes,ty,val);
         Self::Value(val)
     }
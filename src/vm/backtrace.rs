@@ -0,0 +1,196 @@
+use std::{cell::UnsafeCell, fmt::Write};
+
+use crate::{bytecode_compiler::FunctionBytecode, ir::Span};
+
+use super::{thread_local::ThreadLocals, Function};
+
+/// The interpreted functions being called on one OS thread, innermost last. Shared by every
+/// `VMThread` on that thread, so backtraces include the callers of drops, panics and callbacks.
+///
+/// It is pushed to on every call, so it has no borrow checks: only its own thread uses it, and
+/// no method calls back into the interpreter while holding a reference to the frames.
+#[derive(Default)]
+pub struct CallStack {
+    frames: UnsafeCell<Vec<Frame>>,
+}
+
+struct Frame {
+    /// A `&Function`, with its lifetime erased so the stack can live in host thread locals.
+    func: *const (),
+    /// The instruction being run, or the last call made. `None` for native and JIT frames.
+    pc: Option<usize>,
+    /// The frame's base on the interpreter stack.
+    stack: *mut u8,
+}
+
+/// A frame of the call stack, as seen by a debugger.
+pub struct FrameInfo<'vm> {
+    pub func: &'vm Function<'vm>,
+    pub pc: Option<usize>,
+    pub stack: *mut u8,
+}
+
+impl Frame {
+    /// The bytecode of an interpreted frame.
+    fn bytecode<'vm>(&self) -> Option<&'vm FunctionBytecode<'vm>> {
+        self.pc?;
+        // functions are arena allocated, and outlive any thread calling them
+        let func = unsafe { &*(self.func as *const Function<'vm>) };
+        func.get_bytecode()
+    }
+
+    fn span<'vm>(&self, bc: &FunctionBytecode<'vm>) -> (&'vm str, Span) {
+        (bc.spans.file, bc.spans.span_at(self.pc.unwrap()))
+    }
+}
+
+impl CallStack {
+    fn frames_ref(&self) -> &Vec<Frame> {
+        unsafe { &*self.frames.get() }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn frames_mut(&self) -> &mut Vec<Frame> {
+        unsafe { &mut *self.frames.get() }
+    }
+
+    pub fn push(&self, func: &Function, interpreted: bool, stack: *mut u8) {
+        self.frames_mut().push(Frame {
+            func: func as *const Function as *const (),
+            pc: interpreted.then_some(0),
+            stack,
+        });
+    }
+
+    pub fn pop(&self) {
+        self.frames_mut().pop();
+    }
+
+    /// Set the pc of the innermost frame. Must be done before anything which can call out of
+    /// the interpreter loop, so the frame's position is known if it never returns.
+    // inlined into each of those arms, it takes registers from the rest of the loop
+    #[inline(never)]
+    pub fn set_pc(&self, pc: usize) {
+        if let Some(frame) = self.frames_mut().last_mut() {
+            frame.pc = Some(pc);
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames_ref().len()
+    }
+
+    /// Removes the frames of calls which were unwound by a panic.
+    pub fn truncate(&self, depth: usize) {
+        self.frames_mut().truncate(depth);
+    }
+
+    /// Where the innermost frame, a `#[track_caller]` function, was called from. This is the
+    /// current position of the closest caller which doesn't track its own caller.
+    pub fn caller_span<'vm>(&self) -> (&'vm str, Span) {
+        for frame in self.frames_ref().iter().rev() {
+            let Some(bc) = frame.bytecode() else {
+                break;
+            };
+            if !bc.track_caller {
+                return frame.span(bc);
+            }
+        }
+        ("", Span::NONE)
+    }
+
+    /// Where the innermost frame was called from, if its caller was interpreted.
+    pub fn call_site<'vm>(&self) -> Option<(&'vm str, Span)> {
+        let frames = self.frames_ref();
+        let caller = frames.iter().rev().nth(1)?;
+        caller.bytecode().map(|bc| caller.span(bc))
+    }
+
+    pub fn innermost<'vm>(&self) -> Option<FrameInfo<'vm>> {
+        let frames = self.frames_ref();
+        frames.last().map(|frame| FrameInfo {
+            // functions are arena allocated, and outlive any thread calling them
+            func: unsafe { &*(frame.func as *const Function<'vm>) },
+            pc: frame.pc,
+            stack: frame.stack,
+        })
+    }
+
+    /// Every frame on the stack, innermost first.
+    pub fn frames<'vm>(&self) -> Vec<FrameInfo<'vm>> {
+        let frames = self.frames_ref();
+        frames
+            .iter()
+            .rev()
+            .map(|frame| FrameInfo {
+                // functions are arena allocated, and outlive any thread calling them
+                func: unsafe { &*(frame.func as *const Function<'vm>) },
+                pc: frame.pc,
+                stack: frame.stack,
+            })
+            .collect()
+    }
+
+    /// Formats the stack like a Rust backtrace, innermost first. Consecutive repeats of the same
+    /// frame, as in deep recursion, are collapsed into one line.
+    pub fn format(&self) -> String {
+        let frames = self.frames_ref();
+        let mut res = String::new();
+
+        let mut frames = frames.iter().rev().peekable();
+        let mut index = 0;
+        while let Some(frame) = frames.next() {
+            let mut count = 1;
+            while frames
+                .peek()
+                .is_some_and(|next| next.func == frame.func && next.pc == frame.pc)
+            {
+                frames.next();
+                count += 1;
+            }
+
+            // functions are arena allocated, and outlive any thread calling them
+            let func = unsafe { &*(frame.func as *const Function) };
+
+            _ = write!(res, "{:>4}: {}", index, func.debug_name());
+            if let Some(pc) = frame.pc {
+                match frame.bytecode().map(|bc| frame.span(bc)) {
+                    Some((file, span)) if !span.is_none() => {
+                        _ = write!(res, " at {}:{}:{}", file, span.line, span.col);
+                    }
+                    _ => _ = write!(res, " at pc {}", pc),
+                }
+            }
+            if count > 1 {
+                _ = write!(res, " ({} times)", count);
+            }
+            res.push('\n');
+
+            index += 1;
+        }
+
+        res
+    }
+}
+
+pub fn backtrace_enabled() -> bool {
+    match std::env::var("RUST_BACKTRACE") {
+        Ok(val) => val != "0",
+        Err(_) => false,
+    }
+}
+
+/// Prints a backtrace of the interpreted code running on the current thread, if `RUST_BACKTRACE`
+/// is set. Meant to be called from a panic hook, after an error inside the interpreter.
+pub fn print_backtrace() {
+    if !backtrace_enabled() {
+        return;
+    }
+
+    ThreadLocals::for_each_on_current_thread(|locals| {
+        if locals.call_stack.depth() > 0 {
+            eprintln!("interpreter backtrace:");
+            eprint!("{}", locals.call_stack.format());
+        }
+    });
+}
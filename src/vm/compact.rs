@@ -0,0 +1,343 @@
+//! The compact form of bytecode, which is how functions keep their code. Each instruction is its
+//! opcode followed by its fields, without padding, so most instructions take much less room than
+//! an `Instr`. The encoder, the decoder and the layout of each instruction's fields are derived
+//! from `Instr` with `#[derive(Compact)]`, and `build.rs` generates the interpreter loop's match
+//! from the arms of `exec_match.rs`. The debugger, disassembler and JIT work on `Instr`s, which
+//! are decoded from the compact form the first time one of them asks for a function's code.
+//!
+//! Functions, items and strings are kept in a pool next to the code, and referred to by their
+//! index in it. The pool holds references to the VM's functions and items, so code is only valid
+//! in the VM which compiled it.
+//!
+//! Reading is only forced inline in release builds. Without optimizations, every inlined read
+//! keeps its own locals in the interpreter loop's frame, which grows too big to nest many calls.
+
+use std::sync::{atomic::Ordering, OnceLock};
+
+use crate::{items::Item, types::DropBit};
+
+use super::{
+    instr::{opcode, Instr, Slot},
+    Function,
+};
+
+/// Written before instructions which the interpreter loop has no arm for. They are written whole
+/// after it, without a jump offset, and the loop's catch-all arm reads them back as `Instr`s.
+pub const FALLBACK_OPCODE: u16 = opcode::COUNT;
+
+// `has_compact_arm`, generated by build.rs from the arms of the loop
+include!(concat!(env!("OUT_DIR"), "/compact_arms.rs"));
+
+/// A function's code in compact form, and the pool of values it refers to.
+pub struct CompactCode<'vm> {
+    code: Box<[u8]>,
+    pool: ConstPool<'vm>,
+    /// The code as `Instr`s, decoded when it is first asked for.
+    instrs: OnceLock<Vec<Instr<'vm>>>,
+}
+
+/// Fields which are not plain data, referred to from the code by index.
+#[derive(Default)]
+struct ConstPool<'vm> {
+    functions: Vec<&'vm Function<'vm>>,
+    items: Vec<&'vm Item<'vm>>,
+    strings: Vec<String>,
+}
+
+impl<'vm> CompactCode<'vm> {
+    pub fn new(code: Vec<Instr<'vm>>) -> Self {
+        let mut out = CompactWriter {
+            code: Vec::new(),
+            pool: ConstPool::default(),
+        };
+        let mut starts = Vec::with_capacity(code.len() + 1);
+        // jumps are patched once every instruction has been placed
+        let mut jumps = Vec::new();
+
+        for (pc, instr) in code.iter().enumerate() {
+            starts.push(out.code.len());
+            if !has_compact_arm(instr) {
+                out.put_opcode(FALLBACK_OPCODE);
+                instr.write_compact(&mut out);
+                continue;
+            }
+            instr.write_compact(&mut out);
+            if instr.jump_offset().is_some() {
+                jumps.push((pc, out.code.len()));
+                out.put_bytes(&0i32);
+            }
+        }
+        starts.push(out.code.len());
+
+        for (pc, at) in jumps {
+            let target = (pc as isize + code[pc].jump_offset().unwrap() as isize) as usize;
+            let offset = (starts[target] as isize - starts[pc + 1] as isize) as i32;
+            out.code[at..at + 4].copy_from_slice(&offset.to_ne_bytes());
+        }
+        Self {
+            code: out.code.into_boxed_slice(),
+            pool: out.pool,
+            instrs: OnceLock::new(),
+        }
+    }
+
+    /// The code as `Instr`s, for tools which need to index it or look at every field.
+    pub fn instrs(&self) -> &[Instr<'vm>] {
+        self.instrs.get_or_init(|| self.decode())
+    }
+
+    fn decode(&self) -> Vec<Instr<'vm>> {
+        let mut input = CompactReader::new(self);
+        let end = self.code.as_ptr_range().end;
+        let mut code = Vec::new();
+
+        while input.cursor < end {
+            let instr = unsafe {
+                let opcode = input.take_opcode();
+                if opcode == FALLBACK_OPCODE {
+                    Instr::read_compact(input.take_opcode(), &mut input)
+                } else {
+                    let instr = Instr::read_compact(opcode, &mut input);
+                    if instr.jump_offset().is_some() {
+                        input.take_jump();
+                    }
+                    instr
+                }
+            };
+            code.push(instr);
+        }
+        code
+    }
+}
+
+/// Adds a value to the pool, and returns the index it is referred to by.
+fn add_to_pool<T>(list: &mut Vec<T>, value: T) -> u32 {
+    list.push(value);
+    list.len() as u32 - 1
+}
+
+pub struct CompactWriter<'vm> {
+    code: Vec<u8>,
+    pool: ConstPool<'vm>,
+}
+
+impl<'vm> CompactWriter<'vm> {
+    pub fn put_opcode(&mut self, opcode: u16) {
+        self.put_bytes(&opcode);
+    }
+
+    fn put_bytes<T: Copy>(&mut self, value: &T) {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>())
+        };
+        self.code.extend_from_slice(bytes);
+    }
+}
+
+/// Reads compact code, one opcode or field at a time.
+pub struct CompactReader<'a, 'vm> {
+    cursor: *const u8,
+    pool: &'a ConstPool<'vm>,
+}
+
+impl<'a, 'vm> CompactReader<'a, 'vm> {
+    /// Reads `code` from its first instruction.
+    pub fn new(code: &'a CompactCode<'vm>) -> Self {
+        Self {
+            cursor: code.code.as_ptr(),
+            pool: &code.pool,
+        }
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    pub unsafe fn take_opcode(&mut self) -> u16 {
+        self.take_bytes()
+    }
+
+    /// Reads the next opcode without moving past it.
+    #[cfg(feature = "profile-opcodes")]
+    pub unsafe fn peek_opcode(&self) -> u16 {
+        std::ptr::read_unaligned(self.cursor as *const u16)
+    }
+
+    /// The offset in bytes which follows a jump, from the start of the next instruction.
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    pub unsafe fn take_jump(&mut self) -> i32 {
+        self.take_bytes()
+    }
+
+    /// Moves by `offset` bytes. Once a jump's fields and offset have been read, this is a jump to
+    /// its target.
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    pub unsafe fn jump(&mut self, offset: i32) {
+        self.cursor = self.cursor.offset(offset as isize);
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    unsafe fn take_bytes<T: Copy>(&mut self) -> T {
+        let value = std::ptr::read_unaligned(self.cursor as *const T);
+        self.cursor = self.cursor.add(std::mem::size_of::<T>());
+        value
+    }
+}
+
+/// A type of field in an instruction, and how it is stored in the compact form.
+pub trait CompactField<'vm>: Sized {
+    /// The field as the interpreter reads it back, which may borrow from the pool.
+    type Read<'a>
+    where
+        'vm: 'a;
+
+    fn put(&self, out: &mut CompactWriter<'vm>);
+
+    unsafe fn take<'a>(input: &mut CompactReader<'a, 'vm>) -> Self::Read<'a>;
+
+    /// Reads the field back as it is stored in an `Instr`.
+    unsafe fn take_owned(input: &mut CompactReader<'_, 'vm>) -> Self;
+}
+
+/// Fields stored as their bytes.
+macro_rules! plain_fields {
+    ($($ty:ty),*) => {
+        $(
+            impl<'vm> CompactField<'vm> for $ty {
+                type Read<'a> = Self
+    where
+        'vm: 'a;
+
+                fn put(&self, out: &mut CompactWriter<'vm>) {
+                    out.put_bytes(self);
+                }
+
+                #[cfg_attr(not(debug_assertions), inline(always))]
+                unsafe fn take(input: &mut CompactReader<'_, 'vm>) -> Self {
+                    input.take_bytes()
+                }
+
+                unsafe fn take_owned(input: &mut CompactReader<'_, 'vm>) -> Self {
+                    input.take_bytes()
+                }
+            }
+        )*
+    };
+}
+
+plain_fields!(i8, i16, i32, i64, i128, u16, u32, Slot, DropBit);
+
+impl<'vm> CompactField<'vm> for Ordering {
+    type Read<'a> = Self
+    where
+        'vm: 'a;
+
+    fn put(&self, out: &mut CompactWriter<'vm>) {
+        let n: u8 = match self {
+            Ordering::Relaxed => 0,
+            Ordering::Release => 1,
+            Ordering::Acquire => 2,
+            Ordering::AcqRel => 3,
+            Ordering::SeqCst => 4,
+            _ => panic!("unknown ordering {:?}", self),
+        };
+        out.put_bytes(&n);
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    unsafe fn take(input: &mut CompactReader<'_, 'vm>) -> Self {
+        match input.take_bytes::<u8>() {
+            0 => Ordering::Relaxed,
+            1 => Ordering::Release,
+            2 => Ordering::Acquire,
+            3 => Ordering::AcqRel,
+            _ => Ordering::SeqCst,
+        }
+    }
+
+    unsafe fn take_owned(input: &mut CompactReader<'_, 'vm>) -> Self {
+        Self::take(input)
+    }
+}
+
+impl<'vm, A: CompactField<'vm>, B: CompactField<'vm>> CompactField<'vm> for (A, B) {
+    type Read<'a> = (A::Read<'a>, B::Read<'a>)
+    where
+        'vm: 'a;
+
+    fn put(&self, out: &mut CompactWriter<'vm>) {
+        self.0.put(out);
+        self.1.put(out);
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    unsafe fn take<'a>(input: &mut CompactReader<'a, 'vm>) -> Self::Read<'a> {
+        let a = A::take(input);
+        (a, B::take(input))
+    }
+
+    unsafe fn take_owned(input: &mut CompactReader<'_, 'vm>) -> Self {
+        let a = A::take_owned(input);
+        (a, B::take_owned(input))
+    }
+}
+
+impl<'vm> CompactField<'vm> for Box<String> {
+    type Read<'a> = &'a str
+    where
+        'vm: 'a;
+
+    fn put(&self, out: &mut CompactWriter<'vm>) {
+        let index = add_to_pool(&mut out.pool.strings, String::clone(self));
+        out.put_bytes(&index);
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    unsafe fn take<'a>(input: &mut CompactReader<'a, 'vm>) -> &'a str {
+        let index: u32 = input.take_bytes();
+        &input.pool.strings[index as usize]
+    }
+
+    unsafe fn take_owned(input: &mut CompactReader<'_, 'vm>) -> Self {
+        Box::new(Self::take(input).to_owned())
+    }
+}
+
+impl<'vm> CompactField<'vm> for &'vm Function<'vm> {
+    type Read<'a> = Self
+    where
+        'vm: 'a;
+
+    fn put(&self, out: &mut CompactWriter<'vm>) {
+        let index = add_to_pool(&mut out.pool.functions, *self);
+        out.put_bytes(&index);
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    unsafe fn take(input: &mut CompactReader<'_, 'vm>) -> Self {
+        let index: u32 = input.take_bytes();
+        input.pool.functions[index as usize]
+    }
+
+    unsafe fn take_owned(input: &mut CompactReader<'_, 'vm>) -> Self {
+        Self::take(input)
+    }
+}
+
+impl<'vm> CompactField<'vm> for &'vm Item<'vm> {
+    type Read<'a> = Self
+    where
+        'vm: 'a;
+
+    fn put(&self, out: &mut CompactWriter<'vm>) {
+        let index = add_to_pool(&mut out.pool.items, *self);
+        out.put_bytes(&index);
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    unsafe fn take(input: &mut CompactReader<'_, 'vm>) -> Self {
+        let index: u32 = input.take_bytes();
+        input.pool.items[index as usize]
+    }
+
+    unsafe fn take_owned(input: &mut CompactReader<'_, 'vm>) -> Self {
+        Self::take(input)
+    }
+}
@@ -0,0 +1,630 @@
+use std::{
+    ffi::{c_int, OsString},
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    os::fd::{AsRawFd, FromRawFd},
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Mutex, OnceLock,
+    },
+    thread::{JoinHandle, ThreadId},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    types::Type,
+    value_debug::{format_value, value_fields},
+    Engine, Error, Options,
+};
+
+use super::{
+    backtrace::FrameInfo,
+    debugger::{
+        describe_frame, frame_locals, frame_span, DebugState, Frontend, Location, Step, StopReason,
+    },
+    VMThread,
+};
+
+extern "C" {
+    fn dup(fd: c_int) -> c_int;
+    fn dup2(old_fd: c_int, new_fd: c_int) -> c_int;
+    fn pipe(fds: *mut c_int) -> c_int;
+    fn close(fd: c_int) -> c_int;
+}
+
+/// The connection to the editor. Messages are read from stdin and written to the process's
+/// original stdout. Anything else written to stdout or stderr, by the program or by skitter, is
+/// sent to the editor as `output` events.
+struct Connection {
+    output: Mutex<Output>,
+    requests: Mutex<Receiver<Value>>,
+    forwarders: Mutex<Vec<JoinHandle<()>>>,
+}
+
+struct Output {
+    file: File,
+    seq: u64,
+}
+
+static CONNECTION: OnceLock<Connection> = OnceLock::new();
+
+fn connection() -> &'static Connection {
+    CONNECTION.get().expect("no debug adapter connection")
+}
+
+/// The debug adapter's part of the debugger state.
+pub(super) struct DapSession {
+    /// Threads seen by the editor, numbered by their index plus one.
+    threads: Vec<ThreadId>,
+}
+
+impl DapSession {
+    /// Must be created on the thread which runs `main`.
+    pub fn new() -> Self {
+        Self {
+            threads: vec![std::thread::current().id()],
+        }
+    }
+
+    fn current_thread(&mut self) -> usize {
+        let id = std::thread::current().id();
+        match self.threads.iter().position(|thread| *thread == id) {
+            Some(index) => index + 1,
+            None => {
+                self.threads.push(id);
+                self.threads.len()
+            }
+        }
+    }
+}
+
+/// Something a stopped debugger can show the children of, numbered by its index plus one.
+enum VarRef<'vm> {
+    /// The locals of a frame.
+    Locals(usize),
+    /// The fields of a value.
+    Value(Type<'vm>, *const u8),
+}
+
+/// Runs a program under the Debug Adapter Protocol, talking to an editor over stdin and stdout.
+/// The editor provides the program and its arguments with a `launch` request. Returns the exit
+/// code of the program.
+pub fn run_dap_server(options: Options) -> i32 {
+    let connection = CONNECTION.get_or_init(Connection::open);
+    connection.capture_output();
+
+    let mut engine = Engine::new(Options {
+        dap: true,
+        ..options
+    });
+    let debugger = engine.vm().debugger().unwrap();
+
+    let mut program_args = None;
+    let mut configured = false;
+    while program_args.is_none() || !configured {
+        let Some(request) = connection.recv() else {
+            return 0;
+        };
+        let args = &request["arguments"];
+        match command(&request) {
+            "initialize" => {
+                connection.respond(
+                    &request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsFunctionBreakpoints": true,
+                        "supportsSteppingGranularity": true,
+                        "supportsEvaluateForHovers": true,
+                        "supportsTerminateRequest": true,
+                    }),
+                );
+                connection.event("initialized", json!({}));
+            }
+            "launch" => {
+                let Some(program) = args["program"].as_str() else {
+                    connection.respond_error(&request, "no program to launch");
+                    continue;
+                };
+                if let Err(err) = engine.load_file(program) {
+                    connection.respond_error(&request, &err.to_string());
+                    continue;
+                }
+
+                let mut list = vec![OsString::from(program)];
+                list.extend(strings(&args["args"]).map(OsString::from));
+                program_args = Some(list);
+
+                debugger.state().stop_at_main = args["stopOnEntry"].as_bool().unwrap_or(false);
+                connection.respond(&request, json!({}));
+            }
+            "configurationDone" => {
+                configured = true;
+                connection.respond(&request, json!({}));
+            }
+            _ => handle_common(
+                &mut debugger.state(),
+                &request,
+                "the program has not been launched",
+            ),
+        }
+    }
+
+    let code = match engine.run_main(&program_args.unwrap()) {
+        Ok(code) => code,
+        Err(Error::Panic) => 101,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    };
+
+    connection.release_output();
+    connection.event("exited", json!({ "exitCode": code }));
+    connection.event("terminated", json!({}));
+
+    while let Some(request) = connection.recv() {
+        if let "disconnect" | "terminate" = command(&request) {
+            connection.respond(&request, json!({}));
+            break;
+        }
+        connection.respond_error(&request, "the program has exited");
+    }
+    code
+}
+
+/// Handles the requests which arrive while the program is running.
+pub(super) fn poll(state: &mut DebugState) {
+    let connection = connection();
+    loop {
+        let request = match connection.requests.lock().unwrap().try_recv() {
+            Ok(request) => request,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                state.detached = true;
+                return;
+            }
+        };
+        if command(&request) == "pause" {
+            state.pause = true;
+            connection.respond(&request, json!({}));
+        } else {
+            handle_common(state, &request, "the program is running");
+        }
+    }
+}
+
+/// Tells the editor the program stopped, then handles requests until one resumes it.
+pub(super) fn stopped<'vm>(state: &mut DebugState, thread: &VMThread<'vm>, reason: StopReason) {
+    let connection = connection();
+    let Frontend::Dap(session) = &mut state.frontend else {
+        panic!("not a debug adapter");
+    };
+    let thread_id = session.current_thread();
+
+    let mut body = json!({
+        "threadId": thread_id,
+        "allThreadsStopped": true,
+    });
+    body["reason"] = match reason {
+        StopReason::Entry => "entry",
+        StopReason::Step => "step",
+        StopReason::Pause => "pause",
+        StopReason::Breakpoint(id) => {
+            body["hitBreakpointIds"] = json!([id]);
+            "breakpoint"
+        }
+    }
+    .into();
+    connection.event("stopped", body);
+
+    let frames = thread.call_stack().frames();
+    let mut refs: Vec<VarRef<'vm>> = Vec::new();
+
+    loop {
+        let Some(request) = connection.recv() else {
+            state.detached = true;
+            return;
+        };
+        let args = &request["arguments"];
+        let by_instr = args["granularity"] == "instruction";
+
+        let step = match command(&request) {
+            "continue" => {
+                connection.respond(&request, json!({ "allThreadsContinued": true }));
+                return;
+            }
+            "next" if by_instr => Step::Instr,
+            "next" => Step::Over(frames.len()),
+            "stepIn" if by_instr => Step::Instr,
+            "stepIn" => Step::Line,
+            "stepOut" => Step::Out(frames.len()),
+            "pause" => {
+                connection.respond(&request, json!({}));
+                continue;
+            }
+            "stackTrace" => {
+                let start = args["startFrame"].as_u64().unwrap_or(0) as usize;
+                let levels = match args["levels"].as_u64() {
+                    Some(levels) if levels > 0 => levels as usize,
+                    _ => frames.len(),
+                };
+                let stack_frames: Vec<Value> = frames
+                    .iter()
+                    .enumerate()
+                    .skip(start)
+                    .take(levels)
+                    .map(|(index, frame)| stack_frame(index, frame))
+                    .collect();
+                connection.respond(
+                    &request,
+                    json!({ "stackFrames": stack_frames, "totalFrames": frames.len() }),
+                );
+                continue;
+            }
+            "scopes" => {
+                let Some(index) = frame_index(args, frames.len()) else {
+                    connection.respond_error(&request, "no such frame");
+                    continue;
+                };
+                refs.push(VarRef::Locals(index));
+                connection.respond(
+                    &request,
+                    json!({ "scopes": [{
+                        "name": "Locals",
+                        "presentationHint": "locals",
+                        "variablesReference": refs.len(),
+                        "expensive": false,
+                    }] }),
+                );
+                continue;
+            }
+            "variables" => {
+                let reference = args["variablesReference"].as_u64().unwrap_or(0) as usize;
+                let children = match reference.checked_sub(1).and_then(|i| refs.get(i)) {
+                    Some(VarRef::Locals(index)) => {
+                        let frame = &frames[*index];
+                        frame_locals(frame)
+                            .iter()
+                            .map(|local| {
+                                let ptr = unsafe { frame.stack.add(local.slot.index()) };
+                                (local.name.to_owned(), local.ty, ptr as *const u8)
+                            })
+                            .collect()
+                    }
+                    Some(VarRef::Value(ty, ptr)) => unsafe { value_fields(*ty, *ptr) },
+                    None => {
+                        connection.respond_error(&request, "no such variables reference");
+                        continue;
+                    }
+                };
+                let variables: Vec<Value> = children
+                    .into_iter()
+                    .map(|(name, ty, ptr)| {
+                        let mut variable = describe_value(&mut refs, ty, ptr);
+                        variable["name"] = name.into();
+                        variable
+                    })
+                    .collect();
+                connection.respond(&request, json!({ "variables": variables }));
+                continue;
+            }
+            "evaluate" => {
+                let name = args["expression"].as_str().unwrap_or("").trim();
+                let local = frame_index(args, frames.len()).and_then(|index| {
+                    let frame = &frames[index];
+                    let local = frame_locals(frame)
+                        .into_iter()
+                        .rev()
+                        .find(|local| local.name == name)?;
+                    Some((local, frame))
+                });
+                match local {
+                    Some((local, frame)) => {
+                        let ptr = unsafe { frame.stack.add(local.slot.index()) };
+                        let value = describe_value(&mut refs, local.ty, ptr);
+                        connection.respond(
+                            &request,
+                            json!({
+                                "result": value["value"],
+                                "type": value["type"],
+                                "variablesReference": value["variablesReference"],
+                            }),
+                        );
+                    }
+                    None => connection
+                        .respond_error(&request, &format!("no variable named {} in scope", name)),
+                }
+                continue;
+            }
+            _ => {
+                handle_common(state, &request, "not supported");
+                continue;
+            }
+        };
+
+        state.step = Some((std::thread::current().id(), step));
+        connection.respond(&request, json!({}));
+        return;
+    }
+}
+
+/// Handles the requests which are valid whether or not the program is stopped.
+fn handle_common(state: &mut DebugState, request: &Value, unavailable: &str) {
+    let connection = connection();
+    let args = &request["arguments"];
+    match command(request) {
+        "setBreakpoints" => {
+            let Some(path) = args["source"]["path"].as_str() else {
+                connection.respond_error(request, "no source path");
+                return;
+            };
+            state
+                .breakpoints
+                .retain(|bp| !matches!(&bp.location, Location::Line(file, _) if file == path));
+
+            let breakpoints: Vec<Value> = args["breakpoints"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|bp| bp["line"].as_u64())
+                .map(|line| {
+                    let location = Location::Line(path.to_owned(), line as u32);
+                    let id = state.add_breakpoint(location);
+                    json!({ "id": id, "verified": true, "line": line })
+                })
+                .collect();
+            connection.respond(request, json!({ "breakpoints": breakpoints }));
+        }
+        "setFunctionBreakpoints" => {
+            state
+                .breakpoints
+                .retain(|bp| !matches!(bp.location, Location::Item(_)));
+
+            let breakpoints: Vec<Value> = args["breakpoints"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|bp| bp["name"].as_str())
+                .map(|name| {
+                    let id = state.add_breakpoint(Location::item(name));
+                    json!({ "id": id, "verified": true })
+                })
+                .collect();
+            connection.respond(request, json!({ "breakpoints": breakpoints }));
+        }
+        // panics are not caught by the debugger
+        "setExceptionBreakpoints" => connection.respond(request, json!({})),
+        "threads" => {
+            let Frontend::Dap(session) = &state.frontend else {
+                panic!("not a debug adapter");
+            };
+            let threads: Vec<Value> = (1..=session.threads.len())
+                .map(|id| {
+                    let name = if id == 1 {
+                        "main".to_owned()
+                    } else {
+                        format!("thread {}", id)
+                    };
+                    json!({ "id": id, "name": name })
+                })
+                .collect();
+            connection.respond(request, json!({ "threads": threads }));
+        }
+        "disconnect" | "terminate" => {
+            connection.respond(request, json!({}));
+            std::process::exit(0);
+        }
+        _ => connection.respond_error(request, unavailable),
+    }
+}
+
+fn command(request: &Value) -> &str {
+    request["command"].as_str().unwrap_or("")
+}
+
+fn strings(list: &Value) -> impl Iterator<Item = &str> {
+    list.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.as_str())
+}
+
+/// The frame selected by a request's `frameId`, or the innermost one.
+fn frame_index(args: &Value, frame_count: usize) -> Option<usize> {
+    let index = match args["frameId"].as_u64() {
+        Some(id) => (id as usize).checked_sub(1)?,
+        None => 0,
+    };
+    (index < frame_count).then_some(index)
+}
+
+fn stack_frame(index: usize, frame: &FrameInfo) -> Value {
+    let mut res = json!({
+        "id": index + 1,
+        "name": frame.func.debug_name(),
+        "line": 0,
+        "column": 0,
+    });
+    match frame_span(frame) {
+        Some((file, span)) => {
+            let path = std::fs::canonicalize(file)
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| file.to_owned());
+            res["source"] = json!({ "path": path });
+            res["line"] = span.line.into();
+            res["column"] = span.col.into();
+        }
+        None => {
+            // native frames and code without spans can't be shown in an editor
+            res["name"] = describe_frame(frame).into();
+            res["presentationHint"] = "subtle".into();
+        }
+    }
+    res
+}
+
+/// A value as shown by the editor, which can be expanded if it has fields.
+fn describe_value<'vm>(refs: &mut Vec<VarRef<'vm>>, ty: Type<'vm>, ptr: *const u8) -> Value {
+    let value = unsafe { format_value(ty, ptr, 0) };
+    let reference = if unsafe { value_fields(ty, ptr) }.is_empty() {
+        0
+    } else {
+        refs.push(VarRef::Value(ty, ptr));
+        refs.len()
+    };
+    json!({
+        "value": value,
+        "type": ty.to_string(),
+        "variablesReference": reference,
+    })
+}
+
+impl Connection {
+    fn open() -> Self {
+        let (sender, requests) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut input = BufReader::new(std::io::stdin());
+            while let Some(message) = read_message(&mut input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // messages must go to the real stdout, before it is replaced
+        let file = unsafe { File::from_raw_fd(dup(1)) };
+        Self {
+            output: Mutex::new(Output { file, seq: 0 }),
+            requests: Mutex::new(requests),
+            forwarders: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Replaces stdout and stderr with pipes, which are forwarded to the editor.
+    fn capture_output(&'static self) {
+        let mut forwarders = self.forwarders.lock().unwrap();
+        for (fd, category) in [(1, "stdout"), (2, "stderr")] {
+            let mut fds = [0; 2];
+            let mut reader = unsafe {
+                assert_eq!(pipe(fds.as_mut_ptr()), 0, "failed to create pipe");
+                dup2(fds[1], fd);
+                close(fds[1]);
+                File::from_raw_fd(fds[0])
+            };
+
+            forwarders.push(std::thread::spawn(move || {
+                let mut buffer = [0; 4096];
+                // the end of a read can split a character, which is kept for the next one
+                let mut pending = 0;
+                loop {
+                    let n = match reader.read(&mut buffer[pending..]) {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => pending + n,
+                    };
+                    let valid = match std::str::from_utf8(&buffer[..n]) {
+                        Ok(_) => n,
+                        Err(err) if err.error_len().is_none() => err.valid_up_to(),
+                        Err(_) => n,
+                    };
+                    let text = String::from_utf8_lossy(&buffer[..valid]);
+                    self.event("output", json!({ "category": category, "output": text }));
+                    buffer.copy_within(valid..n, 0);
+                    pending = n - valid;
+                }
+            }));
+        }
+    }
+
+    /// Sends everything written so far to the editor. Later output is discarded.
+    fn release_output(&self) {
+        _ = std::io::stdout().flush();
+        _ = std::io::stderr().flush();
+        if let Ok(null) = File::options().write(true).open("/dev/null") {
+            unsafe {
+                dup2(null.as_raw_fd(), 1);
+                dup2(null.as_raw_fd(), 2);
+            }
+        }
+        for forwarder in self.forwarders.lock().unwrap().drain(..) {
+            _ = forwarder.join();
+        }
+    }
+
+    /// Waits for the next request. `None` once the editor has closed stdin.
+    fn recv(&self) -> Option<Value> {
+        self.requests.lock().unwrap().recv().ok()
+    }
+
+    fn send(&self, mut message: Value) {
+        let mut output = self.output.lock().unwrap();
+        output.seq += 1;
+        message["seq"] = output.seq.into();
+        let body = message.to_string();
+        _ = write!(
+            output.file,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        _ = output.file.flush();
+    }
+
+    fn respond(&self, request: &Value, body: Value) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    fn respond_error(&self, request: &Value, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+
+    fn event(&self, event: &str, body: Value) {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }));
+    }
+}
+
+/// Reads a message framed by a `Content-Length` header. `None` at the end of the input.
+fn read_message(input: &mut impl BufRead) -> Option<Value> {
+    loop {
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let Some(length) = length else {
+            continue;
+        };
+        let mut body = vec![0; length];
+        input.read_exact(&mut body).ok()?;
+        match serde_json::from_slice(&body) {
+            Ok(message) => return Some(message),
+            Err(err) => eprintln!("bad debug adapter message: {}", err),
+        }
+    }
+}
@@ -0,0 +1,575 @@
+use std::{
+    io::{BufRead, Write},
+    sync::{Mutex, MutexGuard},
+    thread::ThreadId,
+};
+
+use ahash::AHashMap;
+
+use crate::{
+    bytecode_compiler::{DebugLocal, FunctionBytecode},
+    ir::Span,
+    types::Type,
+    value_debug::format_value,
+    Options,
+};
+
+use super::{
+    backtrace::FrameInfo,
+    dap::{self, DapSession},
+    VMThread, VM,
+};
+
+/// A debugger for interpreted code, driven from the command line with `--debug` or by an editor
+/// with `--dap`. When enabled, the interpreter calls `before_instr` ahead of every instruction,
+/// which stops at breakpoints and while stepping. The command-line debugger stops at the entry of
+/// `main` first, so breakpoints can be set up.
+pub struct Debugger {
+    state: Mutex<DebugState>,
+}
+
+pub(super) struct DebugState {
+    pub breakpoints: Vec<Breakpoint>,
+    next_breakpoint: u32,
+    pub stop_at_main: bool,
+    /// The thread being stepped, and how far.
+    pub step: Option<(ThreadId, Step)>,
+    /// Set to stop all threads at the next instruction any of them runs.
+    pub pause: bool,
+    /// Lines of the source files shown so far, `None` if a file could not be read.
+    sources: AHashMap<String, Option<Vec<String>>>,
+    /// Set once the frontend goes away. The program then runs to completion.
+    pub detached: bool,
+    pub frontend: Frontend,
+}
+
+pub(super) enum Frontend {
+    Cli,
+    Dap(DapSession),
+}
+
+pub(super) struct Breakpoint {
+    pub id: u32,
+    pub location: Location,
+}
+
+pub(super) enum Location {
+    /// The entry of any function instantiated from the item at this path.
+    Item(String),
+    /// The start of a line, in any file whose path ends with the given name or the other way
+    /// around.
+    Line(String, u32),
+}
+
+#[derive(Clone, Copy)]
+pub(super) enum Step {
+    /// Stop before the next instruction.
+    Instr,
+    /// Stop at the next line reached, in any frame.
+    Line,
+    /// Stop at the next line reached by a frame at this call depth or less.
+    Over(usize),
+    /// Stop once the frame at this call depth returns.
+    Out(usize),
+}
+
+#[derive(Clone, Copy)]
+pub(super) enum StopReason {
+    Entry,
+    Step,
+    Pause,
+    Breakpoint(u32),
+}
+
+const HELP: &str = "\
+commands:
+  c, continue            run until the next breakpoint
+  s, step                run to the next line, entering calls
+  n, next                run to the next line in this frame
+  si, stepi              run a single instruction
+  finish                 run until this frame returns
+  b, break <location>    stop at an item path like `::foo::bar`, at `file.rs:12`,
+                         or at a line of the current file
+  d, delete <n>          remove a breakpoint
+  info breakpoints       list breakpoints
+  bt, backtrace          list the frames on the call stack
+  f, frame [n]           select a frame, or show the selected one
+  up, down               select the caller or callee of the selected frame
+  l, list                show the source around the selected frame
+  locals                 print the variables in scope in the selected frame
+  p, print <name>        print a variable in the selected frame
+  x <offset> <type>      print the stack slot at a byte offset in the selected frame,
+                         as a primitive type like `u32` or `ptr`
+  q, quit                exit the program";
+
+impl Debugger {
+    /// The debugger selected by the options, if any.
+    pub fn for_options(options: &Options) -> Option<Self> {
+        if options.dap {
+            Some(Self::new(Frontend::Dap(DapSession::new())))
+        } else if options.debug {
+            Some(Self::new(Frontend::Cli))
+        } else {
+            None
+        }
+    }
+
+    fn new(frontend: Frontend) -> Self {
+        Self {
+            state: Mutex::new(DebugState {
+                breakpoints: Vec::new(),
+                next_breakpoint: 1,
+                stop_at_main: matches!(frontend, Frontend::Cli),
+                step: None,
+                pause: false,
+                sources: AHashMap::new(),
+                detached: false,
+                frontend,
+            }),
+        }
+    }
+
+    pub(super) fn state(&self) -> MutexGuard<DebugState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Called before running the instruction at `pc`. `line` is the last line the calling loop
+    /// ran code from, which is used to detect when a new line is reached.
+    pub fn before_instr<'vm>(
+        &self,
+        thread: &VMThread<'vm>,
+        bc: &FunctionBytecode<'vm>,
+        pc: usize,
+        line: &mut u32,
+    ) {
+        let call_stack = thread.call_stack();
+        call_stack.set_pc(pc);
+
+        // code run outside of a call, like a promoted constant, has no frame of its own
+        let Some(frame) = call_stack.innermost() else {
+            return;
+        };
+        if !frame
+            .func
+            .get_bytecode()
+            .is_some_and(|frame_bc| std::ptr::eq(frame_bc, bc))
+        {
+            return;
+        }
+
+        let span = bc.spans.span_at(pc);
+        let new_line = !span.is_none() && span.line != *line;
+        if !span.is_none() {
+            *line = span.line;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.detached {
+            return;
+        }
+        if let Frontend::Dap(_) = state.frontend {
+            dap::poll(&mut state);
+        }
+
+        let stepping_instr = matches!(state.step, Some((_, Step::Instr)));
+        let depth = call_stack.depth();
+        if let Some(reason) = state.stop_reason(&frame, bc.spans.file, span, new_line, depth) {
+            state.step = None;
+            state.pause = false;
+            match state.frontend {
+                Frontend::Cli => {
+                    println!("{}", reason);
+                    state.print_frame(0, &frame);
+                    if stepping_instr || span.is_none() {
+                        println!("  pc {}: {:?}", pc, bc.code.instrs()[pc]);
+                    }
+                    state.prompt(thread);
+                }
+                Frontend::Dap(_) => dap::stopped(&mut state, thread, reason),
+            }
+        }
+    }
+}
+
+impl DebugState {
+    fn stop_reason(
+        &mut self,
+        frame: &FrameInfo,
+        file: &str,
+        span: Span,
+        new_line: bool,
+        depth: usize,
+    ) -> Option<StopReason> {
+        if self.pause {
+            return Some(StopReason::Pause);
+        }
+
+        if let Some((thread_id, step)) = self.step {
+            if thread_id == std::thread::current().id() {
+                let done = match step {
+                    Step::Instr => true,
+                    Step::Line => new_line,
+                    Step::Over(step_depth) => new_line && depth <= step_depth,
+                    Step::Out(step_depth) => depth < step_depth,
+                };
+                if done {
+                    return Some(StopReason::Step);
+                }
+            }
+        }
+
+        let path = frame.func.item_path().map(|path| path.as_string());
+        if frame.pc == Some(0) && self.stop_at_main && path == Some("::main") {
+            self.stop_at_main = false;
+            return Some(StopReason::Entry);
+        }
+
+        for breakpoint in &self.breakpoints {
+            let hit = match &breakpoint.location {
+                Location::Item(bp_path) => frame.pc == Some(0) && path == Some(bp_path),
+                Location::Line(bp_file, bp_line) => {
+                    new_line && span.line == *bp_line && file_matches(file, bp_file)
+                }
+            };
+            if hit {
+                return Some(StopReason::Breakpoint(breakpoint.id));
+            }
+        }
+
+        None
+    }
+
+    pub fn add_breakpoint(&mut self, location: Location) -> u32 {
+        let id = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.breakpoints.push(Breakpoint { id, location });
+        id
+    }
+
+    /// Reads and runs commands until one resumes the program.
+    fn prompt(&mut self, thread: &VMThread) {
+        let frames = thread.call_stack().frames();
+        let mut selected = 0;
+
+        let stdin = std::io::stdin();
+        loop {
+            print!("(skitter) ");
+            _ = std::io::stdout().flush();
+
+            let mut input = String::new();
+            if stdin.lock().read_line(&mut input).unwrap_or(0) == 0 {
+                println!();
+                self.detached = true;
+                return;
+            }
+
+            let mut words = input.split_whitespace();
+            let Some(command) = words.next() else {
+                continue;
+            };
+            let args: Vec<&str> = words.collect();
+
+            let thread_id = std::thread::current().id();
+            // the call depth of the selected frame
+            let depth = frames.len() - selected;
+
+            match (command, args.as_slice()) {
+                ("c" | "continue", []) => return,
+                ("s" | "step", []) => {
+                    self.step = Some((thread_id, Step::Line));
+                    return;
+                }
+                ("n" | "next", []) => {
+                    self.step = Some((thread_id, Step::Over(depth)));
+                    return;
+                }
+                ("si" | "stepi", []) => {
+                    self.step = Some((thread_id, Step::Instr));
+                    return;
+                }
+                ("finish", []) => {
+                    self.step = Some((thread_id, Step::Out(depth)));
+                    return;
+                }
+                ("b" | "break", [location]) => {
+                    let file = frames.get(selected).and_then(frame_file);
+                    match parse_location(location, file) {
+                        Ok(location) => {
+                            let desc = location.to_string();
+                            let id = self.add_breakpoint(location);
+                            println!("breakpoint {} at {}", id, desc);
+                        }
+                        Err(msg) => println!("{}", msg),
+                    }
+                }
+                ("d" | "delete", [id]) => {
+                    let count = self.breakpoints.len();
+                    self.breakpoints
+                        .retain(|breakpoint| id.parse() != Ok(breakpoint.id));
+                    if self.breakpoints.len() == count {
+                        println!("no breakpoint {}", id);
+                    }
+                }
+                ("info", ["breakpoints" | "b"]) => {
+                    if self.breakpoints.is_empty() {
+                        println!("no breakpoints");
+                    }
+                    for breakpoint in &self.breakpoints {
+                        println!("{:>4}: {}", breakpoint.id, breakpoint.location);
+                    }
+                }
+                ("info", ["locals"]) | ("locals", []) => print_locals(&frames[selected]),
+                ("bt" | "backtrace", []) => {
+                    for (index, frame) in frames.iter().enumerate() {
+                        let marker = if index == selected { '*' } else { ' ' };
+                        println!("{}{:>3}: {}", marker, index, describe_frame(frame));
+                    }
+                }
+                ("f" | "frame", []) => self.print_frame(selected, &frames[selected]),
+                ("f" | "frame", [index]) => match index.parse::<usize>() {
+                    Ok(index) if index < frames.len() => {
+                        selected = index;
+                        self.print_frame(selected, &frames[selected]);
+                    }
+                    _ => println!("no frame {}", index),
+                },
+                ("up", []) => {
+                    if selected + 1 < frames.len() {
+                        selected += 1;
+                        self.print_frame(selected, &frames[selected]);
+                    } else {
+                        println!("already at the outermost frame");
+                    }
+                }
+                ("down", []) => {
+                    if selected > 0 {
+                        selected -= 1;
+                        self.print_frame(selected, &frames[selected]);
+                    } else {
+                        println!("already at the innermost frame");
+                    }
+                }
+                ("l" | "list", []) => self.list(&frames[selected]),
+                ("p" | "print", [name]) => print_local(&frames[selected], name),
+                ("x", [offset, ty_name]) => {
+                    print_slot(thread.vm, &frames[selected], offset, ty_name)
+                }
+                ("q" | "quit", []) => std::process::exit(1),
+                ("h" | "help", _) => println!("{}", HELP),
+                _ => println!("unknown command, try `help`"),
+            }
+        }
+    }
+
+    fn print_frame(&mut self, index: usize, frame: &FrameInfo) {
+        println!("{:>4}: {}", index, describe_frame(frame));
+        if let Some((file, span)) = frame_span(frame) {
+            if let Some(text) = self.source_line(file, span.line) {
+                println!("{:>6} | {}", span.line, text);
+            }
+        }
+    }
+
+    fn list(&mut self, frame: &FrameInfo) {
+        let Some((file, span)) = frame_span(frame) else {
+            println!("no source for this frame");
+            return;
+        };
+        let first = span.line.saturating_sub(5).max(1);
+        for line in first..span.line + 6 {
+            let Some(text) = self.source_line(file, line) else {
+                break;
+            };
+            let marker = if line == span.line { "->" } else { "  " };
+            println!("{} {:>4} | {}", marker, line, text);
+        }
+    }
+
+    fn source_line(&mut self, file: &str, line: u32) -> Option<&str> {
+        let lines = self.sources.entry(file.to_owned()).or_insert_with(|| {
+            let source = std::fs::read_to_string(file).ok()?;
+            Some(source.lines().map(|line| line.to_owned()).collect())
+        });
+        let index = (line as usize).checked_sub(1)?;
+        lines.as_ref()?.get(index).map(|line| line.as_str())
+    }
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Entry => write!(f, "entered main"),
+            StopReason::Step => write!(f, "step"),
+            StopReason::Pause => write!(f, "paused"),
+            StopReason::Breakpoint(id) => write!(f, "breakpoint {}", id),
+        }
+    }
+}
+
+impl Location {
+    /// An item path, with the leading `::` added if it is missing.
+    pub fn item(path: &str) -> Self {
+        if path.starts_with("::") {
+            Location::Item(path.to_owned())
+        } else {
+            Location::Item(format!("::{}", path))
+        }
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Item(path) => write!(f, "{}", path),
+            Location::Line(file, line) => write!(f, "{}:{}", file, line),
+        }
+    }
+}
+
+/// Parses a breakpoint location. A bare line number refers to `current_file`.
+fn parse_location(arg: &str, current_file: Option<&str>) -> Result<Location, String> {
+    if let Ok(line) = arg.parse::<u32>() {
+        let file = current_file.ok_or_else(|| "no current file, use file.rs:line".to_owned())?;
+        return Ok(Location::Line(file.to_owned(), line));
+    }
+
+    // item paths contain `::`, but never end with a number
+    if let Some((file, line)) = arg.rsplit_once(':') {
+        if let Ok(line) = line.parse::<u32>() {
+            if !file.is_empty() && !file.ends_with(':') {
+                return Ok(Location::Line(file.to_owned(), line));
+            }
+        }
+    }
+
+    Ok(Location::item(arg))
+}
+
+/// Whether either path is a suffix of the other, so a relative path matches an absolute one.
+fn file_matches(file: &str, name: &str) -> bool {
+    ends_with_path(file, name) || ends_with_path(name, file)
+}
+
+fn ends_with_path(path: &str, suffix: &str) -> bool {
+    path == suffix || (path.ends_with(suffix) && path[..path.len() - suffix.len()].ends_with('/'))
+}
+
+fn frame_bytecode<'vm>(frame: &FrameInfo<'vm>) -> Option<(&'vm FunctionBytecode<'vm>, usize)> {
+    let pc = frame.pc?;
+    Some((frame.func.get_bytecode()?, pc))
+}
+
+pub(super) fn frame_span<'vm>(frame: &FrameInfo<'vm>) -> Option<(&'vm str, Span)> {
+    let (bc, pc) = frame_bytecode(frame)?;
+    let span = bc.spans.span_at(pc);
+    (!span.is_none()).then_some((bc.spans.file, span))
+}
+
+fn frame_file<'vm>(frame: &FrameInfo<'vm>) -> Option<&'vm str> {
+    frame_span(frame).map(|(file, _)| file)
+}
+
+pub(super) fn describe_frame(frame: &FrameInfo) -> String {
+    let name = frame.func.debug_name();
+    match (frame.pc, frame_span(frame)) {
+        (_, Some((file, span))) => format!("{} at {}:{}:{}", name, file, span.line, span.col),
+        (Some(pc), None) => format!("{} at pc {}", name, pc),
+        (None, None) => format!("{} [native]", name),
+    }
+}
+
+/// The variables in scope at the frame's pc. Later variables shadow earlier ones.
+pub(super) fn frame_locals<'vm>(frame: &FrameInfo<'vm>) -> Vec<&'vm DebugLocal<'vm>> {
+    let Some((bc, pc)) = frame_bytecode(frame) else {
+        return Vec::new();
+    };
+    let pc = pc as u32;
+    bc.locals
+        .iter()
+        .filter(|local| local.start <= pc && pc < local.end)
+        .collect()
+}
+
+fn print_locals(frame: &FrameInfo) {
+    let locals = frame_locals(frame);
+    if locals.is_empty() {
+        println!("no locals");
+    }
+    for local in locals {
+        print_typed(local.name, local.ty, frame, local.slot.index());
+    }
+}
+
+fn print_local(frame: &FrameInfo, name: &str) {
+    match frame_locals(frame)
+        .iter()
+        .rev()
+        .find(|local| local.name == name)
+    {
+        Some(local) => print_typed(local.name, local.ty, frame, local.slot.index()),
+        None => println!("no variable named {} in scope", name),
+    }
+}
+
+fn print_slot<'vm>(vm: &'vm VM<'vm>, frame: &FrameInfo<'vm>, offset: &str, ty_name: &str) {
+    let Some((bc, _)) = frame_bytecode(frame) else {
+        println!("frame is not interpreted");
+        return;
+    };
+    let Ok(offset) = offset.parse::<usize>() else {
+        println!("bad offset {}", offset);
+        return;
+    };
+
+    if ty_name == "ptr" {
+        if offset + std::mem::size_of::<usize>() > bc.frame_size as usize {
+            println!("slot is outside of the frame ({} bytes)", bc.frame_size);
+            return;
+        }
+        let ptr: usize = unsafe { std::ptr::read_unaligned(frame.stack.add(offset) as _) };
+        println!("[{}]: ptr = {:#x}", offset, ptr);
+        return;
+    }
+
+    let Some(ty) = primitive_type(vm, ty_name) else {
+        println!(
+            "unknown type {}, expected a primitive type or `ptr`",
+            ty_name
+        );
+        return;
+    };
+    if offset + ty.layout().assert_size() as usize > bc.frame_size as usize {
+        println!("slot is outside of the frame ({} bytes)", bc.frame_size);
+        return;
+    }
+    print_typed(&format!("[{}]", offset), ty, frame, offset);
+}
+
+fn print_typed(name: &str, ty: Type, frame: &FrameInfo, offset: usize) {
+    let value = unsafe { format_value(ty, frame.stack.add(offset), 0) };
+    println!("{}: {} = {}", name, ty, value);
+}
+
+fn primitive_type<'vm>(vm: &'vm VM<'vm>, name: &str) -> Option<Type<'vm>> {
+    let types = vm.common_types();
+    let ty = match name {
+        "bool" => types.bool,
+        "char" => types.char,
+        "u8" => types.u8,
+        "u16" => types.u16,
+        "u32" => types.u32,
+        "u64" => types.u64,
+        "u128" => types.u128,
+        "usize" => types.usize,
+        "i8" => types.i8,
+        "i16" => types.i16,
+        "i32" => types.i32,
+        "i64" => types.i64,
+        "i128" => types.i128,
+        "isize" => types.isize,
+        "f32" => types.f32,
+        "f64" => types.f64,
+        _ => return None,
+    };
+    Some(ty)
+}
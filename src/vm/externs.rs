@@ -1,132 +1,422 @@
+use std::sync::RwLock;
+
+use ahash::AHashMap;
+
 use super::{
+    ffi::{self, ThunkTarget},
+    host_stack,
+    panic::{builtin_panic, builtin_panic_cleanup, builtin_start_panic},
     read_stack,
-    vm::{NativeFunc, VMThread},
+    vm::{NativeFunc, VMThread, VTable},
     write_stack,
 };
 use crate::{
-    abi::POINTER_SIZE,
-    items::{FunctionAbi, Item},
+    abi::{align, POINTER_SIZE},
+    items::{FunctionAbi, FunctionSig, Item},
+    types::{SubList, Type, TypeKind},
+    variants::VariantIndex,
     vm::{instr::Slot, VM},
 };
 
-pub fn get_extern_fn<'vm>(item: &Item<'vm>) -> Option<NativeFunc> {
-    let path = item.path.as_string();
-    if path.starts_with("::_builtin::") {
+/// Native functions and statics provided by the host, which extern items resolve to.
+#[derive(Default)]
+pub struct HostRegistry {
+    functions: RwLock<AHashMap<(FunctionAbi, String), NativeFunc>>,
+    builtins: RwLock<AHashMap<String, NativeFunc>>,
+    statics: RwLock<AHashMap<(FunctionAbi, String), usize>>,
+}
+
+impl HostRegistry {
+    pub fn register_extern(&self, abi: FunctionAbi, symbol: &str, native: NativeFunc) {
+        let mut functions = self.functions.write().unwrap();
+        functions.insert((abi, symbol.to_owned()), native);
+    }
+
+    /// Only used for our own `_builtin` module, which embedders shouldn't add to.
+    fn register_builtin(&self, path: &str, native: NativeFunc) {
+        let mut builtins = self.builtins.write().unwrap();
+        builtins.insert(path.to_owned(), native);
+    }
+
+    pub fn register_extern_static(&self, abi: FunctionAbi, symbol: &str, ptr: *const u8) {
+        let mut statics = self.statics.write().unwrap();
+        statics.insert((abi, symbol.to_owned()), ptr as usize);
+    }
+
+    fn function(&self, abi: FunctionAbi, symbol: &str) -> Option<NativeFunc> {
+        let functions = self.functions.read().unwrap();
+        functions.get(&(abi, symbol.to_owned())).copied()
+    }
+
+    fn builtin(&self, path: &str) -> Option<NativeFunc> {
+        let builtins = self.builtins.read().unwrap();
+        builtins.get(path).copied()
+    }
+
+    fn extern_static(&self, abi: FunctionAbi, symbol: &str) -> Option<*mut u8> {
+        let statics = self.statics.read().unwrap();
+        statics
+            .get(&(abi, symbol.to_owned()))
+            .map(|ptr| *ptr as *mut u8)
+    }
+
+    /// Registers the functions and statics skitter itself provides.
+    pub fn register_defaults(&self) {
         // hack for skitter builtins, should be removed at some point in the future
-        Some(match path {
-            "::_builtin::print_int" => builtin_print_int,
-            "::_builtin::print_uint" => builtin_print_uint,
-            "::_builtin::print_float" => builtin_print_float,
-            "::_builtin::print_bool" => builtin_print_bool,
-            "::_builtin::print_char" => builtin_print_char,
-            "::_builtin::print_raw" => builtin_print_raw,
-            _ => panic!("unknown builtin {}", path),
-        })
-    } else {
-        if let Some(item_extern) = item.get_extern() {
-            Some(match (item_extern.0, item_extern.1.as_str()) {
-                (FunctionAbi::Rust, "__rust_alloc") => builtin_alloc_zeroed,
-                (FunctionAbi::Rust, "__rust_alloc_zeroed") => builtin_alloc_zeroed,
-                (FunctionAbi::Rust, "__rust_realloc") => builtin_realloc,
-                (FunctionAbi::Rust, "__rust_dealloc") => builtin_free,
-
-                (FunctionAbi::Rust, "panic_impl") => builtin_panic,
-                _ => panic!("todo extern? {:?}", item_extern),
-            })
-        } else {
-            None
+        self.register_builtin("::_builtin::print_int", builtin_print_int);
+        self.register_builtin("::_builtin::print_uint", builtin_print_uint);
+        self.register_builtin("::_builtin::print_float", builtin_print_float);
+        self.register_builtin("::_builtin::print_bool", builtin_print_bool);
+        self.register_builtin("::_builtin::print_char", builtin_print_char);
+        self.register_builtin("::_builtin::print_raw", builtin_print_raw);
+        self.register_builtin("::_builtin::spawn", builtin_spawn);
+        self.register_builtin("::_builtin::join", builtin_join);
+
+        self.register_extern(FunctionAbi::Rust, "__rust_alloc", builtin_alloc_zeroed);
+        self.register_extern(
+            FunctionAbi::Rust,
+            "__rust_alloc_zeroed",
+            builtin_alloc_zeroed,
+        );
+        self.register_extern(FunctionAbi::Rust, "__rust_realloc", builtin_realloc);
+        self.register_extern(FunctionAbi::Rust, "__rust_dealloc", builtin_free);
+
+        self.register_extern(FunctionAbi::Rust, "rust_begin_unwind", builtin_panic);
+        // the panic runtime std expects to be linked with
+        self.register_extern(FunctionAbi::Rust, "__rust_start_panic", builtin_start_panic);
+        self.register_extern(
+            FunctionAbi::C,
+            "__rust_panic_cleanup",
+            builtin_panic_cleanup,
+        );
+
+        // llvm intrinsic behind `spin_loop`, used by contended locks
+        self.register_extern(FunctionAbi::C, "llvm.x86.sse2.pause", host_spin_loop);
+
+        // only defined in the static part of libc, so it can't be found with dlsym
+        self.register_extern(FunctionAbi::C, "atexit", host_atexit);
+
+        self.register_extern_static(
+            FunctionAbi::Rust,
+            "__rust_no_alloc_shim_is_unstable",
+            &BUILTIN_ALLOC_DUMMY,
+        );
+        // weak symbol, used by std to register thread-local destructors
+        self.register_extern(
+            FunctionAbi::C,
+            "__cxa_thread_atexit_impl",
+            host_thread_atexit,
+        );
+        // std sizes its threads' stacks for compiled code
+        self.register_extern(
+            FunctionAbi::C,
+            "pthread_attr_setstacksize",
+            host_pthread_attr_setstacksize,
+        );
+        // only the address is used
+        self.register_extern_static(
+            FunctionAbi::C,
+            "__dso_handle",
+            &BUILTIN_NULL as *const _ as _,
+        );
+    }
+}
+
+/// Typed access to the frame of a native call, which holds the return value followed by each
+/// argument aligned to its type.
+pub struct NativeArgs {
+    stack: *mut u8,
+    offset: u32,
+}
+
+impl NativeArgs {
+    /// `R` is the return type, which the arguments follow.
+    ///
+    /// # Safety
+    ///
+    /// `stack` must be the frame a native function was called with, which is valid for reads and
+    /// writes of the return value and every argument. The interpreter lays this frame out as the
+    /// return value at offset 0, then each argument in order at the next offset aligned for its
+    /// type, so `R` must be the function's real return type.
+    pub unsafe fn new<R>(stack: *mut u8) -> Self {
+        Self {
+            stack,
+            offset: std::mem::size_of::<R>() as u32,
         }
     }
+
+    /// Read the next argument.
+    ///
+    /// # Safety
+    ///
+    /// Arguments must be read in order, each with the type the function was declared with, and
+    /// no more of them than the function takes.
+    pub unsafe fn next<T: Copy>(&mut self) -> T {
+        self.offset = align(self.offset, std::mem::align_of::<T>() as u32);
+        let res = read_stack(self.stack, Slot::new(self.offset));
+        self.offset += std::mem::size_of::<T>() as u32;
+        res
+    }
+
+    /// Write the return value, over the start of the frame.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the `R` given to `new`, which is the space the frame has for it.
+    pub unsafe fn ret<T>(self, res: T) {
+        write_stack(self.stack, Slot::new(0), res);
+    }
 }
 
-pub fn get_extern_static(item: &Item) -> Option<*mut u8> {
-    if let Some(item_extern) = item.get_extern() {
-        Some(match (item_extern.0, item_extern.1.as_str()) {
-            (FunctionAbi::Rust, "__rust_no_alloc_shim_is_unstable") => {
-                (&BUILTIN_ALLOC_DUMMY) as *const _ as *mut _
+pub fn get_extern_fn<'vm>(item: &Item<'vm>, vm: &'vm VM<'vm>) -> Option<NativeFunc> {
+    let path = item.path.as_string();
+    if path.starts_with("::_builtin::") {
+        let builtin = vm.host.builtin(path);
+        Some(builtin.unwrap_or_else(|| panic!("unknown builtin {}", path)))
+    } else if let Some((abi, name)) = item.get_extern() {
+        if let Some(native) = vm.host.function(*abi, name) {
+            return Some(native);
+        }
+
+        match abi {
+            FunctionAbi::C => {
+                let sig = item.func_sig(&SubList { list: vec![] });
+                Some(c_function(name, &sig, vm))
             }
-            _ => panic!("todo extern? {:?}", item_extern),
-        })
+            _ => panic!("unknown extern function {:?} {}", abi, name),
+        }
     } else {
         None
     }
 }
 
-unsafe extern "C" fn builtin_print_int<'vm>(stack: *mut u8, _thread: &VMThread<'vm>) {
-    let x: i128 = read_stack(stack, Slot::new(0));
+/// Resolves a C function and builds a native function which calls it.
+///
+/// Failures produce a stub which panics when called: fallback paths in std reference functions
+/// which may not exist, and a program should only fail if it actually calls them.
+pub fn c_function(name: &str, sig: &FunctionSig, vm: &VM) -> NativeFunc {
+    let Some(func_ptr) = vm.native_libs.find_symbol(name) else {
+        return ffi::build_error_stub(format!("call to unresolved extern function `{}`", name));
+    };
+
+    ffi::build_thunk(ThunkTarget::Fixed(func_ptr), &sig.inputs, sig.output).unwrap_or_else(|err| {
+        ffi::build_error_stub(format!("cannot call extern function `{}`: {}", name, err))
+    })
+}
+
+pub fn get_extern_static<'vm>(
+    item: &Item<'vm>,
+    ty: Type<'vm>,
+    vm: &'vm VM<'vm>,
+) -> Option<*mut u8> {
+    let (abi, name) = item.get_extern()?;
+
+    if let Some(ptr) = vm.host.extern_static(*abi, name) {
+        return Some(ptr);
+    }
+
+    Some(match abi {
+        // weak symbols are optional function pointers
+        FunctionAbi::C if matches!(ty.kind(), TypeKind::Adt(_)) => weak_function(name, ty, vm),
+        FunctionAbi::C => vm
+            .native_libs
+            .find_symbol(name)
+            .unwrap_or_else(|| panic!("unresolved extern static `{}`", name))
+            as *mut u8,
+        _ => panic!("unknown extern static {:?} {}", abi, name),
+    })
+}
+
+/// Builds an `Option<fn>` for a weak symbol, which is `None` if the symbol can't be found.
+fn weak_function<'vm>(name: &str, ty: Type<'vm>, vm: &'vm VM<'vm>) -> *mut u8 {
+    let TypeKind::Adt(adt) = ty.kind() else {
+        panic!("weak symbol should be an option");
+    };
+    let adt_info = adt.item.adt_info();
+    let enum_info = adt_info
+        .enum_info()
+        .expect("weak symbol should be an option");
+
+    let layout = ty.layout();
+    let mut bytes = vec![0; layout.assert_size() as usize];
+
+    let some_variant = VariantIndex::new(1);
+    let fn_ty = adt_info.variant_fields.get(some_variant)[0].sub(&adt.subs);
+    let TypeKind::FunctionPointer(sig, FunctionAbi::C) = fn_ty.kind() else {
+        panic!("weak symbol should be a C function pointer");
+    };
+
+    // host functions take priority, like for other extern functions
+    let func_ptr = if let Some(native) = vm.host.function(FunctionAbi::C, name) {
+        let func = vm.alloc_native_function(vm.alloc_path(name), native);
+        Some(vm.c_trampoline(func, sig))
+    } else {
+        vm.native_libs.find_symbol(name)
+    };
+
+    // we don't use a niche for the `None` variant
+    let variant = if let Some(func_ptr) = func_ptr {
+        let func_offset = layout.field_offsets.get(some_variant)[0] as usize;
+        bytes[func_offset..func_offset + POINTER_SIZE.bytes() as usize]
+            .copy_from_slice(&(func_ptr as usize).to_ne_bytes());
+
+        some_variant
+    } else {
+        VariantIndex::new(0)
+    };
+
+    let disc_size = enum_info.discriminant_internal.layout().assert_size() as usize;
+    let disc = adt_info
+        .variant_discriminants(vm)
+        .get(variant)
+        .value()
+        .unwrap();
+    bytes[..disc_size].copy_from_slice(&disc.to_le_bytes()[..disc_size]);
+
+    vm.alloc_static(bytes)
+}
+
+unsafe extern "C-unwind" fn builtin_print_int(stack: *mut u8, _thread: &VMThread) {
+    let x: i128 = NativeArgs::new::<()>(stack).next();
     println!("{}", x);
 }
 
-unsafe extern "C" fn builtin_print_uint<'vm>(stack: *mut u8, _thread: &VMThread<'vm>) {
-    let x: u128 = read_stack(stack, Slot::new(0));
+unsafe extern "C-unwind" fn builtin_print_uint(stack: *mut u8, _thread: &VMThread) {
+    let x: u128 = NativeArgs::new::<()>(stack).next();
     println!("{}", x);
 }
 
-unsafe extern "C" fn builtin_print_float<'vm>(stack: *mut u8, _thread: &VMThread) {
-    let x: f64 = read_stack(stack, Slot::new(0));
+unsafe extern "C-unwind" fn builtin_print_float(stack: *mut u8, _thread: &VMThread) {
+    let x: f64 = NativeArgs::new::<()>(stack).next();
     println!("{}", x);
 }
 
-unsafe extern "C" fn builtin_print_bool<'vm>(stack: *mut u8, _thread: &VMThread) {
-    let x: bool = read_stack(stack, Slot::new(0));
+unsafe extern "C-unwind" fn builtin_print_bool(stack: *mut u8, _thread: &VMThread) {
+    let x: bool = NativeArgs::new::<()>(stack).next();
     println!("{}", x);
 }
 
-unsafe extern "C" fn builtin_print_char<'vm>(stack: *mut u8, _thread: &VMThread) {
-    let x: char = read_stack(stack, Slot::new(0));
+unsafe extern "C-unwind" fn builtin_print_char(stack: *mut u8, _thread: &VMThread) {
+    let x: char = NativeArgs::new::<()>(stack).next();
     println!("{}", x);
 }
 
-unsafe extern "C" fn builtin_print_raw<'vm>(stack: *mut u8, _thread: &VMThread) {
-    let x: &str = read_stack(stack, Slot::new(0));
+unsafe extern "C-unwind" fn builtin_print_raw(stack: *mut u8, _thread: &VMThread) {
+    let x: &str = NativeArgs::new::<()>(stack).next();
     print!("{}", x);
 }
 
-static BUILTIN_ALLOC_DUMMY: u8 = 0;
+/// Runs a `Box<dyn FnOnce() + Send>` on a new OS thread, returning a handle for `join`.
+unsafe extern "C-unwind" fn builtin_spawn(stack: *mut u8, thread: &VMThread) {
+    let mut args = NativeArgs::new::<usize>(stack);
+    let data: usize = args.next();
+    let vtable: usize = args.next();
+
+    let vm = thread
+        .vm
+        .static_self
+        .get()
+        .copied()
+        .expect("threads can only be spawned by a VM which is never freed");
+
+    let handle = std::thread::Builder::new()
+        .stack_size(host_stack::MIN_THREAD_STACK_SIZE)
+        .spawn(move || {
+            let vtable = &*(vtable as *const VTable);
+            vm.make_thread().call_boxed_fn_once(data as *mut u8, vtable);
+        })
+        .expect("failed to spawn thread");
 
-unsafe extern "C" fn builtin_alloc<'vm>(stack: *mut u8, thread: &VMThread) {
-    let ptr_size = POINTER_SIZE.bytes();
-    let size: usize = read_stack(stack, Slot::new(ptr_size));
-    let align: usize = read_stack(stack, Slot::new(ptr_size * 2));
+    args.ret(Box::into_raw(Box::new(handle)) as usize);
+}
 
-    let res = thread.vm.alloc_bytes(size, align);
+/// Waits for a thread started by `spawn`. A panic in the thread continues in the caller.
+unsafe extern "C-unwind" fn builtin_join(stack: *mut u8, _thread: &VMThread) {
+    let handle: usize = NativeArgs::new::<()>(stack).next();
+    let handle = Box::from_raw(handle as *mut std::thread::JoinHandle<()>);
 
-    write_stack(stack, Slot::new(0), res);
+    if let Err(payload) = handle.join() {
+        std::panic::resume_unwind(payload);
+    }
 }
 
-unsafe extern "C" fn builtin_alloc_zeroed<'vm>(stack: *mut u8, thread: &VMThread) {
-    let ptr_size = POINTER_SIZE.bytes();
-    let size: usize = read_stack(stack, Slot::new(ptr_size));
-    let align: usize = read_stack(stack, Slot::new(ptr_size * 2));
+static BUILTIN_ALLOC_DUMMY: u8 = 0;
+static BUILTIN_NULL: usize = 0;
 
-    let res = thread.vm.alloc_bytes_zeroed(size, align);
+unsafe extern "C-unwind" fn builtin_alloc_zeroed(stack: *mut u8, thread: &VMThread) {
+    let mut args = NativeArgs::new::<*mut u8>(stack);
+    let size: usize = args.next();
+    let align: usize = args.next();
 
-    write_stack(stack, Slot::new(0), res);
+    args.ret(thread.vm.alloc_bytes_zeroed(size, align));
 }
 
-unsafe extern "C" fn builtin_realloc<'vm>(stack: *mut u8, thread: &VMThread) {
-    let ptr_size = POINTER_SIZE.bytes();
+unsafe extern "C-unwind" fn builtin_realloc(stack: *mut u8, thread: &VMThread) {
+    let mut args = NativeArgs::new::<*mut u8>(stack);
+    let ptr: *mut u8 = args.next();
+    let old_size: usize = args.next();
+    let align: usize = args.next();
+    let new_size: usize = args.next();
 
-    let ptr: *mut u8 = read_stack(stack, Slot::new(ptr_size));
-    let old_size: usize = read_stack(stack, Slot::new(ptr_size * 2));
-    let align: usize = read_stack(stack, Slot::new(ptr_size * 3));
-    let new_size: usize = read_stack(stack, Slot::new(ptr_size * 4));
+    args.ret(thread.vm.realloc_bytes(ptr, old_size, align, new_size));
+}
 
-    let res = thread.vm.realloc_bytes(ptr, old_size, align, new_size);
+unsafe extern "C-unwind" fn builtin_free(stack: *mut u8, thread: &VMThread) {
+    let mut args = NativeArgs::new::<()>(stack);
+    let ptr: *mut u8 = args.next();
+    let size: usize = args.next();
+    let align: usize = args.next();
 
-    write_stack(stack, Slot::new(0), res);
+    thread.vm.free_bytes(ptr, size, align);
 }
 
-unsafe extern "C" fn builtin_free<'vm>(stack: *mut u8, thread: &VMThread) {
-    let ptr_size = POINTER_SIZE.bytes();
+/// Registers a thread-local destructor, which runs when the current thread's last `VMThread` is
+/// dropped.
+unsafe extern "C-unwind" fn host_thread_atexit(stack: *mut u8, thread: &VMThread) {
+    let mut args = NativeArgs::new::<std::ffi::c_int>(stack);
+    let dtor: unsafe extern "C" fn(*mut u8) = args.next();
+    let arg: *mut u8 = args.next();
 
-    let ptr: *mut u8 = read_stack(stack, Slot::new(0));
-    let size: usize = read_stack(stack, Slot::new(ptr_size));
-    let align: usize = read_stack(stack, Slot::new(ptr_size * 2));
+    thread.register_thread_local_dtor(dtor, arg);
+    args.ret(0 as std::ffi::c_int);
+}
 
-    thread.vm.free_bytes(ptr, size, align);
+unsafe extern "C-unwind" fn host_pthread_attr_setstacksize(stack: *mut u8, _thread: &VMThread) {
+    extern "C" {
+        fn pthread_attr_setstacksize(
+            attr: *mut std::ffi::c_void,
+            stack_size: usize,
+        ) -> std::ffi::c_int;
+    }
+
+    let mut args = NativeArgs::new::<std::ffi::c_int>(stack);
+    let attr = args.next();
+    let stack_size: usize = args.next();
+    args.ret(pthread_attr_setstacksize(
+        attr,
+        stack_size.max(host_stack::MIN_THREAD_STACK_SIZE),
+    ));
+}
+
+unsafe extern "C-unwind" fn host_atexit(stack: *mut u8, _thread: &VMThread) {
+    extern "C" {
+        fn __cxa_atexit(
+            func: *const std::ffi::c_void,
+            arg: *mut std::ffi::c_void,
+            dso_handle: *mut std::ffi::c_void,
+        ) -> std::ffi::c_int;
+    }
+
+    // the handler ignores its argument
+    let mut args = NativeArgs::new::<std::ffi::c_int>(stack);
+    let func = args.next();
+    args.ret(__cxa_atexit(
+        func,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+    ));
 }
 
-unsafe extern "C" fn builtin_panic<'vm>(_stack: *mut u8, _thread: &VMThread) {
-    panic!("skitter panic?");
+unsafe extern "C-unwind" fn host_spin_loop(_stack: *mut u8, _thread: &VMThread) {
+    std::hint::spin_loop();
 }
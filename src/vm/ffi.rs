@@ -0,0 +1,792 @@
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+
+use dynasmrt::{dynasm, DynasmApi};
+
+use crate::{
+    abi::{self, POINTER_SIZE},
+    types::{IntSign, Type, TypeKind},
+};
+
+use super::{Function, NativeFunc, VMThread, VM};
+
+extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlerror() -> *mut c_char;
+}
+
+const RTLD_NOW: c_int = 0x2;
+const RTLD_GLOBAL: c_int = 0x100;
+/// Searches the global symbol scope, which includes the libc that skitter itself is linked against.
+const RTLD_DEFAULT: *mut c_void = std::ptr::null_mut();
+
+/// Shared objects which extern symbols are resolved from.
+pub struct NativeLibs {
+    /// Handles are stored as addresses, so the VM can be shared between threads.
+    handles: Vec<usize>,
+}
+
+impl NativeLibs {
+    /// Opens each library, given either as a path / soname or as a bare name like `-l` accepts.
+    pub fn open(libs: &[String]) -> Result<Self, String> {
+        let mut handles = Vec::new();
+
+        for lib in libs {
+            let file_name = if lib.contains('/') || lib.contains(".so") {
+                lib.clone()
+            } else {
+                format!("lib{}.so", lib)
+            };
+            let c_name = CString::new(file_name).map_err(|e| e.to_string())?;
+
+            let handle = unsafe { dlopen(c_name.as_ptr(), RTLD_NOW | RTLD_GLOBAL) };
+            if handle.is_null() {
+                return Err(format!(
+                    "failed to load library {}: {}",
+                    lib,
+                    last_dl_error()
+                ));
+            }
+            handles.push(handle as usize);
+        }
+
+        Ok(Self { handles })
+    }
+
+    /// Libraries given on the command line take priority over the global scope.
+    pub fn find_symbol(&self, name: &str) -> Option<*const c_void> {
+        let c_name = CString::new(name).ok()?;
+
+        self.handles
+            .iter()
+            .map(|handle| *handle as *mut c_void)
+            .chain(std::iter::once(RTLD_DEFAULT))
+            .find_map(|handle| {
+                let sym = unsafe { dlsym(handle, c_name.as_ptr()) };
+                if sym.is_null() {
+                    None
+                } else {
+                    Some(sym as *const c_void)
+                }
+            })
+    }
+}
+
+fn last_dl_error() -> String {
+    unsafe {
+        let err = dlerror();
+        if err.is_null() {
+            "unknown error".to_owned()
+        } else {
+            CStr::from_ptr(err).to_string_lossy().into_owned()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RegClass {
+    Int,
+    Sse,
+}
+
+/// An eightbyte of a value passed in a register.
+#[derive(Debug, Clone, Copy)]
+struct Piece {
+    class: RegClass,
+    /// Bytes of the value contained in this eightbyte.
+    size: u32,
+    /// Whether small integers must be sign-extended.
+    signed: bool,
+}
+
+/// How a value is passed under the System V x86-64 calling convention.
+#[derive(Debug)]
+enum PassMode {
+    Void,
+    Regs(Vec<Piece>),
+    /// Arguments are copied to the stack, return values are written through a hidden pointer.
+    Memory,
+    /// Passed like a pointer, but converted from / to the VM's layout.
+    Nullable(Nullable),
+}
+
+/// An `Option`-like enum around a non-null pointer, which C sees as a nullable pointer.
+/// The VM doesn't use a niche for the `None` variant, so these are converted at the boundary.
+#[derive(Debug, Clone, Copy)]
+struct Nullable {
+    disc_size: u32,
+    none_disc: i64,
+    some_disc: i64,
+    payload_offset: u32,
+}
+
+fn nullable_pointer(ty: Type) -> Option<Nullable> {
+    let TypeKind::Adt(item) = ty.kind() else {
+        return None;
+    };
+    let adt_info = item.item.adt_info();
+    let enum_info = adt_info.enum_info()?;
+
+    let mut none_variant = None;
+    let mut some_field = None;
+    for (variant, fields) in adt_info.variant_fields.iter() {
+        let mut sized_fields = fields
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| field.sub(&item.subs).layout().assert_size() > 0);
+
+        match (sized_fields.next(), sized_fields.next()) {
+            (None, _) if none_variant.is_none() => none_variant = Some(variant),
+            (Some((index, field)), None) if some_field.is_none() => {
+                some_field = Some((variant, index, field.sub(&item.subs)))
+            }
+            _ => return None,
+        }
+    }
+
+    let none_variant = none_variant?;
+    let (some_variant, field_index, field_ty) = some_field?;
+    if !is_non_null(field_ty) {
+        return None;
+    }
+
+    let discs = adt_info.variant_discriminants(ty.vm());
+    Some(Nullable {
+        disc_size: enum_info.discriminant_internal.layout().assert_size(),
+        none_disc: discs.get(none_variant).value()? as i64,
+        some_disc: discs.get(some_variant).value()? as i64,
+        payload_offset: ty.layout().field_offsets.get(some_variant)[field_index],
+    })
+}
+
+/// Thin pointers which are never null, so rustc would use null for the `None` variant around them.
+fn is_non_null(ty: Type) -> bool {
+    match ty.kind() {
+        TypeKind::Ref(..) | TypeKind::FunctionPointer(..) => {
+            ty.layout().assert_size() == POINTER_SIZE.bytes()
+        }
+        TypeKind::Adt(item) if item.item.adt_info().is_struct() => {
+            let fields = item.item.adt_info().variant_fields.assert_single();
+            let mut sized_fields = fields
+                .iter()
+                .map(|field| field.sub(&item.subs))
+                .filter(|field| field.layout().assert_size() > 0);
+
+            match (sized_fields.next(), sized_fields.next()) {
+                (Some(field), None) => {
+                    let is_non_null_ptr = matches!(field.kind(), TypeKind::Ptr(..))
+                        && item.item.path.as_string() == "::ptr::non_null::NonNull";
+                    is_non_null_ptr || is_non_null(field)
+                }
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn pass_mode(ty: Type) -> Result<PassMode, String> {
+    let size = ty.layout().assert_size();
+    if size == 0 {
+        return Ok(PassMode::Void);
+    }
+
+    if let Some(nullable) = nullable_pointer(ty) {
+        return Ok(PassMode::Nullable(nullable));
+    }
+
+    // (offset, size, is_float) for every scalar in the value
+    let mut scalars = Vec::new();
+    collect_scalars(ty, 0, &mut scalars)?;
+
+    if size > 16 {
+        return Ok(PassMode::Memory);
+    }
+
+    let is_single_int = matches!(scalars[..], [(0, _, false)]) && size <= 8;
+    if is_single_int {
+        // single integers are the only values which must be extended
+        let signed = ty.sign() == IntSign::Signed;
+        return Ok(PassMode::Regs(vec![Piece {
+            class: RegClass::Int,
+            size,
+            signed,
+        }]));
+    }
+
+    let pieces = (0..(size + 7) / 8)
+        .map(|i| {
+            let start = i * 8;
+            let end = start + 8;
+
+            let all_float = scalars
+                .iter()
+                .filter(|(offset, size, _)| *offset < end && offset + size > start)
+                .all(|(_, _, is_float)| *is_float);
+
+            Piece {
+                class: if all_float {
+                    RegClass::Sse
+                } else {
+                    RegClass::Int
+                },
+                size: (size - start).min(8),
+                signed: false,
+            }
+        })
+        .collect();
+
+    Ok(PassMode::Regs(pieces))
+}
+
+fn collect_scalars(ty: Type, base: u32, out: &mut Vec<(u32, u32, bool)>) -> Result<(), String> {
+    let layout = ty.layout();
+    match ty.kind() {
+        TypeKind::Bool | TypeKind::Char | TypeKind::Int(..) => {
+            out.push((base, layout.assert_size(), false));
+        }
+        TypeKind::Float(_) => {
+            out.push((base, layout.assert_size(), true));
+        }
+        TypeKind::Ref(..) | TypeKind::Ptr(..) => {
+            if layout.assert_size() != POINTER_SIZE.bytes() {
+                return Err(format!("fat pointer {}", ty));
+            }
+            out.push((base, layout.assert_size(), false));
+        }
+        TypeKind::FunctionPointer(..) => {
+            out.push((base, layout.assert_size(), false));
+        }
+        TypeKind::Tuple(fields) => {
+            let offsets = layout.field_offsets.assert_single();
+            for (field, offset) in fields.iter().zip(offsets) {
+                collect_scalars(*field, base + offset, out)?;
+            }
+        }
+        TypeKind::Array(elem, _) => {
+            let elem_size = elem.layout().assert_size();
+            if elem_size > 0 {
+                for i in 0..layout.assert_size() / elem_size {
+                    collect_scalars(*elem, base + i * elem_size, out)?;
+                }
+            }
+        }
+        TypeKind::Adt(item) => {
+            let adt_info = item.item.adt_info();
+            if let Some(enum_info) = adt_info.enum_info() {
+                let fieldless = adt_info
+                    .variant_fields
+                    .iter()
+                    .all(|(_, fields)| fields.is_empty());
+                if !fieldless {
+                    return Err(format!("enum with fields {}", ty));
+                }
+                let disc_size = enum_info.discriminant_internal.layout().assert_size();
+                out.push((base, disc_size, false));
+            } else {
+                let fields = adt_info.variant_fields.assert_single();
+                let offsets = layout.field_offsets.assert_single();
+                for (field, offset) in fields.iter().zip(offsets) {
+                    collect_scalars(field.sub(&item.subs), base + offset, out)?;
+                }
+            }
+        }
+        _ => return Err(format!("type {}", ty)),
+    }
+    Ok(())
+}
+
+/// System V integer argument registers: rdi, rsi, rdx, rcx, r8, r9
+const ARG_REGS_INT: [u8; 6] = [7, 6, 2, 1, 8, 9];
+const ARG_REGS_SSE: usize = 8;
+
+/// Where an argument is passed. Registers are given as an index into the argument registers of their class.
+enum ArgDest {
+    Regs(Vec<(Piece, usize)>),
+    Stack {
+        size: u32,
+        offset: i32,
+    },
+    /// Converted to a pointer, which is passed in an integer register or on the stack.
+    Nullable(Nullable, Result<usize, i32>),
+}
+
+/// The System V layout of a call, and the VM frame it corresponds to.
+///
+/// VM frames contain the return value first, then each argument aligned to its type.
+struct CallLayout {
+    ret_mode: PassMode,
+    ret_size: u32,
+    /// The offset of each argument in the VM frame, and where it is passed natively.
+    args: Vec<(i32, ArgDest)>,
+    /// Bytes of arguments passed on the native stack.
+    stack_size: u32,
+    /// Vector registers used, which variadic functions rely on.
+    sse_count: usize,
+}
+
+impl CallLayout {
+    fn new(inputs: &[Type], output: Type) -> Result<Self, String> {
+        let ret_mode = pass_mode(output).map_err(|e| format!("unsupported return {}", e))?;
+
+        let mut next_int = 0;
+        let mut next_sse = 0;
+        let mut stack_size = 0;
+
+        if let PassMode::Memory = ret_mode {
+            // hidden pointer to the return value
+            next_int += 1;
+        }
+
+        let mut frame_offset = output.layout().assert_size();
+        let mut args = Vec::new();
+
+        for ty in inputs {
+            let layout = ty.layout();
+            frame_offset = abi::align(frame_offset, layout.align);
+            let src_offset = frame_offset as i32;
+            frame_offset += layout.assert_size();
+
+            let mode = pass_mode(*ty).map_err(|e| format!("unsupported argument {}", e))?;
+
+            let dest = match mode {
+                PassMode::Void => continue,
+                PassMode::Nullable(nullable) => {
+                    if next_int < ARG_REGS_INT.len() {
+                        next_int += 1;
+                        ArgDest::Nullable(nullable, Ok(next_int - 1))
+                    } else {
+                        stack_size += 8;
+                        ArgDest::Nullable(nullable, Err(stack_size as i32 - 8))
+                    }
+                }
+                PassMode::Regs(pieces) => {
+                    let int_count = pieces.iter().filter(|p| p.class == RegClass::Int).count();
+                    let sse_count = pieces.len() - int_count;
+
+                    if next_int + int_count <= ARG_REGS_INT.len()
+                        && next_sse + sse_count <= ARG_REGS_SSE
+                    {
+                        let regs = pieces
+                            .into_iter()
+                            .map(|piece| {
+                                let next = match piece.class {
+                                    RegClass::Int => &mut next_int,
+                                    RegClass::Sse => &mut next_sse,
+                                };
+                                *next += 1;
+                                (piece, *next - 1)
+                            })
+                            .collect();
+                        ArgDest::Regs(regs)
+                    } else {
+                        // the whole value is passed on the stack if it doesn't fit in the remaining registers
+                        let size = abi::align(layout.assert_size(), 8);
+                        stack_size += size;
+                        ArgDest::Stack {
+                            size,
+                            offset: (stack_size - size) as i32,
+                        }
+                    }
+                }
+                PassMode::Memory => {
+                    let size = abi::align(layout.assert_size(), 8);
+                    stack_size += size;
+                    ArgDest::Stack {
+                        size,
+                        offset: (stack_size - size) as i32,
+                    }
+                }
+            };
+
+            args.push((src_offset, dest));
+        }
+
+        Ok(Self {
+            ret_mode,
+            ret_size: output.layout().assert_size(),
+            args,
+            stack_size,
+            sse_count: next_sse,
+        })
+    }
+}
+
+/// The function called by a thunk.
+pub enum ThunkTarget {
+    /// A function at a fixed address.
+    Fixed(*const c_void),
+    /// A function pointer stored in the VM frame, at the given offset.
+    FrameSlot(i32),
+}
+
+/// Builds a native function which forwards an interpreted call to a C function.
+///
+/// The thunk reads each argument from the VM frame, moves it to its System V register
+/// or stack position, and writes the result back to the frame.
+pub fn build_thunk(
+    target: ThunkTarget,
+    inputs: &[Type],
+    output: Type,
+) -> Result<NativeFunc, String> {
+    let call = CallLayout::new(inputs, output)?;
+
+    // after pushing rbp and rbx, rsp is 8 mod 16
+    let frame_size = (abi::align(call.stack_size, 16) + 8) as i32;
+
+    let mut ops = dynasmrt::x64::Assembler::new().map_err(|e| e.to_string())?;
+    let start = ops.offset();
+
+    dynasm!(ops
+        ; push rbp
+        ; mov rbp, rsp
+        ; push rbx
+        ; mov rbx, rdi  // VM frame
+        ; sub rsp, frame_size
+    );
+
+    for (src, dest) in &call.args {
+        match dest {
+            ArgDest::Stack { size, offset } => {
+                for i in (0..*size as i32).step_by(8) {
+                    dynasm!(ops
+                        ; mov rax, QWORD [rbx + src + i]
+                        ; mov QWORD [rsp + offset + i], rax
+                    );
+                }
+            }
+            ArgDest::Regs(regs) => {
+                for (i, (piece, index)) in regs.iter().enumerate() {
+                    let src = src + i as i32 * 8;
+                    let reg = match piece.class {
+                        RegClass::Int => ARG_REGS_INT[*index],
+                        RegClass::Sse => *index as u8,
+                    };
+                    match (piece.class, piece.size, piece.signed) {
+                        (RegClass::Int, 1, true) => dynasm!(ops; movsx Rd(reg), BYTE [rbx + src]),
+                        (RegClass::Int, 1, false) => dynasm!(ops; movzx Rd(reg), BYTE [rbx + src]),
+                        (RegClass::Int, 2, true) => dynasm!(ops; movsx Rd(reg), WORD [rbx + src]),
+                        (RegClass::Int, 2, false) => dynasm!(ops; movzx Rd(reg), WORD [rbx + src]),
+                        (RegClass::Int, 3..=4, _) => dynasm!(ops; mov Rd(reg), DWORD [rbx + src]),
+                        (RegClass::Int, _, _) => dynasm!(ops; mov Rq(reg), QWORD [rbx + src]),
+                        (RegClass::Sse, 4, _) => dynasm!(ops; movss Rx(reg), DWORD [rbx + src]),
+                        (RegClass::Sse, _, _) => dynasm!(ops; movq Rx(reg), QWORD [rbx + src]),
+                    }
+                }
+            }
+            ArgDest::Nullable(nullable, dest) => {
+                load_nullable(&mut ops, *src, nullable);
+                match dest {
+                    Ok(index) => dynasm!(ops; mov Rq(ARG_REGS_INT[*index]), r10),
+                    Err(offset) => dynasm!(ops; mov QWORD [rsp + *offset], r10),
+                }
+            }
+        }
+    }
+
+    if let PassMode::Memory = call.ret_mode {
+        dynasm!(ops
+            ; mov rdi, rbx
+        );
+    }
+
+    match target {
+        ThunkTarget::Fixed(func_ptr) => dynasm!(ops; mov r11, QWORD func_ptr as i64),
+        ThunkTarget::FrameSlot(offset) => dynasm!(ops; mov r11, QWORD [rbx + offset]),
+    }
+
+    // al holds an upper bound on the vector registers used, which variadic functions rely on
+    dynasm!(ops
+        ; mov eax, call.sse_count as i32
+        ; call r11
+    );
+
+    if let PassMode::Nullable(nullable) = &call.ret_mode {
+        dynasm!(ops
+            ; mov QWORD [rbx + nullable.payload_offset as i32], rax
+            ; mov r10, QWORD nullable.some_disc
+            ; mov r11, QWORD nullable.none_disc
+            ; test rax, rax
+            ; cmovz r10, r11
+        );
+        store_int(&mut ops, 10, 0, nullable.disc_size);
+    }
+
+    if let PassMode::Regs(pieces) = &call.ret_mode {
+        let mut next_int = [0u8, 2].iter(); // rax, rdx
+        let mut next_sse = [0u8, 1].iter(); // xmm0, xmm1
+
+        for (i, piece) in pieces.iter().enumerate() {
+            let dst = i as i32 * 8;
+            match piece.class {
+                RegClass::Int => {
+                    let reg = *next_int.next().unwrap();
+                    store_int(&mut ops, reg, dst, piece.size);
+                }
+                RegClass::Sse => {
+                    let reg = *next_sse.next().unwrap();
+                    if piece.size == 4 {
+                        dynasm!(ops; movss DWORD [rbx + dst], Rx(reg));
+                    } else {
+                        dynasm!(ops; movq QWORD [rbx + dst], Rx(reg));
+                    }
+                }
+            }
+        }
+    }
+
+    dynasm!(ops
+        ; lea rsp, [rbp - 8]
+        ; pop rbx
+        ; pop rbp
+        ; ret
+    );
+
+    let buf = ops
+        .finalize()
+        .map_err(|_| "failed to finalize thunk".to_owned())?;
+    let ptr = buf.ptr(start);
+    // thunks live as long as the functions which use them
+    std::mem::forget(buf);
+
+    unsafe { Ok(std::mem::transmute(ptr)) }
+}
+
+/// Passed to `enter_vm` by a trampoline.
+struct TrampolineInfo<'vm> {
+    vm: &'vm VM<'vm>,
+    func: &'vm Function<'vm>,
+    call: CallLayout,
+}
+
+/// Register arguments saved by a trampoline, and the return registers it loads.
+#[repr(C)]
+struct TrampolineRegs {
+    int: [u64; 6],
+    sse: [u64; ARG_REGS_SSE],
+    ret_int: [u64; 2],
+    ret_sse: [u64; 2],
+}
+
+/// Builds a native function with the C calling convention, which calls an interpreted function.
+///
+/// The trampoline saves the argument registers and passes them to `enter_vm`, which
+/// copies the arguments into a frame on a new VM thread.
+pub fn build_trampoline<'vm>(
+    vm: &'vm VM<'vm>,
+    func: &'vm Function<'vm>,
+    inputs: &[Type<'vm>],
+    output: Type<'vm>,
+) -> Result<*const c_void, String> {
+    let call = CallLayout::new(inputs, output)?;
+    let info: &'vm TrampolineInfo<'vm> = Box::leak(Box::new(TrampolineInfo { vm, func, call }));
+
+    const REGS_SIZE: i32 = std::mem::size_of::<TrampolineRegs>() as i32;
+    const RET_INT: i32 = 8 * (6 + ARG_REGS_SSE as i32);
+    const RET_SSE: i32 = RET_INT + 16;
+
+    let mut ops = dynasmrt::x64::Assembler::new().map_err(|e| e.to_string())?;
+    let start = ops.offset();
+
+    // after pushing rbp, rsp is 0 mod 16, and the regs are a multiple of 16 bytes
+    dynasm!(ops
+        ; push rbp
+        ; mov rbp, rsp
+        ; sub rsp, REGS_SIZE
+    );
+
+    for (i, reg) in ARG_REGS_INT.iter().enumerate() {
+        dynasm!(ops; mov QWORD [rsp + i as i32 * 8], Rq(*reg));
+    }
+    for i in 0..ARG_REGS_SSE {
+        dynasm!(ops; movq QWORD [rsp + 48 + i as i32 * 8], Rx(i as u8));
+    }
+
+    dynasm!(ops
+        ; mov rdi, QWORD info as *const TrampolineInfo as i64
+        ; mov rsi, rsp
+        ; lea rdx, [rbp + 16] // stack arguments
+        ; mov rax, QWORD enter_vm as *const () as i64
+        ; call rax
+        ; mov rax, QWORD [rsp + RET_INT]
+        ; mov rdx, QWORD [rsp + RET_INT + 8]
+        ; movq xmm0, QWORD [rsp + RET_SSE]
+        ; movq xmm1, QWORD [rsp + RET_SSE + 8]
+        ; mov rsp, rbp
+        ; pop rbp
+        ; ret
+    );
+
+    let buf = ops
+        .finalize()
+        .map_err(|_| "failed to finalize trampoline".to_owned())?;
+    let ptr = buf.ptr(start);
+    std::mem::forget(buf);
+
+    Ok(ptr as *const c_void)
+}
+
+/// Runs an interpreted function for a trampoline. Panics can't unwind into native code, so they abort.
+unsafe extern "C" fn enter_vm(
+    info: &TrampolineInfo,
+    regs: &mut TrampolineRegs,
+    stack_args: *const u8,
+) {
+    let mut thread = info.vm.make_thread();
+    let frame = thread.root_frame();
+
+    for (dst, src) in &info.call.args {
+        let dst = frame.offset(*dst as isize);
+        match src {
+            ArgDest::Stack { size, offset } => {
+                std::ptr::copy_nonoverlapping(
+                    stack_args.offset(*offset as isize),
+                    dst,
+                    *size as usize,
+                );
+            }
+            ArgDest::Regs(pieces) => {
+                for (i, (piece, index)) in pieces.iter().enumerate() {
+                    let reg = match piece.class {
+                        RegClass::Int => &regs.int[*index],
+                        RegClass::Sse => &regs.sse[*index],
+                    };
+                    let src = reg as *const u64 as *const u8;
+                    std::ptr::copy_nonoverlapping(src, dst.add(i * 8), piece.size as usize);
+                }
+            }
+            ArgDest::Nullable(nullable, src) => {
+                let ptr = match src {
+                    Ok(index) => regs.int[*index],
+                    Err(offset) => *(stack_args.offset(*offset as isize) as *const u64),
+                };
+                let disc = if ptr == 0 {
+                    nullable.none_disc
+                } else {
+                    *(dst.add(nullable.payload_offset as usize) as *mut u64) = ptr;
+                    nullable.some_disc
+                };
+                let disc_bytes = disc.to_le_bytes();
+                std::ptr::copy_nonoverlapping(
+                    disc_bytes.as_ptr(),
+                    dst,
+                    nullable.disc_size as usize,
+                );
+            }
+        }
+    }
+
+    thread.call(info.func, frame);
+
+    match &info.call.ret_mode {
+        PassMode::Void => (),
+        PassMode::Nullable(nullable) => {
+            let mut disc_bytes = [0; 8];
+            std::ptr::copy_nonoverlapping(
+                frame,
+                disc_bytes.as_mut_ptr(),
+                nullable.disc_size as usize,
+            );
+
+            regs.ret_int[0] = if i64::from_le_bytes(disc_bytes) == nullable.none_disc {
+                0
+            } else {
+                *(frame.add(nullable.payload_offset as usize) as *const u64)
+            };
+        }
+        PassMode::Regs(pieces) => {
+            let mut next_int = regs.ret_int.iter_mut();
+            let mut next_sse = regs.ret_sse.iter_mut();
+
+            for (i, piece) in pieces.iter().enumerate() {
+                let reg = match piece.class {
+                    RegClass::Int => next_int.next().unwrap(),
+                    RegClass::Sse => next_sse.next().unwrap(),
+                };
+                let dst = reg as *mut u64 as *mut u8;
+                std::ptr::copy_nonoverlapping(frame.add(i * 8), dst, piece.size as usize);
+            }
+        }
+        PassMode::Memory => {
+            // the caller passes a pointer to the result, which is also returned
+            let out = regs.int[0];
+            std::ptr::copy_nonoverlapping(frame, out as *mut u8, info.call.ret_size as usize);
+            regs.ret_int[0] = out;
+        }
+    }
+}
+
+/// Loads a nullable pointer from the VM frame into r10. Clobbers rax and r11.
+fn load_nullable(ops: &mut dynasmrt::x64::Assembler, src: i32, nullable: &Nullable) {
+    dynasm!(ops
+        ; mov r10, QWORD [rbx + src + nullable.payload_offset as i32]
+        ; xor eax, eax
+    );
+    // the discriminant is always at the start of the value
+    match nullable.disc_size {
+        1 => dynasm!(ops; movzx r11d, BYTE [rbx + src]),
+        2 => dynasm!(ops; movzx r11d, WORD [rbx + src]),
+        4 => dynasm!(ops; mov r11d, DWORD [rbx + src]),
+        _ => dynasm!(ops; mov r11, QWORD [rbx + src]),
+    }
+    dynasm!(ops
+        ; cmp r11, nullable.none_disc as i32
+        ; cmove r10, rax
+    );
+}
+
+/// Stores the low `size` bytes of a register, without writing past the end of the value.
+fn store_int(ops: &mut dynasmrt::x64::Assembler, reg: u8, dst: i32, size: u32) {
+    let mut written = 0;
+    while written < size {
+        let dst = dst + written as i32;
+        let chunk = match size - written {
+            8.. => {
+                dynasm!(ops; mov QWORD [rbx + dst], Rq(reg));
+                8
+            }
+            4..=7 => {
+                dynasm!(ops; mov DWORD [rbx + dst], Rd(reg));
+                4
+            }
+            2..=3 => {
+                dynasm!(ops; mov WORD [rbx + dst], Rw(reg));
+                2
+            }
+            _ => {
+                dynasm!(ops; mov BYTE [rbx + dst], Rb(reg));
+                1
+            }
+        };
+        written += chunk;
+        if written < size {
+            dynasm!(ops; shr Rq(reg), (chunk * 8) as i8);
+        }
+    }
+}
+
+/// Builds a native function which panics with the given message when called. Used for externs which
+/// can't be called, so the error is only raised if a program actually reaches them.
+pub fn build_error_stub(message: String) -> NativeFunc {
+    unsafe extern "C-unwind" fn raise(_stack: *mut u8, _thread: &VMThread, message: &String) {
+        panic!("{}", message);
+    }
+
+    let message: &'static String = Box::leak(Box::new(message));
+
+    let mut ops = dynasmrt::x64::Assembler::new().unwrap();
+    let start = ops.offset();
+
+    // the stack and thread are passed through in rdi and rsi
+    dynasm!(ops
+        ; mov rdx, QWORD message as *const String as i64
+        ; mov rax, QWORD raise as *const () as i64
+        ; jmp rax
+    );
+
+    let buf = ops.finalize().unwrap();
+    let ptr = buf.ptr(start);
+    std::mem::forget(buf);
+
+    unsafe { std::mem::transmute(ptr) }
+}
@@ -0,0 +1,74 @@
+use std::{alloc::Layout, cell::Cell, panic::AssertUnwindSafe, thread::Result};
+
+/// Host stack kept free for a call's own frames, and for compiling its bytecode.
+const RED_ZONE: usize = 256 << 10;
+/// Size of the host stack segments used by growable stacks.
+const SEGMENT_SIZE: usize = 8 << 20;
+/// Smallest host stack given to threads started by interpreted code. Each interpreted call nests
+/// several host frames, so the sizes programs ask for, which suit compiled code, run out quickly.
+pub const MIN_THREAD_STACK_SIZE: usize = 8 << 20;
+
+thread_local! {
+    /// Lowest usable address of the host stack this thread is running on, or 0 if not known yet.
+    static LIMIT: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Lowest host stack address an interpreted call may start at, or 0 if it isn't known. Every
+/// interpreted call also nests host frames, so the host stack usually runs out before the
+/// interpreter's. Looking this up is too slow to do per call, so threads keep it in `VMThread`.
+pub fn call_limit() -> usize {
+    let mut limit = LIMIT.with(|limit| limit.get());
+    if limit == 0 {
+        // unknown for threads we didn't create, like the main thread
+        let sp = psm::stack_pointer() as usize;
+        limit = match stacker::remaining_stack() {
+            Some(remaining) => sp - remaining,
+            None => return 0,
+        };
+        LIMIT.with(|l| l.set(limit));
+    }
+    limit + RED_ZONE
+}
+
+/// Address of the caller's frame on the host stack, close enough to compare with `call_limit`.
+#[inline(always)]
+pub fn stack_pointer() -> usize {
+    let marker = 0u8;
+    std::ptr::addr_of!(marker) as usize
+}
+
+/// An extra host stack, kept around for reuse since deep recursion tends to cross the same
+/// segment boundary many times.
+pub struct HostSegment {
+    base: *mut u8,
+}
+
+impl HostSegment {
+    pub fn new() -> Self {
+        let base = unsafe { std::alloc::alloc(Self::layout()) };
+        assert!(!base.is_null(), "failed to allocate host stack");
+        Self { base }
+    }
+
+    fn layout() -> Layout {
+        Layout::from_size_align(SEGMENT_SIZE, 4096).unwrap()
+    }
+
+    /// Runs a function on this stack, catching any panic so it can unwind on the original stack.
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> Result<R> {
+        let old_limit = LIMIT.with(|limit| limit.replace(self.base as usize));
+        let res = unsafe {
+            psm::on_stack(self.base, SEGMENT_SIZE, || {
+                std::panic::catch_unwind(AssertUnwindSafe(f))
+            })
+        };
+        LIMIT.with(|limit| limit.set(old_limit));
+        res
+    }
+}
+
+impl Drop for HostSegment {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.base, Self::layout()) };
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{abi::CALL_ALIGN, types::DropBit};
+use std::sync::atomic::Ordering;
+
+use paste::paste;
+use skitter_macro::Compact;
+
+use crate::{abi::CALL_ALIGN, items::Item, types::DropBit};
 
 use super::vm::Function;
 
@@ -39,7 +44,7 @@ impl Slot {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Debug)]
+#[derive(Debug, Compact)]
 #[repr(u16)]
 pub enum Instr<'vm> {
     I8_Const(Slot, i8),
@@ -134,7 +139,7 @@ pub enum Instr<'vm> {
     I64_U_Rem(Slot, Slot, Slot),
     I64_U_ShiftR(Slot, Slot, Slot),
 
-    I128_Const(Slot, Box<i128>),
+    I128_Const(Slot, i128),
     I128_Neg(Slot, Slot),
     I128_Not(Slot, Slot),
     I128_Eq(Slot, Slot, Slot),
@@ -347,6 +352,27 @@ pub enum Instr<'vm> {
     },
 
     VTableFunc(Slot, Slot, u32),
+    VTableSize(Slot, Slot),
+    VTableAlign(Slot, Slot),
+    /// Drops the value behind a `dyn` pointer, by calling the glue in its vtable with the given frame.
+    VTableDrop {
+        frame: Slot,
+        arg: Slot,
+    },
+
+    /// Writes the address of the current thread's copy of a `#[thread_local]` static.
+    ThreadLocalAddr(Slot, &'vm Item<'vm>),
+
+    /// Implements `caller_location` in `#[track_caller]` functions, which depends on the call stack.
+    CallerLocation(Slot),
+
+    /// Implements the `try` intrinsic. The frame holds the try function, data pointer and catch function.
+    /// They are called in `call_frame`, which has room for the data pointer and panic payload.
+    CatchUnwind {
+        out: Slot,
+        frame: Slot,
+        call_frame: Slot,
+    },
 
     LocalInit((DropBit, DropBit)),
     LocalMove((DropBit, DropBit)),
@@ -355,6 +381,7 @@ pub enum Instr<'vm> {
     LocalDropInit((DropBit, DropBit)),
 
     Return,
+    Abort,
     Error(Box<String>),
     Skipped,
     Debug(Box<String>),
@@ -421,12 +448,78 @@ pub enum Instr<'vm> {
     I64_U_OverflowingMul(Slot, Slot, Slot),
     I128_U_OverflowingMul(Slot, Slot, Slot),
 
+    I8_S_CheckedAdd(Slot, Slot, Slot),
+    I16_S_CheckedAdd(Slot, Slot, Slot),
+    I32_S_CheckedAdd(Slot, Slot, Slot),
+    I64_S_CheckedAdd(Slot, Slot, Slot),
+    I128_S_CheckedAdd(Slot, Slot, Slot),
+
+    I8_U_CheckedAdd(Slot, Slot, Slot),
+    I16_U_CheckedAdd(Slot, Slot, Slot),
+    I32_U_CheckedAdd(Slot, Slot, Slot),
+    I64_U_CheckedAdd(Slot, Slot, Slot),
+    I128_U_CheckedAdd(Slot, Slot, Slot),
+
+    I8_S_CheckedSub(Slot, Slot, Slot),
+    I16_S_CheckedSub(Slot, Slot, Slot),
+    I32_S_CheckedSub(Slot, Slot, Slot),
+    I64_S_CheckedSub(Slot, Slot, Slot),
+    I128_S_CheckedSub(Slot, Slot, Slot),
+
+    I8_U_CheckedSub(Slot, Slot, Slot),
+    I16_U_CheckedSub(Slot, Slot, Slot),
+    I32_U_CheckedSub(Slot, Slot, Slot),
+    I64_U_CheckedSub(Slot, Slot, Slot),
+    I128_U_CheckedSub(Slot, Slot, Slot),
+
+    I8_S_CheckedMul(Slot, Slot, Slot),
+    I16_S_CheckedMul(Slot, Slot, Slot),
+    I32_S_CheckedMul(Slot, Slot, Slot),
+    I64_S_CheckedMul(Slot, Slot, Slot),
+    I128_S_CheckedMul(Slot, Slot, Slot),
+
+    I8_U_CheckedMul(Slot, Slot, Slot),
+    I16_U_CheckedMul(Slot, Slot, Slot),
+    I32_U_CheckedMul(Slot, Slot, Slot),
+    I64_U_CheckedMul(Slot, Slot, Slot),
+    I128_U_CheckedMul(Slot, Slot, Slot),
+
+    I8_CheckedNeg(Slot, Slot),
+    I16_CheckedNeg(Slot, Slot),
+    I32_CheckedNeg(Slot, Slot),
+    I64_CheckedNeg(Slot, Slot),
+    I128_CheckedNeg(Slot, Slot),
+
+    I8_CheckedShiftL(Slot, Slot, Slot),
+    I16_CheckedShiftL(Slot, Slot, Slot),
+    I32_CheckedShiftL(Slot, Slot, Slot),
+    I64_CheckedShiftL(Slot, Slot, Slot),
+    I128_CheckedShiftL(Slot, Slot, Slot),
+
+    I8_S_CheckedShiftR(Slot, Slot, Slot),
+    I16_S_CheckedShiftR(Slot, Slot, Slot),
+    I32_S_CheckedShiftR(Slot, Slot, Slot),
+    I64_S_CheckedShiftR(Slot, Slot, Slot),
+    I128_S_CheckedShiftR(Slot, Slot, Slot),
+
+    I8_U_CheckedShiftR(Slot, Slot, Slot),
+    I16_U_CheckedShiftR(Slot, Slot, Slot),
+    I32_U_CheckedShiftR(Slot, Slot, Slot),
+    I64_U_CheckedShiftR(Slot, Slot, Slot),
+    I128_U_CheckedShiftR(Slot, Slot, Slot),
+
     I8_PopCount(Slot, Slot),
     I16_PopCount(Slot, Slot),
     I32_PopCount(Slot, Slot),
     I64_PopCount(Slot, Slot),
     I128_PopCount(Slot, Slot),
 
+    I8_LeadingZeros(Slot, Slot),
+    I16_LeadingZeros(Slot, Slot),
+    I32_LeadingZeros(Slot, Slot),
+    I64_LeadingZeros(Slot, Slot),
+    I128_LeadingZeros(Slot, Slot),
+
     I8_TrailingZeros(Slot, Slot),
     I16_TrailingZeros(Slot, Slot),
     I32_TrailingZeros(Slot, Slot),
@@ -456,6 +549,277 @@ pub enum Instr<'vm> {
 
     F32_Max(Slot, Slot, Slot),
     F64_Max(Slot, Slot, Slot),
+
+    // Atomics on a pointer, lowered to host atomics. There are no 128-bit atomics, the target's
+    // max atomic width is 64. Orderings come first so they pack next to the tag.
+    I8_AtomicLoad(Ordering, Slot, Slot),
+    I16_AtomicLoad(Ordering, Slot, Slot),
+    I32_AtomicLoad(Ordering, Slot, Slot),
+    I64_AtomicLoad(Ordering, Slot, Slot),
+
+    I8_AtomicStore(Ordering, Slot, Slot),
+    I16_AtomicStore(Ordering, Slot, Slot),
+    I32_AtomicStore(Ordering, Slot, Slot),
+    I64_AtomicStore(Ordering, Slot, Slot),
+
+    I8_AtomicXchg(Ordering, Slot, Slot, Slot),
+    I16_AtomicXchg(Ordering, Slot, Slot, Slot),
+    I32_AtomicXchg(Ordering, Slot, Slot, Slot),
+    I64_AtomicXchg(Ordering, Slot, Slot, Slot),
+
+    I8_AtomicAdd(Ordering, Slot, Slot, Slot),
+    I16_AtomicAdd(Ordering, Slot, Slot, Slot),
+    I32_AtomicAdd(Ordering, Slot, Slot, Slot),
+    I64_AtomicAdd(Ordering, Slot, Slot, Slot),
+
+    I8_AtomicSub(Ordering, Slot, Slot, Slot),
+    I16_AtomicSub(Ordering, Slot, Slot, Slot),
+    I32_AtomicSub(Ordering, Slot, Slot, Slot),
+    I64_AtomicSub(Ordering, Slot, Slot, Slot),
+
+    I8_AtomicAnd(Ordering, Slot, Slot, Slot),
+    I16_AtomicAnd(Ordering, Slot, Slot, Slot),
+    I32_AtomicAnd(Ordering, Slot, Slot, Slot),
+    I64_AtomicAnd(Ordering, Slot, Slot, Slot),
+
+    I8_AtomicOr(Ordering, Slot, Slot, Slot),
+    I16_AtomicOr(Ordering, Slot, Slot, Slot),
+    I32_AtomicOr(Ordering, Slot, Slot, Slot),
+    I64_AtomicOr(Ordering, Slot, Slot, Slot),
+
+    I8_AtomicXor(Ordering, Slot, Slot, Slot),
+    I16_AtomicXor(Ordering, Slot, Slot, Slot),
+    I32_AtomicXor(Ordering, Slot, Slot, Slot),
+    I64_AtomicXor(Ordering, Slot, Slot, Slot),
+
+    I8_AtomicNand(Ordering, Slot, Slot, Slot),
+    I16_AtomicNand(Ordering, Slot, Slot, Slot),
+    I32_AtomicNand(Ordering, Slot, Slot, Slot),
+    I64_AtomicNand(Ordering, Slot, Slot, Slot),
+
+    I8_S_AtomicMax(Ordering, Slot, Slot, Slot),
+    I16_S_AtomicMax(Ordering, Slot, Slot, Slot),
+    I32_S_AtomicMax(Ordering, Slot, Slot, Slot),
+    I64_S_AtomicMax(Ordering, Slot, Slot, Slot),
+
+    I8_S_AtomicMin(Ordering, Slot, Slot, Slot),
+    I16_S_AtomicMin(Ordering, Slot, Slot, Slot),
+    I32_S_AtomicMin(Ordering, Slot, Slot, Slot),
+    I64_S_AtomicMin(Ordering, Slot, Slot, Slot),
+
+    I8_U_AtomicMax(Ordering, Slot, Slot, Slot),
+    I16_U_AtomicMax(Ordering, Slot, Slot, Slot),
+    I32_U_AtomicMax(Ordering, Slot, Slot, Slot),
+    I64_U_AtomicMax(Ordering, Slot, Slot, Slot),
+
+    I8_U_AtomicMin(Ordering, Slot, Slot, Slot),
+    I16_U_AtomicMin(Ordering, Slot, Slot, Slot),
+    I32_U_AtomicMin(Ordering, Slot, Slot, Slot),
+    I64_U_AtomicMin(Ordering, Slot, Slot, Slot),
+
+    /// The output is an (old value, success) pair, which must hold the expected value beforehand.
+    I8_AtomicCxchg(Ordering, Ordering, Slot, Slot, Slot),
+    I16_AtomicCxchg(Ordering, Ordering, Slot, Slot, Slot),
+    I32_AtomicCxchg(Ordering, Ordering, Slot, Slot, Slot),
+    I64_AtomicCxchg(Ordering, Ordering, Slot, Slot, Slot),
+
+    I8_AtomicCxchgWeak(Ordering, Ordering, Slot, Slot, Slot),
+    I16_AtomicCxchgWeak(Ordering, Ordering, Slot, Slot, Slot),
+    I32_AtomicCxchgWeak(Ordering, Ordering, Slot, Slot, Slot),
+    I64_AtomicCxchgWeak(Ordering, Ordering, Slot, Slot, Slot),
+
+    AtomicFence(Ordering),
+    CompilerFence(Ordering),
+
+    // Superinstructions, fused by the bytecode optimizer from common sequences. Comparisons with a
+    // branch jump by the offset when the comparison is false, like `JumpF`.
+
+    // lhs, rhs, offset
+    I8_EqJumpF(Slot, Slot, i32),
+    I8_NotEqJumpF(Slot, Slot, i32),
+    I8_S_LtJumpF(Slot, Slot, i32),
+    I8_S_LtEqJumpF(Slot, Slot, i32),
+    I8_U_LtJumpF(Slot, Slot, i32),
+    I8_U_LtEqJumpF(Slot, Slot, i32),
+
+    I16_EqJumpF(Slot, Slot, i32),
+    I16_NotEqJumpF(Slot, Slot, i32),
+    I16_S_LtJumpF(Slot, Slot, i32),
+    I16_S_LtEqJumpF(Slot, Slot, i32),
+    I16_U_LtJumpF(Slot, Slot, i32),
+    I16_U_LtEqJumpF(Slot, Slot, i32),
+
+    I32_EqJumpF(Slot, Slot, i32),
+    I32_NotEqJumpF(Slot, Slot, i32),
+    I32_S_LtJumpF(Slot, Slot, i32),
+    I32_S_LtEqJumpF(Slot, Slot, i32),
+    I32_U_LtJumpF(Slot, Slot, i32),
+    I32_U_LtEqJumpF(Slot, Slot, i32),
+
+    I64_EqJumpF(Slot, Slot, i32),
+    I64_NotEqJumpF(Slot, Slot, i32),
+    I64_S_LtJumpF(Slot, Slot, i32),
+    I64_S_LtEqJumpF(Slot, Slot, i32),
+    I64_U_LtJumpF(Slot, Slot, i32),
+    I64_U_LtEqJumpF(Slot, Slot, i32),
+
+    // lhs, immediate rhs, offset
+    I8_EqImmJumpF(Slot, i32, i32),
+    I8_NotEqImmJumpF(Slot, i32, i32),
+    I8_S_LtImmJumpF(Slot, i32, i32),
+    I8_S_LtEqImmJumpF(Slot, i32, i32),
+    I8_S_GtImmJumpF(Slot, i32, i32),
+    I8_S_GtEqImmJumpF(Slot, i32, i32),
+    I8_U_LtImmJumpF(Slot, i32, i32),
+    I8_U_LtEqImmJumpF(Slot, i32, i32),
+    I8_U_GtImmJumpF(Slot, i32, i32),
+    I8_U_GtEqImmJumpF(Slot, i32, i32),
+
+    I16_EqImmJumpF(Slot, i32, i32),
+    I16_NotEqImmJumpF(Slot, i32, i32),
+    I16_S_LtImmJumpF(Slot, i32, i32),
+    I16_S_LtEqImmJumpF(Slot, i32, i32),
+    I16_S_GtImmJumpF(Slot, i32, i32),
+    I16_S_GtEqImmJumpF(Slot, i32, i32),
+    I16_U_LtImmJumpF(Slot, i32, i32),
+    I16_U_LtEqImmJumpF(Slot, i32, i32),
+    I16_U_GtImmJumpF(Slot, i32, i32),
+    I16_U_GtEqImmJumpF(Slot, i32, i32),
+
+    I32_EqImmJumpF(Slot, i32, i32),
+    I32_NotEqImmJumpF(Slot, i32, i32),
+    I32_S_LtImmJumpF(Slot, i32, i32),
+    I32_S_LtEqImmJumpF(Slot, i32, i32),
+    I32_S_GtImmJumpF(Slot, i32, i32),
+    I32_S_GtEqImmJumpF(Slot, i32, i32),
+    I32_U_LtImmJumpF(Slot, i32, i32),
+    I32_U_LtEqImmJumpF(Slot, i32, i32),
+    I32_U_GtImmJumpF(Slot, i32, i32),
+    I32_U_GtEqImmJumpF(Slot, i32, i32),
+
+    I64_EqImmJumpF(Slot, i32, i32),
+    I64_NotEqImmJumpF(Slot, i32, i32),
+    I64_S_LtImmJumpF(Slot, i32, i32),
+    I64_S_LtEqImmJumpF(Slot, i32, i32),
+    I64_S_GtImmJumpF(Slot, i32, i32),
+    I64_S_GtEqImmJumpF(Slot, i32, i32),
+    I64_U_LtImmJumpF(Slot, i32, i32),
+    I64_U_LtEqImmJumpF(Slot, i32, i32),
+    I64_U_GtImmJumpF(Slot, i32, i32),
+    I64_U_GtEqImmJumpF(Slot, i32, i32),
+
+    // the lhs is read at an offset from a pointer, like `MovSP`: field offset, pointer, rhs, offset
+    I8_EqFieldJumpF(i16, Slot, Slot, i32),
+    I8_NotEqFieldJumpF(i16, Slot, Slot, i32),
+    I8_S_LtFieldJumpF(i16, Slot, Slot, i32),
+    I8_S_LtEqFieldJumpF(i16, Slot, Slot, i32),
+    I8_S_GtFieldJumpF(i16, Slot, Slot, i32),
+    I8_S_GtEqFieldJumpF(i16, Slot, Slot, i32),
+    I8_U_LtFieldJumpF(i16, Slot, Slot, i32),
+    I8_U_LtEqFieldJumpF(i16, Slot, Slot, i32),
+    I8_U_GtFieldJumpF(i16, Slot, Slot, i32),
+    I8_U_GtEqFieldJumpF(i16, Slot, Slot, i32),
+
+    I16_EqFieldJumpF(i16, Slot, Slot, i32),
+    I16_NotEqFieldJumpF(i16, Slot, Slot, i32),
+    I16_S_LtFieldJumpF(i16, Slot, Slot, i32),
+    I16_S_LtEqFieldJumpF(i16, Slot, Slot, i32),
+    I16_S_GtFieldJumpF(i16, Slot, Slot, i32),
+    I16_S_GtEqFieldJumpF(i16, Slot, Slot, i32),
+    I16_U_LtFieldJumpF(i16, Slot, Slot, i32),
+    I16_U_LtEqFieldJumpF(i16, Slot, Slot, i32),
+    I16_U_GtFieldJumpF(i16, Slot, Slot, i32),
+    I16_U_GtEqFieldJumpF(i16, Slot, Slot, i32),
+
+    I32_EqFieldJumpF(i16, Slot, Slot, i32),
+    I32_NotEqFieldJumpF(i16, Slot, Slot, i32),
+    I32_S_LtFieldJumpF(i16, Slot, Slot, i32),
+    I32_S_LtEqFieldJumpF(i16, Slot, Slot, i32),
+    I32_S_GtFieldJumpF(i16, Slot, Slot, i32),
+    I32_S_GtEqFieldJumpF(i16, Slot, Slot, i32),
+    I32_U_LtFieldJumpF(i16, Slot, Slot, i32),
+    I32_U_LtEqFieldJumpF(i16, Slot, Slot, i32),
+    I32_U_GtFieldJumpF(i16, Slot, Slot, i32),
+    I32_U_GtEqFieldJumpF(i16, Slot, Slot, i32),
+
+    I64_EqFieldJumpF(i16, Slot, Slot, i32),
+    I64_NotEqFieldJumpF(i16, Slot, Slot, i32),
+    I64_S_LtFieldJumpF(i16, Slot, Slot, i32),
+    I64_S_LtEqFieldJumpF(i16, Slot, Slot, i32),
+    I64_S_GtFieldJumpF(i16, Slot, Slot, i32),
+    I64_S_GtEqFieldJumpF(i16, Slot, Slot, i32),
+    I64_U_LtFieldJumpF(i16, Slot, Slot, i32),
+    I64_U_LtEqFieldJumpF(i16, Slot, Slot, i32),
+    I64_U_GtFieldJumpF(i16, Slot, Slot, i32),
+    I64_U_GtEqFieldJumpF(i16, Slot, Slot, i32),
+
+    // out, src, immediate
+    I8_AddImm(Slot, Slot, i32),
+    I16_AddImm(Slot, Slot, i32),
+    I32_AddImm(Slot, Slot, i32),
+    I64_AddImm(Slot, Slot, i32),
+}
+
+/// Matches any jump, binding its offset. Expands to the same match for shared and mutable access.
+macro_rules! match_jump_offset {
+    ($instr:expr, $($ty:ident),*) => {
+        paste! {
+            match $instr {
+                Instr::Jump(offset) | Instr::JumpF(offset, _) | Instr::JumpT(offset, _) => Some(offset),
+                $(
+                    Instr::[<$ty _EqJumpF>](_, _, offset)
+                    | Instr::[<$ty _NotEqJumpF>](_, _, offset)
+                    | Instr::[<$ty _S_LtJumpF>](_, _, offset)
+                    | Instr::[<$ty _S_LtEqJumpF>](_, _, offset)
+                    | Instr::[<$ty _U_LtJumpF>](_, _, offset)
+                    | Instr::[<$ty _U_LtEqJumpF>](_, _, offset)
+                    | Instr::[<$ty _EqImmJumpF>](_, _, offset)
+                    | Instr::[<$ty _NotEqImmJumpF>](_, _, offset)
+                    | Instr::[<$ty _S_LtImmJumpF>](_, _, offset)
+                    | Instr::[<$ty _S_LtEqImmJumpF>](_, _, offset)
+                    | Instr::[<$ty _S_GtImmJumpF>](_, _, offset)
+                    | Instr::[<$ty _S_GtEqImmJumpF>](_, _, offset)
+                    | Instr::[<$ty _U_LtImmJumpF>](_, _, offset)
+                    | Instr::[<$ty _U_LtEqImmJumpF>](_, _, offset)
+                    | Instr::[<$ty _U_GtImmJumpF>](_, _, offset)
+                    | Instr::[<$ty _U_GtEqImmJumpF>](_, _, offset)
+                    | Instr::[<$ty _EqFieldJumpF>](_, _, _, offset)
+                    | Instr::[<$ty _NotEqFieldJumpF>](_, _, _, offset)
+                    | Instr::[<$ty _S_LtFieldJumpF>](_, _, _, offset)
+                    | Instr::[<$ty _S_LtEqFieldJumpF>](_, _, _, offset)
+                    | Instr::[<$ty _S_GtFieldJumpF>](_, _, _, offset)
+                    | Instr::[<$ty _S_GtEqFieldJumpF>](_, _, _, offset)
+                    | Instr::[<$ty _U_LtFieldJumpF>](_, _, _, offset)
+                    | Instr::[<$ty _U_LtEqFieldJumpF>](_, _, _, offset)
+                    | Instr::[<$ty _U_GtFieldJumpF>](_, _, _, offset)
+                    | Instr::[<$ty _U_GtEqFieldJumpF>](_, _, _, offset) => Some(offset),
+                )*
+                _ => None,
+            }
+        }
+    };
+}
+
+impl<'vm> Instr<'vm> {
+    /// The relative offset of a jump, including superinstructions which end in one.
+    pub fn jump_offset(&self) -> Option<i32> {
+        match_jump_offset!(self, I8, I16, I32, I64).copied()
+    }
+
+    pub fn jump_offset_mut(&mut self) -> Option<&mut i32> {
+        match_jump_offset!(self, I8, I16, I32, I64)
+    }
+
+    /// Whether this is a jump which may also fall through to the next instruction.
+    pub fn is_conditional_jump(&self) -> bool {
+        !matches!(self, Instr::Jump(_)) && self.jump_offset().is_some()
+    }
+
+    /// The instruction's opcode in the compact form. `Instr` is `repr(u16)`, so it starts with it.
+    #[cfg(feature = "profile-opcodes")]
+    pub fn opcode(&self) -> u16 {
+        unsafe { *(self as *const Self as *const u16) }
+    }
 }
 
 /* This used to be used to setup call frames, but it was buggy. Probably best to stop using it.
@@ -733,7 +1097,12 @@ impl<'vm> Instr<'vm> {
             | Instr::I64_PopCount(x, _)
             | Instr::I128_PopCount(x, _) => Some(x),
 
-            Instr::I8_TrailingZeros(x, _)
+            Instr::I8_LeadingZeros(x, _)
+            | Instr::I16_LeadingZeros(x, _)
+            | Instr::I32_LeadingZeros(x, _)
+            | Instr::I64_LeadingZeros(x, _)
+            | Instr::I128_LeadingZeros(x, _)
+            | Instr::I8_TrailingZeros(x, _)
             | Instr::I16_TrailingZeros(x, _)
             | Instr::I32_TrailingZeros(x, _)
             | Instr::I64_TrailingZeros(x, _)
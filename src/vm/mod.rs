@@ -1,7 +1,22 @@
+mod backtrace;
+mod compact;
+mod dap;
+mod debugger;
 mod externs;
+mod ffi;
+mod host_stack;
 pub mod instr;
+mod panic;
+mod thread_local;
+mod threaded;
 mod vm;
 
+pub use backtrace::print_backtrace;
+pub(crate) use compact::CompactCode;
+pub use dap::run_dap_server;
+pub use externs::NativeArgs;
+pub(crate) use panic::{StackOverflow, VMPanic};
+pub use threaded::ThreadedCode;
 pub use vm::{Function, FunctionSource, NativeFunc, VMThread, VM};
 
 use self::instr::Slot;
@@ -0,0 +1,408 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{
+    backtrace::backtrace_enabled,
+    read_stack,
+    vm::{Function, VTable},
+    write_stack, VMThread,
+};
+use crate::{
+    abi::{align, POINTER_SIZE},
+    items::{AssocValue, CrateId, ItemPath},
+    types::{ItemWithSubs, Mutability, SubList, Type, TypeKind},
+    variants::VariantIndex,
+    vm::{instr::Slot, VM},
+};
+
+/// Payload used to unwind through interpreted frames when interpreted code panics.
+/// Raised with `resume_unwind` so the panic hook does not kill the process.
+pub struct VMPanic {
+    /// Pointer to the interpreted panic payload, passed to the catch function of `try`.
+    /// With std, this holds a `*mut (dyn Any + Send)` for `__rust_panic_cleanup`.
+    /// Null for panics that do not carry one.
+    pub payload: usize,
+}
+
+/// Payload used to unwind out of a thread whose stack ran out. Unlike `VMPanic`, it can't be
+/// caught by interpreted code and runs no drops. Holds the error message with a backtrace.
+pub struct StackOverflow(pub String);
+
+static FIRST_PANIC: AtomicBool = AtomicBool::new(true);
+
+/// Our `panic_impl`. Prints the panic message the same way std does, then unwinds.
+pub unsafe extern "C-unwind" fn builtin_panic(stack: *mut u8, thread: &VMThread) {
+    let vm = thread.vm;
+    let info: *const u8 = read_stack(stack, Slot::new(0));
+
+    let info_ty = vm.core_adt("::panic::panic_info::PanicInfo", 1);
+
+    let args_ptr = {
+        let (message_offset, message_ty) = field(info_ty, 0, 1);
+        let message_ptr = info.add(message_offset as usize);
+
+        // Option<&fmt::Arguments>
+        let is_some = read_discriminant(message_ty, message_ptr) == 1;
+        if is_some {
+            let (args_offset, _) = field(message_ty, 1, 0);
+            let args_ptr: *const u8 = *(message_ptr.add(args_offset as usize) as *const _);
+            Some(args_ptr)
+        } else {
+            None
+        }
+    };
+
+    let msg = if let Some(args_ptr) = args_ptr {
+        format_args(thread, args_ptr)
+    } else {
+        // we never have any other payload
+        "Box<dyn Any>".to_owned()
+    };
+
+    let (file, line, col) = {
+        let (location_offset, location_ref_ty) = field(info_ty, 0, 2);
+        let location: *const u8 = *(info.add(location_offset as usize) as *const _);
+
+        let TypeKind::Ref(location_ty, _) = location_ref_ty.kind() else {
+            panic!("bad location type");
+        };
+
+        let (file_offset, _) = field(*location_ty, 0, 0);
+        let (line_offset, _) = field(*location_ty, 0, 1);
+        let (col_offset, _) = field(*location_ty, 0, 2);
+
+        let file: &str = *(location.add(file_offset as usize) as *const _);
+        let line: u32 = *(location.add(line_offset as usize) as *const _);
+        let col: u32 = *(location.add(col_offset as usize) as *const _);
+
+        (file, line, col)
+    };
+
+    let can_unwind: bool = {
+        let (offset, _) = field(info_ty, 0, 3);
+        *(info.add(offset as usize) as *const _)
+    };
+
+    // code without a source location, like generated glue, reports line zero
+    if line == 0 {
+        eprintln!("thread '{}' panicked:", thread_name(vm));
+    } else {
+        eprintln!(
+            "thread '{}' panicked at {}:{}:{}:",
+            thread_name(vm),
+            file,
+            line,
+            col
+        );
+    }
+    eprintln!("{}", msg);
+    if backtrace_enabled() {
+        eprintln!("stack backtrace:");
+        eprint!("{}", thread.call_stack().format());
+    } else if FIRST_PANIC.swap(false, Ordering::Relaxed) {
+        // like std, only suggest a backtrace once
+        eprintln!("note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace");
+    }
+
+    if !can_unwind {
+        eprintln!("thread caused non-unwinding panic. aborting.");
+        std::process::abort();
+    }
+
+    // std counts panics itself when they go through its hook, and expects catching them to
+    // undo that
+    let payload = if let Some(std_id) = vm.std_crate.get() {
+        let increase = lib_function(vm, std_id, "::panicking::panic_count::increase");
+        let mut count_thread = vm.make_thread();
+        let frame = count_thread.stack_ptr();
+        // increase(run_panic_hook: bool) -> Option<MustAbort>, which we ignore
+        write_stack(frame, Slot::new(1), false);
+        count_thread.call_root(increase);
+
+        box_payload(thread, args_ptr)
+    } else {
+        0
+    };
+
+    // unwind through interpreted frames, without invoking the panic hook
+    std::panic::resume_unwind(Box::new(VMPanic { payload }));
+}
+
+/// Builds the `Box<dyn Any + Send>` std's `catch_unwind` returns for a panic: the message as a
+/// `&'static str` if it has no arguments, otherwise as a formatted `String`. Returns a pointer
+/// to the box, for `__rust_panic_cleanup`.
+unsafe fn box_payload(thread: &VMThread, args: Option<*const u8>) -> usize {
+    let vm = thread.vm;
+    let ptr_size = POINTER_SIZE.bytes();
+
+    let static_str = match args {
+        Some(args) => args_as_str(vm, args),
+        None => Some("Box<dyn Any>"),
+    };
+
+    let (value_ty, value) = if let Some(s) = static_str {
+        let str_ty = vm.types.intern(TypeKind::StringSlice, vm);
+        let ty = vm
+            .types
+            .intern(TypeKind::Ref(str_ty, Mutability::Const), vm);
+
+        let mut value = Vec::new();
+        value.extend_from_slice(&(s.as_ptr() as usize).to_ne_bytes());
+        value.extend_from_slice(&s.len().to_ne_bytes());
+        (ty, value)
+    } else {
+        let alloc_id = vm.alloc_crate.get().expect("no alloc crate");
+        let string_ty = {
+            let item = vm
+                .crate_provider(*alloc_id)
+                .item_by_path(&ItemPath::for_type("::string::String"))
+                .expect("missing String");
+            vm.ty_adt(ItemWithSubs {
+                item,
+                subs: SubList::empty(),
+            })
+        };
+        let string_size = string_ty.layout().assert_size();
+
+        // format(args: Arguments) -> String
+        let format = lib_function(vm, alloc_id, "::fmt::format");
+        let args_layout = vm.core_adt("::fmt::Arguments", 1).layout();
+        let args_offset = align(string_size, args_layout.align);
+
+        let mut format_thread = vm.make_thread();
+        let frame = format_thread.stack_ptr();
+        std::ptr::copy_nonoverlapping(
+            args.unwrap(),
+            frame.add(args_offset as usize),
+            args_layout.assert_size() as usize,
+        );
+        format_thread.call_root(format);
+
+        (
+            string_ty,
+            format_thread.copy_result(0, string_size as usize),
+        )
+    };
+
+    let layout = value_ty.layout();
+    let data = vm.alloc_bytes(layout.assert_size() as usize, layout.align as usize);
+    std::ptr::copy_nonoverlapping(value.as_ptr(), data, value.len());
+
+    let any_trait = vm
+        .crate_provider(*vm.core_crate.get().expect("no core crate"))
+        .item_by_path(&ItemPath::for_type("::any::Any"))
+        .expect("missing Any");
+    let vtable = vm.find_vtable(any_trait, &SubList::empty(), value_ty);
+
+    let payload = vm.alloc_bytes(ptr_size as usize * 2, ptr_size as usize);
+    write_stack(payload, Slot::new(0), data);
+    write_stack(payload, Slot::new(ptr_size), vtable as *const VTable);
+    payload as usize
+}
+
+/// `fmt::Arguments::as_str`, for arguments of a panic message.
+unsafe fn args_as_str<'vm>(vm: &'vm VM<'vm>, args: *const u8) -> Option<&'static str> {
+    let args_ty = vm.core_adt("::fmt::Arguments", 1);
+    let (pieces_offset, _) = field(args_ty, 0, 0);
+    let (values_offset, _) = field(args_ty, 0, 2);
+
+    let pieces: &[&'static str] = read_stack(args as *mut u8, Slot::new(pieces_offset));
+    let values: &[u8] = read_stack(args as *mut u8, Slot::new(values_offset));
+
+    match (pieces, values.len()) {
+        ([], 0) => Some(""),
+        ([s], 0) => Some(s),
+        _ => None,
+    }
+}
+
+/// Takes back the payload built by `box_payload` or `builtin_start_panic`, once `try` has
+/// caught the panic.
+pub unsafe extern "C-unwind" fn builtin_panic_cleanup(stack: *mut u8, thread: &VMThread) {
+    let ptr_size = POINTER_SIZE.bytes();
+    // __rust_panic_cleanup(payload: *mut u8) -> *mut (dyn Any + Send)
+    let payload: *mut u8 = read_stack(stack, Slot::new(ptr_size * 2));
+
+    let data: usize = read_stack(payload, Slot::new(0));
+    let vtable: usize = read_stack(payload, Slot::new(ptr_size));
+    thread
+        .vm
+        .free_bytes(payload, ptr_size as usize * 2, ptr_size as usize);
+
+    write_stack(stack, Slot::new(0), (data, vtable));
+}
+
+/// Our panic runtime, used by std for panics which don't go through `panic_impl`, such as
+/// `resume_unwind`. Takes the payload out of a `&mut dyn PanicPayload`, then unwinds.
+pub unsafe extern "C-unwind" fn builtin_start_panic(stack: *mut u8, thread: &VMThread) {
+    let vm = thread.vm;
+    let ptr_size = POINTER_SIZE.bytes();
+    // __rust_start_panic(payload: &mut dyn PanicPayload) -> u32
+    let data: *mut u8 = read_stack(stack, Slot::new(ptr_size));
+    let vtable: &VTable = read_stack(stack, Slot::new(ptr_size * 2));
+
+    // take_box(&mut self) -> *mut (dyn Any + Send)
+    let take_box = vtable.method(0).expect("no take_box in vtable");
+    let mut take_thread = vm.make_thread();
+    let frame = take_thread.stack_ptr();
+    write_stack(frame, Slot::new(ptr_size * 2), data);
+    take_thread.call_root(take_box);
+
+    let payload = vm.alloc_bytes(ptr_size as usize * 2, ptr_size as usize);
+    std::ptr::copy_nonoverlapping(frame, payload, ptr_size as usize * 2);
+
+    std::panic::resume_unwind(Box::new(VMPanic {
+        payload: payload as usize,
+    }));
+}
+
+/// The name of the current thread, or `<unnamed>`, as std's panic hook prints it.
+unsafe fn thread_name<'vm>(vm: &'vm VM<'vm>) -> String {
+    let Some(std_id) = vm.std_crate.get() else {
+        // without std, main runs on the host's main thread and `builtin_spawn` starts unnamed ones
+        return std::thread::current()
+            .name()
+            .unwrap_or("<unnamed>")
+            .to_owned();
+    };
+    let std_items = vm.crate_provider(*std_id);
+    let subs = SubList::empty();
+
+    // current_thread() -> Option<Thread>, which is `None` once thread locals are destroyed
+    let current_item = std_items
+        .item_by_path(&ItemPath::for_value(
+            "::sys_common::thread_info::current_thread",
+        ))
+        .expect("missing current_thread");
+    let current_ty = current_item.func_sig(&subs).output;
+
+    let mut current_thread = vm.make_thread();
+    let current = current_thread.stack_ptr();
+    current_thread.call_root(current_item.func_mono(&subs));
+
+    let mut name = None;
+    if read_discriminant(current_ty, current) == 1 {
+        let (thread_offset, thread_ty) = field(current_ty, 1, 0);
+
+        // Thread::name(&self) -> Option<&str>
+        let name_item = {
+            let key = format!(
+                "{}::name",
+                thread_ty.impl_key().expect("no impl key for Thread")
+            );
+            let Some(AssocValue::Item(name_id)) = std_items.inherent_impl(&key, thread_ty) else {
+                panic!("missing Thread::name");
+            };
+            std_items.item_by_id(name_id)
+        };
+        let name_sig = name_item.func_sig(&subs);
+
+        let mut name_thread = vm.make_thread();
+        let frame = name_thread.stack_ptr();
+        write_stack(
+            frame,
+            Slot::new(name_sig.input_offsets()[0]),
+            current.add(thread_offset as usize),
+        );
+        name_thread.call_root(name_item.func_mono(&subs));
+
+        if read_discriminant(name_sig.output, frame) == 1 {
+            let (str_offset, _) = field(name_sig.output, 1, 0);
+            let name_str: &str = read_stack(frame, Slot::new(str_offset));
+            name = Some(name_str.to_owned());
+        }
+    }
+
+    if let Some(glue) = current_ty.drop_info().glue() {
+        let mut drop_thread = vm.make_thread();
+        write_stack(drop_thread.stack_ptr(), Slot::new(0), current);
+        drop_thread.call_root(glue.function());
+    }
+
+    name.unwrap_or_else(|| "<unnamed>".to_owned())
+}
+
+/// Get a function from a library crate which has no generic parameters.
+fn lib_function<'vm>(
+    vm: &'vm VM<'vm>,
+    crate_id: &CrateId,
+    path: &'static str,
+) -> &'vm Function<'vm> {
+    vm.crate_provider(*crate_id)
+        .item_by_path(&ItemPath::for_value(path))
+        .unwrap_or_else(|| panic!("missing library function: {}", path))
+        .func_mono(&SubList::empty())
+}
+
+/// Formats a `core::fmt::Arguments` using the interpreted `core::fmt::write`.
+unsafe fn format_args(thread: &VMThread, args: *const u8) -> String {
+    let vm = thread.vm;
+    let ptr_size = POINTER_SIZE.bytes();
+
+    let args_layout = vm.core_adt("::fmt::Arguments", 1).layout();
+
+    let func = vm.core_function("::fmt::write", SubList::empty());
+
+    let mut output = String::new();
+    let vtable = vm.fmt_write_vtable();
+
+    // fmt::write(output: &mut dyn Write, args: Arguments) -> fmt::Result
+    let mut write_thread = vm.make_thread();
+    let frame = write_thread.stack_ptr();
+
+    let args_offset = ptr_size * 3;
+    assert!(args_layout.align <= ptr_size);
+
+    write_stack(frame, Slot::new(ptr_size), &mut output as *mut String);
+    write_stack(frame, Slot::new(ptr_size * 2), vtable as *const _);
+    std::ptr::copy_nonoverlapping(
+        args,
+        frame.add(args_offset as usize),
+        args_layout.assert_size() as usize,
+    );
+
+    write_thread.call_root(func);
+
+    output
+}
+
+/// `<String as fmt::Write>::write_str`, used by the vtable from `VM::fmt_write_vtable`.
+pub unsafe extern "C-unwind" fn builtin_fmt_write_str(stack: *mut u8, _thread: &VMThread) {
+    let ptr_size = POINTER_SIZE.bytes();
+    let output: *mut String = read_stack(stack, Slot::new(ptr_size));
+    let s: &str = read_stack(stack, Slot::new(ptr_size * 2));
+
+    (*output).push_str(s);
+
+    // Ok(())
+    write_stack(stack, Slot::new(0), 0i32);
+}
+
+/// `<String as fmt::Write>::write_char`, used by the vtable from `VM::fmt_write_vtable`.
+pub unsafe extern "C-unwind" fn builtin_fmt_write_char(stack: *mut u8, _thread: &VMThread) {
+    let ptr_size = POINTER_SIZE.bytes();
+    let output: *mut String = read_stack(stack, Slot::new(ptr_size));
+    let c: char = read_stack(stack, Slot::new(ptr_size * 2));
+
+    (*output).push(c);
+
+    // Ok(())
+    write_stack(stack, Slot::new(0), 0i32);
+}
+
+/// Get the offset and type of a field in an ADT.
+fn field(ty: Type, variant: u32, index: usize) -> (u32, Type) {
+    ty.field(VariantIndex::new(variant), index)
+}
+
+unsafe fn read_discriminant(ty: Type, ptr: *const u8) -> i128 {
+    let enum_info = ty.adt_info().enum_info().expect("expected enum");
+
+    let disc_ty = enum_info.discriminant_internal;
+    match disc_ty.layout().assert_size() {
+        1 => *ptr as i128,
+        2 => *(ptr as *const u16) as i128,
+        4 => *(ptr as *const u32) as i128,
+        8 => *(ptr as *const u64) as i128,
+        _ => panic!("bad discriminant: {}", disc_ty),
+    }
+}
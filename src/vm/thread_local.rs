@@ -0,0 +1,116 @@
+use std::{alloc::Layout, cell::RefCell, rc::Rc};
+
+use ahash::AHashMap;
+
+use super::backtrace::CallStack;
+
+type ThreadLocalDtor = unsafe extern "C" fn(*mut u8);
+
+/// The `#[thread_local]` statics of one OS thread, and its call stack. Every `VMThread` running
+/// on that thread shares them, including the short-lived ones used for drops, panics and calls
+/// from native code.
+#[derive(Default)]
+pub struct ThreadLocals {
+    pub call_stack: CallStack,
+    /// Maps static items, by address, to this thread's copy of their value.
+    values: RefCell<AHashMap<usize, (*mut u8, Layout)>>,
+    /// Destructors registered with `__cxa_thread_atexit_impl`, with their arguments.
+    dtors: RefCell<Vec<(ThreadLocalDtor, *mut u8)>>,
+    /// Whether the current thread's map holds a reference.
+    registered: bool,
+}
+
+thread_local! {
+    /// Thread locals for each VM on the current OS thread, keyed by the VM's address.
+    static THREAD_LOCALS: RefCell<AHashMap<usize, Rc<ThreadLocals>>> = Default::default();
+}
+
+impl ThreadLocals {
+    /// Native code may call into the VM after the host's thread locals are destroyed, like from
+    /// `atexit` handlers. Those calls get thread locals of their own.
+    pub fn for_current_thread(vm_addr: usize) -> Rc<Self> {
+        THREAD_LOCALS
+            .try_with(|map| {
+                let mut map = map.borrow_mut();
+                let entry = map.entry(vm_addr).or_insert_with(|| {
+                    Rc::new(Self {
+                        call_stack: Default::default(),
+                        values: Default::default(),
+                        dtors: Default::default(),
+                        registered: true,
+                    })
+                });
+                entry.clone()
+            })
+            .unwrap_or_default()
+    }
+
+    /// True if no other `VMThread` uses these thread locals.
+    pub fn is_last_user(this: &Rc<Self>) -> bool {
+        let users = Rc::strong_count(this);
+        if this.registered {
+            users == 2
+        } else {
+            users == 1
+        }
+    }
+
+    pub fn for_each_on_current_thread(mut f: impl FnMut(&Self)) {
+        _ = THREAD_LOCALS.try_with(|map| {
+            for locals in map.borrow().values() {
+                f(locals);
+            }
+        });
+    }
+
+    /// Removes the current thread's entry, so the next `VMThread` on it starts fresh.
+    pub fn release(vm_addr: usize) {
+        _ = THREAD_LOCALS.try_with(|map| map.borrow_mut().remove(&vm_addr));
+    }
+
+    pub fn get(&self, key: usize) -> Option<*mut u8> {
+        self.values.borrow().get(&key).map(|(ptr, _)| *ptr)
+    }
+
+    /// Allocates a copy of `initial` for this thread.
+    pub unsafe fn insert(
+        &self,
+        key: usize,
+        initial: *const u8,
+        size: usize,
+        align: usize,
+    ) -> *mut u8 {
+        let layout = Layout::from_size_align(size, align).expect("bad thread local layout");
+
+        let ptr = if size == 0 {
+            align as *mut u8
+        } else {
+            let ptr = std::alloc::alloc(layout);
+            std::ptr::copy_nonoverlapping(initial, ptr, size);
+            ptr
+        };
+
+        let mut values = self.values.borrow_mut();
+        values.insert(key, (ptr, layout));
+        ptr
+    }
+
+    pub fn register_dtor(&self, dtor: ThreadLocalDtor, arg: *mut u8) {
+        self.dtors.borrow_mut().push((dtor, arg));
+    }
+
+    /// Destructors run in the reverse order they were registered, like glibc.
+    pub fn pop_dtor(&self) -> Option<(ThreadLocalDtor, *mut u8)> {
+        self.dtors.borrow_mut().pop()
+    }
+}
+
+impl Drop for ThreadLocals {
+    fn drop(&mut self) {
+        for (ptr, layout) in self.values.get_mut().values() {
+            if layout.size() != 0 {
+                unsafe { std::alloc::dealloc(*ptr, *layout) };
+            }
+        }
+    }
+}
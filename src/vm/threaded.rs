@@ -0,0 +1,32 @@
+//! The call-threaded interpreter loop, used instead of the match with the `threaded-dispatch`
+//! feature. `build.rs` turns each arm of the match into a handler method, and each function gets
+//! a table with the handler for each of its instructions. The loop calls the handler at the pc,
+//! which returns the next pc.
+
+#[cfg(feature = "threaded-dispatch")]
+use super::{instr::Instr, VMThread};
+#[cfg(feature = "threaded-dispatch")]
+use crate::bytecode_compiler::FunctionBytecode;
+
+/// Runs the instruction at the pc, which is passed last, and returns the next pc.
+#[cfg(feature = "threaded-dispatch")]
+pub(super) type Handler<'vm> = unsafe fn(
+    &mut VMThread<'vm>,
+    &FunctionBytecode<'vm>,
+    *mut u8,
+    usize,
+    &Instr<'vm>,
+    usize,
+) -> usize;
+
+/// Returned by handlers to leave the loop.
+#[cfg(feature = "threaded-dispatch")]
+pub(super) const RETURN_PC: usize = usize::MAX;
+
+/// A function's handlers, resolved the first time it runs.
+#[derive(Default)]
+pub struct ThreadedCode<'vm> {
+    #[cfg(feature = "threaded-dispatch")]
+    pub(super) handlers: std::sync::OnceLock<Box<[Handler<'vm>]>>,
+    _vm: std::marker::PhantomData<&'vm ()>,
+}
@@ -2,47 +2,72 @@ use ahash::{AHashMap, AHashSet};
 use colosseum::sync::Arena;
 
 use crate::abi::align;
+use crate::abi::CALL_ALIGN;
+use crate::abi::POINTER_SIZE;
 use crate::bytecode_compiler::BytecodeCompiler;
-use crate::bytecode_compiler::FunctionBytecode;
+use crate::bytecode_compiler::CompilerStack;
+use crate::bytecode_compiler::{FunctionBytecode, SpanTable};
+use crate::bytecode_select;
 use crate::cache_provider::CacheProvider;
-use crate::cli::CliArgs;
 use crate::closure::Closure;
 use crate::closure::ClosureRef;
 use crate::crate_provider::CrateProvider;
 use crate::crate_provider::TraitImplResult;
+use crate::disasm::{disassemble, glob_matches};
+use crate::engine::Options;
 use crate::ir::IRFunction;
+use crate::ir::Span;
 use crate::items::AssocValue;
 use crate::items::CrateId;
+use crate::items::FunctionAbi;
+use crate::items::FunctionSig;
 use crate::items::Item;
 use crate::items::ItemPath;
+use crate::profiler;
 use crate::rustc_worker::RustCWorker;
 use crate::rustc_worker::RustCWorkerConfig;
 use crate::simple_jit;
 use crate::types::CommonTypes;
-use crate::types::DropGlue;
+use crate::types::ConstGeneric;
 use crate::types::ItemWithSubs;
+use crate::types::Mutability;
 use crate::types::Sub;
 use crate::types::SubList;
 use crate::types::Type;
 use crate::types::TypeContext;
 use crate::types::TypeKind;
 use crate::value_debug::print_value;
-use crate::vm::instr::Slot;
+use crate::vm::compact::{CompactCode, CompactReader, FALLBACK_OPCODE};
+use crate::vm::instr::{compact_fields, opcode, Slot};
 
 use std::{
-    borrow::Cow, sync::atomic::AtomicPtr, sync::atomic::Ordering, sync::Arc, sync::Mutex,
-    sync::OnceLock, sync::RwLock,
+    borrow::Cow, panic::AssertUnwindSafe, rc::Rc, sync::atomic::AtomicPtr, sync::atomic::Ordering,
+    sync::Arc, sync::Mutex, sync::OnceLock, sync::RwLock,
 };
 
+use super::backtrace::CallStack;
+use super::debugger::Debugger;
+use super::externs::c_function;
 use super::externs::get_extern_fn;
 use super::externs::get_extern_static;
+use super::externs::HostRegistry;
+use super::ffi::{self, NativeLibs, ThunkTarget};
+use super::host_stack::{self, HostSegment};
+use super::panic::{builtin_fmt_write_char, builtin_fmt_write_str, StackOverflow, VMPanic};
+use super::thread_local::ThreadLocals;
+#[cfg(feature = "threaded-dispatch")]
+use super::threaded::{Handler, RETURN_PC};
 use super::{read_stack, write_stack};
 
 use super::instr::Instr;
 
 pub struct VM<'vm> {
-    pub cli_args: CliArgs,
+    pub options: Options,
     pub types: TypeContext<'vm>,
+    pub(super) native_libs: NativeLibs,
+    pub(super) host: HostRegistry,
+    /// Set when running with `--debug`.
+    debugger: Option<Debugger>,
 
     pub core_crate: OnceLock<CrateId>,
     pub alloc_crate: OnceLock<CrateId>,
@@ -51,6 +76,10 @@ pub struct VM<'vm> {
     common_types: OnceLock<CommonTypes<'vm>>,
     crates: RwLock<Vec<&'vm Box<dyn CrateProvider<'vm>>>>,
 
+    /// This VM, if it lives for the rest of the program. Spawned threads need it, since they can
+    /// outlive the native call which started them.
+    pub(super) static_self: OnceLock<&'static VM<'static>>,
+
     stack_pool: Mutex<Vec<Vec<u128>>>,
 
     arena_crates: Arena<Box<dyn CrateProvider<'vm>>>,
@@ -64,8 +93,17 @@ pub struct VM<'vm> {
 
     map_paths: Mutex<AHashSet<&'vm str>>,
     map_vtables: Mutex<AHashMap<(&'vm Item<'vm>, SubList<'vm>), &'vm VTable<'vm>>>,
+    map_variadic_functions: Mutex<AHashMap<(&'vm Item<'vm>, FunctionSig<'vm>), &'vm Function<'vm>>>,
+    map_c_pointer_callers: Mutex<AHashMap<(FunctionSig<'vm>, i32), &'vm Function<'vm>>>,
+    /// Maps function addresses to C-callable trampolines.
+    map_c_trampolines: Mutex<AHashMap<usize, usize>>,
+    /// Maps source positions to `core::panic::Location`s.
+    map_locations: Mutex<AHashMap<(&'vm str, Span), usize>>,
+    /// The type of `core::panic::Location`, set when the first `caller_location` is compiled.
+    location_ty: OnceLock<Type<'vm>>,
 
     drop_trait: OnceLock<&'vm Item<'vm>>,
+    fmt_write_vtable: OnceLock<&'vm VTable<'vm>>,
 }
 
 static TRACE_CALL_DEPTH: Mutex<usize> = Mutex::new(0);
@@ -73,29 +111,111 @@ static TRACE_CALL_DEPTH: Mutex<usize> = Mutex::new(0);
 pub struct VMThread<'vm> {
     pub vm: &'vm VM<'vm>,
     stack: Vec<u128>,
+    /// End of the stack segment in use. Frames which don't fit fail or move to a new segment.
+    stack_end: usize,
+    /// Extra segments of a growable stack, and how many of them are in use.
+    segments: Vec<Vec<u128>>,
+    segments_used: usize,
+    /// Extra host stacks, used by growable stacks when the host's own stack runs low.
+    host_segments: Vec<HostSegment>,
+    host_segments_used: usize,
+    /// `host_stack::call_limit` of the host stack in use.
+    host_limit: usize,
     drop_flags: Vec<u8>,
+    thread_locals: Rc<ThreadLocals>,
 }
 
 impl<'vm> VMThread<'vm> {
-    pub fn call_root(&mut self, func: &Function<'vm>) {
+    pub fn call_root(&mut self, func: &'vm Function<'vm>) {
         let stack_ptr = self.stack.as_ptr() as *mut u8;
         self.call(func, stack_ptr);
     }
 
+    /// Call a function which returns nothing, with arguments laid out like a `#[repr(C)]` struct.
+    pub fn call_root_with_args<A: Copy>(&mut self, func: &'vm Function<'vm>, args: A) {
+        let stack_ptr = self.stack.as_ptr() as *mut u8;
+        unsafe {
+            write_stack(stack_ptr, Slot::new(0), args);
+        }
+        self.call(func, stack_ptr);
+    }
+
+    /// Call a `Box<dyn FnOnce()>` given its data pointer and vtable, consuming it.
+    ///
+    /// # Safety
+    ///
+    /// `data` and `vtable` must be the parts of an interpreted `Box<dyn FnOnce()>` which the caller
+    /// owns. The box must not be used again, its allocation is freed once the closure returns.
+    pub unsafe fn call_boxed_fn_once(&mut self, data: *mut u8, vtable: &VTable<'vm>) {
+        // `call_once` is the only method of `FnOnce`, and takes `self` through a shim
+        let call_once = vtable
+            .methods
+            .iter()
+            .flatten()
+            .next()
+            .expect("no call_once in vtable");
+        self.call_root_with_args(call_once, data);
+
+        // the closure was moved out, only its allocation remains
+        if vtable.size != 0 {
+            self.vm
+                .free_bytes(data, vtable.size as usize, vtable.align as usize);
+        }
+    }
+
+    pub(crate) fn call_stack(&self) -> &CallStack {
+        &self.thread_locals.call_stack
+    }
+
+    /// Get the location a `#[track_caller]` function running in the innermost frame was called from.
+    pub fn caller_location(&self) -> *const u8 {
+        let (file, span) = self.call_stack().caller_span();
+        self.vm.alloc_location(file, span)
+    }
+
+    /// The frame used for root calls, for callers which write arguments themselves.
+    pub fn root_frame(&mut self) -> *mut u8 {
+        self.stack.as_mut_ptr() as *mut u8
+    }
+
     pub fn run_bytecode_root(&mut self, func: &FunctionBytecode<'vm>) {
         let stack_ptr = self.stack.as_ptr() as *mut u8;
         self.run_bytecode(func, stack_ptr);
     }
 
-    pub extern "C" fn call(&mut self, func: &Function<'vm>, stack_ptr: *mut u8) {
-        let native = if self.vm.cli_args.jit {
+    pub(crate) extern "C-unwind" fn call(&mut self, func: &'vm Function<'vm>, stack_ptr: *mut u8) {
+        if host_stack::stack_pointer() < self.host_limit {
+            return self.call_on_new_host_stack(func, stack_ptr);
+        }
+
+        let native = if self.vm.options.jit {
             let res = func.compile_native();
             Some(res.expect("jit failed"))
         } else {
             func.get_native()
         };
 
-        if self.vm.cli_args.debug_trace_calls {
+        // host functions have no bytecode, and run on the host's stack
+        let bc = if native.is_some() {
+            func.get_bytecode()
+        } else {
+            Some(func.bytecode())
+        };
+
+        if let Some(bc) = bc {
+            if stack_ptr as usize + bc.frame_size as usize > self.stack_end {
+                return self.call_in_new_segment(func, stack_ptr, bc.frame_size as usize);
+            }
+        }
+
+        self.call_stack().push(func, native.is_none(), stack_ptr);
+        let profile_depth = self
+            .vm
+            .options
+            .profile_functions
+            .then(|| profiler::profile_function_enter(func));
+
+        if self.vm.options.debug_trace_calls {
             let mut call_depth = TRACE_CALL_DEPTH.lock().unwrap();
             for _ in 0..*call_depth {
                 print!("  ");
@@ -124,7 +244,13 @@ impl<'vm> VMThread<'vm> {
             } else {
                 print!("[?]");
             }
-            println!(" )");
+            print!(" )");
+            match self.call_stack().call_site() {
+                Some((file, span)) if !span.is_none() => {
+                    println!(" at {}:{}:{}", file, span.line, span.col);
+                }
+                _ => println!(),
+            }
         }
 
         if let Some(native) = native {
@@ -132,12 +258,15 @@ impl<'vm> VMThread<'vm> {
                 native(stack_ptr, self);
             }
         } else {
-            // fetch bytecode
-            let bc = func.bytecode();
-            self.run_bytecode(bc, stack_ptr);
+            self.run_bytecode(bc.unwrap(), stack_ptr);
+        }
+
+        if let Some(depth) = profile_depth {
+            profiler::profile_function_exit(depth);
         }
+        self.call_stack().pop();
 
-        if self.vm.cli_args.debug_trace_calls {
+        if self.vm.options.debug_trace_calls {
             let mut call_depth = TRACE_CALL_DEPTH.lock().unwrap();
             *call_depth -= 1;
             for _ in 0..*call_depth {
@@ -159,28 +288,404 @@ impl<'vm> VMThread<'vm> {
         }
     }
 
-    pub fn run_bytecode(&mut self, func: &FunctionBytecode<'vm>, stack: *mut u8) {
+    /// Called when the host stack runs low. Fails with a stack overflow, or if the stack is
+    /// growable, continues the call on another host stack.
+    #[cold]
+    fn call_on_new_host_stack(&mut self, func: &'vm Function<'vm>, stack_ptr: *mut u8) {
+        if !self.vm.options.growable_stack {
+            self.stack_overflow(func);
+        }
+
+        if self.host_segments_used == self.host_segments.len() {
+            self.host_segments.push(HostSegment::new());
+        }
+        let segment: *const HostSegment = &self.host_segments[self.host_segments_used];
+        self.host_segments_used += 1;
+
+        // segments are only dropped with the thread, which can't happen during the call
+        let old_limit = self.host_limit;
+        let res = unsafe {
+            (*segment).run(|| {
+                self.host_limit = host_stack::call_limit();
+                self.call(func, stack_ptr)
+            })
+        };
+
+        self.host_limit = old_limit;
+        self.host_segments_used -= 1;
+        if let Err(payload) = res {
+            std::panic::resume_unwind(payload);
+        }
+    }
+
+    /// Called when a frame doesn't fit in the current stack segment. Fails with a stack overflow,
+    /// or if the stack is growable, copies the arguments to the next segment and calls from there.
+    #[cold]
+    fn call_in_new_segment(
+        &mut self,
+        func: &'vm Function<'vm>,
+        stack_ptr: *mut u8,
+        frame_size: usize,
+    ) {
+        // only the arguments and return value are live, but we don't know their size here
+        let arg_size = frame_size.min(self.stack_end.saturating_sub(stack_ptr as usize));
+
+        self.in_new_segment(func, frame_size, |thread, new_ptr| unsafe {
+            std::ptr::copy_nonoverlapping(stack_ptr, new_ptr, arg_size);
+            thread.call(func, new_ptr);
+            std::ptr::copy_nonoverlapping(new_ptr, stack_ptr, arg_size);
+        });
+    }
+
+    /// Fails with a stack overflow while calling `func`, or if the stack is growable, runs `f`
+    /// with the start of the next stack segment, which has room for at least `size` bytes.
+    #[cold]
+    fn in_new_segment(
+        &mut self,
+        func: &'vm Function<'vm>,
+        size: usize,
+        f: impl FnOnce(&mut Self, *mut u8),
+    ) {
+        if !self.vm.options.growable_stack {
+            self.stack_overflow(func);
+        }
+
+        let words = self.vm.options.stack_size.max(size) / 16 + 1;
+        if self.segments_used == self.segments.len() {
+            self.segments.push(vec![0; words]);
+        } else if self.segments[self.segments_used].len() < words {
+            self.segments[self.segments_used] = vec![0; words];
+        }
+
+        let segment = &mut self.segments[self.segments_used];
+        let new_ptr = segment.as_mut_ptr() as *mut u8;
+        let new_end = new_ptr as usize + segment.len() * 16;
+
+        let old_end = std::mem::replace(&mut self.stack_end, new_end);
+        self.segments_used += 1;
+
+        let res = std::panic::catch_unwind(AssertUnwindSafe(|| f(self, new_ptr)));
+
+        self.segments_used -= 1;
+        self.stack_end = old_end;
+
+        if let Err(payload) = res {
+            std::panic::resume_unwind(payload);
+        }
+    }
+
+    /// Abort the thread with a stack overflow error. Like compiled code, this doesn't run drops
+    /// and can't be caught.
+    #[cold]
+    fn stack_overflow(&self, func: &'vm Function<'vm>) -> ! {
+        let message = format!(
+            "stack overflow in {}\nbacktrace:\n{}",
+            func.debug_name(),
+            self.call_stack().format().trim_end()
+        );
+        std::panic::resume_unwind(Box::new(StackOverflow(message)));
+    }
+
+    pub(crate) fn run_bytecode(&mut self, func: &FunctionBytecode<'vm>, stack: *mut u8) {
         let drops_base = self.drop_flags.len();
         if func.drops.len() > 0 {
+            let call_depth = self.call_stack().depth();
+
             let bytes = (func.drops.len() - 1) / 8 + 1;
             self.drop_flags.resize(drops_base + bytes, 0);
+
+            let res = std::panic::catch_unwind(AssertUnwindSafe(|| unsafe {
+                self.run_bytecode_inner(func, stack, drops_base)
+            }));
+
+            if let Err(payload) = res {
+                if payload.is::<StackOverflow>() {
+                    std::panic::resume_unwind(payload);
+                }
+                self.call_stack().truncate(call_depth);
+
+                // drop any live locals, then continue unwinding
+                let drop_res = std::panic::catch_unwind(AssertUnwindSafe(|| unsafe {
+                    for i in (0..func.drops.len()).rev() {
+                        self.local_drop(drops_base, i as u32, func, stack);
+                    }
+                }));
+                if let Err(drop_payload) = drop_res {
+                    if !drop_payload.is::<VMPanic>() {
+                        std::panic::resume_unwind(drop_payload);
+                    }
+                    self.panic_in_cleanup(func, stack);
+                }
+                self.drop_flags.truncate(drops_base);
+                std::panic::resume_unwind(payload);
+            }
+        } else {
+            // frames without drops have nothing to clean up, let panics pass straight through them
+            unsafe { self.run_bytecode_inner(func, stack, drops_base) }
         }
 
-        unsafe {
-            let mut pc = 0;
+        self.drop_flags.truncate(drops_base);
+    }
+
+    unsafe fn run_bytecode_inner(
+        &mut self,
+        func: &FunctionBytecode<'vm>,
+        stack: *mut u8,
+        drops_base: usize,
+    ) {
+        // the debugger and opcode profiler get their own copies of the loop, so they cost
+        // nothing when disabled
+        #[cfg(feature = "profile-opcodes")]
+        if self.vm.options.profile_opcodes {
+            return if self.vm.options.compact {
+                self.run_compact_loop::<true>(func, stack, drops_base)
+            } else {
+                self.run_bytecode_loop::<false, true>(func, stack, drops_base)
+            };
+        }
+        #[cfg(feature = "threaded-dispatch")]
+        if self.vm.debugger.is_none() {
+            return self.run_threaded_loop(func, stack, drops_base);
+        }
+        if self.vm.debugger.is_some() {
+            self.run_bytecode_loop::<true, false>(func, stack, drops_base)
+        } else if self.vm.options.compact {
+            self.run_compact_loop::<false>(func, stack, drops_base)
+        } else {
+            self.run_bytecode_loop::<false, false>(func, stack, drops_base)
+        }
+    }
+
+    #[cfg(feature = "threaded-dispatch")]
+    unsafe fn run_threaded_loop(
+        &mut self,
+        func: &FunctionBytecode<'vm>,
+        stack: *mut u8,
+        drops_base: usize,
+    ) {
+        let code = func.code.instrs();
+        let handlers = func
+            .threaded
+            .handlers
+            .get_or_init(|| code.iter().map(Self::threaded_handler).collect())
+            .as_ptr();
+        let code = code.as_ptr();
+        let mut pc = 0;
+
+        // handlers take their instruction by reference, so the code is never copied
+        while pc != RETURN_PC {
+            pc = (*handlers.add(pc))(self, func, stack, drops_base, &*code.add(pc), pc);
+        }
+
+        for i in (0..func.drops.len()).rev() {
+            self.local_drop(drops_base, i as u32, func, stack);
+        }
+    }
+
+    /// The loop used without a debugger, which runs the compact form of the code. `pc` still
+    /// counts instructions, for backtraces and spans.
+    unsafe fn run_compact_loop<const PROFILE: bool>(
+        &mut self,
+        func: &FunctionBytecode<'vm>,
+        stack: *mut u8,
+        drops_base: usize,
+    ) {
+        let mut input = CompactReader::new(&func.code);
+        let mut pc = 0;
+
+        loop {
+            let opcode = input.take_opcode();
+            #[cfg(feature = "profile-opcodes")]
+            if PROFILE {
+                // instructions without an arm are counted as themselves
+                let opcode = if opcode == FALLBACK_OPCODE {
+                    input.peek_opcode()
+                } else {
+                    opcode
+                };
+                profiler::profile_opcode(opcode, self.vm.options.profile_opcode_pairs);
+            }
+            include!(concat!(env!("OUT_DIR"), "/exec_compact.rs"));
+            pc += 1;
+        }
 
-            loop {
-                let instr = &func.code[pc];
-                include!(concat!(env!("OUT_DIR"), "/exec_match.rs"));
-                pc += 1;
+        for i in (0..func.drops.len()).rev() {
+            self.local_drop(drops_base, i as u32, func, stack);
+        }
+    }
+
+    unsafe fn run_bytecode_loop<const DEBUG: bool, const PROFILE: bool>(
+        &mut self,
+        func: &FunctionBytecode<'vm>,
+        stack: *mut u8,
+        drops_base: usize,
+    ) {
+        let code = func.code.instrs();
+        let mut pc = 0;
+        let mut line = 0;
+
+        loop {
+            if DEBUG {
+                if let Some(debugger) = &self.vm.debugger {
+                    debugger.before_instr(self, func, pc, &mut line);
+                }
             }
+            let instr = &code[pc];
+            #[cfg(feature = "profile-opcodes")]
+            if PROFILE {
+                profiler::profile_opcode(instr.opcode(), self.vm.options.profile_opcode_pairs);
+            }
+            include!(concat!(env!("OUT_DIR"), "/exec_match.rs"));
+            pc += 1;
+        }
+
+        for i in (0..func.drops.len()).rev() {
+            self.local_drop(drops_base, i as u32, func, stack);
+        }
+    }
+
+    /// Implements the `try` intrinsic. The frame contains the try function, data pointer and catch function,
+    /// which are called in `call_frame`. Returns 0 if the try function returned normally, or 1 if it
+    /// panicked and the catch function was run.
+    unsafe fn call_try(&mut self, frame: *mut u8, call_frame: *mut u8) -> i32 {
+        let ptr_size = POINTER_SIZE.bytes();
+
+        let try_fn: &Function = read_stack(frame, Slot::new(0));
+        let data: *mut u8 = read_stack(frame, Slot::new(ptr_size));
+        let catch_fn: &Function = read_stack(frame, Slot::new(ptr_size * 2));
+
+        let drops_base = self.drop_flags.len();
+        let call_depth = self.call_stack().depth();
+
+        write_stack(call_frame, Slot::new(0), data);
+        let res = std::panic::catch_unwind(AssertUnwindSafe(|| self.call(try_fn, call_frame)));
+
+        match res {
+            Ok(_) => 0,
+            Err(payload) => {
+                let Some(vm_panic) = payload.downcast_ref::<VMPanic>() else {
+                    // not raised by interpreted code, don't try to catch it
+                    std::panic::resume_unwind(payload);
+                };
+
+                self.drop_flags.truncate(drops_base);
+                self.call_stack().truncate(call_depth);
 
-            for i in (0..func.drops.len()).rev() {
-                self.local_drop(drops_base, i as u32, &func.drops, stack);
+                write_stack(call_frame, Slot::new(0), data);
+                write_stack(call_frame, Slot::new(ptr_size), vm_panic.payload);
+                self.call(catch_fn, call_frame);
+                1
             }
         }
+    }
 
-        self.drop_flags.truncate(drops_base);
+    /// Calls `func` with `args` for the VM itself, in a frame past the end of the one `stack`
+    /// belongs to. `bc` is the function that frame belongs to.
+    fn call_from_vm<A: Copy>(
+        &mut self,
+        func: &'vm Function<'vm>,
+        bc: &FunctionBytecode<'vm>,
+        stack: *mut u8,
+        args: A,
+    ) {
+        let arg_size = std::mem::size_of::<A>();
+        unsafe {
+            let frame = stack.add(align(bc.frame_size, CALL_ALIGN) as usize);
+            // the arguments go past the end of the caller's frame, which may be the end of a segment
+            if frame as usize + arg_size > self.stack_end {
+                self.in_new_segment(func, arg_size, |thread, frame| {
+                    write_stack(frame, Slot::new(0), args);
+                    thread.call(func, frame);
+                });
+            } else {
+                write_stack(frame, Slot::new(0), args);
+                self.call(func, frame);
+            }
+        }
+    }
+
+    /// Raise a panic from inside the interpreter. This goes through `core::panicking`,
+    /// so it behaves exactly like a panic raised by compiled code. `pc` is the instruction
+    /// raising it, in the function `bc` whose frame is at `stack`.
+    pub fn panic_with_message(
+        &mut self,
+        bc: &FunctionBytecode<'vm>,
+        stack: *mut u8,
+        pc: usize,
+        msg: &'static str,
+    ) -> ! {
+        self.call_stack().set_pc(pc);
+
+        // `panic` is a const fn, called from a non-const context
+        let host = Sub::Const(self.vm.common_types().bool, ConstGeneric::Value(1));
+        let func = self
+            .vm
+            .core_function("::panicking::panic", SubList { list: vec![host] });
+
+        self.call_from_vm(func, bc, stack, msg);
+
+        panic!("panic function returned");
+    }
+
+    pub fn panic_bounds_check(
+        &mut self,
+        bc: &FunctionBytecode<'vm>,
+        stack: *mut u8,
+        pc: usize,
+        index: usize,
+        len: usize,
+    ) -> ! {
+        self.call_stack().set_pc(pc);
+
+        let func = self
+            .vm
+            .core_function("::panicking::panic_bounds_check", SubList::empty());
+
+        self.call_from_vm(func, bc, stack, [index, len]);
+
+        panic!("panic function returned");
+    }
+
+    /// Abort after a drop run while unwinding panicked, like the cleanup blocks of compiled code.
+    /// `bc` is the function being unwound, whose frame is at `stack`.
+    fn panic_in_cleanup(&mut self, bc: &FunctionBytecode<'vm>, stack: *mut u8) -> ! {
+        let func = self
+            .vm
+            .core_function("::panicking::panic_in_cleanup", SubList::empty());
+
+        self.call_from_vm(func, bc, stack, ());
+
+        panic!("panic function returned");
+    }
+
+    /// Get this thread's copy of a `#[thread_local]` static, copying its initial value on first use.
+    pub fn thread_local_addr(&self, item: &'vm Item<'vm>) -> *mut u8 {
+        let key = item as *const _ as usize;
+        if let Some(ptr) = self.thread_locals.get(key) {
+            return ptr;
+        }
+
+        let (ir, _) = item.ir(&SubList::empty());
+        let layout = ir.sig.output.layout();
+        let initial = item.static_value(&SubList::empty());
+
+        unsafe {
+            self.thread_locals.insert(
+                key,
+                initial,
+                layout.assert_size() as usize,
+                layout.align as usize,
+            )
+        }
+    }
+
+    /// Register a thread-local destructor, which runs once no `VMThread` is left on this OS thread.
+    pub fn register_thread_local_dtor(&self, dtor: unsafe extern "C" fn(*mut u8), arg: *mut u8) {
+        self.thread_locals.register_dtor(dtor, arg);
+    }
+
+    pub fn stack_ptr(&mut self) -> *mut u8 {
+        self.stack.as_mut_ptr() as _
     }
 
     pub fn copy_result(&self, offset: usize, size: usize) -> Vec<u8> {
@@ -230,7 +735,7 @@ impl<'vm> VMThread<'vm> {
         &mut self,
         base: usize,
         n: u32,
-        drops: &[(Slot, DropGlue<'vm>)],
+        func: &FunctionBytecode<'vm>,
         stack: *mut u8,
     ) {
         let offset = (n / 8) as usize;
@@ -242,14 +747,10 @@ impl<'vm> VMThread<'vm> {
         if (*byte & mask) != 0 {
             *byte ^= mask;
 
-            let (slot, glue) = drops[n as usize];
+            let (slot, glue) = func.drops[n as usize];
 
             let slot_ptr = stack.add(slot.index());
-
-            let mut drop_thread = self.vm.make_thread();
-            write_stack(drop_thread.stack.as_mut_ptr() as _, Slot::new(0), slot_ptr);
-
-            drop_thread.call_root(glue.function());
+            self.call_from_vm(glue.function(), func, stack, slot_ptr);
         }
     }
 
@@ -257,7 +758,7 @@ impl<'vm> VMThread<'vm> {
         &mut self,
         base: usize,
         n: u32,
-        drops: &[(Slot, DropGlue<'vm>)],
+        func: &FunctionBytecode<'vm>,
         stack: *mut u8,
     ) {
         let offset = (n / 8) as usize;
@@ -267,14 +768,10 @@ impl<'vm> VMThread<'vm> {
         let mask = 1u8 << (bit as u8);
 
         if (*byte & mask) != 0 {
-            let (slot, glue) = drops[n as usize];
+            let (slot, glue) = func.drops[n as usize];
 
             let slot_ptr = stack.add(slot.index());
-
-            let mut drop_thread = self.vm.make_thread();
-            write_stack(drop_thread.stack.as_mut_ptr() as _, Slot::new(0), slot_ptr);
-
-            drop_thread.call_root(glue.function());
+            self.call_from_vm(glue.function(), func, stack, slot_ptr);
         } else {
             *byte |= mask;
         }
@@ -283,6 +780,15 @@ impl<'vm> VMThread<'vm> {
 
 impl<'vm> std::ops::Drop for VMThread<'vm> {
     fn drop(&mut self) {
+        // the last thread on this OS thread runs the thread-local destructors, which may
+        // register more destructors while running
+        if ThreadLocals::is_last_user(&self.thread_locals) {
+            while let Some((dtor, arg)) = self.thread_locals.pop_dtor() {
+                unsafe { dtor(arg) };
+            }
+            ThreadLocals::release(self.vm as *const _ as usize);
+        }
+
         let stack = std::mem::take(&mut self.stack);
 
         // TODO provide some upper limit on stack pool size?
@@ -291,10 +797,24 @@ impl<'vm> std::ops::Drop for VMThread<'vm> {
     }
 }
 
+impl VM<'static> {
+    /// Create a VM which is never freed, which is required for it to spawn threads.
+    pub fn new_static(options: Options) -> &'static Self {
+        let vm: &'static Self = Box::leak(Box::new(Self::new(options)));
+        // the VM was just created, so this is the first and only set
+        let _ = vm.static_self.set(vm);
+        vm
+    }
+}
+
 impl<'vm> VM<'vm> {
-    pub fn new(cli_args: &CliArgs) -> Self {
-        Self {
-            cli_args: cli_args.clone(),
+    pub fn new(options: Options) -> Self {
+        let vm = Self {
+            native_libs: NativeLibs::open(&options.link_libs)
+                .unwrap_or_else(|err| panic!("{}", err)),
+            debugger: Debugger::for_options(&options),
+            options,
+            host: Default::default(),
 
             core_crate: OnceLock::new(),
             alloc_crate: OnceLock::new(),
@@ -304,6 +824,8 @@ impl<'vm> VM<'vm> {
             crates: Default::default(),
             common_types: OnceLock::new(),
 
+            static_self: OnceLock::new(),
+
             stack_pool: Default::default(),
 
             arena_crates: Arena::new(),
@@ -317,9 +839,30 @@ impl<'vm> VM<'vm> {
 
             map_paths: Default::default(),
             map_vtables: Default::default(),
+            map_variadic_functions: Default::default(),
+            map_c_pointer_callers: Default::default(),
+            map_c_trampolines: Default::default(),
+            map_locations: Default::default(),
+            location_ty: Default::default(),
 
             drop_trait: Default::default(),
-        }
+            fmt_write_vtable: Default::default(),
+        };
+
+        vm.host.register_defaults();
+
+        vm
+    }
+
+    /// Provide a native function for extern items with the given ABI and symbol name.
+    /// Must be registered before the items are first used.
+    pub fn register_extern(&self, abi: FunctionAbi, symbol: &str, native: NativeFunc) {
+        self.host.register_extern(abi, symbol, native);
+    }
+
+    /// Provide the address of an extern static.
+    pub fn register_extern_static(&self, abi: FunctionAbi, symbol: &str, ptr: *const u8) {
+        self.host.register_extern_static(abi, symbol, ptr);
     }
 
     pub fn setup_common_types() {
@@ -329,13 +872,21 @@ impl<'vm> VM<'vm> {
     pub fn make_thread(&'vm self) -> VMThread<'vm> {
         let stack = {
             let mut stack_pool = self.stack_pool.lock().unwrap();
-            // 1M stack
-            stack_pool.pop().unwrap_or_else(|| vec![0; 65536])
+            stack_pool
+                .pop()
+                .unwrap_or_else(|| vec![0; self.options.stack_size / 16])
         };
         VMThread {
             vm: self,
+            stack_end: stack.as_ptr() as usize + stack.len() * 16,
             stack,
+            segments: Vec::new(),
+            segments_used: 0,
+            host_segments: Vec::new(),
+            host_segments_used: 0,
+            host_limit: host_stack::call_limit(),
             drop_flags: Vec::new(),
+            thread_locals: ThreadLocals::for_current_thread(self as *const _ as usize),
         }
     }
 
@@ -345,18 +896,23 @@ impl<'vm> VM<'vm> {
     /// Got it working, and then had it break again when transitioning off THIR.
     ///
     /// To hell with it. Just require a static VM to use a rustc worker.
-    pub fn add_provider_auto(&'static self, worker_config: RustCWorkerConfig) -> CrateId {
+    ///
+    /// Fails if the crate has errors, which rustc will have printed.
+    pub fn add_provider_auto(
+        &'static self,
+        worker_config: RustCWorkerConfig,
+    ) -> Result<CrateId, String> {
         let mut crates = self.crates.write().unwrap();
         let crate_id = CrateId::new(crates.len() as u32);
 
-        // attempt to load cached IR -- but not if we want to save
-        if !worker_config.save_file {
+        // attempt to load cached IR -- but not if we want to save, or need rustc to write metadata
+        if !worker_config.save_file && worker_config.metadata_out.is_none() {
             match CacheProvider::new(&worker_config, self, crate_id) {
                 Ok(provider) => {
                     let worker_ref = self.arena_crates.alloc(Box::new(provider));
                     crates.push(worker_ref);
 
-                    return crate_id;
+                    return Ok(crate_id);
                 }
                 Err(_) => {
                     if worker_config.crate_path.is_internal() {
@@ -376,12 +932,12 @@ impl<'vm> VM<'vm> {
             }
         }
 
-        let worker = Box::new(RustCWorker::new(worker_config, self, crate_id));
+        let worker = Box::new(RustCWorker::new(worker_config, self, crate_id)?);
 
         let worker_ref = self.arena_crates.alloc(worker);
         crates.push(worker_ref);
 
-        crate_id
+        Ok(crate_id)
     }
 
     pub fn crate_provider(&self, crate_id: CrateId) -> &'vm Box<dyn CrateProvider<'vm>> {
@@ -402,7 +958,7 @@ impl<'vm> VM<'vm> {
         };
 
         if let FunctionSource::Item(item) = source {
-            let extern_native = get_extern_fn(item);
+            let extern_native = get_extern_fn(item, self);
 
             if let Some(extern_native) = extern_native {
                 func.set_native(extern_native);
@@ -412,8 +968,85 @@ impl<'vm> VM<'vm> {
         self.arena_functions.alloc(func)
     }
 
-    pub fn static_value(&'vm self, static_ref: &ItemWithSubs<'vm>) -> *mut u8 {
-        if let Some(res) = get_extern_static(static_ref.item) {
+    pub fn alloc_native_function(
+        &'vm self,
+        name: &'vm str,
+        native: NativeFunc,
+    ) -> &'vm Function<'vm> {
+        let func = self.alloc_function(FunctionSource::Native(name), SubList { list: vec![] });
+        func.set_native(native);
+        func
+    }
+
+    /// Variadic C functions need a separate native function for each list of argument types
+    /// they are called with.
+    pub fn variadic_function(
+        &'vm self,
+        item: &'vm Item<'vm>,
+        sig: FunctionSig<'vm>,
+    ) -> &'vm Function<'vm> {
+        let (_, name) = item.get_extern().expect("variadic function is not extern");
+
+        let mut map_variadic_functions = self.map_variadic_functions.lock().unwrap();
+        map_variadic_functions
+            .entry((item, sig))
+            .or_insert_with_key(|(_, sig)| {
+                let native = c_function(name, sig, self);
+                self.alloc_native_function(self.alloc_path(name), native)
+            })
+    }
+
+    /// Get a function which calls the C function pointer stored at `ptr_offset` in its frame.
+    pub fn c_pointer_caller(
+        &'vm self,
+        sig: FunctionSig<'vm>,
+        ptr_offset: i32,
+    ) -> &'vm Function<'vm> {
+        let mut map_c_pointer_callers = self.map_c_pointer_callers.lock().unwrap();
+        map_c_pointer_callers
+            .entry((sig, ptr_offset))
+            .or_insert_with_key(|(sig, ptr_offset)| {
+                let target = ThunkTarget::FrameSlot(*ptr_offset);
+                let native = ffi::build_thunk(target, &sig.inputs, sig.output)
+                    .unwrap_or_else(|err| panic!("cannot call C function pointer: {}", err));
+                self.alloc_native_function("[c_pointer_call]", native)
+            })
+    }
+
+    /// Get a native function with the C calling convention, which calls an interpreted function.
+    /// Foreign functions are already native, and their own address is used.
+    pub fn c_trampoline(
+        &'vm self,
+        func: &'vm Function<'vm>,
+        sig: &FunctionSig<'vm>,
+    ) -> *const std::ffi::c_void {
+        if let FunctionSource::Item(item) = func.source {
+            if let Some((FunctionAbi::C, name)) = item.get_extern() {
+                if let Some(func_ptr) = self.native_libs.find_symbol(name) {
+                    return func_ptr;
+                }
+            }
+        }
+
+        let mut map_c_trampolines = self.map_c_trampolines.lock().unwrap();
+        let res = map_c_trampolines
+            .entry(func as *const _ as usize)
+            .or_insert_with(|| {
+                let res = ffi::build_trampoline(self, func, &sig.inputs, sig.output);
+                let trampoline = res.unwrap_or_else(|err| {
+                    panic!(
+                        "cannot make {} callable from C: {}",
+                        func.source.debug_name(),
+                        err
+                    )
+                });
+                trampoline as usize
+            });
+        *res as *const _
+    }
+
+    pub fn static_value(&'vm self, static_ref: &ItemWithSubs<'vm>, ty: Type<'vm>) -> *mut u8 {
+        if let Some(res) = get_extern_static(static_ref.item, ty, self) {
             res
         } else {
             static_ref.item.static_value(&static_ref.subs)
@@ -444,6 +1077,42 @@ impl<'vm> VM<'vm> {
         ClosureRef::new(closure)
     }
 
+    pub fn set_location_ty(&self, loc_ty: Type<'vm>) {
+        self.location_ty.get_or_init(|| loc_ty);
+    }
+
+    /// Get a `core::panic::Location` for a source position, as returned by `caller_location`.
+    pub fn alloc_location(&'vm self, file: &'vm str, span: Span) -> *const u8 {
+        let loc_ty = *self.location_ty.get().expect("location type not set");
+        let mut map_locations = self.map_locations.lock().unwrap();
+        let ptr = map_locations.entry((file, span)).or_insert_with(|| {
+            let (file, span) = if span.is_none() {
+                ("<unknown>", span)
+            } else {
+                (file, span)
+            };
+
+            let loc_layout = loc_ty.layout();
+            let offsets = loc_layout.field_offsets.assert_single();
+            let ptr_size = POINTER_SIZE.bytes() as usize;
+
+            // file: &str, line: u32, col: u32
+            let mut loc_bytes = vec![0; loc_layout.assert_size() as usize];
+            let file_offset = offsets[0] as usize;
+            loc_bytes[file_offset..file_offset + ptr_size]
+                .copy_from_slice(&(file.as_ptr() as usize).to_ne_bytes());
+            loc_bytes[file_offset + ptr_size..file_offset + ptr_size * 2]
+                .copy_from_slice(&file.len().to_ne_bytes());
+            let line_offset = offsets[1] as usize;
+            loc_bytes[line_offset..line_offset + 4].copy_from_slice(&span.line.to_ne_bytes());
+            let col_offset = offsets[2] as usize;
+            loc_bytes[col_offset..col_offset + 4].copy_from_slice(&span.col.to_ne_bytes());
+
+            self.alloc_constant(loc_bytes).as_ptr() as usize
+        });
+        *ptr as *const u8
+    }
+
     pub fn alloc_path(&'vm self, path: &str) -> &'vm str {
         let mut map_paths = self.map_paths.lock().unwrap();
         if let Some(existing) = map_paths.get(path) {
@@ -455,62 +1124,143 @@ impl<'vm> VM<'vm> {
         }
     }
 
+    /// The trait's subs are its generic arguments, without the self type.
     pub fn find_vtable(
         &'vm self,
         trait_item: &'vm Item<'vm>,
+        trait_subs: &SubList<'vm>,
         primary_ty: Type<'vm>,
     ) -> &'vm VTable<'vm> {
-        let for_tys = SubList {
+        let mut for_tys = SubList {
             list: vec![Sub::Type(primary_ty)],
         };
+        for_tys.list.extend(trait_subs.list.iter().cloned());
 
         assert!(for_tys.is_concrete());
 
-        let mut map_vtables = self.map_vtables.lock().unwrap();
+        let key = (trait_item, for_tys);
+        if let Some(vtable) = self.map_vtables.lock().unwrap().get(&key) {
+            return vtable;
+        }
+        let for_tys = &key.1;
+
+        // built without holding the lock: finding methods can lower IR, which may need other
+        // vtables. If another thread finishes first, its vtable is used instead.
+        let TraitImplResult::Static(impl_result) = trait_item.find_trait_impl(for_tys) else {
+            panic!(
+                "cannot build vtable from trait {} for {}",
+                trait_item.path.as_string(),
+                primary_ty
+            );
+        };
 
-        map_vtables
-            .entry((trait_item, for_tys.clone()))
-            .or_insert_with(|| {
-                let TraitImplResult::Static(impl_result) = trait_item.find_trait_impl(&for_tys)
-                else {
-                    panic!(
-                        "cannot build vtable from trait {} for {}",
-                        trait_item.path.as_string(),
-                        primary_ty
-                    );
+        let impl_crate = self.crate_provider(impl_result.crate_id);
+
+        // TODO include methods in the base trait def?
+        // can specialization cause issues?
+        let methods: Vec<_> = impl_result
+            .assoc_values
+            .iter()
+            .enumerate()
+            .map(|(member_index, val)| {
+                let (item, subs) = match val {
+                    Some(AssocValue::Item(item_id)) => {
+                        (impl_crate.item_by_id(*item_id), &impl_result.impl_subs)
+                    }
+                    Some(AssocValue::Type(_)) => return None,
+                    None | Some(AssocValue::RawFunctionIR(..)) => {
+                        // provided or builtin method, resolved through the trait
+                        let member = trait_item
+                            .trait_member(member_index as u32)
+                            .expect("missing trait member");
+                        (member, for_tys)
+                    }
                 };
+                if !item.is_function() {
+                    return None;
+                }
 
-                let impl_crate = self.crate_provider(impl_result.crate_id);
-
-                // TODO include methods in the base trait def?
-                // can specialization cause issues?
-                let methods: Vec<_> = impl_result
-                    .assoc_values
-                    .iter()
-                    .map(|val| match val {
-                        Some(AssocValue::Item(item_id)) => {
-                            let item = impl_crate.item_by_id(*item_id);
-                            if item.is_function() {
-                                Some(item.func_mono(&impl_result.impl_subs))
-                            } else {
-                                None
-                            }
-                        }
-                        _ => {
-                            println!("{:?}", val);
-                            panic!();
-                        }
-                    })
-                    .collect();
-
-                let layout = primary_ty.layout();
-
-                self.arena_vtables.alloc(VTable {
-                    size: layout.assert_size(),
-                    align: layout.align,
-                    methods,
-                })
+                let func = item.func_mono(subs);
+                // stable code only calls methods taking self by value through a vtable for
+                // `Box<dyn FnOnce>`, which is builtin. Other methods can be generic, and their
+                // signatures can't be resolved without their own subs.
+                if let Some(AssocValue::RawFunctionIR(..)) = val {
+                    let sig = item.func_sig(subs);
+                    if takes_self_by_value(&sig, primary_ty) {
+                        return Some(self.vtable_shim(func, &sig));
+                    }
+                }
+                Some(func)
             })
+            .collect();
+
+        let layout = primary_ty.layout();
+        let drop = primary_ty.drop_info().glue().map(|glue| glue.function());
+
+        let vtable = self.arena_vtables.alloc(VTable {
+            size: layout.assert_size(),
+            align: layout.align,
+            drop,
+            methods,
+        });
+
+        let mut map_vtables = self.map_vtables.lock().unwrap();
+        map_vtables.entry(key).or_insert(vtable)
+    }
+
+    /// Trait objects pass methods a pointer to their receiver, even when the method takes `self`
+    /// by value. The shim moves the receiver out of that pointer, then calls the method.
+    fn vtable_shim(
+        &'vm self,
+        method: &'vm Function<'vm>,
+        sig: &FunctionSig<'vm>,
+    ) -> &'vm Function<'vm> {
+        let self_ty = sig.inputs[0];
+        let ptr_ty = self_ty.ref_to(Mutability::Mut);
+
+        let mut stack = CompilerStack::new();
+        let out_slot = stack.alloc_no_drop(sig.output);
+        let self_ptr_slot = stack.alloc_no_drop(ptr_ty);
+        let arg_slots: Vec<_> = sig.inputs[1..]
+            .iter()
+            .map(|ty| stack.alloc_no_drop(*ty))
+            .collect();
+
+        let call_slot = stack.align_for_call();
+        let call_out_slot = stack.alloc_no_drop(sig.output);
+        assert_eq!(call_slot, call_out_slot);
+        let call_self_slot = stack.alloc_no_drop(self_ty);
+        let call_arg_slots: Vec<_> = sig.inputs[1..]
+            .iter()
+            .map(|ty| stack.alloc_no_drop(*ty))
+            .collect();
+
+        let mut code = Vec::new();
+        code.extend(bytecode_select::copy_from_ptr(
+            call_self_slot,
+            self_ptr_slot,
+            self_ty,
+            0,
+        ));
+        for ((dst, src), ty) in call_arg_slots.iter().zip(&arg_slots).zip(&sig.inputs[1..]) {
+            code.extend(bytecode_select::copy(*dst, *src, *ty));
+        }
+        code.push(Instr::Call(call_slot, method));
+        code.extend(bytecode_select::copy(out_slot, call_out_slot, sig.output));
+        code.push(Instr::Return);
+
+        let bc = self.alloc_bytecode(FunctionBytecode {
+            code: CompactCode::new(code),
+            drops: Vec::new(),
+            frame_size: stack.frame_size(),
+            spans: SpanTable::default(),
+            track_caller: false,
+            locals: Vec::new(),
+            threaded: Default::default(),
+        });
+        let name = self.alloc_path(&format!("<vtable shim {:?}>", method));
+
+        self.alloc_function(FunctionSource::RawBytecode(bc, name), SubList::empty())
     }
 
     pub fn find_drop(&self, ty: Type<'vm>) -> Option<&'vm Item<'vm>> {
@@ -545,6 +1295,68 @@ impl<'vm> VM<'vm> {
         }
     }
 
+    /// Get a function from core. Note that const fns have a hidden `host` const parameter.
+    pub fn core_function(&'vm self, path: &'static str, subs: SubList<'vm>) -> &'vm Function<'vm> {
+        let core_id = *self.core_crate.get().expect("no core crate");
+        let core_provider = self.crate_provider(core_id);
+
+        let item = core_provider
+            .item_by_path(&ItemPath::for_value(path))
+            .unwrap_or_else(|| panic!("missing core function: {}", path));
+
+        item.func_mono(&subs)
+    }
+
+    /// Whether arithmetic in a function from the given crate should panic on overflow.
+    /// Only the program being run follows `--debug-profile`, the standard library is unchecked.
+    /// Its `#[rustc_inherit_overflow_checks]` functions, like the operator impls for integers,
+    /// follow the program instead, as rustc does when they are instantiated in it.
+    pub fn overflow_checks(&self, crate_id: CrateId, inherit: bool) -> bool {
+        self.options.debug_profile
+            && (inherit
+                || (self.core_crate.get() != Some(&crate_id)
+                    && self.alloc_crate.get() != Some(&crate_id)
+                    && self.std_crate.get() != Some(&crate_id)))
+    }
+
+    /// Get an ADT from core, with all lifetime parameters erased.
+    pub fn core_adt(&'vm self, path: &'static str, lifetimes: usize) -> Type<'vm> {
+        let core_id = *self.core_crate.get().expect("no core crate");
+        let core_provider = self.crate_provider(core_id);
+
+        let item = core_provider
+            .item_by_path(&ItemPath::for_type(path))
+            .unwrap_or_else(|| panic!("missing core type: {}", path));
+
+        self.ty_adt(ItemWithSubs {
+            item,
+            subs: SubList {
+                list: vec![Sub::Lifetime; lifetimes],
+            },
+        })
+    }
+
+    /// A vtable for `dyn core::fmt::Write`, which writes to a host `String`.
+    pub fn fmt_write_vtable(&'vm self) -> &'vm VTable<'vm> {
+        self.fmt_write_vtable.get_or_init(|| {
+            let write_str = self.alloc_native_function("[fmt_write_str]", builtin_fmt_write_str);
+            let write_char = self.alloc_native_function("[fmt_write_char]", builtin_fmt_write_char);
+
+            self.arena_vtables.alloc(VTable {
+                size: std::mem::size_of::<String>() as u32,
+                align: std::mem::align_of::<String>() as u32,
+                // only ever borrowed
+                drop: None,
+                // write_fmt is not provided, nothing should call it directly through the vtable
+                methods: vec![Some(write_str), Some(write_char), None],
+            })
+        })
+    }
+
+    pub(super) fn debugger(&self) -> Option<&Debugger> {
+        self.debugger.as_ref()
+    }
+
     pub fn common_types(&'vm self) -> &CommonTypes<'vm> {
         self.common_types.get_or_init(|| CommonTypes::new(self))
     }
@@ -596,6 +1408,7 @@ pub enum FunctionSource<'vm> {
     Item(&'vm Item<'vm>),
     Closure(&'vm Closure<'vm>),
     RawBytecode(&'vm FunctionBytecode<'vm>, &'vm str),
+    Native(&'vm str),
 }
 
 impl<'vm> FunctionSource<'vm> {
@@ -604,6 +1417,7 @@ impl<'vm> FunctionSource<'vm> {
             Self::Item(item) => item.vm,
             Self::Closure(closure) => closure.vm,
             Self::RawBytecode(..) => panic!("raw bytecode has no vm"),
+            Self::Native(..) => panic!("native function has no vm"),
         }
     }
 
@@ -612,14 +1426,16 @@ impl<'vm> FunctionSource<'vm> {
             Self::Item(item) => item.ir(subs),
             Self::Closure(closure) => (closure.ir_base(), Cow::Borrowed(subs)),
             Self::RawBytecode(..) => panic!("raw bytecode has no ir"),
+            Self::Native(..) => panic!("native function has no ir"),
         }
     }
 
     pub fn debug_name(&self) -> &str {
         match self {
             Self::Item(item) => item.path.as_string(),
-            Self::Closure(_) => "[closure]",
+            Self::Closure(closure) => closure.def_full_path,
             Self::RawBytecode(_, name) => name,
+            Self::Native(name) => name,
         }
     }
 
@@ -628,6 +1444,17 @@ impl<'vm> FunctionSource<'vm> {
             Self::Item(item) => item.func_has_ir(),
             Self::Closure(..) => true,
             Self::RawBytecode(..) => false,
+            Self::Native(..) => false,
+        }
+    }
+
+    pub fn overflow_checks(&self, ir: &IRFunction<'vm>) -> bool {
+        let inherit = ir.inherit_overflow_checks;
+        match self {
+            Self::Item(item) => item.vm.overflow_checks(item.crate_id, inherit),
+            Self::Closure(closure) => closure.vm.overflow_checks(closure.def_crate_id, inherit),
+            Self::RawBytecode(..) => false,
+            Self::Native(..) => false,
         }
     }
 }
@@ -641,9 +1468,26 @@ pub struct Function<'vm> {
     bytecode: AtomicPtr<FunctionBytecode<'vm>>,
 }
 
-pub type NativeFunc = unsafe extern "C" fn(*mut u8, &VMThread);
+pub type NativeFunc = unsafe extern "C-unwind" fn(*mut u8, &VMThread);
 
 impl<'vm> Function<'vm> {
+    /// The function's path with its substitutions, for diagnostics.
+    pub fn debug_name(&self) -> String {
+        if self.subs.list.is_empty() {
+            self.source.debug_name().to_owned()
+        } else {
+            format!("{}{}", self.source.debug_name(), self.subs)
+        }
+    }
+
+    /// The path of the item this function was instantiated from, if it has one.
+    pub fn item_path(&self) -> Option<&'vm ItemPath<'vm>> {
+        match self.source {
+            FunctionSource::Item(item) => Some(&item.path),
+            _ => None,
+        }
+    }
+
     pub fn get_native(&self) -> Option<NativeFunc> {
         let raw = self.native.load(Ordering::Acquire);
         if raw.is_null() {
@@ -675,15 +1519,24 @@ impl<'vm> Function<'vm> {
         if let Some(native) = self.get_native() {
             Ok(native)
         } else {
-            let res = simple_jit::compile(self.bytecode());
-            if let Ok(native) = res {
-                self.set_native(native);
-            }
-            res
+            let native = simple_jit::compile(self.bytecode())?;
+
+            // another thread may have compiled the function first, keep its version
+            self.native
+                .compare_exchange(
+                    std::ptr::null_mut(),
+                    native as *mut _,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .ok();
+            Ok(self.get_native().unwrap())
         }
     }
 
-    fn bytecode(&self) -> &'vm FunctionBytecode<'vm> {
+    /// Get bytecode for the function, compiling it if needed. Several threads may compile the same
+    /// function at once: the first to finish stores its bytecode, which all of them then use.
+    pub(crate) fn bytecode(&self) -> &'vm FunctionBytecode<'vm> {
         loop {
             if let Some(bc) = self.get_bytecode() {
                 return bc;
@@ -696,11 +1549,32 @@ impl<'vm> Function<'vm> {
                 let (ir, new_subs) = self.source.ir(&self.subs);
                 let path = self.source.debug_name();
 
-                let bc = BytecodeCompiler::compile(vm, &ir, &new_subs, path, &self.subs);
+                let overflow_checks = self.source.overflow_checks(&ir);
+
+                let bc = BytecodeCompiler::compile(
+                    vm,
+                    &ir,
+                    &new_subs,
+                    path,
+                    &self.subs,
+                    overflow_checks,
+                );
+                if let Some(pattern) = &vm.options.dump_bytecode {
+                    if glob_matches(pattern, path) {
+                        eprint!("{}", disassemble(&self.debug_name(), &bc));
+                    }
+                }
                 vm.alloc_bytecode(bc)
             };
 
-            self.set_bytecode(bc);
+            self.bytecode
+                .compare_exchange(
+                    std::ptr::null_mut(),
+                    bc as *const _ as _,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .ok();
         }
     }
 }
@@ -708,13 +1582,36 @@ impl<'vm> Function<'vm> {
 pub struct VTable<'vm> {
     size: u32,
     align: u32,
-    // TODO drop???
+    drop: Option<&'vm Function<'vm>>,
     // currently only stores methods of the primary trait -- no super traits
     methods: Vec<Option<&'vm Function<'vm>>>,
 }
 
+impl<'vm> VTable<'vm> {
+    /// The method at `index` in the primary trait, if it can be called through the vtable.
+    pub fn method(&self, index: usize) -> Option<&'vm Function<'vm>> {
+        self.methods.get(index).copied().flatten()
+    }
+}
+
+/// Does a method reached through a vtable for `self_ty` take its receiver by value?
+fn takes_self_by_value<'vm>(sig: &FunctionSig<'vm>, self_ty: Type<'vm>) -> bool {
+    let Some(receiver_ty) = sig.inputs.first() else {
+        return false;
+    };
+    match self_ty.kind() {
+        // builtin closure methods take the closure's environment, or a reference to it
+        TypeKind::Closure(..) => !matches!(receiver_ty.kind(), TypeKind::Ref(..)),
+        _ => *receiver_ty == self_ty,
+    }
+}
+
 impl<'vm> std::fmt::Debug for Function<'vm> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Function(\"{}{}\")", self.source.debug_name(), self.subs)
     }
 }
+
+include!(concat!(env!("OUT_DIR"), "/exec_compact_fallback.rs"));
+#[cfg(feature = "threaded-dispatch")]
+include!(concat!(env!("OUT_DIR"), "/exec_threaded.rs"));
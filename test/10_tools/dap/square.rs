@@ -0,0 +1,10 @@
+fn square(n: u32) -> u32 {
+    let result = n * n;
+    result
+}
+
+fn main() {
+    let a = square(3);
+    let b = square(a);
+    println!("{} {}", a, b);
+}
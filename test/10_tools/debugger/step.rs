@@ -0,0 +1,16 @@
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+fn add(a: i32, b: i32) -> i32 {
+    let sum = a + b;
+    sum * 2
+}
+
+fn main() {
+    let p = Point { x: 3, y: 4 };
+    let total = add(p.x, p.y);
+    let twice = add(total, total);
+    println!("{} {}", total, twice);
+}
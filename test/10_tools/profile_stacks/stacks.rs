@@ -0,0 +1,38 @@
+// Every function loops without calling into libraries, so each one has some self time and its
+// stacks are always written.
+
+fn spin(n: u32) -> u32 {
+    let mut x = 0;
+    let mut i = 0;
+    while i < n {
+        x = (x + i) & 0xffff;
+        i += 1;
+    }
+    x
+}
+
+fn inner() -> u32 {
+    spin(100_000) + spin(50_000)
+}
+
+fn outer() -> u32 {
+    let mut x = 0;
+    let mut i = 0;
+    while i < 100_000 {
+        x = (x ^ i) & 0xffff;
+        i += 1;
+    }
+    x + inner()
+}
+
+fn main() {
+    let mut x = 0;
+    let mut i = 0;
+    while i < 100_000 {
+        x = (x + i * 3) & 0xffff;
+        i += 1;
+    }
+    if x + outer() == 1 {
+        panic!();
+    }
+}
@@ -0,0 +1,78 @@
+// shifts and widens of constants, at every width
+
+macro_rules! shifts {
+    ($name:ident, $ty:ty, $a:expr) => {
+        fn $name() -> [$ty; 7] {
+            let a: $ty = $a;
+            let n = 3;
+            let m = <$ty>::BITS - 1;
+            [a << 1, a >> 1, a << n, a >> n, a >> m, a << m, (a >> 2) << 2]
+        }
+    };
+}
+
+shifts!(shifts_i8, i8, -100);
+shifts!(shifts_u8, u8, 0xB7);
+shifts!(shifts_i16, i16, -12345);
+shifts!(shifts_u16, u16, 0xBEEF);
+shifts!(shifts_i32, i32, -123456789);
+shifts!(shifts_u32, u32, 0xDEAD_BEEF);
+shifts!(shifts_i64, i64, -1234567890123456789);
+shifts!(shifts_u64, u64, 0xDEAD_BEEF_CAFE_F00D);
+shifts!(shifts_i128, i128, -(1 << 100) - 12345);
+shifts!(shifts_u128, u128, 0xDEAD_BEEF_CAFE_F00D_0123_4567_89AB_CDEF);
+
+fn widen_signed() -> (i16, i32, i64, i128, i32, i64, i128, i64, i128, i128) {
+    let a: i8 = -7;
+    let b: i16 = -300;
+    let c: i32 = -70000;
+    let d: i64 = -5_000_000_000;
+    (
+        a as i16, a as i32, a as i64, a as i128, b as i32, b as i64, b as i128, c as i64,
+        c as i128, d as i128,
+    )
+}
+
+fn widen_unsigned() -> (u16, u32, u64, u128, u32, u64, u128, u64, u128, u128) {
+    let a: u8 = 200;
+    let b: u16 = 60000;
+    let c: u32 = 4_000_000_000;
+    let d: u64 = 18_000_000_000_000_000_000;
+    (
+        a as u16, a as u32, a as u64, a as u128, b as u32, b as u64, b as u128, c as u64,
+        c as u128, d as u128,
+    )
+}
+
+fn widen_mixed() -> (u32, i64, u128, i128) {
+    let a: i8 = -1;
+    let b: u16 = 0xFFFF;
+    let c: i32 = i32::MIN;
+    let d: u64 = u64::MAX;
+    (a as u32, b as i64, c as u128, d as i128)
+}
+
+fn folded_compare() -> [bool; 6] {
+    let a: i8 = -1;
+    let b: u8 = 255;
+    let c: i128 = -1;
+    let d: u128 = 1 << 127;
+    [a < 0, b > 0, (a as u8) == b, c < 0, (c as u128) > d, d >> 127 == 1]
+}
+
+fn main() {
+    println!("{:?}", shifts_i8());
+    println!("{:?}", shifts_u8());
+    println!("{:?}", shifts_i16());
+    println!("{:?}", shifts_u16());
+    println!("{:?}", shifts_i32());
+    println!("{:?}", shifts_u32());
+    println!("{:?}", shifts_i64());
+    println!("{:?}", shifts_u64());
+    println!("{:?}", shifts_i128());
+    println!("{:?}", shifts_u128());
+    println!("{:?}", widen_signed());
+    println!("{:?}", widen_unsigned());
+    println!("{:?}", widen_mixed());
+    println!("{:?}", folded_compare());
+}
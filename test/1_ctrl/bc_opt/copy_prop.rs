@@ -0,0 +1,65 @@
+// copies of a slot whose address is taken can't be propagated past writes through the pointer
+
+fn bump(x: &mut i32) {
+    *x += 10;
+}
+
+fn through_ref() -> i32 {
+    let mut a = 1;
+    let b = a;
+    let p = &mut a;
+    *p = 5;
+    let c = a;
+    b * 100 + c
+}
+
+fn through_call() -> (i32, i32) {
+    let mut x = 10;
+    let y = x;
+    bump(&mut x);
+    let z = x;
+    (y, z)
+}
+
+fn through_raw(n: i32) -> i32 {
+    let mut x = n;
+    let ptr = &mut x as *mut i32;
+    let before = x;
+    unsafe {
+        *ptr += 1;
+    }
+    let after = x;
+    let copy = after;
+    unsafe {
+        *ptr *= 3;
+    }
+    before * 10000 + after * 100 + copy + x
+}
+
+fn field_ref() -> (u8, u8) {
+    let mut pair = (1u8, 2u8);
+    let first = pair.0;
+    let second = &mut pair.1;
+    *second = first + 40;
+    let r = &mut pair;
+    r.0 = 9;
+    (pair.0, pair.1)
+}
+
+fn chain(n: u64) -> u64 {
+    let a = n;
+    let b = a;
+    let c = b;
+    let mut d = c;
+    d += 1;
+    let e = d;
+    a + b + c + d + e
+}
+
+fn main() {
+    println!("{}", through_ref());
+    println!("{:?}", through_call());
+    println!("{}", through_raw(4));
+    println!("{:?}", field_ref());
+    println!("{}", chain(7));
+}
@@ -0,0 +1,65 @@
+// stores are only dead if nothing, including a call, can read them before they are overwritten
+
+fn record(x: i32) -> i32 {
+    println!("record {}", x);
+    x + 1
+}
+
+fn observe(x: &i32) {
+    println!("observe {}", x);
+}
+
+fn log() -> i32 {
+    println!("log");
+    3
+}
+
+fn around_calls(n: i32) -> i32 {
+    let mut x = n * 2;
+    x = n + 1;
+    let mut y = 0;
+    y = record(y + x);
+    let mut z = 7;
+    observe(&z);
+    z = 8;
+    log();
+    let _ = log();
+    x + y + z
+}
+
+fn overwritten_in_branch(n: i32) -> i32 {
+    let mut x = 1;
+    if n > 3 {
+        x = record(n);
+    }
+    x = x * 2;
+    x
+}
+
+fn held_pointer(n: i32) -> i32 {
+    let mut x = n;
+    let p = &x as *const i32;
+    x = 50;
+    let seen = unsafe { *p };
+    x = 60;
+    seen + x
+}
+
+fn in_loop(n: i32) -> i32 {
+    let mut last = -1;
+    let mut i = 0;
+    while i < n {
+        last = i * i;
+        i += 1;
+    }
+    last
+}
+
+fn main() {
+    println!("{}", around_calls(5));
+    println!("{}", overwritten_in_branch(2));
+    println!("{}", overwritten_in_branch(9));
+    println!("{}", held_pointer(4));
+    println!("{}", in_loop(6));
+    println!("{}", in_loop(0));
+}
@@ -0,0 +1,67 @@
+// values are dropped exactly as without the optimizer, including ones moved conditionally
+
+struct Noisy(&'static str);
+
+impl Drop for Noisy {
+    fn drop(&mut self) {
+        println!("drop {}", self.0);
+    }
+}
+
+fn consume(n: Noisy) {
+    println!("consume {}", n.0);
+}
+
+fn conditional_move(flag: bool) {
+    let a = Noisy("a");
+    let b = Noisy("b");
+    if flag {
+        consume(a);
+    }
+    let c = b;
+    println!("end {}", c.0);
+}
+
+fn reassign() {
+    let mut x = Noisy("x1");
+    println!("{}", x.0);
+    x = Noisy("x2");
+    println!("{}", x.0);
+    let y = x;
+    x = Noisy("x3");
+    println!("{} {}", x.0, y.0);
+}
+
+fn moves_in_loop(n: u32) {
+    let mut held = Some(Noisy("held"));
+    for i in 0..n {
+        if i == 1 {
+            if let Some(h) = held.take() {
+                consume(h);
+            }
+        }
+        let _tmp = Noisy("tmp");
+    }
+    println!("held is some: {}", held.is_some());
+}
+
+fn early_return(flag: bool) -> u32 {
+    let a = Noisy("early a");
+    if flag {
+        return 1;
+    }
+    let b = Noisy("early b");
+    let moved = a;
+    println!("{} {}", moved.0, b.0);
+    2
+}
+
+fn main() {
+    conditional_move(true);
+    conditional_move(false);
+    reassign();
+    moves_in_loop(0);
+    moves_in_loop(3);
+    println!("{}", early_return(true));
+    println!("{}", early_return(false));
+}
@@ -0,0 +1,109 @@
+// jumps to jumps, from nested loops, labelled breaks and chains of conditions
+
+fn nested(n: u32) -> u32 {
+    let mut total = 0;
+    let mut i = 0;
+    'outer: loop {
+        i += 1;
+        if i > n {
+            break;
+        }
+        let mut j = 0;
+        while j < i {
+            j += 1;
+            if j == 3 {
+                continue;
+            }
+            if i * j > 20 {
+                continue 'outer;
+            }
+            if i + j == 11 {
+                break 'outer;
+            }
+            total += j;
+        }
+    }
+    total * 1000 + i
+}
+
+fn chain(x: i32) -> i32 {
+    if x < 0 {
+        if x < -10 {
+            -2
+        } else {
+            -1
+        }
+    } else if x == 0 {
+        0
+    } else if x < 10 {
+        if x % 2 == 0 {
+            2
+        } else {
+            1
+        }
+    } else {
+        10
+    }
+}
+
+fn matches(x: u8) -> &'static str {
+    match x {
+        0 => "zero",
+        1 | 2 | 3 => "small",
+        4..=9 => "digit",
+        _ if x % 2 == 0 => "even",
+        _ => "odd",
+    }
+}
+
+fn constant_condition() -> i32 {
+    let always = true;
+    let never = false;
+    let mut c = 0;
+    if always {
+        c += 1;
+    }
+    if never {
+        c += 10;
+    }
+    while never {
+        c += 100;
+    }
+    let mut i = 0;
+    loop {
+        if always {
+            i += 1;
+        }
+        if i == 4 {
+            break;
+        }
+    }
+    c + i
+}
+
+fn empty_blocks(n: i32) -> i32 {
+    let mut c = 0;
+    for i in 0..n {
+        if i > 2 {
+        } else {
+        }
+        if i == 1 {
+        } else if i == 2 {
+            c += 1;
+        } else {
+        }
+    }
+    c
+}
+
+fn main() {
+    println!("{} {} {}", nested(3), nested(8), nested(20));
+    for x in [-20, -5, 0, 4, 7, 12] {
+        println!("{}", chain(x));
+    }
+    for x in [0, 2, 5, 10, 11] {
+        println!("{}", matches(x));
+    }
+    println!("{}", constant_condition());
+    println!("{}", empty_blocks(5));
+}
@@ -0,0 +1,130 @@
+mod _builtin;
+
+const LIMIT: u64 = 20;
+const BIG: u64 = 0xFFFF_FFFF;
+
+struct Pair {
+    a: i16,
+    b: u32,
+}
+
+fn count_u64(n: u64) -> i32 {
+    let mut i: u64 = 0;
+    let mut c = 0;
+    while i < LIMIT {
+        if i > n {
+            c += 1;
+        }
+        if n >= i {
+            c += 100;
+        }
+        i += 1;
+    }
+    c
+}
+
+fn signed(x: i8) -> i32 {
+    let mut c = 0;
+    if x < -1 {
+        c += 1;
+    }
+    if x <= 0 {
+        c += 10;
+    }
+    if x > 100 {
+        c += 100;
+    }
+    if x >= -128 {
+        c += 1000;
+    }
+    if x == 5 {
+        c += 10000;
+    }
+    if x != -7 {
+        c += 100000;
+    }
+    c
+}
+
+fn unsigned(x: u8) -> i32 {
+    let mut c = 0;
+    if x < 200 {
+        c += 1;
+    }
+    if x <= 255 {
+        c += 10;
+    }
+    if x > 127 {
+        c += 100;
+    }
+    if x >= 1 {
+        c += 1000;
+    }
+    c
+}
+
+fn wide(x: u64, y: i64) -> i32 {
+    let mut c = 0;
+    if x < BIG {
+        c += 1;
+    }
+    if x == u64::MAX {
+        c += 10;
+    }
+    if y < -3_000_000_000 {
+        c += 100;
+    }
+    if y > i32::MAX as i64 {
+        c += 1000;
+    }
+    c
+}
+
+fn fields(p: &Pair, x: i16, y: u32) -> i32 {
+    let mut c = 0;
+    if p.a < x {
+        c += 1;
+    }
+    if x < p.a {
+        c += 10;
+    }
+    if p.b >= y {
+        c += 100;
+    }
+    if y == p.b {
+        c += 1000;
+    }
+    c
+}
+
+fn step(mut x: i16, by: i16) -> i16 {
+    x += 7;
+    x -= by;
+    x -= -32768;
+    x
+}
+
+pub fn main() {
+    _builtin::print_int(count_u64(0) as _);
+    _builtin::print_int(count_u64(9) as _);
+    _builtin::print_int(count_u64(u64::MAX) as _);
+
+    for x in [-128i8, -7, -2, -1, 0, 5, 100, 101, 127] {
+        _builtin::print_int(signed(x) as _);
+    }
+    for x in [0u8, 1, 127, 128, 199, 200, 255] {
+        _builtin::print_int(unsigned(x) as _);
+    }
+
+    _builtin::print_int(wide(0, 0) as _);
+    _builtin::print_int(wide(BIG, -3_000_000_001) as _);
+    _builtin::print_int(wide(u64::MAX, 3_000_000_000) as _);
+
+    let p = Pair { a: -3, b: 40 };
+    _builtin::print_int(fields(&p, -4, 40) as _);
+    _builtin::print_int(fields(&p, 2, 41) as _);
+    _builtin::print_int(fields(&p, -3, 39) as _);
+
+    _builtin::print_int(step(10, 3) as _);
+    _builtin::print_int(step(-32000, 1000) as _);
+}
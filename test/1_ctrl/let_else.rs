@@ -0,0 +1,36 @@
+mod _builtin;
+
+fn first_even(values: &[i32]) -> i32 {
+    for &n in values {
+        let 0 = n % 2 else {
+            continue;
+        };
+        return n;
+    }
+    -1
+}
+
+fn unwrap_or_zero(x: Option<i32>) -> i32 {
+    let Some(n) = x else {
+        return 0;
+    };
+    n * 10
+}
+
+pub fn main() {
+    _builtin::print_int(unwrap_or_zero(Some(4)) as _);
+    _builtin::print_int(unwrap_or_zero(None) as _);
+    _builtin::print_int(first_even(&[3, 7, 8, 5]) as _);
+    _builtin::print_int(first_even(&[1]) as _);
+
+    let mut total = 0;
+    let mut i = 0;
+    loop {
+        i += 1;
+        let true = i < 5 else {
+            break;
+        };
+        total += i;
+    }
+    _builtin::print_int(total);
+}
@@ -0,0 +1,110 @@
+fn bytes(x: u8, y: i8) -> i32 {
+    let mut c = 0;
+    if x > 200 {
+        c += 1;
+    }
+    if x <= 7 {
+        c += 10;
+    }
+    if y < -100 {
+        c += 100;
+    }
+    if 3 >= y {
+        c += 1000;
+    }
+    c
+}
+
+fn halves(x: u16, y: i16) -> i32 {
+    let mut c = 0;
+    if x >= 60000 {
+        c += 1;
+    }
+    if y != -1 {
+        c += 10;
+    }
+    if 500 < x {
+        c += 100;
+    }
+    c
+}
+
+fn wide(x: u64, y: i64) -> i32 {
+    let mut c = 0;
+    // too big for an immediate
+    if x > 0x1_0000_0000 {
+        c += 1;
+    }
+    if y < -5_000_000_000 {
+        c += 10;
+    }
+    if x == 0xFFFF_FFFF {
+        c += 100;
+    }
+    if y > -2 {
+        c += 1000;
+    }
+    c
+}
+
+fn chars(ch: char, b: bool) -> i32 {
+    let mut c = 0;
+    if ch < 'm' {
+        c += 1;
+    }
+    if ch == 'z' {
+        c += 10;
+    }
+    if b != true {
+        c += 100;
+    }
+    c
+}
+
+#[allow(unused_comparisons)]
+fn literals() -> i32 {
+    let mut c = 0;
+    if 3 < 4 {
+        c += 1;
+    }
+    if 5 == 6 {
+        c += 10;
+    }
+    c
+}
+
+fn adds(x: u8, y: i32, z: u64) -> (u8, u8, i32, i32, u64, u64) {
+    let mut a = x;
+    a += 100;
+    let mut b = x;
+    b -= 3;
+    let mut w = y;
+    w -= -7;
+    (a, x + 250, w, y - 1000, z + 0x2_0000_0000, z - 1)
+}
+
+fn main() {
+    for (x, y) in [(0, 0), (7, -101), (201, 3), (255, 4), (8, -128)] {
+        println!("{}", bytes(x, y));
+    }
+    for (x, y) in [(0, -1), (501, 0), (60000, 1), (65535, -32768)] {
+        println!("{}", halves(x, y));
+    }
+    for (x, y) in [(0, -1), (0xFFFF_FFFF, -2), (0x1_0000_0001, -5_000_000_001), (u64::MAX, i64::MAX)] {
+        println!("{}", wide(x, y));
+    }
+    for (ch, b) in [('a', true), ('m', false), ('z', true)] {
+        println!("{}", chars(ch, b));
+    }
+    println!("{}", literals());
+    let mut i = 0u32;
+    let mut n = 0;
+    while i < 20 {
+        if i >= 10 {
+            n += i;
+        }
+        i += 3;
+    }
+    println!("{} {}", i, n);
+    println!("{:?}", adds(5, 10, 1));
+}
@@ -0,0 +1 @@
+include!("../bc_opt/const_fold.rs");
@@ -0,0 +1 @@
+include!("../bc_opt/copy_prop.rs");
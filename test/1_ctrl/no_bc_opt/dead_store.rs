@@ -0,0 +1 @@
+include!("../bc_opt/dead_store.rs");
@@ -0,0 +1 @@
+include!("../bc_opt/drop_flags.rs");
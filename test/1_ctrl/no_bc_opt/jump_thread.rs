@@ -0,0 +1 @@
+include!("../bc_opt/jump_thread.rs");
@@ -0,0 +1,36 @@
+fn collatz(mut n: u64) -> u32 {
+    let mut steps = 0;
+    while n != 1 {
+        n = if n % 2 == 0 { n / 2 } else { 3 * n + 1 };
+        steps += 1;
+    }
+    steps
+}
+
+fn wide(x: i128) -> i128 {
+    x * 0x1234_5678_9abc_def0_i128 - 170141183460469231731687303715884105727
+}
+
+fn main() {
+    let mut longest = (0, 0);
+    for n in 1..1000 {
+        let steps = collatz(n);
+        if steps > longest.1 {
+            longest = (n, steps);
+        }
+    }
+    println!("{:?}", longest);
+
+    println!("{}", wide(3));
+
+    let words = vec!["alpha", "beta", "gamma"];
+    let mut total = 0;
+    for (i, w) in words.iter().enumerate() {
+        match w.len() {
+            4 => total += i * 10,
+            5 => total += i,
+            _ => unreachable!(),
+        }
+    }
+    println!("{}", total);
+}
@@ -0,0 +1,78 @@
+use std::sync::atomic::*;
+
+mod _builtin;
+
+pub fn main() {
+    {
+        let x = AtomicU8::new(250);
+        _builtin::print_uint(x.fetch_add(10, Ordering::SeqCst) as _);
+        _builtin::print_uint(x.load(Ordering::Relaxed) as _);
+        _builtin::print_uint(x.fetch_sub(5, Ordering::AcqRel) as _);
+        _builtin::print_uint(x.swap(77, Ordering::Acquire) as _);
+        _builtin::print_uint(x.fetch_nand(0xF0, Ordering::Release) as _);
+        _builtin::print_uint(x.load(Ordering::SeqCst) as _);
+    }
+    {
+        let x = AtomicI16::new(-100);
+        _builtin::print_int(x.fetch_max(-200, Ordering::SeqCst) as _);
+        _builtin::print_int(x.fetch_max(300, Ordering::SeqCst) as _);
+        _builtin::print_int(x.fetch_min(-5, Ordering::Relaxed) as _);
+        _builtin::print_int(x.load(Ordering::SeqCst) as _);
+    }
+    {
+        let x = AtomicU32::new(0b1100);
+        _builtin::print_uint(x.fetch_and(0b1010, Ordering::SeqCst) as _);
+        _builtin::print_uint(x.fetch_or(0b0101, Ordering::SeqCst) as _);
+        _builtin::print_uint(x.fetch_xor(0b1111, Ordering::SeqCst) as _);
+        _builtin::print_uint(x.fetch_max(1000, Ordering::SeqCst) as _);
+        _builtin::print_uint(x.fetch_min(7, Ordering::SeqCst) as _);
+        _builtin::print_uint(x.load(Ordering::SeqCst) as _);
+    }
+    {
+        let x = AtomicI64::new(5);
+        let a = x.compare_exchange(5, 10, Ordering::SeqCst, Ordering::Relaxed);
+        let b = x.compare_exchange(5, 20, Ordering::AcqRel, Ordering::Acquire);
+        _builtin::print_int(a.unwrap() as _);
+        _builtin::print_int(b.unwrap_err() as _);
+
+        let mut old = x.load(Ordering::Relaxed);
+        loop {
+            match x.compare_exchange_weak(old, old * 3, Ordering::SeqCst, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(v) => old = v,
+            }
+        }
+        _builtin::print_int(x.load(Ordering::SeqCst) as _);
+
+        let res = x.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| Some(v - 1));
+        _builtin::print_int(res.unwrap() as _);
+        _builtin::print_int(x.into_inner() as _);
+    }
+    {
+        let flag = AtomicBool::new(false);
+        _builtin::print_bool(flag.fetch_or(true, Ordering::SeqCst));
+        _builtin::print_bool(flag.fetch_and(false, Ordering::SeqCst));
+        _builtin::print_bool(flag.fetch_xor(true, Ordering::SeqCst));
+        _builtin::print_bool(flag.load(Ordering::SeqCst));
+    }
+    {
+        let mut a = 1;
+        let mut b = 2;
+        let ptr = AtomicPtr::new(&mut a as *mut i32);
+        let old = ptr.swap(&mut b, Ordering::SeqCst);
+        let res = ptr.compare_exchange(old, &mut a, Ordering::SeqCst, Ordering::SeqCst);
+        unsafe {
+            _builtin::print_int(*old as _);
+            _builtin::print_int(*res.unwrap_err() as _);
+            _builtin::print_int(*ptr.load(Ordering::SeqCst) as _);
+        }
+    }
+    {
+        let x = AtomicUsize::new(3);
+        fence(Ordering::SeqCst);
+        fence(Ordering::Acquire);
+        compiler_fence(Ordering::Release);
+        _builtin::print_uint(x.fetch_add(4, Ordering::Release) as _);
+        _builtin::print_uint(x.load(Ordering::Acquire) as _);
+    }
+}
@@ -0,0 +1,45 @@
+mod _builtin;
+
+struct HasDrop;
+
+impl Drop for HasDrop {
+    fn drop(&mut self) {}
+}
+
+fn counts<T: Copy>(vals: &[T], f: fn(T) -> (u32, u32, u32)) {
+    for val in vals {
+        let (ones, leading, trailing) = f(*val);
+        _builtin::print_uint(ones as _);
+        _builtin::print_uint(leading as _);
+        _builtin::print_uint(trailing as _);
+    }
+}
+
+pub fn main() {
+    counts(&[0u8, 1, 0x80, 0x3c, 0xff], |x| {
+        (x.count_ones(), x.leading_zeros(), x.trailing_zeros())
+    });
+    counts(&[0i16, -1, 0x100, 0x7ff0], |x| {
+        (x.count_ones(), x.leading_zeros(), x.trailing_zeros())
+    });
+    counts(&[0u32, 1, 0xf000, u32::MAX], |x| {
+        (x.count_ones(), x.leading_zeros(), x.trailing_zeros())
+    });
+    counts(&[0i64, -8, 0x100_0000_0000], |x| {
+        (x.count_ones(), x.leading_zeros(), x.trailing_zeros())
+    });
+    counts(&[0u128, 0x10_0000_0000_0000_0000_0000_0000, 0x1fff_ffff_ffff_ffff_ffff_ffff_ffff_ffff], |x| {
+        (x.count_ones(), x.leading_zeros(), x.trailing_zeros())
+    });
+
+    // powers of two are found through the leading zeros
+    _builtin::print_uint(5u32.next_power_of_two() as _);
+    _builtin::print_uint(1000u64.next_power_of_two() as _);
+    _builtin::print_uint(0x1234usize.ilog2() as _);
+
+    _builtin::print_bool(std::mem::needs_drop::<u32>());
+    _builtin::print_bool(std::mem::needs_drop::<HasDrop>());
+    _builtin::print_bool(std::mem::needs_drop::<(u8, HasDrop)>());
+    _builtin::print_bool(std::mem::needs_drop::<[HasDrop; 0]>());
+    _builtin::print_bool(std::mem::needs_drop::<&HasDrop>());
+}
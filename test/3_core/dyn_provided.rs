@@ -0,0 +1,43 @@
+mod _builtin;
+
+trait Shape {
+    fn area(&self) -> u32;
+
+    fn name(&self) -> &'static str {
+        "shape"
+    }
+
+    fn describe(&self) {
+        _builtin::print_raw(self.name());
+        _builtin::print_raw(" ");
+        _builtin::print_uint(self.area() as _);
+    }
+}
+
+struct Square(u32);
+struct Rect(u32, u32);
+
+impl Shape for Square {
+    fn area(&self) -> u32 {
+        self.0 * self.0
+    }
+
+    fn name(&self) -> &'static str {
+        "square"
+    }
+}
+
+impl Shape for Rect {
+    fn area(&self) -> u32 {
+        self.0 * self.1
+    }
+}
+
+pub fn main() {
+    let shapes: [&dyn Shape; 3] = [&Square(3), &Rect(2, 5), &Square(1)];
+    for shape in shapes {
+        shape.describe();
+        _builtin::print_raw(shape.name());
+        _builtin::print_raw("\n");
+    }
+}
@@ -0,0 +1,46 @@
+mod _builtin;
+
+static GREETING: &str = "static";
+
+fn words() -> &'static [&'static str] {
+    &["promoted", "string", "literals"]
+}
+
+fn nested() -> &'static &'static str {
+    &"nested"
+}
+
+fn shifted() -> &'static [u32] {
+    &[1 << 3, 2 + 2, 10 * 10, 100 >> 2]
+}
+
+fn erased() -> &'static [u8] {
+    &[1u8, 2, 3] as &[u8]
+}
+
+fn pick(i: usize) -> &'static str {
+    let table: &'static [&'static str; 2] = &["even", "odd"];
+    table[i % 2]
+}
+
+pub fn main() {
+    for word in words() {
+        _builtin::print_raw(word);
+        _builtin::print_raw("\n");
+    }
+    _builtin::print_raw(nested());
+    _builtin::print_raw("\n");
+    for n in shifted() {
+        _builtin::print_uint(*n as _);
+    }
+    for n in erased() {
+        _builtin::print_uint(*n as _);
+    }
+    for i in 0..3 {
+        _builtin::print_raw(pick(i));
+        _builtin::print_raw("\n");
+    }
+    let r: &&str = &GREETING;
+    _builtin::print_raw(r);
+    _builtin::print_raw("\n");
+}
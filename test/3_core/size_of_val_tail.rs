@@ -0,0 +1,37 @@
+mod _builtin;
+
+struct Tail<T: ?Sized> {
+    tag: u8,
+    data: T,
+}
+
+struct Nested<T: ?Sized> {
+    count: u32,
+    inner: Tail<T>,
+}
+
+pub fn main() {
+    let a: &Tail<[u16]> = &Tail {
+        tag: 1,
+        data: [1, 2, 3],
+    };
+    _builtin::print_uint(std::mem::size_of_val(a) as _);
+
+    let b: &Tail<[u64]> = &Tail {
+        tag: 2,
+        data: [1, 2, 3, 4, 5],
+    };
+    _builtin::print_uint(std::mem::size_of_val(b) as _);
+
+    let c: &Nested<[u8]> = &Nested {
+        count: 3,
+        inner: Tail {
+            tag: 3,
+            data: [1, 2, 3, 4, 5, 6, 7],
+        },
+    };
+    _builtin::print_uint(std::mem::size_of_val(c) as _);
+
+    let d: &Tail<[u32]> = &Tail { tag: 4, data: [] };
+    _builtin::print_uint(std::mem::size_of_val(d) as _);
+}
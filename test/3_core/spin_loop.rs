@@ -0,0 +1,21 @@
+mod _builtin;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// waits for a counter which is bumped while spinning
+fn spin_until(counter: &AtomicU32, target: u32) -> u32 {
+    let mut spins = 0;
+    while counter.load(Ordering::Acquire) < target {
+        std::hint::spin_loop();
+        counter.fetch_add(1, Ordering::Release);
+        spins += 1;
+    }
+    spins
+}
+
+pub fn main() {
+    let counter = AtomicU32::new(0);
+    _builtin::print_uint(spin_until(&counter, 10) as _);
+    _builtin::print_uint(spin_until(&counter, 5) as _);
+    _builtin::print_uint(counter.load(Ordering::Relaxed) as _);
+}
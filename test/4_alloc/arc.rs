@@ -0,0 +1,40 @@
+use std::sync::{Arc, Weak};
+
+mod _builtin;
+
+pub fn main() {
+    {
+        let n1 = Arc::new(5);
+        let n2 = n1.clone();
+        let n3 = Arc::clone(&n2);
+
+        _builtin::print_int(*n1 + *n2 + *n3);
+        _builtin::print_uint(Arc::strong_count(&n1) as _);
+        drop(n3);
+        _builtin::print_uint(Arc::strong_count(&n1) as _);
+    }
+    {
+        let shared: Arc<[u64]> = Arc::new([1, 2, 3, 4]);
+        let weak: Weak<[u64]> = Arc::downgrade(&shared);
+        _builtin::print_uint(Arc::weak_count(&shared) as _);
+
+        {
+            let upgraded = weak.upgrade().unwrap();
+            let sum: u64 = upgraded.iter().sum();
+            _builtin::print_uint(sum as _);
+        }
+
+        drop(shared);
+        _builtin::print_bool(weak.upgrade().is_none());
+    }
+    {
+        let mut unique = Arc::new(10);
+        *Arc::make_mut(&mut unique) += 1;
+        let other = unique.clone();
+        *Arc::make_mut(&mut unique) += 1;
+        _builtin::print_int(*unique);
+        _builtin::print_int(*other);
+        _builtin::print_bool(Arc::ptr_eq(&unique, &other));
+        _builtin::print_int(Arc::try_unwrap(unique).unwrap());
+    }
+}
@@ -3,6 +3,14 @@
 
 mod _builtin;
 
+fn slice_len(b: &Box<[u8]>) -> usize {
+    b.len()
+}
+
+fn unbox(b: &&Box<i32>) -> i32 {
+    ***b
+}
+
 pub fn main() {
     {
         let void = Box::new(());
@@ -35,4 +43,15 @@ pub fn main() {
         }
         _builtin::print_int(sum as _);
     }
+
+    {
+        // reading a box through a reference must leave it owned by the referent
+        let slice: Box<[u8]> = Box::new([1, 2, 3]);
+        let n = Box::new(9);
+        _builtin::print_int(slice_len(&slice) as _);
+        _builtin::print_int(unbox(&&n) as _);
+        let again: Box<[u8]> = Box::new([4, 5]);
+        _builtin::print_int((slice[2] + again[1]) as _);
+        _builtin::print_int(*n as _);
+    }
 }
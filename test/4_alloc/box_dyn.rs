@@ -0,0 +1,60 @@
+mod _builtin;
+mod _log_drop;
+
+use _log_drop::LogDrop;
+
+trait Named {
+    fn name(&self) -> &'static str;
+}
+
+impl Named for LogDrop {
+    fn name(&self) -> &'static str {
+        self.0
+    }
+}
+
+fn make_named(s: &'static str) -> Box<dyn Named> {
+    Box::new(LogDrop(s))
+}
+
+fn make_call(s: &'static str) -> Box<dyn FnOnce() -> usize> {
+    let log = LogDrop(s);
+    Box::new(move || {
+        let len = log.0.len();
+        drop(log);
+        len
+    })
+}
+
+fn main() {
+    // a boxed trait object returned from a function is dropped through its vtable
+    {
+        let named = make_named("named");
+        println!("{}", named.name());
+        let named_2: Box<dyn Named> = named;
+        println!("{}", named_2.name());
+    }
+    println!("-");
+
+    // calling a boxed FnOnce consumes it
+    let call = make_call("call");
+    println!("{}", call());
+    println!("-");
+
+    // an uncalled FnOnce still drops its captures
+    {
+        let _call = make_call("uncalled");
+    }
+    println!("-");
+
+    // moving out of a box frees the box without dropping the value twice
+    let boxed = Box::new(LogDrop("unboxed"));
+    let unboxed = *boxed;
+    println!("moved {}", unboxed.0);
+    drop(unboxed);
+    println!("-");
+
+    let pair = Box::new((LogDrop("left"), 5));
+    let (left, n) = *pair;
+    println!("{} {}", left.0, n);
+}
@@ -0,0 +1,53 @@
+mod _builtin;
+mod _log_drop;
+
+use _log_drop::LogDrop;
+
+trait Named {
+    fn name(&self) -> &'static str;
+}
+
+impl Named for LogDrop {
+    fn name(&self) -> &'static str {
+        self.0
+    }
+}
+
+struct Plain(u64, u8);
+
+impl Named for Plain {
+    fn name(&self) -> &'static str {
+        "plain"
+    }
+}
+
+fn make_named(s: &'static str) -> Box<dyn Named> {
+    Box::new(LogDrop(s))
+}
+
+pub fn main() {
+    {
+        let named = make_named("named");
+        _builtin::print_raw(named.name());
+        _builtin::print_raw("\n");
+        let plain: Box<dyn Named> = Box::new(Plain(1, 2));
+        _builtin::print_raw(plain.name());
+        _builtin::print_raw("\n");
+
+        _builtin::print_uint(std::mem::size_of_val(&*named) as _);
+        _builtin::print_uint(std::mem::align_of_val(&*named) as _);
+        _builtin::print_uint(std::mem::size_of_val(&*plain) as _);
+        _builtin::print_uint(std::mem::align_of_val(&*plain) as _);
+    }
+    _builtin::print_raw("-\n");
+
+    let mut list: [Box<dyn Named>; 3] = [
+        make_named("first"),
+        Box::new(Plain(3, 4)),
+        make_named("third"),
+    ];
+    list[0] = make_named("replaced");
+    _builtin::print_raw("-\n");
+    drop(list);
+    _builtin::print_raw("-\n");
+}
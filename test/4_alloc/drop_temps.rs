@@ -0,0 +1,48 @@
+mod _builtin;
+mod _log_drop;
+
+use _log_drop::LogDrop;
+
+fn is(x: &LogDrop, name: &str) -> bool {
+    x.0 == name
+}
+
+pub fn main() {
+    // temporaries in an `if` condition are dropped before either branch runs
+    if is(&LogDrop("if 1"), "if 1") {
+        _builtin::print_raw("then 1\n");
+    }
+    if is(&LogDrop("if 2"), "other") {
+        _builtin::print_raw("then 2\n");
+    } else {
+        _builtin::print_raw("else 2\n");
+    }
+    let x = if is(&LogDrop("if 3"), "if 3") { 3 } else { 0 };
+    _builtin::print_int(x);
+
+    // and in a `while` condition, before each iteration
+    let mut n = 0;
+    while is(&LogDrop("while"), "while") && n < 2 {
+        _builtin::print_int(n);
+        n += 1;
+    }
+
+    // unlike in the scrutinee of `if let` and `match`, which live to the end of it
+    if let true = is(&LogDrop("if let"), "if let") {
+        _builtin::print_raw("then if let\n");
+    }
+    match is(&LogDrop("match"), "match") {
+        _ => _builtin::print_raw("arm\n"),
+    }
+
+    // a `for` loop's iterator is dropped when the loop ends, before the rest of the statement
+    let sum = {
+        let mut sum = 0;
+        for i in [LogDrop("for")].iter().map(|d| d.0.len()) {
+            sum += i;
+        }
+        _builtin::print_raw("after for\n");
+        sum
+    };
+    _builtin::print_int(sum as _);
+}
@@ -0,0 +1,53 @@
+mod _builtin;
+
+trait Source {
+    type Out;
+
+    fn get(&self) -> Self::Out;
+}
+
+struct Counter(u64);
+
+impl Source for Counter {
+    type Out = u64;
+
+    fn get(&self) -> u64 {
+        self.0 * 2
+    }
+}
+
+struct Pair(i32, i32);
+
+impl Source for Pair {
+    type Out = (i32, i32);
+
+    fn get(&self) -> (i32, i32) {
+        (self.1, self.0)
+    }
+}
+
+fn sum(iter: &mut dyn Iterator<Item = u32>) -> u32 {
+    let mut total = 0;
+    while let Some(x) = iter.next() {
+        total += x;
+    }
+    total
+}
+
+pub fn main() {
+    let counter: &dyn Source<Out = u64> = &Counter(21);
+    _builtin::print_uint(counter.get() as _);
+
+    let pair: Box<dyn Source<Out = (i32, i32)>> = Box::new(Pair(1, -2));
+    let (a, b) = pair.get();
+    _builtin::print_int(a as _);
+    _builtin::print_int(b as _);
+
+    _builtin::print_uint(sum(&mut (1..5)) as _);
+    _builtin::print_uint(sum(&mut [10, 20, 30].iter().copied()) as _);
+
+    let chars: &mut dyn Iterator<Item = char> = &mut "xyz".chars().rev();
+    while let Some(c) = chars.next() {
+        _builtin::print_char(c);
+    }
+}
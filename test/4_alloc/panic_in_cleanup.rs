@@ -0,0 +1,23 @@
+mod _builtin;
+mod _log_drop;
+
+use _log_drop::LogDrop;
+
+struct Bomb;
+
+impl Drop for Bomb {
+    fn drop(&mut self) {
+        // unwinding out of the drop itself still runs its own drops
+        let _inner = LogDrop("inner");
+        _builtin::print_raw("exploding\n");
+        panic!("bomb exploded");
+    }
+}
+
+pub fn main() {
+    // never dropped, the second panic aborts
+    let _outer = LogDrop("outer");
+    let _bomb = Bomb;
+    let _last = LogDrop("last");
+    panic!("first");
+}
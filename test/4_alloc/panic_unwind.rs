@@ -0,0 +1,58 @@
+#![feature(core_intrinsics)]
+
+mod _builtin;
+mod _log_drop;
+
+use _log_drop::LogDrop;
+
+struct Guard(&'static str);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        _builtin::print_raw("restoring ");
+        _builtin::print_raw(self.0);
+        _builtin::print_raw("\n");
+    }
+}
+
+fn recurse(n: i32) {
+    let _a = LogDrop("enter");
+    if n == 3 {
+        let moved = LogDrop("moved");
+        core::mem::forget(moved);
+        panic!("boom");
+    }
+    let _b = LogDrop("after check");
+    recurse(n + 1);
+    _builtin::print_raw("unreachable\n");
+}
+
+fn try_fn(data: *mut u8) {
+    let n = unsafe { *(data as *mut i32) };
+    let _guard = Guard("invariants");
+    let _list = [LogDrop("list 1"), LogDrop("list 2")];
+    recurse(n);
+}
+
+fn catch_fn(data: *mut u8, _payload: *mut u8) {
+    let n = unsafe { *(data as *mut i32) };
+    _builtin::print_raw("caught panic, n = ");
+    _builtin::print_int(n as _);
+}
+
+fn ok_fn(_data: *mut u8) {
+    let _a = LogDrop("ok");
+}
+
+pub fn main() {
+    let _outer = LogDrop("outer");
+
+    let mut n: i32 = 0;
+    let res = unsafe { core::intrinsics::r#try(ok_fn, &mut n as *mut i32 as *mut u8, catch_fn) };
+    _builtin::print_int(res as _);
+
+    let res = unsafe { core::intrinsics::r#try(try_fn, &mut n as *mut i32 as *mut u8, catch_fn) };
+    _builtin::print_int(res as _);
+
+    _builtin::print_raw("done\n");
+}
@@ -33,4 +33,17 @@ pub fn main() {
         /*data.pop();
         _builtin::print_int(data.len() as _);*/
     }
+    {
+        // collecting a mapped `into_iter` reuses its allocation
+        let data: Vec<i64> = vec!(1,2,3,4);
+        let ptr = data.as_ptr();
+        let doubled: Vec<i64> = data.into_iter().map(|x| x * 2).collect();
+        _builtin::print_int(doubled.iter().sum::<i64>() as _);
+        _builtin::print_int((doubled.as_ptr() == ptr) as _);
+
+        // the items which aren't taken are dropped
+        let data = vec!(LogDrop("G"),LogDrop("H"),LogDrop("I"));
+        let kept: Vec<LogDrop> = data.into_iter().take(1).collect();
+        _builtin::print_int(kept.len() as _);
+    }
 }
@@ -0,0 +1,51 @@
+use std::cell::Cell;
+
+thread_local! {
+    static DROPPED: Cell<u32> = Cell::new(0);
+}
+
+struct Guard(u32);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        DROPPED.with(|d| d.set(d.get() + 1));
+    }
+}
+
+// big enough frames to need many stack segments
+fn sum_down(n: u32) -> u64 {
+    let buffer = [n as u64; 16];
+    if n == 0 {
+        0
+    } else {
+        buffer.iter().sum::<u64>() / 16 + sum_down(n - 1)
+    }
+}
+
+fn guarded(n: u32, limit: u32) -> u32 {
+    let _guard = Guard(n);
+    if n == limit {
+        return n;
+    }
+    guarded(n + 1, limit)
+}
+
+fn ackermann(m: u64, n: u64) -> u64 {
+    if m == 0 {
+        n + 1
+    } else if n == 0 {
+        ackermann(m - 1, 1)
+    } else {
+        ackermann(m - 1, ackermann(m, n - 1))
+    }
+}
+
+fn main() {
+    println!("{}", sum_down(10_000));
+    println!("{}", ackermann(2, 2000));
+
+    let res = guarded(0, 5_000);
+    println!("{} {}", res, DROPPED.with(|d| d.get()));
+
+    println!("{}", sum_down(10_000));
+}
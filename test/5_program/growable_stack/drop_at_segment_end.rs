@@ -0,0 +1,38 @@
+use std::hint::black_box;
+
+struct Guard(u32);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        black_box(self.0);
+    }
+}
+
+// dropping the guard calls its drop glue right after this frame, which can be the end of a
+// stack segment
+fn guarded(n: u32) -> u32 {
+    let guard = Guard(n);
+    if n == 0 {
+        return 0;
+    }
+    let res = guarded(n - 1) + 1;
+    drop(guard);
+    res
+}
+
+// each padding moves the recursion's frames to a different offset in the segments
+fn padded<const N: usize>(n: u32) -> u32 {
+    let pad = [N as u128; N];
+    black_box(&pad);
+    guarded(n)
+}
+
+macro_rules! padded {
+    ($($n:literal)*) => {
+        $( println!("{}", padded::<$n>(5_000)); )*
+    };
+}
+
+fn main() {
+    padded!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31);
+}
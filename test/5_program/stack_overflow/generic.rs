@@ -0,0 +1,25 @@
+// Frames of generic functions are named with their substitutions, written as types.
+fn descend<F: Fn(u64) -> u64, L: Fn(&str) + ?Sized, P: Copy, const N: usize>(
+    f: &F,
+    log: &L,
+    pick: P,
+    n: u64,
+) -> u64 {
+    if n == 0 {
+        f(n)
+    } else {
+        descend::<F, L, P, N>(f, log, pick, n - 1) + 1
+    }
+}
+
+fn main() {
+    println!("start");
+    let offset = 1;
+    let total = descend::<_, dyn Fn(&str) + Send, fn(bool, [u8; 3]) -> char, 3>(
+        &move |n| n + offset,
+        &|msg| println!("{}", msg),
+        |b, _| if b { 'y' } else { 'n' },
+        u64::MAX,
+    );
+    println!("{}", total);
+}
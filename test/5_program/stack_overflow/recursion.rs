@@ -0,0 +1,22 @@
+// Without a growable stack, running out of stack aborts without running drops.
+struct Noisy;
+
+impl Drop for Noisy {
+    fn drop(&mut self) {
+        println!("dropped");
+    }
+}
+
+fn descend(n: u64) -> u64 {
+    let _noisy = Noisy;
+    if n == 0 {
+        0
+    } else {
+        descend(n - 1) + 1
+    }
+}
+
+fn main() {
+    println!("start");
+    println!("{}", descend(u64::MAX));
+}
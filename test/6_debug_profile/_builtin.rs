@@ -0,0 +1 @@
+include!("../_builtin.rs");
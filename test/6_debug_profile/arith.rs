@@ -0,0 +1,50 @@
+mod _builtin;
+
+fn shift_amount<T>(x: T) -> T {
+    x
+}
+
+pub fn main() {
+    _builtin::print_bool(cfg!(debug_assertions));
+
+    {
+        let x: u8 = 200;
+        let y: u8 = 55;
+        _builtin::print_uint((x + y) as _);
+        _builtin::print_uint((x - y) as _);
+        _builtin::print_uint((y * 4) as _);
+        _builtin::print_uint((x << shift_amount(0i64)) as _);
+        _builtin::print_uint((x >> shift_amount(7u128)) as _);
+        _builtin::print_uint(x.wrapping_add(y + 1) as _);
+    }
+
+    {
+        let x: i32 = i32::MAX;
+        let y: i32 = i32::MIN + 1;
+        _builtin::print_int((x + y) as _);
+        _builtin::print_int((-y) as _);
+        _builtin::print_int((y - 1) as _);
+        _builtin::print_int((1 << shift_amount(31u8)) as _);
+        _builtin::print_int((y >> shift_amount(31i16)) as _);
+
+        let mut m = x;
+        m -= 1;
+        m /= 2;
+        m *= 2;
+        m += 1;
+        _builtin::print_int(m as _);
+
+        let mut values = [x, y];
+        for v in values.iter_mut() {
+            *v -= v.signum();
+        }
+        _builtin::print_int(values[0] as _);
+        _builtin::print_int(values[1] as _);
+    }
+
+    {
+        let x: i128 = i128::MIN / 2;
+        _builtin::print_int((x + x) as _);
+        _builtin::print_int((x * 2) as _);
+    }
+}
@@ -0,0 +1,32 @@
+mod _builtin;
+
+use std::ops::{Add, AddAssign, Mul};
+
+// the operator impls for integers live in core, which is built without overflow checks, but they
+// are `#[rustc_inherit_overflow_checks]` and check whenever the program does
+fn add<T: Add<Output = T>>(a: T, b: T) -> T {
+    a + b
+}
+
+fn add_assign<T: AddAssign>(a: &mut T, b: T) {
+    *a += b;
+}
+
+fn square<T: Mul<Output = T> + Copy>(x: T) -> T {
+    x * x
+}
+
+pub fn main() {
+    _builtin::print_uint(add(100u8, 100u8) as _);
+    _builtin::print_int(square(-181i16) as _);
+
+    let mut n = 60_000u16;
+    add_assign(&mut n, 5_000);
+    _builtin::print_uint(n as _);
+
+    let sum: u32 = [1u32, 2, 3].iter().copied().sum();
+    _builtin::print_uint(sum as _);
+
+    _builtin::print_uint(add(200u8, 100u8) as _);
+    _builtin::print_raw("unreachable\n");
+}
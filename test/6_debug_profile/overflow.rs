@@ -0,0 +1,18 @@
+mod _builtin;
+
+struct Counter {
+    count: u16,
+}
+
+fn step(counter: &mut Counter, n: u16) {
+    counter.count += n;
+    _builtin::print_uint(counter.count as _);
+}
+
+pub fn main() {
+    let mut counter = Counter { count: 0 };
+    for _ in 0..10 {
+        step(&mut counter, 10_000);
+    }
+    _builtin::print_raw("unreachable\n");
+}
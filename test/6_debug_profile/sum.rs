@@ -0,0 +1,13 @@
+mod _builtin;
+
+// `Sum` for integers folds with a `#[rustc_inherit_overflow_checks]` closure
+pub fn main() {
+    let bytes = [100u8, 100, 55];
+    let sum: u8 = bytes.iter().copied().sum();
+    _builtin::print_uint(sum as _);
+
+    let bytes = [100u8, 100, 56];
+    let sum: u8 = bytes.iter().copied().sum();
+    _builtin::print_uint(sum as _);
+    _builtin::print_raw("unreachable\n");
+}
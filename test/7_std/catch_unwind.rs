@@ -0,0 +1,27 @@
+use std::panic;
+use std::sync::Mutex;
+
+fn main() {
+    let res = panic::catch_unwind(|| panic!("boom"));
+    println!("{}", res.is_err());
+    println!("{:?}", res.unwrap_err().downcast_ref::<&str>());
+
+    let n = 3;
+    let res = panic::catch_unwind(|| panic!("boom {}", n));
+    println!("{:?}", res.unwrap_err().downcast_ref::<String>());
+
+    let res = panic::catch_unwind(|| n * 2);
+    println!("{:?}", res.ok());
+
+    // a guard dropped while panicking poisons its lock, but the panic ends once it is caught
+    let lock = Mutex::new(n);
+    let res = panic::catch_unwind(|| {
+        let _guard = lock.lock().unwrap();
+        panic!("poisoned");
+    });
+    println!("{} {}", res.is_err(), lock.is_poisoned());
+    println!("{}", std::thread::panicking());
+
+    let res = panic::catch_unwind(|| panic::resume_unwind(Box::new(7u32)));
+    println!("{:?}", res.unwrap_err().downcast_ref::<u32>());
+}
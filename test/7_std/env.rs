@@ -0,0 +1,36 @@
+use std::cell::{Cell, RefCell};
+
+thread_local! {
+    static COUNTER: Cell<u32> = Cell::new(0);
+    static NAMES: RefCell<Vec<String>> = RefCell::new(Vec::new());
+}
+
+fn bump() -> u32 {
+    COUNTER.with(|c| {
+        c.set(c.get() + 1);
+        c.get()
+    })
+}
+
+fn main() {
+    std::env::set_var("SKITTER_TEST_VAR", "some value");
+    println!("{:?}", std::env::var("SKITTER_TEST_VAR"));
+    std::env::remove_var("SKITTER_TEST_VAR");
+    println!("{:?}", std::env::var("SKITTER_TEST_VAR").is_err());
+
+    println!("{} args", std::env::args().count());
+
+    bump();
+    bump();
+    println!("counter = {}", bump());
+
+    NAMES.with(|names| {
+        let mut names = names.borrow_mut();
+        names.push("a".to_owned());
+        names.push(format!("b{}", 2));
+    });
+    NAMES.with(|names| println!("{:?}", names.borrow()));
+
+    let start = std::time::Instant::now();
+    println!("{}", start.elapsed().as_secs() < 60);
+}
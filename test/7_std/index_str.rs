@@ -0,0 +1,23 @@
+use std::panic;
+
+fn main() {
+    let s = String::from("héllo");
+    let n = std::env::args().count();
+
+    let res = panic::catch_unwind(|| s[0..2].len());
+    println!("{}", res.is_err());
+
+    let res = panic::catch_unwind(|| println!("{}", &s[n + 2..]));
+    println!("{}", res.is_err());
+
+    let t: &str = "abc";
+    let res = panic::catch_unwind(|| t[n + 1..1].len());
+    println!("{}", res.is_err());
+
+    let res = panic::catch_unwind(|| t[..n + 9].len());
+    println!("{}", res.is_err());
+
+    // the last one isn't caught
+    let u: &str = &s[1..n + 2];
+    println!("{}", u);
+}
@@ -0,0 +1,24 @@
+use std::panic::{self, AssertUnwindSafe};
+
+fn main() {
+    let mut v = vec![1, 2, 3];
+    let n = std::env::args().count();
+
+    let res = panic::catch_unwind(|| v[n + 5]);
+    println!("{}", res.is_err());
+
+    let res = panic::catch_unwind(|| println!("{}", v[3]));
+    println!("{}", res.is_err());
+
+    let res = panic::catch_unwind(AssertUnwindSafe(|| v[n + 2] = 4));
+    println!("{}", res.is_err());
+
+    let res = panic::catch_unwind(|| v[1..n + 4].len());
+    println!("{}", res.is_err());
+
+    let res = panic::catch_unwind(|| v[n + 1..1].len());
+    println!("{}", res.is_err());
+
+    // the last one isn't caught
+    println!("{:?}", &v[..n + 3]);
+}
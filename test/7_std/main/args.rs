@@ -0,0 +1,11 @@
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    println!("{} args", args.len());
+    for arg in &args {
+        println!("arg: {}", arg);
+    }
+
+    let mut args_os = std::env::args_os();
+    args_os.next();
+    println!("{:?}", args_os.next());
+}
@@ -0,0 +1,7 @@
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let count = std::env::args().count();
+    println!("count = {}", count);
+    ExitCode::from(40 + count as u8)
+}
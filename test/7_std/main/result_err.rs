@@ -0,0 +1,19 @@
+#[derive(Debug)]
+enum AppError {
+    Missing(&'static str),
+}
+
+fn find(name: &'static str) -> Result<u32, AppError> {
+    if name == "alpha" {
+        Ok(1)
+    } else {
+        Err(AppError::Missing(name))
+    }
+}
+
+fn main() -> Result<(), AppError> {
+    println!("alpha = {}", find("alpha")?);
+    println!("gamma = {}", find("gamma")?);
+    println!("unreachable");
+    Ok(())
+}
@@ -0,0 +1,5 @@
+fn main() -> Result<(), String> {
+    let total: u64 = std::env::args().skip(1).map(|arg| arg.len() as u64).sum();
+    println!("total length = {}", total);
+    Ok(())
+}
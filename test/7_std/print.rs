@@ -0,0 +1,23 @@
+use std::rc::Rc;
+
+#[derive(Debug)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+fn main() {
+    println!("hello {}", 5);
+    println!("{:?}", Point { x: 1, y: -2 });
+    println!("{:>8.3}|{:<5}|{:#x}", 3.14159, "ab", 255);
+
+    let words = vec!["one", "two", "three"];
+    let joined = words.join(", ");
+    println!("{}", joined);
+
+    let shared: Rc<str> = Rc::from("shared");
+    let bytes: Rc<[u8]> = Rc::from(&b"xyz"[..]);
+    println!("{} {:?} {}", shared, bytes, std::mem::size_of_val(&*shared));
+
+    eprintln!("to stderr: {}", words.len());
+}
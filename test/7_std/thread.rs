@@ -0,0 +1,56 @@
+mod _builtin;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+struct Token(usize);
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        DROPS.fetch_add(self.0, Ordering::SeqCst);
+    }
+}
+
+fn main() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let log = Arc::new(Mutex::new(Vec::new()));
+
+    let mut handles = Vec::new();
+    for i in 0..4 {
+        let counter = counter.clone();
+        let log = log.clone();
+        let token = Token(i + 1);
+        handles.push(_builtin::spawn(Box::new(move || {
+            for _ in 0..1000 {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+            log.lock().unwrap().push(token.0 * 10);
+        })));
+    }
+
+    for handle in handles {
+        _builtin::join(handle);
+    }
+
+    let mut log = log.lock().unwrap().clone();
+    log.sort();
+
+    println!("{}", counter.load(Ordering::Relaxed));
+    println!("{:?}", log);
+    println!("{}", Arc::strong_count(&counter));
+    println!("{}", DROPS.load(Ordering::SeqCst));
+
+    // captures are released once the thread finishes
+    let shared = Arc::new(Mutex::new(String::from("start")));
+    let handle = {
+        let shared = shared.clone();
+        _builtin::spawn(Box::new(move || {
+            shared.lock().unwrap().push_str(" -> thread");
+        }))
+    };
+    _builtin::join(handle);
+    shared.lock().unwrap().push_str(" -> main");
+    println!("{} {}", shared.lock().unwrap(), Arc::strong_count(&shared));
+}
@@ -0,0 +1,71 @@
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+struct Guard(usize);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        DROPPED.fetch_add(self.0, Ordering::SeqCst);
+    }
+}
+
+struct LastWords;
+
+impl Drop for LastWords {
+    fn drop(&mut self) {
+        println!("main thread exited");
+    }
+}
+
+thread_local! {
+    static LAST_WORDS: LastWords = LastWords;
+    static COUNTER: Cell<u32> = Cell::new(0);
+    static GUARD: RefCell<Option<Guard>> = RefCell::new(None);
+}
+
+fn bump(n: u32) -> u32 {
+    COUNTER.with(|c| {
+        c.set(c.get() + n);
+        c.get()
+    })
+}
+
+fn main() {
+    bump(5);
+
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let handles: Vec<_> = (1..=3)
+        .map(|i| {
+            let results = results.clone();
+            thread::spawn(move || {
+                // each thread starts from the initial value
+                let before = COUNTER.with(|c| c.get());
+                bump(i);
+                bump(i);
+                GUARD.with(|g| *g.borrow_mut() = Some(Guard(i as usize * 100)));
+                results.lock().unwrap().push((i, before, bump(0)));
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let mut results = std::mem::take(&mut *results.lock().unwrap());
+    results.sort();
+    println!("{:?}", results);
+
+    // destructors ran when each thread exited
+    println!("dropped = {}", DROPPED.load(Ordering::SeqCst));
+
+    println!("main counter = {}", bump(1));
+    GUARD.with(|g| println!("main guard = {}", g.borrow().is_some()));
+
+    // destructors for the main thread run once it returns
+    LAST_WORDS.with(|_| ());
+}
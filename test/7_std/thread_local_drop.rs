@@ -0,0 +1,18 @@
+use std::cell::RefCell;
+
+struct Noisy(&'static str);
+
+impl Drop for Noisy {
+    fn drop(&mut self) {
+        println!("drop {}", self.0);
+    }
+}
+
+thread_local! {
+    static SLOT: RefCell<Option<Noisy>> = RefCell::new(None);
+}
+
+fn main() {
+    SLOT.with(|slot| *slot.borrow_mut() = Some(Noisy("main")));
+    println!("end of main");
+}
@@ -0,0 +1,23 @@
+use std::thread;
+
+fn main() {
+    println!("{:?}", thread::current().name());
+
+    let res = thread::spawn(|| panic!("boom")).join();
+    println!("{}", res.is_err());
+    println!("{:?}", res.unwrap_err().downcast_ref::<&str>());
+
+    // the panic message names the thread
+    let res = thread::Builder::new()
+        .name("worker".into())
+        .spawn(|| {
+            println!("{:?}", thread::current().name());
+            panic!("worker {}", 7);
+        })
+        .unwrap()
+        .join();
+    println!("{:?}", res.unwrap_err().downcast_ref::<&str>());
+
+    let res = thread::spawn(|| 5).join();
+    println!("{:?}", res.ok());
+}
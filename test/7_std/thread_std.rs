@@ -0,0 +1,93 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+struct Token(usize);
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        DROPS.fetch_add(self.0, Ordering::SeqCst);
+    }
+}
+
+fn main() {
+    let counter = Arc::new(AtomicUsize::new(0));
+
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let counter = counter.clone();
+            let token = Token(i + 1);
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+                token.0 * 10
+            })
+        })
+        .collect();
+
+    // join returns what the closure returned
+    let mut results: Vec<usize> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    results.sort();
+
+    println!("{}", counter.load(Ordering::Relaxed));
+    println!("{:?}", results);
+    println!("{}", Arc::strong_count(&counter));
+    println!("{}", DROPS.load(Ordering::SeqCst));
+
+    // a thread which panics drops its captures, and join returns the payload
+    let shared = Arc::new(Mutex::new(Vec::new()));
+    let handle = {
+        let shared = shared.clone();
+        let token = Token(100);
+        thread::spawn(move || {
+            let _token = token;
+            shared.lock().unwrap().push(1);
+            if shared.lock().unwrap().len() == 1 {
+                panic!("thread failed");
+            }
+            2
+        })
+    };
+    let res = handle.join();
+    println!("{}", res.is_err());
+    println!("{:?}", res.unwrap_err().downcast_ref::<&str>());
+    println!("{}", DROPS.load(Ordering::SeqCst));
+    println!("{:?} {}", shared.lock().unwrap(), Arc::strong_count(&shared));
+
+    // the lock wasn't held when the thread panicked, so it isn't poisoned
+    println!("{}", shared.is_poisoned());
+
+    // other threads keep running
+    let (tx, rx) = mpsc::channel();
+    let senders: Vec<_> = (0..3)
+        .map(|i| {
+            let tx = tx.clone();
+            thread::spawn(move || tx.send(i * i).unwrap())
+        })
+        .collect();
+    drop(tx);
+    for sender in senders {
+        sender.join().unwrap();
+    }
+    let mut received: Vec<i32> = rx.iter().collect();
+    received.sort();
+    println!("{:?}", received);
+
+    // a panic while holding a lock poisons it
+    let poisoned = Arc::new(Mutex::new(0));
+    let res = {
+        let poisoned = poisoned.clone();
+        thread::spawn(move || {
+            let mut guard = poisoned.lock().unwrap();
+            *guard += 1;
+            panic!("poisoning {}", *guard);
+        })
+        .join()
+    };
+    println!("{:?}", res.unwrap_err().downcast_ref::<String>());
+    println!("{}", poisoned.is_poisoned());
+    println!("{}", poisoned.lock().unwrap_or_else(|err| err.into_inner()));
+}
@@ -0,0 +1,49 @@
+use std::panic::Location;
+
+#[track_caller]
+fn here() -> &'static Location<'static> {
+    Location::caller()
+}
+
+#[track_caller]
+fn forwarded() -> &'static Location<'static> {
+    here()
+}
+
+struct Checked(u32);
+
+impl Checked {
+    #[track_caller]
+    fn get(&self, limit: u32) -> u32 {
+        if self.0 > limit {
+            let loc = Location::caller();
+            println!("too big at line {} col {}", loc.line(), loc.column());
+        }
+        self.0
+    }
+}
+
+macro_rules! locate {
+    () => {
+        here()
+    };
+}
+
+pub fn main() {
+    println!("{}", Location::caller());
+    println!("{}", here());
+    println!("{}", forwarded());
+
+    let closure = || here();
+    println!("{}", closure());
+    println!("{}", locate!());
+
+    let checked = Checked(10);
+    checked.get(5);
+    checked
+        .get(1);
+
+    let values: Vec<u32> = vec![1, 2, 3];
+    let missing: Option<&u32> = values.iter().find(|v| **v > 3);
+    missing.expect("nothing above three");
+}
@@ -0,0 +1,142 @@
+use std::ffi::{c_char, c_double, c_int, c_void};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Point {
+    x: f32,
+    y: f32,
+    id: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Big {
+    a: u64,
+    b: u64,
+    c: u64,
+}
+
+extern "C" {
+    fn qsort(
+        base: *mut c_void,
+        n: usize,
+        size: usize,
+        compare: extern "C" fn(*const c_void, *const c_void) -> c_int,
+    );
+    fn bsearch(
+        key: *const c_void,
+        base: *const c_void,
+        n: usize,
+        size: usize,
+        compare: extern "C" fn(*const c_void, *const c_void) -> c_int,
+    ) -> *mut c_void;
+    fn atexit(func: extern "C" fn()) -> c_int;
+    fn abs(x: c_int) -> c_int;
+    // null pointers are `None`
+    #[allow(clashing_extern_declarations)]
+    #[link_name = "qsort"]
+    fn qsort_opt(
+        base: *mut c_void,
+        n: usize,
+        size: usize,
+        compare: Option<extern "C" fn(*const c_void, *const c_void) -> c_int>,
+    );
+    fn strchr(s: *const c_char, c: c_int) -> Option<&'static c_char>;
+}
+
+extern "C" fn compare_i32(a: *const c_void, b: *const c_void) -> c_int {
+    let (a, b) = unsafe { (*(a as *const i32), *(b as *const i32)) };
+    a.cmp(&b) as c_int
+}
+
+extern "C" fn compare_points(a: *const c_void, b: *const c_void) -> c_int {
+    let (a, b) = unsafe { (&*(a as *const Point), &*(b as *const Point)) };
+    (a.x + a.y).partial_cmp(&(b.x + b.y)).unwrap() as c_int
+}
+
+extern "C" fn compare_u8(a: *const c_void, b: *const c_void) -> c_int {
+    let (a, b) = unsafe { (*(a as *const u8), *(b as *const u8)) };
+    a as c_int - b as c_int
+}
+
+extern "C" fn goodbye() {
+    println!("goodbye from atexit");
+}
+
+extern "C" fn scale(p: Point, factor: c_double) -> Point {
+    Point {
+        x: p.x * factor as f32,
+        y: p.y * factor as f32,
+        id: p.id + 1,
+    }
+}
+
+extern "C" fn sum_big(a: Big, b: Big, n: u8) -> Big {
+    Big {
+        a: a.a + b.a,
+        b: a.b + b.b,
+        c: (a.c + b.c) * n as u64,
+    }
+}
+
+fn call_indirect(f: unsafe extern "C" fn(c_int) -> c_int, x: c_int) -> c_int {
+    unsafe { f(x) }
+}
+
+extern "C" fn square(x: c_int) -> c_int {
+    x * x
+}
+
+fn main() {
+    unsafe {
+        atexit(goodbye);
+
+        let mut numbers = [5, -3, 12, 0, 7, 7, -20, 1];
+        qsort(numbers.as_mut_ptr() as _, numbers.len(), 4, compare_i32);
+        println!("{:?}", numbers);
+
+        let key = 7;
+        let found = bsearch(
+            &key as *const i32 as _,
+            numbers.as_ptr() as _,
+            numbers.len(),
+            4,
+            compare_i32,
+        ) as *const i32;
+        println!("{}", *found);
+
+        let mut points = [
+            Point { x: 3.0, y: 1.5, id: 0 },
+            Point { x: -1.0, y: 0.5, id: 1 },
+            Point { x: 2.0, y: 0.0, id: 2 },
+        ];
+        qsort(
+            points.as_mut_ptr() as _,
+            points.len(),
+            std::mem::size_of::<Point>(),
+            compare_points,
+        );
+        let mut bytes = [3u8, 1, 2];
+        qsort_opt(bytes.as_mut_ptr() as _, 3, 1, Some(compare_u8));
+        println!("{:?}", bytes);
+
+        let text = b"abc\0".as_ptr() as *const c_char;
+        println!("{:?} {:?}", strchr(text, 'b' as c_int), strchr(text, 'z' as c_int));
+
+        for p in points {
+            print!("{} ", p.id);
+        }
+        println!();
+    }
+
+    // C function pointers called from interpreted code
+    let f: extern "C" fn(Point, c_double) -> Point = scale;
+    println!("{:?}", f(Point { x: 1.0, y: -2.0, id: 5 }, 2.5));
+
+    let g: extern "C" fn(Big, Big, u8) -> Big = sum_big;
+    println!("{:?}", g(Big { a: 1, b: 2, c: 3 }, Big { a: 10, b: 20, c: 30 }, 2));
+
+    println!("{}", call_indirect(square, -9));
+    println!("{}", call_indirect(abs, -9));
+    println!("done");
+}
@@ -0,0 +1,64 @@
+use std::ffi::{c_char, c_double, c_float, c_int, c_long, c_void, CStr};
+
+#[repr(C)]
+#[derive(Debug)]
+struct DivT {
+    quot: c_int,
+    rem: c_int,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct LDivT {
+    quot: c_long,
+    rem: c_long,
+}
+
+extern "C" {
+    fn strlen(s: *const c_char) -> usize;
+    fn strchr(s: *const c_char, c: c_int) -> *const c_char;
+    fn memcmp(a: *const c_void, b: *const c_void, n: usize) -> c_int;
+    fn abs(x: c_int) -> c_int;
+    fn labs(x: c_long) -> c_long;
+    fn div(num: c_int, denom: c_int) -> DivT;
+    fn ldiv(num: c_long, denom: c_long) -> LDivT;
+    fn cos(x: c_double) -> c_double;
+    fn pow(x: c_double, y: c_double) -> c_double;
+    fn sqrtf(x: c_float) -> c_float;
+    fn toupper(c: c_int) -> c_int;
+    fn atoi(s: *const c_char) -> c_int;
+    fn malloc(size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+fn main() {
+    let text = b"hello world\0";
+    let ptr = text.as_ptr() as *const c_char;
+
+    unsafe {
+        println!("{}", strlen(ptr));
+
+        let space = strchr(ptr, ' ' as c_int);
+        println!("{:?}", CStr::from_ptr(space));
+        println!("{}", memcmp(ptr as _, b"help".as_ptr() as _, 3));
+        println!("{}", memcmp(ptr as _, b"help".as_ptr() as _, 4) < 0);
+
+        println!("{} {}", abs(-12), labs(-5_000_000_000));
+        println!("{:?}", div(-17, 5));
+        println!("{:?}", ldiv(10_000_000_000, 3));
+
+        println!("{}", cos(0.0));
+        println!("{}", pow(2.0, 10.0));
+        println!("{}", sqrtf(2.25));
+
+        println!("{}", toupper('q' as c_int) as u8 as char);
+        println!("{}", atoi(b"-1234xyz\0".as_ptr() as _));
+
+        let buf = malloc(16) as *mut u32;
+        for i in 0..4 {
+            *buf.add(i) = i as u32 * 10;
+        }
+        println!("{}", *buf.add(3));
+        free(buf as _);
+    }
+}
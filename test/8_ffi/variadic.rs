@@ -0,0 +1,53 @@
+use std::ffi::{c_char, c_double, c_int, c_long, CStr};
+
+extern "C" {
+    fn snprintf(buf: *mut c_char, size: usize, format: *const c_char, ...) -> c_int;
+}
+
+fn format(f: impl FnOnce(*mut c_char, usize) -> c_int) {
+    let mut buf = [0 as c_char; 64];
+    let n = f(buf.as_mut_ptr(), buf.len());
+    let res = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    println!("{} {:?}", n, res);
+}
+
+fn main() {
+    unsafe {
+        format(|buf, size| snprintf(buf, size, b"no args\0".as_ptr() as _));
+        format(|buf, size| snprintf(buf, size, b"%d + %d\0".as_ptr() as _, 1 as c_int, -2 as c_int));
+        format(|buf, size| {
+            snprintf(
+                buf,
+                size,
+                b"%s=%ld\0".as_ptr() as _,
+                b"key\0".as_ptr(),
+                1_i64 << 40 as c_long,
+            )
+        });
+        format(|buf, size| {
+            snprintf(buf, size, b"%.3f %g\0".as_ptr() as _, 3.14159 as c_double, 0.5 as c_double)
+        });
+        // more arguments than fit in registers
+        format(|buf, size| {
+            snprintf(
+                buf,
+                size,
+                b"%d %d %d %d %d %.1f %.1f %.1f %.1f %.1f %.1f %.1f %.1f %.1f\0".as_ptr() as _,
+                1 as c_int,
+                2 as c_int,
+                3 as c_int,
+                4 as c_int,
+                5 as c_int,
+                1.0 as c_double,
+                2.0 as c_double,
+                3.0 as c_double,
+                4.0 as c_double,
+                5.0 as c_double,
+                6.0 as c_double,
+                7.0 as c_double,
+                8.0 as c_double,
+                9.0 as c_double,
+            )
+        });
+    }
+}
@@ -0,0 +1,20 @@
+use std::ffi::{c_char, c_uint, c_ulong, CStr};
+
+#[link(name = "z")]
+extern "C" {
+    fn zlibVersion() -> *const c_char;
+    fn crc32(crc: c_ulong, buf: *const u8, len: c_uint) -> c_ulong;
+    fn adler32(adler: c_ulong, buf: *const u8, len: c_uint) -> c_ulong;
+}
+
+fn main() {
+    let data = b"The quick brown fox jumps over the lazy dog";
+
+    unsafe {
+        let version = CStr::from_ptr(zlibVersion());
+        println!("{}", version.to_bytes()[0] == b'1');
+
+        println!("{:08x}", crc32(0, data.as_ptr(), data.len() as c_uint));
+        println!("{:08x}", adler32(1, data.as_ptr(), data.len() as c_uint));
+    }
+}
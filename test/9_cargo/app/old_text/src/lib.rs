@@ -0,0 +1,5 @@
+// `async` is only a keyword from edition 2018 on
+pub fn shout(s: &str) -> String {
+    let async = s.to_uppercase();
+    async + "!"
+}
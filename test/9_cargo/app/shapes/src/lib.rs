@@ -0,0 +1,25 @@
+use std::fmt::Debug;
+
+pub struct Rect {
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Rect {
+    pub fn area(&self) -> u32 {
+        self.w * self.h
+    }
+
+    #[cfg(feature = "perimeter")]
+    pub fn perimeter(&self) -> u32 {
+        2 * (self.w + self.h)
+    }
+}
+
+pub fn describe<T: Debug>(x: T) -> String {
+    format!("<{:?}>", x)
+}
+
+pub fn unused_enabled() -> bool {
+    cfg!(feature = "unused")
+}
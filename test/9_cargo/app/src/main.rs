@@ -0,0 +1,12 @@
+fn main() {
+    let rect = shapes::Rect { w: 3, h: 4 };
+    println!("area = {}", rect.area());
+    println!("perimeter = {}", rect.perimeter());
+    println!("unused = {}", shapes::unused_enabled());
+
+    println!("{}", shapes::describe(vec![1, 2, 3]));
+    println!("{}", shapes::describe(Some("x")));
+
+    println!("{}", text::shout("hello"));
+    println!("{}", app::greeting());
+}
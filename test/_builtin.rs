@@ -21,3 +21,13 @@ pub fn print_bool(x: bool) {
 pub fn print_raw(x: &str) {
     print!("{}", x);
 }
+
+/// Run a closure on a new thread, returning a handle for `join`.
+pub fn spawn(f: Box<dyn FnOnce() + Send>) -> usize {
+    Box::into_raw(Box::new(std::thread::spawn(f))) as usize
+}
+
+pub fn join(handle: usize) {
+    let handle = unsafe { Box::from_raw(handle as *mut std::thread::JoinHandle<()>) };
+    handle.join().unwrap();
+}